@@ -0,0 +1,14 @@
+fn main() {
+    // The gRPC service is generated from the .proto with a pure-Rust protobuf
+    // compiler, so building it does not need protoc
+    #[cfg(feature = "grpc")]
+    {
+        println!("cargo:rerun-if-changed=proto/data_manifest.proto");
+        let descriptors =
+            protox::compile(["proto/data_manifest.proto"], ["proto"]).expect("Failed to parse data_manifest.proto");
+        tonic_build::configure()
+            .build_client(false)
+            .compile_fds(descriptors)
+            .expect("Failed to generate the gRPC service");
+    }
+}
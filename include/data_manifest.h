@@ -0,0 +1,38 @@
+/* C interface to data-manifest. Build with `cargo build --release --features ffi`
+ * and link against target/release/libdata_manifest.so (or .dylib / .dll). */
+#ifndef DATA_MANIFEST_H
+#define DATA_MANIFEST_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Called after each file is processed with the number of files done so far,
+ * the number of files in the pass, and the file's path. */
+typedef void (*dm_progress_fn)(void *user_data, uint64_t done, uint64_t total, const char *path);
+
+/* Both functions return the command line's exit status: 0 on success, 2 or 3
+ * when validation fails, and 1 for any other error. `extra_args` holds
+ * `extra_count` further command-line options such as "--extended" and may be
+ * NULL when `extra_count` is 0. `progress` may be NULL. Progress goes through
+ * a process-wide sink, so calls with a callback must not overlap. */
+int dm_generate_manifest(const char *archive, const char *manifest,
+                         const char *const *extra_args, size_t extra_count,
+                         dm_progress_fn progress, void *user_data);
+
+int dm_validate_manifest(const char *archive, const char *manifest,
+                         const char *const *extra_args, size_t extra_count,
+                         dm_progress_fn progress, void *user_data);
+
+/* Message of the last failed call on this thread, or NULL after a success.
+ * Valid until the next call on the same thread. */
+const char *dm_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* DATA_MANIFEST_H */
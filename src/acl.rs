@@ -0,0 +1,97 @@
+//! `--metadata acl`: POSIX access control lists recorded per file in the
+//! extended manifest and checked by validation, so that ACLs reset by a
+//! migration or restore are noticed although the contents are intact.
+//!
+//! The `acl` column holds the access ACL in the short text form `getfacl -c
+//! -n` prints, entries separated by commas, e.g.
+//! `user::rw-,user:1001:r--,group::r--,mask::r--,other::r--`. Users and
+//! groups are given by ID. `none` is a file without an extended ACL, whose
+//! permissions are its mode bits alone. ACLs are read from the
+//! `system.posix_acl_access` attribute, which only Linux has.
+
+use anyhow::Result;
+use std::path::Path;
+
+use crate::{container, Args, Error, FileInfo, ManifestEntry, MetadataKind};
+
+/// The `acl` value of a file without an extended ACL
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+const NONE: &str = "none";
+
+/// Whether `args` records ACLs.
+pub(crate) fn recording(args: &Args) -> bool {
+    args.metadata.contains(&MetadataKind::Acl)
+}
+
+/// The `acl` value of a file, or `None` for container members, which have
+/// no ACL of their own.
+pub(crate) fn record(file_info: &FileInfo, args: &Args) -> Result<Option<String>> {
+    if args.descend_archives && container::split_member(&file_info.path).is_some() {
+        return Ok(None);
+    }
+    read(&file_info.path).map(Some)
+}
+
+#[cfg(target_os = "linux")]
+fn read(path: &Path) -> Result<String> {
+    match xattr::get_deref(path, "system.posix_acl_access") {
+        Ok(Some(value)) => Ok(decode(&value)),
+        Ok(None) => Ok(NONE.to_string()),
+        // Filesystems without ACL support hold none
+        Err(e) if e.kind() == std::io::ErrorKind::Unsupported => Ok(NONE.to_string()),
+        Err(e) => Err(Error::io("Failed to read ACL", path)(e).into()),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn read(_path: &Path) -> Result<String> {
+    Err(Error::Config("--metadata acl is not supported on this platform".into()).into())
+}
+
+/// The text form of an ACL in the kernel's attribute format: a 4-byte
+/// version followed by 8-byte entries of tag, permissions, and ID, little
+/// endian.
+#[cfg(target_os = "linux")]
+fn decode(value: &[u8]) -> String {
+    let entries: Vec<String> = value
+        .get(4..)
+        .unwrap_or_default()
+        .chunks_exact(8)
+        .filter_map(|entry| {
+            let tag = u16::from_le_bytes([entry[0], entry[1]]);
+            let perm = u16::from_le_bytes([entry[2], entry[3]]);
+            let id = u32::from_le_bytes([entry[4], entry[5], entry[6], entry[7]]);
+            let qualified = match tag {
+                0x01 => "user:".to_string(),
+                0x02 => format!("user:{}", id),
+                0x04 => "group:".to_string(),
+                0x08 => format!("group:{}", id),
+                0x10 => "mask:".to_string(),
+                0x20 => "other:".to_string(),
+                _ => return None,
+            };
+            let perms: String = [(4, 'r'), (2, 'w'), (1, 'x')]
+                .iter()
+                .map(|&(bit, c)| if perm & bit != 0 { c } else { '-' })
+                .collect();
+            Some(format!("{}:{}", qualified, perms))
+        })
+        .collect();
+    if entries.is_empty() {
+        NONE.to_string()
+    } else {
+        entries.join(",")
+    }
+}
+
+/// How the ACL of `file_info` differs from the one recorded in `expected`,
+/// as `recorded -> actual`, or `None` if it matches or none was recorded.
+pub(crate) fn changes(expected: &ManifestEntry, file_info: &FileInfo, args: &Args) -> Result<Option<String>> {
+    let Some(ref recorded) = expected.acl else {
+        return Ok(None);
+    };
+    let Some(actual) = record(file_info, args)? else {
+        return Ok(None);
+    };
+    Ok((actual != *recorded).then(|| format!("{} -> {}", recorded, actual)))
+}
@@ -0,0 +1,96 @@
+//! `--ads`: alternate data streams on Windows. An NTFS file can carry named
+//! streams next to its main contents, addressed as `file:stream`, which
+//! ordinary directory listings do not show.
+//!
+//! By default the files that have streams are reported while the archive is
+//! walked. With `--ads hash` each stream is hashed as a file of its own and
+//! recorded as `path/to/file:stream`, so hidden stream content is covered by
+//! the manifest. Streams share the mtime of their file. Other platforms have
+//! no alternate data streams and ignore the option.
+
+use std::path::PathBuf;
+
+use crate::FileInfo;
+
+/// What to do with alternate data streams.
+#[derive(clap::ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AdsMode {
+    /// Do not look for streams
+    Ignore,
+    /// Warn about each stream found
+    Report,
+    /// Hash each stream as a file of its own
+    Hash,
+}
+
+/// The path of the stream `name` of `file`.
+fn stream_path(file: &FileInfo, name: &str) -> PathBuf {
+    let mut path = file.path.as_os_str().to_owned();
+    path.push(":");
+    path.push(name);
+    PathBuf::from(path)
+}
+
+/// Report the streams of `file`, or with `--ads hash` add them to `files`.
+pub(crate) fn add_streams(files: &mut Vec<FileInfo>, file: &FileInfo, mode: AdsMode) {
+    if mode == AdsMode::Ignore {
+        return;
+    }
+    let streams = match streams(file) {
+        Ok(streams) => streams,
+        Err(e) => {
+            eprintln!("Warning: Cannot list alternate data streams of {}: {}", file.path.display(), e);
+            return;
+        }
+    };
+    for (name, size) in streams {
+        let path = stream_path(file, &name);
+        match mode {
+            AdsMode::Hash => files.push(FileInfo { path, size, mtime: file.mtime }),
+            _ => eprintln!("Warning: Alternate data stream not hashed: {} ({} bytes; pass --ads hash)", path.display(), size),
+        }
+    }
+}
+
+/// Names and sizes of the alternate data streams of `file`.
+#[cfg(windows)]
+fn streams(file: &FileInfo) -> std::io::Result<Vec<(String, u64)>> {
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::Foundation::{ERROR_HANDLE_EOF, ERROR_INVALID_PARAMETER, INVALID_HANDLE_VALUE};
+    use windows_sys::Win32::Storage::FileSystem::{
+        FindClose, FindFirstStreamW, FindNextStreamW, FindStreamInfoStandard, WIN32_FIND_STREAM_DATA,
+    };
+
+    let wide: Vec<u16> = file.path.as_os_str().encode_wide().chain(std::iter::once(0)).collect();
+    let mut data: WIN32_FIND_STREAM_DATA = unsafe { std::mem::zeroed() };
+    let handle = unsafe { FindFirstStreamW(wide.as_ptr(), FindStreamInfoStandard, &mut data as *mut _ as *mut _, 0) };
+    if handle == INVALID_HANDLE_VALUE {
+        let error = std::io::Error::last_os_error();
+        // No streams to list, or a filesystem without streams (FAT, network shares)
+        return match error.raw_os_error().map(|code| code as u32) {
+            Some(ERROR_HANDLE_EOF) | Some(ERROR_INVALID_PARAMETER) => Ok(Vec::new()),
+            _ => Err(error),
+        };
+    }
+    let mut streams = Vec::new();
+    loop {
+        let length = data.cStreamName.iter().position(|&c| c == 0).unwrap_or(data.cStreamName.len());
+        let name = String::from_utf16_lossy(&data.cStreamName[..length]);
+        // Named data streams are listed as `:name:$DATA`, the main one as `::$DATA`
+        if let Some(name) = name.strip_prefix(':').and_then(|name| name.strip_suffix(":$DATA")) {
+            if !name.is_empty() {
+                streams.push((name.to_string(), data.StreamSize as u64));
+            }
+        }
+        if unsafe { FindNextStreamW(handle, &mut data as *mut _ as *mut _) } == 0 {
+            break;
+        }
+    }
+    unsafe { FindClose(handle) };
+    Ok(streams)
+}
+
+#[cfg(not(windows))]
+fn streams(_file: &FileInfo) -> std::io::Result<Vec<(String, u64)>> {
+    Ok(Vec::new())
+}
@@ -0,0 +1,202 @@
+//! Hashing archives on another host: `--remote ssh://host/path` runs
+//! `data-manifest agent` there over SSH, which walks and hashes the archive
+//! locally and streams the entries back as JSON lines, so only the digests
+//! cross the network.
+
+use anyhow::Result;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+
+use crate::progress::Progress;
+use crate::sftp::parse_ssh_url;
+use crate::{collect_files, get_file_hash, manifest_key, Args, Error, ManifestEntry};
+
+/// One line of agent output.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+enum Message {
+    /// Sent first: the number of files that follow
+    Files { count: usize },
+    File {
+        /// Relative to the archive root, `/` separated
+        path: String,
+        hash: String,
+        size: u64,
+        mtime: Option<i64>,
+    },
+    Error { path: String, message: String },
+}
+
+/// Walk and hash `root`, writing one [`Message`] per line to stdout.
+pub fn run_agent(root: &Path, args: &Args) -> Result<()> {
+    let root = fs::canonicalize(root).map_err(Error::io("Archive path does not exist", root))?;
+    let files = collect_files(&root, args)?;
+    let stdout = Mutex::new(io::stdout());
+    let send = |message: &Message| -> Result<()> {
+        let line = serde_json::to_string(message)?;
+        let mut stdout = stdout.lock().unwrap();
+        writeln!(stdout, "{}", line)?;
+        stdout.flush()?;
+        Ok(())
+    };
+
+    send(&Message::Files { count: files.len() })?;
+    files.par_iter().try_for_each(|file_info| {
+        if args.cancel.is_cancelled() {
+            return Err(Error::Cancelled.into());
+        }
+        let path = file_info.path.strip_prefix(&root).unwrap_or(&file_info.path).to_string_lossy();
+        let path = if cfg!(windows) { path.replace('\\', "/") } else { path.into_owned() };
+        let message = match get_file_hash(file_info, args.buffer_size) {
+            Ok(hash) => Message::File {
+                path,
+                hash,
+                size: file_info.size,
+                mtime: file_info.mtime,
+            },
+            Err(e) => Message::Error {
+                path,
+                message: format!("{:#}", e),
+            },
+        };
+        send(&message)
+    })
+}
+
+/// Quote `value` for the POSIX shell that runs the remote command.
+fn shell_quote(value: &str) -> String {
+    if !value.is_empty() && value.bytes().all(|b| b.is_ascii_alphanumeric() || b"-_./=:,@%+".contains(&b)) {
+        value.to_string()
+    } else {
+        format!("'{}'", value.replace('\'', r"'\''"))
+    }
+}
+
+/// The options that decide which files the agent walks and how it reads
+/// them, as arguments for its command line.
+fn forwarded_args(args: &Args) -> Vec<String> {
+    let mut forwarded = vec!["--buffer-size".to_string(), args.buffer_size.to_string()];
+    let flags = [
+        (args.follow_symlinks, "--follow-symlinks"),
+        (args.one_file_system, "--one-file-system"),
+        (args.no_default_excludes, "--no-default-excludes"),
+        (args.exclude_hidden, "--exclude-hidden"),
+        (args.exclude_system, "--exclude-system"),
+        (args.exclude_backups, "--exclude-backups"),
+    ];
+    forwarded.extend(flags.iter().filter(|(set, _)| *set).map(|(_, flag)| flag.to_string()));
+    let mut option = |name: &str, value: String| forwarded.extend([name.to_string(), value]);
+    if let Some(threads) = args.threads {
+        option("--threads", threads.to_string());
+    }
+    if let Some(max_depth) = args.max_depth {
+        option("--max-depth", max_depth.to_string());
+    }
+    if let Some(ref bwlimit) = args.bwlimit {
+        option("--bwlimit", bwlimit.clone());
+    }
+    for pattern in &args.exclude {
+        option("--exclude", pattern.clone());
+    }
+    if !args.only_ext.is_empty() {
+        option("--only-ext", args.only_ext.join(","));
+    }
+    if !args.skip_ext.is_empty() {
+        option("--skip-ext", args.skip_ext.join(","));
+    }
+    forwarded
+}
+
+/// Run the agent for the `ssh://` URL `url` and collect its entries, keyed
+/// as a local scan would key them.
+pub(crate) fn hash_over_ssh(url: &str, archive_name: &str, args: &Args) -> Result<HashMap<PathBuf, ManifestEntry>> {
+    let (host, port, path) = parse_ssh_url(url)?;
+    // The remote command is left unquoted so it can carry its own arguments
+    let mut remote = vec![args.remote_command.clone()];
+    remote.extend(forwarded_args(args).iter().map(|arg| shell_quote(arg)));
+    remote.extend(["agent".to_string(), "--".to_string(), shell_quote(if path.is_empty() { "." } else { &path })]);
+
+    let mut command = Command::new("ssh");
+    command.args(["-o", "BatchMode=yes"]);
+    if let Some(port) = port {
+        command.args(["-p", port]);
+    }
+    let mut child = command
+        .args(["--", host.as_str(), &remote.join(" ")])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| Error::Config(format!("Failed to run ssh: {}", e)))?;
+    let stdout = child.stdout.take().expect("piped stdout");
+
+    let mut entries = HashMap::new();
+    let mut progress = None;
+    let mut error_count = 0;
+    for line in BufReader::new(stdout).lines() {
+        if args.cancel.is_cancelled() {
+            let _ = child.kill();
+            break;
+        }
+        let line = line.map_err(|e| Error::Config(format!("Lost connection to the agent on {}: {}", host, e)))?;
+        let message: Message = serde_json::from_str(&line)
+            .map_err(|_| Error::Config(format!("Unexpected output from the agent on {}: {}", host, line.trim())))?;
+        match message {
+            Message::Files { count } => {
+                println!("Found {} files", count);
+                progress = Some(Progress::start(count as u64, 0));
+            }
+            Message::File { path, hash, size, mtime } => {
+                let key = manifest_key(Path::new(&path), Path::new(""), archive_name);
+                if let Some(ref progress) = progress {
+                    progress.file_finished(Path::new(&path));
+                }
+                let entry = ManifestEntry {
+                    hash,
+                    size: Some(size),
+                    mtime,
+                    verified: None,
+                    cid: None,
+                    xattrs: None,
+                    acl: None,
+                    link: None,
+                    format: None,
+                    dev: None,
+                    inode: None,
+                };
+                entries.insert(key, entry);
+            }
+            Message::Error { path, message } => {
+                if let Some(ref progress) = progress {
+                    progress.error(Path::new(&path), &message);
+                }
+                eprintln!("Error processing {} on {}: {}", path, host, message);
+                error_count += 1;
+            }
+        }
+    }
+    let status = child.wait()?;
+    if let Some(progress) = progress {
+        progress.finish("Complete");
+    }
+
+    if args.cancel.is_cancelled() {
+        return Err(Error::Cancelled.into());
+    }
+    if !status.success() {
+        return Err(Error::Config(format!(
+            "The agent on {} failed ({}); check that `{} agent` runs there (see --remote-command)",
+            host, status, args.remote_command
+        ))
+        .into());
+    }
+    if error_count > 0 {
+        return Err(Error::Config(format!("{} files could not be hashed on {}", error_count, host)).into());
+    }
+    Ok(entries)
+}
@@ -0,0 +1,103 @@
+//! `--attest`: a signed in-toto attestation of the finished manifest, made
+//! with `cosign attest-blob` and Sigstore's keyless signing.
+//!
+//! cosign gets an OIDC identity token (ambient in CI, `SIGSTORE_ID_TOKEN`,
+//! or a browser login), has Fulcio certify a short-lived key for that
+//! identity, and logs the signature in Rekor. The resulting bundle is written
+//! next to the manifest as `<manifest>.sigstore.json`; its in-toto statement
+//! names the manifest and its SHA-256 as the subject, and carries a
+//! predicate describing the run. Consumers check it with
+//! `cosign verify-blob-attestation`.
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::report::last_summary;
+use crate::{archive_label, eventlog, format_utc, now_secs, run_mode, Args, Error};
+
+/// in-toto predicate type of the attestations
+pub const PREDICATE_TYPE: &str = "https://github.com/munzz11/data-manifest/attestation/v1";
+
+/// What the attestation says about the manifest.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Predicate {
+    generator: Generator,
+    host: String,
+    /// Archive path or URL
+    archive: String,
+    mode: &'static str,
+    /// UTC, e.g. `2024-06-01T02:00:00Z`
+    finished: String,
+    summary: Option<serde_json::Value>,
+}
+
+#[derive(Serialize)]
+struct Generator {
+    name: &'static str,
+    version: &'static str,
+}
+
+/// Where the Sigstore bundle of `manifest_path` is written.
+pub fn bundle_path(manifest_path: &Path) -> PathBuf {
+    let mut path = manifest_path.as_os_str().to_owned();
+    path.push(".sigstore.json");
+    PathBuf::from(path)
+}
+
+/// Run `run` and attest the manifest it wrote. Validation runs leave the
+/// manifest as it was and are not attested.
+pub(crate) fn run_attested(args: &Args, run: impl FnOnce(&Args) -> Result<()>) -> Result<()> {
+    run(args)?;
+    if args.validate || args.cancel.is_cancelled() {
+        return Ok(());
+    }
+    if !args.output.exists() {
+        status!("No manifest written; skipping attestation");
+        return Ok(());
+    }
+    attest_manifest(&args.output, args)
+}
+
+fn attest_manifest(manifest_path: &Path, args: &Args) -> Result<()> {
+    let predicate = Predicate {
+        generator: Generator {
+            name: env!("CARGO_PKG_NAME"),
+            version: env!("CARGO_PKG_VERSION"),
+        },
+        host: eventlog::hostname(),
+        archive: archive_label(args),
+        mode: run_mode(args),
+        finished: format!("{}Z", format_utc(now_secs())),
+        summary: last_summary(),
+    };
+    let bundle = bundle_path(manifest_path);
+    let mut predicate_path = bundle.as_os_str().to_owned();
+    predicate_path.push(".predicate.tmp");
+    let predicate_path = PathBuf::from(predicate_path);
+    fs::write(&predicate_path, serde_json::to_vec_pretty(&predicate)?)
+        .map_err(Error::io("Failed to write attestation predicate", &predicate_path))?;
+
+    // A failed attestation must not leave the bundle of an older manifest
+    let _ = fs::remove_file(&bundle);
+    status!("Attesting manifest with cosign: {}", manifest_path.display());
+    // stdin and stderr stay attached for cosign's interactive OIDC login
+    let status = Command::new("cosign")
+        .args(["attest-blob", "--yes", "--type", PREDICATE_TYPE, "--predicate"])
+        .arg(&predicate_path)
+        .arg("--bundle")
+        .arg(&bundle)
+        .arg(manifest_path)
+        .status();
+    let _ = fs::remove_file(&predicate_path);
+    let status = status.map_err(|e| Error::Config(format!("Failed to run cosign: {}", e)))?;
+    if !status.success() {
+        return Err(Error::Config(format!("cosign attest-blob {} failed ({})", manifest_path.display(), status)).into());
+    }
+    fs::metadata(&bundle).with_context(|| format!("cosign did not write the bundle {}", bundle.display()))?;
+    status!("Attestation written to: {}", bundle.display());
+    Ok(())
+}
@@ -0,0 +1,468 @@
+//! Azure Blob Storage archives, addressed as `az://container/prefix`.
+//!
+//! The storage account and credentials come from
+//! `AZURE_STORAGE_CONNECTION_STRING`, or from `AZURE_STORAGE_ACCOUNT` with
+//! either `AZURE_STORAGE_KEY` (Shared Key) or `AZURE_STORAGE_SAS_TOKEN`. A
+//! connection string's `BlobEndpoint` selects another endpoint, such as the
+//! Azurite emulator.
+
+use anyhow::{Context, Result};
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use md5::{Digest, Md5};
+use serde::Deserialize;
+use sha2::Sha256;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::remote::{http_date, object_key, parse_http_date, split_url, uri_encode, Destination, FetchRange, RangeOptions, RangeReader};
+use crate::{now_secs, Args, Error, FileInfo, FileSource};
+
+const API_VERSION: &str = "2021-08-06";
+
+/// Put Block List accepts at most this many blocks
+const MAX_BLOCKS: u64 = 50_000;
+
+fn base64(bytes: &[u8]) -> String {
+    base64::engine::general_purpose::STANDARD.encode(bytes)
+}
+
+#[derive(Clone)]
+enum Auth {
+    SharedKey(Vec<u8>),
+    /// Query string of a shared access signature, without the leading `?`
+    Sas(String),
+}
+
+/// The account, endpoint, and credentials to use.
+#[derive(Clone)]
+struct Account {
+    name: String,
+    endpoint: String,
+    auth: Auth,
+}
+
+impl Account {
+    fn from_env() -> Result<Self> {
+        if let Ok(connection_string) = std::env::var("AZURE_STORAGE_CONNECTION_STRING") {
+            return Self::from_connection_string(&connection_string);
+        }
+        let name = std::env::var("AZURE_STORAGE_ACCOUNT")
+            .map_err(|_| Error::Config("AZURE_STORAGE_ACCOUNT or AZURE_STORAGE_CONNECTION_STRING is required".into()))?;
+        let auth = match (std::env::var("AZURE_STORAGE_KEY"), std::env::var("AZURE_STORAGE_SAS_TOKEN")) {
+            (Ok(key), _) => Auth::SharedKey(decode_key(&key)?),
+            (_, Ok(sas)) => Auth::Sas(sas.trim_start_matches('?').to_string()),
+            _ => return Err(Error::Config("AZURE_STORAGE_KEY or AZURE_STORAGE_SAS_TOKEN is required".into()).into()),
+        };
+        Ok(Account {
+            endpoint: format!("https://{}.blob.core.windows.net", name),
+            name,
+            auth,
+        })
+    }
+
+    /// Parse `AccountName=...;AccountKey=...;BlobEndpoint=...` style settings.
+    fn from_connection_string(value: &str) -> Result<Self> {
+        let settings: std::collections::HashMap<&str, &str> =
+            value.split(';').filter_map(|part| part.split_once('=')).map(|(k, v)| (k.trim(), v.trim())).collect();
+        let name = settings
+            .get("AccountName")
+            .ok_or_else(|| Error::Config("The connection string has no AccountName".into()))?
+            .to_string();
+        let auth = match (settings.get("AccountKey"), settings.get("SharedAccessSignature")) {
+            (Some(key), _) => Auth::SharedKey(decode_key(key)?),
+            (_, Some(sas)) => Auth::Sas(sas.trim_start_matches('?').to_string()),
+            _ => return Err(Error::Config("The connection string has no AccountKey or SharedAccessSignature".into()).into()),
+        };
+        let endpoint = match settings.get("BlobEndpoint") {
+            Some(endpoint) => endpoint.trim_end_matches('/').to_string(),
+            None => {
+                let protocol = settings.get("DefaultEndpointsProtocol").unwrap_or(&"https");
+                let suffix = settings.get("EndpointSuffix").unwrap_or(&"core.windows.net");
+                format!("{}://{}.blob.{}", protocol, name, suffix)
+            }
+        };
+        Ok(Account { name, endpoint, auth })
+    }
+}
+
+fn decode_key(key: &str) -> Result<Vec<u8>> {
+    base64::engine::general_purpose::STANDARD
+        .decode(key.trim())
+        .map_err(|_| Error::Config("The storage account key is not valid base64".into()).into())
+}
+
+/// The Shared Key signature of a request: an HMAC-SHA256 of its method,
+/// standard headers, `x-ms-` headers, and resource. `headers` are lowercase.
+fn shared_key_signature(key: &[u8], method: &str, headers: &[(String, String)], resource: &str) -> String {
+    let header = |name: &str| headers.iter().find(|(k, _)| k == name).map(|(_, v)| v.as_str()).unwrap_or("");
+    let mut ms_headers: Vec<&(String, String)> = headers.iter().filter(|(k, _)| k.starts_with("x-ms-")).collect();
+    ms_headers.sort();
+    let mut string_to_sign = method.to_string();
+    for name in [
+        "content-encoding",
+        "content-language",
+        "content-length",
+        "content-md5",
+        "content-type",
+        "date",
+        "if-modified-since",
+        "if-match",
+        "if-none-match",
+        "if-unmodified-since",
+        "range",
+    ] {
+        string_to_sign.push('\n');
+        // A zero length is signed as empty
+        if !(name == "content-length" && header(name) == "0") {
+            string_to_sign.push_str(header(name));
+        }
+    }
+    string_to_sign.push('\n');
+    for (k, v) in ms_headers {
+        string_to_sign.push_str(&format!("{}:{}\n", k, v));
+    }
+    string_to_sign.push_str(resource);
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(string_to_sign.as_bytes());
+    base64(&mac.finalize().into_bytes())
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct EnumerationResults {
+    #[serde(default)]
+    blobs: Blobs,
+    next_marker: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+struct Blobs {
+    #[serde(rename = "Blob", default)]
+    blobs: Vec<ListedBlob>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListedBlob {
+    name: String,
+    properties: BlobProperties,
+}
+
+#[derive(Deserialize)]
+struct BlobProperties {
+    #[serde(rename = "Last-Modified")]
+    last_modified: Option<String>,
+    #[serde(rename = "Content-Length")]
+    content_length: u64,
+}
+
+/// A signed-request client for one container.
+struct AzureClient {
+    agent: ureq::Agent,
+    account: Account,
+    container: String,
+}
+
+impl AzureClient {
+    fn new(container: &str) -> Result<Self> {
+        Ok(AzureClient {
+            agent: ureq::AgentBuilder::new().build(),
+            account: Account::from_env()?,
+            container: container.to_string(),
+        })
+    }
+
+    /// A signed request for `blob` (the container itself when empty).
+    /// `headers` are signed too; `content_length` is that of the body sent.
+    fn request(&self, method: &str, blob: &str, query: &[(&str, &str)], headers: &[(&str, String)], content_length: usize) -> ureq::Request {
+        let mut path = format!("/{}", uri_encode(&self.container, false));
+        if !blob.is_empty() {
+            path.push('/');
+            path.push_str(&uri_encode(blob, true));
+        }
+        let mut url = format!("{}{}", self.account.endpoint, path);
+        let mut query_string: Vec<String> = query.iter().map(|(k, v)| format!("{}={}", k, uri_encode(v, false))).collect();
+        if let Auth::Sas(ref sas) = self.account.auth {
+            query_string.push(sas.clone());
+        }
+        if !query_string.is_empty() {
+            url.push('?');
+            url.push_str(&query_string.join("&"));
+        }
+
+        let mut signed: Vec<(String, String)> = vec![
+            ("x-ms-date".into(), http_date(now_secs())),
+            ("x-ms-version".into(), API_VERSION.into()),
+            ("content-length".into(), content_length.to_string()),
+        ];
+        signed.extend(headers.iter().map(|(k, v)| (k.to_ascii_lowercase(), v.clone())));
+
+        let mut request = self.agent.request(method, &url);
+        for (k, v) in signed.iter().filter(|(k, _)| k != "content-length") {
+            request = request.set(k, v);
+        }
+        if let Auth::SharedKey(ref key) = self.account.auth {
+            // The resource is the account followed by the path as sent,
+            // including any path component of the endpoint (Azurite)
+            let endpoint_path = self.account.endpoint.split_once("://").map(|(_, rest)| rest).unwrap_or("");
+            let endpoint_path = endpoint_path.split_once('/').map(|(_, p)| format!("/{}", p)).unwrap_or_default();
+            let mut resource = format!("/{}{}{}", self.account.name, endpoint_path, path);
+            let mut params: Vec<(String, &str)> = query.iter().map(|(k, v)| (k.to_ascii_lowercase(), *v)).collect();
+            params.sort();
+            for (k, v) in params {
+                resource.push_str(&format!("\n{}:{}", k, v));
+            }
+            let signature = shared_key_signature(key, method, &signed, &resource);
+            request = request.set("authorization", &format!("SharedKey {}:{}", self.account.name, signature));
+        }
+        request
+    }
+
+    fn location(&self, blob: &str) -> String {
+        format!("az://{}/{}", self.container, blob)
+    }
+
+    /// Every blob under `prefix`.
+    fn list(&self, prefix: &str, args: &Args) -> Result<Vec<ListedBlob>> {
+        let mut blobs = Vec::new();
+        let mut marker: Option<String> = None;
+        loop {
+            let mut query = vec![("comp", "list"), ("prefix", prefix), ("restype", "container")];
+            if let Some(ref marker) = marker {
+                query.push(("marker", marker.as_str()));
+            }
+            let body = self
+                .request("GET", "", &query, &[], 0)
+                .call()
+                .map_err(|e| request_error(e, &self.location(prefix)))?
+                .into_string()?;
+            let page: EnumerationResults = quick_xml::de::from_str(&body).context("Invalid List Blobs response")?;
+            blobs.extend(page.blobs.blobs);
+            match page.next_marker.filter(|m| !m.is_empty()) {
+                Some(next) if !args.cancel.is_cancelled() => marker = Some(next),
+                _ => break,
+            }
+        }
+        Ok(blobs)
+    }
+
+    /// Bytes `start..end` of `blob`.
+    fn get_range(&self, blob: &str, start: u64, end: u64) -> io::Result<Vec<u8>> {
+        let range = format!("bytes={}-{}", start, end - 1);
+        let response = self
+            .request("GET", blob, &[], &[("x-ms-range", range)], 0)
+            .call()
+            .map_err(|e| io_error(e, &self.location(blob)))?;
+        let mut data = Vec::with_capacity((end - start) as usize);
+        response.into_reader().read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    /// Upload `data` as a block blob in one request. The service rejects it
+    /// unless it matches `Content-MD5`, and stores that MD5 with the blob.
+    fn put_blob(&self, blob: &str, data: &[u8]) -> Result<()> {
+        let headers = [("x-ms-blob-type", "BlockBlob".to_string()), ("content-md5", base64(&Md5::digest(data)))];
+        self.request("PUT", blob, &[], &headers, data.len())
+            .send_bytes(data)
+            .map_err(|e| request_error(e, &self.location(blob)))?;
+        Ok(())
+    }
+
+    /// Stage one block, verified by the service against its MD5.
+    fn put_block(&self, blob: &str, block_id: &str, data: &[u8]) -> Result<()> {
+        self.request("PUT", blob, &[("blockid", block_id), ("comp", "block")], &[("content-md5", base64(&Md5::digest(data)))], data.len())
+            .send_bytes(data)
+            .map_err(|e| request_error(e, &format!("{} block {}", self.location(blob), block_id)))?;
+        Ok(())
+    }
+
+    /// Commit staged blocks in order, recording the MD5 of the whole blob.
+    fn put_block_list(&self, blob: &str, block_ids: &[String], md5: &[u8]) -> Result<()> {
+        let mut body = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?><BlockList>");
+        for id in block_ids {
+            body.push_str(&format!("<Latest>{}</Latest>", id));
+        }
+        body.push_str("</BlockList>");
+        self.request("PUT", blob, &[("comp", "blocklist")], &[("x-ms-blob-content-md5", base64(md5))], body.len())
+            .send_string(&body)
+            .map_err(|e| request_error(e, &self.location(blob)))?;
+        Ok(())
+    }
+
+    /// The `Content-MD5` stored with `blob`, base64 encoded.
+    fn stored_md5(&self, blob: &str) -> Result<Option<String>> {
+        let response = self.request("HEAD", blob, &[], &[], 0).call().map_err(|e| request_error(e, &self.location(blob)))?;
+        Ok(response.header("content-md5").map(str::to_string))
+    }
+
+    fn blob_size(&self, blob: &str) -> io::Result<u64> {
+        let response = self.request("HEAD", blob, &[], &[], 0).call().map_err(|e| io_error(e, &self.location(blob)))?;
+        Ok(response.header("content-length").and_then(|l| l.parse().ok()).unwrap_or(0))
+    }
+}
+
+/// Turn a failed request into an error that includes the service's message.
+fn request_error(error: ureq::Error, what: &str) -> anyhow::Error {
+    match error {
+        ureq::Error::Status(code, response) => {
+            let message = response.header("x-ms-error-code").map(str::to_string);
+            let body = response.into_string().unwrap_or_default();
+            let message = message.unwrap_or_else(|| body.trim().to_string());
+            anyhow::anyhow!("Request for {} failed with HTTP {}: {}", what, code, message)
+        }
+        e => anyhow::Error::new(e).context(format!("Request for {} failed", what)),
+    }
+}
+
+/// Like [`request_error`], keeping "not found" recognisable for validation.
+fn io_error(error: ureq::Error, what: &str) -> io::Error {
+    match error {
+        ureq::Error::Status(404, _) => io::Error::new(io::ErrorKind::NotFound, format!("{} not found", what)),
+        e => io::Error::other(format!("{:#}", request_error(e, what))),
+    }
+}
+
+/// Blobs under an `az://container/prefix` URL, read with concurrent range GETs.
+pub struct AzureSource {
+    client: Arc<AzureClient>,
+    prefix: String,
+    ranges: RangeOptions,
+    /// Sizes from the listing, so blobs can be read without a HEAD request
+    sizes: std::sync::Mutex<std::collections::HashMap<PathBuf, u64>>,
+}
+
+impl AzureSource {
+    pub fn open(url: &str, args: &Args) -> Result<Self> {
+        let (container, prefix) = split_url(url)?;
+        Ok(AzureSource {
+            client: Arc::new(AzureClient::new(container)?),
+            prefix: prefix.to_string(),
+            ranges: RangeOptions::from_args(args)?,
+            sizes: Default::default(),
+        })
+    }
+}
+
+impl FileSource for AzureSource {
+    fn files(&self, args: &Args) -> Result<Vec<FileInfo>> {
+        let list_prefix = if self.prefix.is_empty() { String::new() } else { format!("{}/", self.prefix) };
+        let mut files = Vec::new();
+        let mut sizes = self.sizes.lock().unwrap();
+        for blob in self.client.list(&list_prefix, args)? {
+            // Folder placeholders, e.g. from Storage Explorer
+            if blob.name.ends_with('/') {
+                continue;
+            }
+            let path = PathBuf::from(&blob.name[list_prefix.len()..]);
+            sizes.insert(path.clone(), blob.properties.content_length);
+            files.push(FileInfo {
+                path,
+                size: blob.properties.content_length,
+                mtime: blob.properties.last_modified.as_deref().and_then(parse_http_date),
+            });
+        }
+        Ok(files)
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send>> {
+        let blob = object_key(&self.prefix, path);
+        let size = match self.sizes.lock().unwrap().get(path) {
+            Some(&size) => size,
+            None => self.client.blob_size(&blob)?,
+        };
+        let client = Arc::clone(&self.client);
+        let fetch: FetchRange = Arc::new(move |start, end| client.get_range(&blob, start, end));
+        Ok(Box::new(RangeReader::new(fetch, size, self.ranges)))
+    }
+}
+
+/// Uploads below an `az://container/prefix` URL, as block blobs.
+pub struct AzureDestination {
+    client: Arc<AzureClient>,
+    prefix: String,
+    ranges: RangeOptions,
+}
+
+impl AzureDestination {
+    pub fn open(url: &str, args: &Args) -> Result<Self> {
+        let (container, prefix) = split_url(url)?;
+        Ok(AzureDestination {
+            client: Arc::new(AzureClient::new(container)?),
+            prefix: prefix.to_string(),
+            ranges: RangeOptions::from_args(args)?,
+        })
+    }
+
+    /// Stage blocks `concurrency` at a time and commit them, returning the
+    /// MD5 of everything sent.
+    fn upload_blocks(&self, blob: &str, reader: &mut dyn Read, block_size: u64) -> Result<Vec<u8>> {
+        let mut md5 = Md5::new();
+        let mut block_ids = Vec::new();
+        loop {
+            let mut window = Vec::new();
+            while window.len() < self.ranges.concurrency {
+                let mut data = Vec::new();
+                reader.take(block_size).read_to_end(&mut data)?;
+                if data.is_empty() {
+                    break;
+                }
+                md5.update(&data);
+                // Block IDs must all have the same length
+                window.push((base64(format!("{:08}", block_ids.len() + window.len()).as_bytes()), data));
+            }
+            if window.is_empty() {
+                break;
+            }
+            let client = &self.client;
+            let staged: Vec<Result<()>> = std::thread::scope(|scope| {
+                let handles: Vec<_> = window
+                    .iter()
+                    .map(|(id, data)| scope.spawn(move || client.put_block(blob, id, data)))
+                    .collect();
+                handles
+                    .into_iter()
+                    .map(|h| h.join().unwrap_or_else(|_| Err(anyhow::anyhow!("Block upload panicked"))))
+                    .collect()
+            });
+            staged.into_iter().collect::<Result<()>>()?;
+            block_ids.extend(window.into_iter().map(|(id, _)| id));
+        }
+        let md5 = md5.finalize().to_vec();
+        self.client.put_block_list(blob, &block_ids, &md5)?;
+        Ok(md5)
+    }
+}
+
+impl Destination for AzureDestination {
+    fn upload(&self, path: &Path, reader: &mut dyn Read, size: u64) -> Result<()> {
+        let blob = object_key(&self.prefix, path);
+        let block_size = self.ranges.part_size.max(size.div_ceil(MAX_BLOCKS));
+        let expected = if size <= block_size {
+            let mut data = Vec::new();
+            reader.read_to_end(&mut data)?;
+            self.client.put_blob(&blob, &data)?;
+            base64(&Md5::digest(&data))
+        } else {
+            // Uncommitted blocks of a failed upload are discarded by the service
+            base64(&self.upload_blocks(&blob, reader, block_size)?)
+        };
+
+        let url = self.client.location(&blob);
+        match self.client.stored_md5(&blob)? {
+            Some(stored) if stored == expected => Ok(()),
+            Some(stored) => Err(Error::Validation {
+                message: format!("Stored MD5 of {} is {}, expected {}", url, stored, expected),
+                exit_code: 1,
+            }
+            .into()),
+            None => Err(Error::Validation {
+                message: format!("{} has no stored MD5", url),
+                exit_code: 1,
+            }
+            .into()),
+        }
+    }
+}
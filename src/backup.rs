@@ -0,0 +1,150 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Copies of `manifest_path` in its directory whose file names start with
+/// `prefix` and end with `suffix`, oldest first. Timestamped names sort
+/// chronologically, with the counter of copies made in the same second
+/// compared as a number.
+pub fn list_copies(manifest_path: &Path, prefix: &str, suffix: &str) -> Result<Vec<PathBuf>> {
+    let dir = match manifest_path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut copies: Vec<PathBuf> = fs::read_dir(dir)
+        .with_context(|| format!("Failed to list directory: {}", dir.display()))?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.len() > prefix.len() + suffix.len() && name.starts_with(prefix) && name.ends_with(suffix))
+        })
+        .collect();
+    copies.sort_by_cached_key(|path| {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let name = &name[..name.len() - suffix.len()];
+        match name.rsplit_once('_').map(|(base, counter)| (base, counter.parse::<usize>())) {
+            Some((base, Ok(counter))) => (base.to_string(), counter),
+            _ => (name.to_string(), 0),
+        }
+    });
+    Ok(copies)
+}
+
+/// The current UTC time as it appears in the names of backups and snapshots.
+fn file_stamp() -> String {
+    let stamp = crate::format_utc(crate::now_secs());
+    if cfg!(windows) {
+        // Colons are not allowed in Windows file names
+        stamp.replace(':', "-")
+    } else {
+        stamp
+    }
+}
+
+/// The path next to `manifest_path` named by `name` for the current time. A
+/// copy already written in the same second is kept by adding a counter to
+/// the time (`_2`, `_3`, ...).
+fn timestamped_path(manifest_path: &Path, name: impl Fn(&str) -> String) -> PathBuf {
+    let stamp = file_stamp();
+    let mut path = manifest_path.with_file_name(name(&stamp));
+    let mut counter = 2;
+    while path.exists() {
+        path = manifest_path.with_file_name(name(&format!("{}_{}", stamp, counter)));
+        counter += 1;
+    }
+    path
+}
+
+/// Delete all but the newest `keep` of `copies` (sorted oldest first).
+pub fn prune_copies(copies: &[PathBuf], keep: usize) -> Result<()> {
+    let excess = copies.len().saturating_sub(keep);
+    for old in &copies[..excess] {
+        fs::remove_file(old).with_context(|| format!("Failed to remove old copy: {}", old.display()))?;
+    }
+    Ok(())
+}
+
+/// Copy the current manifest aside before it is rewritten. With `backups` of
+/// `None` a single `<manifest>.bak` is kept; with `Some(n)` timestamped
+/// `<manifest>.<time>.bak` copies are written and only the newest `n` kept
+/// (`Some(0)` disables backups).
+pub fn backup_manifest(manifest_path: &Path, backups: Option<usize>) -> Result<()> {
+    if !manifest_path.exists() || backups == Some(0) {
+        return Ok(());
+    }
+    let file_name = manifest_path
+        .file_name()
+        .context("Manifest path has no file name")?
+        .to_string_lossy()
+        .to_string();
+
+    let backup_path = match backups {
+        None => manifest_path.with_file_name(format!("{}.bak", file_name)),
+        Some(_) => timestamped_path(manifest_path, |stamp| format!("{}.{}.bak", file_name, stamp)),
+    };
+    fs::copy(manifest_path, &backup_path)
+        .with_context(|| format!("Failed to back up manifest to {}", backup_path.display()))?;
+    status!("Previous manifest backed up to: {}", backup_path.display());
+
+    if let Some(keep) = backups {
+        // Only timestamped backups take part in retention, never the plain .bak
+        let copies = list_copies(manifest_path, &format!("{}.", file_name), ".bak")?;
+        let timestamped: Vec<PathBuf> = copies
+            .into_iter()
+            .filter(|path| path.file_name() != Some(format!("{}.bak", file_name).as_ref()))
+            .collect();
+        prune_copies(&timestamped, keep)?;
+    }
+    Ok(())
+}
+
+/// Whether `value` looks like a timestamp written by `file_stamp`, possibly
+/// followed by the counter of `timestamped_path`.
+fn is_timestamp(value: &str) -> bool {
+    let value = match value.split_once('_') {
+        Some((stamp, counter)) if !counter.is_empty() && counter.bytes().all(|b| b.is_ascii_digit()) => stamp,
+        _ => value,
+    };
+    let bytes = value.as_bytes();
+    bytes.len() == 19
+        && bytes.iter().enumerate().all(|(i, &b)| match i {
+            4 | 7 => b == b'-',
+            10 => b == b'T',
+            13 | 16 => b == b':' || b == b'-',
+            _ => b.is_ascii_digit(),
+        })
+}
+
+/// Write a point-in-time copy of `manifest_path` named
+/// `<stem>-<UTC time>.<ext>` next to it, keeping only the newest `keep`
+/// snapshots when a limit is given.
+pub fn write_snapshot(manifest_path: &Path, keep: Option<usize>) -> Result<PathBuf> {
+    let stem = manifest_path
+        .file_stem()
+        .context("Manifest path has no file name")?
+        .to_string_lossy()
+        .to_string();
+    let suffix = manifest_path
+        .extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_default();
+
+    let snapshot_path = timestamped_path(manifest_path, |stamp| format!("{}-{}{}", stem, stamp, suffix));
+    fs::copy(manifest_path, &snapshot_path)
+        .with_context(|| format!("Failed to write snapshot: {}", snapshot_path.display()))?;
+
+    if let Some(keep) = keep {
+        let prefix = format!("{}-", stem);
+        let snapshots: Vec<PathBuf> = list_copies(manifest_path, &prefix, &suffix)?
+            .into_iter()
+            .filter(|path| {
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                is_timestamp(&name[prefix.len()..name.len() - suffix.len()])
+            })
+            .collect();
+        prune_copies(&snapshots, keep)?;
+    }
+    Ok(snapshot_path)
+}
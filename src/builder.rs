@@ -0,0 +1,380 @@
+use anyhow::{Context, Result};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use clap::Parser;
+
+use crate::progress::Progress;
+use crate::source::{select_files, SharedSource};
+use crate::report::{ReportEntry, ValidationPolicy};
+use crate::{
+    archive_file_path, diff_entries, format_entry, manifest_key, Args,
+    CancellationToken, DiffReport, Error, FileSource, LocalFileSource, Manifest, ManifestEntry, ManifestHeader, ValidationReport,
+};
+
+/// Digest used for manifest hashes. Manifests written by the command line
+/// are always SHA-256; other algorithms are recorded in the manifest header.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    #[default]
+    Sha256,
+    Blake3,
+}
+
+impl HashAlgorithm {
+    pub fn name(self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Blake3 => "blake3",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "sha256" => Some(HashAlgorithm::Sha256),
+            "blake3" => Some(HashAlgorithm::Blake3),
+            _ => None,
+        }
+    }
+
+    /// Hash a file's contents, returning a lowercase hex digest.
+    pub fn hash_path(self, path: &Path, buffer_size: usize) -> Result<String> {
+        let mut file = fs::File::open(path).map_err(Error::io("Failed to open file", path))?;
+        self.hash_reader(&mut file, buffer_size)
+    }
+
+    /// Hash everything read from `reader`, returning a lowercase hex digest.
+    /// Reads are not held to the `--bwlimit` of a command-line run.
+    pub fn hash_reader(self, reader: &mut dyn Read, buffer_size: usize) -> Result<String> {
+        let mut buffer = vec![0; buffer_size];
+        let mut hasher = self.hasher();
+        loop {
+            let bytes_read = reader.read(&mut buffer)?;
+            if bytes_read == 0 {
+                break;
+            }
+            crate::progress::bytes_hashed(bytes_read);
+            hasher.update(&buffer[..bytes_read]);
+        }
+        Ok(hasher.finish())
+    }
+
+    /// An incremental hasher, for data that arrives in chunks.
+    pub fn hasher(self) -> Hasher {
+        match self {
+            HashAlgorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            HashAlgorithm::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+        }
+    }
+}
+
+/// Incremental state of a [`HashAlgorithm`].
+pub enum Hasher {
+    Sha256(Sha256),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Hasher {
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha256(hasher) => hasher.update(data),
+            Hasher::Blake3(hasher) => {
+                hasher.update(data);
+            }
+        }
+    }
+
+    /// The lowercase hex digest of everything passed to [`update`](Self::update).
+    pub fn finish(self) -> String {
+        match self {
+            Hasher::Sha256(hasher) => format!("{:x}", hasher.finalize()),
+            Hasher::Blake3(hasher) => hasher.finalize().to_hex().to_string(),
+        }
+    }
+}
+
+/// Builds a [`Manifest`] by walking and hashing a directory tree.
+///
+/// ```no_run
+/// use data_manifest::{HashAlgorithm, Manifest};
+///
+/// let manifest = Manifest::builder()
+///     .root("/path/to/archive")
+///     .algorithm(HashAlgorithm::Blake3)
+///     .threads(16)
+///     .exclude("*.tmp")
+///     .build()?;
+/// manifest.write("manifest.txt")?;
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct ManifestBuilder {
+    root: Option<PathBuf>,
+    archive_name: Option<String>,
+    algorithm: HashAlgorithm,
+    threads: Option<usize>,
+    buffer_size: Option<usize>,
+    excludes: Vec<String>,
+    extended: bool,
+    cancel: CancellationToken,
+    source: Option<SharedSource>,
+}
+
+impl ManifestBuilder {
+    /// Directory to walk. Required.
+    pub fn root(mut self, root: impl Into<PathBuf>) -> Self {
+        self.root = Some(root.into());
+        self
+    }
+
+    /// Name used as the first path component of every entry (defaults to the
+    /// root directory's name).
+    pub fn archive_name(mut self, name: impl Into<String>) -> Self {
+        self.archive_name = Some(name.into());
+        self
+    }
+
+    pub fn algorithm(mut self, algorithm: HashAlgorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// Number of hashing threads (defaults to the number of CPU cores).
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = Some(threads);
+        self
+    }
+
+    /// Read buffer size in bytes (defaults to 1 MiB).
+    pub fn buffer_size(mut self, buffer_size: usize) -> Self {
+        self.buffer_size = Some(buffer_size);
+        self
+    }
+
+    /// Skip files and directories matching a glob, with the same rules as
+    /// `--exclude`. May be called repeatedly.
+    pub fn exclude(mut self, pattern: impl Into<String>) -> Self {
+        self.excludes.push(pattern.into());
+        self
+    }
+
+    /// Write size and mtime columns when the manifest is written.
+    pub fn extended(mut self, extended: bool) -> Self {
+        self.extended = extended;
+        self
+    }
+
+    /// Stop walking and hashing when `token` is cancelled; `build` then fails
+    /// with [`Error::Cancelled`].
+    pub fn cancel_token(mut self, token: CancellationToken) -> Self {
+        self.cancel = token;
+        self
+    }
+
+    /// Read files from `source` instead of walking a local root. An archive
+    /// name is then required unless a root is also given.
+    pub fn source(mut self, source: impl FileSource + 'static) -> Self {
+        self.source = Some(SharedSource(Arc::new(source)));
+        self
+    }
+
+    /// Walk the root and hash every selected file. Fails if any file cannot be read.
+    pub fn build(self) -> Result<Manifest> {
+        let (source, root) = match (self.source, self.root) {
+            (Some(SharedSource(source)), root) => (source, root),
+            (None, Some(root)) => {
+                if !root.is_dir() {
+                    return Err(Error::Config(format!("Archive path is not a directory: {}", root.display())).into());
+                }
+                let source: Arc<dyn FileSource> = Arc::new(LocalFileSource::new(&root));
+                (source, Some(root))
+            }
+            (None, None) => return Err(Error::Config("ManifestBuilder requires a root directory or a source".into()).into()),
+        };
+        let archive_name = match (self.archive_name, &root) {
+            (Some(name), _) => name,
+            (None, Some(root)) => crate::longpath::default_archive_name(root),
+            (None, None) => return Err(Error::Config("ManifestBuilder requires an archive name with a source".into()).into()),
+        };
+
+        let root = root.unwrap_or_else(|| PathBuf::from("."));
+        let mut args = Args::try_parse_from([std::ffi::OsStr::new("data-manifest"), "-a".as_ref(), root.as_os_str()])?;
+        args.exclude = self.excludes;
+        args.cancel = self.cancel.clone();
+        let buffer_size = self.buffer_size.unwrap_or(args.buffer_size);
+        let files = select_files(source.files(&args)?, &args)?;
+        if self.cancel.is_cancelled() {
+            return Err(Error::Cancelled.into());
+        }
+
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(self.threads.unwrap_or(0))
+            .build()
+            .context("Failed to initialize thread pool")?;
+        let algorithm = self.algorithm;
+        let progress = Progress::start(files.len() as u64, 0);
+        let entries = pool.install(|| {
+            files
+                .par_iter()
+                .map(|file_info| {
+                    if self.cancel.is_cancelled() {
+                        return Err(Error::Cancelled.into());
+                    }
+                    progress.file_started(&file_info.path);
+                    let hash = source
+                        .open(&file_info.path)
+                        .map_err(|e| Error::io("Failed to open file", &file_info.path)(e).into())
+                        .and_then(|mut reader| algorithm.hash_reader(&mut reader, buffer_size));
+                    progress.file_done(&file_info.path, &hash);
+                    let entry = ManifestEntry {
+                        hash: hash?,
+                        size: Some(file_info.size),
+                        mtime: file_info.mtime,
+                        verified: None,
+                        cid: None,
+                        xattrs: None,
+                        acl: None,
+                        link: None,
+                        format: None,
+                        dev: None,
+                        inode: None,
+                    };
+                    Ok((manifest_key(&file_info.path, Path::new(""), &archive_name), entry))
+                })
+                .collect::<Result<HashMap<_, _>>>()
+        })?;
+        progress.finish("Complete");
+
+        Ok(Manifest {
+            extended: self.extended,
+            algorithm,
+            entries,
+        })
+    }
+}
+
+impl Manifest {
+    pub fn builder() -> ManifestBuilder {
+        ManifestBuilder::default()
+    }
+
+    pub fn header(&self) -> ManifestHeader {
+        ManifestHeader::new(self.extended, self.algorithm)
+    }
+
+    /// Write the manifest in the same format the command line produces.
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let file = fs::File::create(path).with_context(|| format!("Failed to create output file: {}", path.display()))?;
+        let mut writer = BufWriter::new(file);
+        let header = ManifestHeader::for_entries(self.extended, self.algorithm, self.entries.values());
+        header.write(&mut writer)?;
+        let mut entries: Vec<_> = self.entries.iter().collect();
+        entries.sort_by(|a, b| a.0.cmp(b.0));
+        for (key, entry) in entries {
+            writeln!(writer, "{}", format_entry(key, entry, &header))?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Re-hash every entry under `root` and report mismatched and missing
+    /// files. Files not listed in the manifest are not looked for.
+    pub fn validate(&self, root: impl AsRef<Path>) -> Result<ValidationReport> {
+        self.validate_cancellable(root, &CancellationToken::new())
+    }
+
+    /// Like [`validate`](Self::validate), but stops checking entries once
+    /// `cancel` is cancelled and returns a report covering the entries checked
+    /// so far.
+    pub fn validate_cancellable(&self, root: impl AsRef<Path>, cancel: &CancellationToken) -> Result<ValidationReport> {
+        let root = root.as_ref();
+        self.validate_with(&LocalFileSource::new(root), root, cancel)
+    }
+
+    /// Like [`validate`](Self::validate), but reads the files from `source`.
+    pub fn validate_source(&self, source: &dyn FileSource) -> Result<ValidationReport> {
+        self.validate_with(source, Path::new(""), &CancellationToken::new())
+    }
+
+    fn validate_with(&self, source: &dyn FileSource, root: &Path, cancel: &CancellationToken) -> Result<ValidationReport> {
+        // Entries are keyed by archive name, which need not match the directory name
+        let archive_name = self
+            .entries
+            .keys()
+            .next()
+            .and_then(|key| key.components().next())
+            .map(|c| c.as_os_str().to_string_lossy().to_string())
+            .unwrap_or_default();
+        let buffer_size = 1024 * 1024;
+
+        let results: Vec<_> = self
+            .entries
+            .par_iter()
+            .filter(|_| !cancel.is_cancelled())
+            .map(|(key, expected)| {
+                let path = archive_file_path(key, Path::new(""), &archive_name);
+                let actual = match source.open(&path) {
+                    Ok(mut reader) => self.algorithm.hash_reader(&mut reader, buffer_size).map(Some),
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                    Err(e) => Err(Error::io("Failed to open file", &root.join(&path))(e).into()),
+                };
+                (key, expected, actual)
+            })
+            .collect();
+
+        let mut report = ValidationReport::new(Path::new(""), root, ValidationPolicy::default());
+        for (key, expected, actual) in results {
+            let entry = |actual_hash: Option<String>| ReportEntry {
+                path: key.clone(),
+                expected_hash: Some(expected.hash.clone()),
+                actual_hash,
+                size: expected.size,
+                mtime: expected.mtime,
+                mismatch_kind: None,
+            };
+            match actual? {
+                Some(hash) if hash == expected.hash => report.valid.push(key.clone()),
+                Some(hash) => report.mismatched.push(entry(Some(hash))),
+                None => report.missing.push(entry(None)),
+            }
+        }
+        report.finish();
+        Ok(report)
+    }
+
+    /// Entries added, removed, changed, or renamed in `other` relative to this manifest.
+    pub fn diff(&self, other: &Manifest) -> DiffReport {
+        diff_entries(&self.entries, &other.entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_and_validate_ignore_the_bandwidth_limit_of_a_run() {
+        let root = std::env::temp_dir().join(format!("data-manifest-builder-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("a.bin"), vec![0u8; 64 * 1024]).unwrap();
+
+        // At one byte per second, a throttled build would not finish
+        let _guard = crate::lock_run();
+        crate::throttle::set_limit(Some(1));
+        let started = std::time::Instant::now();
+        let result = Manifest::builder().root(&root).build().and_then(|manifest| manifest.validate(&root));
+        crate::throttle::set_limit(None);
+        fs::remove_dir_all(&root).unwrap();
+
+        assert!(result.unwrap().passed);
+        assert!(started.elapsed() < std::time::Duration::from_secs(10));
+    }
+}
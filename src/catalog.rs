@@ -0,0 +1,130 @@
+//! `--catalog <URL>`: recording each run in a central PostgreSQL catalog (see
+//! [`crate::PostgresStore`]) so the fixity state of archives on many hosts
+//! can be queried in one place.
+//!
+//! A run is recorded with its host, archive, mode, times, outcome, summary,
+//! and one verification row per file the summary lists. Manifests are saved
+//! as `<host>:<manifest path>` after generation and update, and after
+//! validation when the catalog has no copy yet or `--record-verified` changed
+//! it.
+
+use anyhow::Result;
+use serde_json::Value;
+
+use crate::{Args, Error};
+
+/// A finished run, as recorded.
+#[cfg_attr(not(feature = "postgres"), allow(dead_code))]
+pub(crate) struct CatalogRun {
+    /// Catalog name of the run's manifest
+    pub manifest: String,
+    pub host: String,
+    pub archive: String,
+    pub mode: &'static str,
+    pub started: i64,
+    pub finished: i64,
+    /// `passed`, `failed` when validation found problems, or `error`
+    pub outcome: &'static str,
+    pub error: Option<String>,
+    pub summary: Option<Value>,
+    pub files: Vec<FileResult>,
+}
+
+/// One file listed in a run's summary.
+#[cfg_attr(not(feature = "postgres"), allow(dead_code))]
+pub(crate) struct FileResult {
+    pub path: String,
+    /// The summary list it appeared in: `mismatched`, `missing`, `added`, ...
+    pub result: String,
+    pub expected_hash: Option<String>,
+    pub actual_hash: Option<String>,
+}
+
+/// The files of every list in `summary`, with the hashes each kind of list
+/// records.
+#[cfg_attr(not(feature = "postgres"), allow(dead_code))]
+fn file_results(summary: &Value) -> Vec<FileResult> {
+    let text = |entry: &Value, keys: &[&str]| keys.iter().find_map(|key| entry.get(key)?.as_str()).map(str::to_string);
+    let Some(lists) = summary.as_object() else { return Vec::new() };
+    lists
+        .iter()
+        .filter_map(|(result, entries)| Some((result, entries.as_array()?)))
+        .flat_map(|(result, entries)| {
+            entries.iter().filter_map(move |entry| {
+                Some(FileResult {
+                    path: text(entry, &["path", "to"])?,
+                    result: result.clone(),
+                    expected_hash: text(entry, &["expected_hash", "old_hash", "hash"]),
+                    actual_hash: text(entry, &["actual_hash", "new_hash"]),
+                })
+            })
+        })
+        .collect()
+}
+
+/// Run `run` and record it in the catalog at `url`. The catalog is connected
+/// to (and its schema migrated) before the run so a bad URL fails early. Failed
+/// runs are recorded too; failing to record fails an otherwise successful run.
+#[cfg(feature = "postgres")]
+pub(crate) fn run_cataloged(url: &str, args: &Args, run: impl FnOnce(&Args) -> Result<()>) -> Result<()> {
+    use std::path::Path;
+
+    use crate::report::last_summary;
+    use crate::{archive_label, eventlog, load_existing_manifest, now_secs, run_mode, ManifestStore, PostgresStore};
+
+    drop(PostgresStore::connect(url)?);
+    let started = now_secs();
+    let result = run(args);
+    if args.cancel.is_cancelled() {
+        return result;
+    }
+
+    let (outcome, error) = match result {
+        Ok(()) => ("passed", None),
+        Err(ref e) => match e.downcast_ref::<Error>() {
+            Some(Error::Validation { .. }) => ("failed", Some(format!("{:#}", e))),
+            _ => ("error", Some(format!("{:#}", e))),
+        },
+    };
+    let host = eventlog::hostname();
+    let manifest_path = std::fs::canonicalize(&args.output).unwrap_or_else(|_| args.output.clone());
+    let summary = last_summary();
+    let catalog_run = CatalogRun {
+        manifest: format!("{}:{}", host, manifest_path.display()),
+        host,
+        archive: archive_label(args),
+        mode: run_mode(args),
+        started,
+        finished: now_secs(),
+        outcome,
+        error,
+        files: summary.as_ref().map(file_results).unwrap_or_default(),
+        summary,
+    };
+
+    let record = |manifest_path: &Path| -> Result<()> {
+        // Reconnect: the run may have outlasted an idle connection
+        let store = PostgresStore::connect(url)?;
+        if !args.validate || args.record_verified || !store.contains(&catalog_run.manifest)? {
+            if let Ok(manifest) = load_existing_manifest(manifest_path) {
+                store.save(&catalog_run.manifest, &manifest)?;
+            }
+        }
+        store.record_run(&catalog_run)?;
+        status!("Run recorded in the catalog as {}", catalog_run.manifest);
+        Ok(())
+    };
+    match (result, record(&manifest_path)) {
+        (Ok(()), recorded) => recorded,
+        (Err(e), Ok(())) => Err(e),
+        (Err(e), Err(record_error)) => {
+            eprintln!("Failed to record the run in the catalog: {:#}", record_error);
+            Err(e)
+        }
+    }
+}
+
+#[cfg(not(feature = "postgres"))]
+pub(crate) fn run_cataloged(_url: &str, _args: &Args, _run: impl FnOnce(&Args) -> Result<()>) -> Result<()> {
+    Err(Error::Config("--catalog needs data-manifest built with the postgres feature".into()).into())
+}
@@ -0,0 +1,200 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How often buffered checkpoint records are flushed to disk.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Valid,
+    Mismatch,
+    MetadataChanged,
+    New,
+    AssumedValid,
+}
+
+/// The outcome of validating one file, as persisted in the checkpoint.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CheckpointRecord {
+    pub path: PathBuf,
+    pub status: CheckStatus,
+    pub actual_hash: Option<String>,
+    pub size: u64,
+    /// Modification time of the file when it was checked, in nanoseconds
+    /// since the Unix epoch
+    #[serde(default)]
+    pub mtime: Option<i64>,
+}
+
+/// First line of a checkpoint file, identifying the run it belongs to.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+struct CheckpointHeader {
+    manifest: PathBuf,
+    archive: PathBuf,
+}
+
+/// Append-only log of validated paths, written while validation runs so an
+/// interrupted run can be resumed.
+pub struct Checkpoint {
+    path: PathBuf,
+    state: Mutex<(BufWriter<fs::File>, Instant)>,
+}
+
+impl Checkpoint {
+    /// Read the records of a previous run. Truncated lines (from a crash
+    /// mid-write) are ignored.
+    pub fn load(path: &Path, manifest: &Path, archive: &Path) -> Result<HashMap<PathBuf, CheckpointRecord>> {
+        let mut records = HashMap::new();
+        if !path.exists() {
+            return Ok(records);
+        }
+
+        let file = fs::File::open(path)
+            .with_context(|| format!("Failed to open checkpoint file: {}", path.display()))?;
+        let mut lines = BufReader::new(file).lines();
+
+        if let Some(line) = lines.next() {
+            let header: CheckpointHeader = serde_json::from_str(&line?)
+                .with_context(|| format!("Invalid checkpoint file: {}", path.display()))?;
+            let expected = CheckpointHeader {
+                manifest: manifest.to_path_buf(),
+                archive: archive.to_path_buf(),
+            };
+            if header != expected {
+                anyhow::bail!(
+                    "Checkpoint {} belongs to a different run (manifest {}, archive {})",
+                    path.display(),
+                    header.manifest.display(),
+                    header.archive.display()
+                );
+            }
+        }
+
+        for line in lines {
+            match serde_json::from_str::<CheckpointRecord>(&line?) {
+                Ok(record) => {
+                    records.insert(record.path.clone(), record);
+                }
+                Err(_) => continue,
+            }
+        }
+
+        Ok(records)
+    }
+
+    /// Start a checkpoint file. When resuming, existing records are kept and
+    /// new ones appended; otherwise the file is started fresh.
+    pub fn open(path: &Path, manifest: &Path, archive: &Path, resume: bool) -> Result<Self> {
+        let append = resume && path.exists();
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(append)
+            .truncate(!append)
+            .open(path)
+            .with_context(|| format!("Failed to open checkpoint file: {}", path.display()))?;
+        let ends_with_newline = append && last_byte(path)? == Some(b'\n');
+        let mut writer = BufWriter::new(file);
+
+        if append && !ends_with_newline {
+            // Terminate a record cut short by a crash
+            writeln!(writer)?;
+        } else if !append {
+            let header = CheckpointHeader {
+                manifest: manifest.to_path_buf(),
+                archive: archive.to_path_buf(),
+            };
+            serde_json::to_writer(&mut writer, &header)?;
+            writeln!(writer)?;
+            writer.flush()?;
+        }
+
+        Ok(Checkpoint {
+            path: path.to_path_buf(),
+            state: Mutex::new((writer, Instant::now())),
+        })
+    }
+
+    pub fn record(&self, record: &CheckpointRecord) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let (writer, last_flush) = &mut *state;
+        serde_json::to_writer(&mut *writer, record)?;
+        writeln!(writer)?;
+        if last_flush.elapsed() >= FLUSH_INTERVAL {
+            writer.flush()?;
+            *last_flush = Instant::now();
+        }
+        Ok(())
+    }
+
+    /// Remove the checkpoint once validation has run to completion.
+    pub fn finish(self) -> Result<()> {
+        drop(self.state);
+        fs::remove_file(&self.path)
+            .with_context(|| format!("Failed to remove checkpoint file: {}", self.path.display()))
+    }
+}
+
+fn last_byte(path: &Path) -> Result<Option<u8>> {
+    let mut file = fs::File::open(path)?;
+    if file.metadata()?.len() == 0 {
+        return Ok(None);
+    }
+    let mut byte = [0u8; 1];
+    file.seek(SeekFrom::End(-1))?;
+    file.read_exact(&mut byte)?;
+    Ok(Some(byte[0]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(path: &str) -> CheckpointRecord {
+        CheckpointRecord {
+            path: PathBuf::from(path),
+            status: CheckStatus::Valid,
+            actual_hash: Some("a".repeat(64)),
+            size: 1,
+            mtime: Some(1),
+        }
+    }
+
+    #[test]
+    fn resumes_only_the_run_it_belongs_to() {
+        let dir = std::env::temp_dir().join(format!("data-manifest-checkpoint-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("checkpoint.jsonl");
+        let (manifest, archive) = (Path::new("manifest.txt"), Path::new("/archive"));
+
+        let checkpoint = Checkpoint::open(&path, manifest, archive, false).unwrap();
+        checkpoint.record(&record("archive/a")).unwrap();
+        drop(checkpoint);
+        // A record cut short by a crash is ignored, and the next one starts on its own line
+        fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(b"{\"path\":\"archive/b\",\"sta").unwrap();
+        let checkpoint = Checkpoint::open(&path, manifest, archive, true).unwrap();
+        checkpoint.record(&record("archive/c")).unwrap();
+        drop(checkpoint);
+
+        let records = Checkpoint::load(&path, manifest, archive).unwrap();
+        let mut paths: Vec<_> = records.keys().cloned().collect();
+        paths.sort();
+        assert_eq!(paths, [PathBuf::from("archive/a"), PathBuf::from("archive/c")]);
+
+        assert!(Checkpoint::load(&path, Path::new("other.txt"), archive).is_err());
+        assert!(Checkpoint::load(&path, manifest, Path::new("/other")).is_err());
+        // Starting afresh discards the earlier records
+        drop(Checkpoint::open(&path, manifest, archive, false).unwrap());
+        assert!(Checkpoint::load(&path, manifest, archive).unwrap().is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
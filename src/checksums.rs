@@ -0,0 +1,192 @@
+use anyhow::{Context, Result};
+use md5::Md5;
+use sha1::Sha1;
+use sha2::digest::DynDigest;
+use sha2::{Digest, Sha256, Sha512};
+use std::fs;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+/// Digest algorithms found in third-party checksum files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChecksumAlgorithm {
+    Md5,
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl ChecksumAlgorithm {
+    pub fn name(self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Md5 => "md5",
+            ChecksumAlgorithm::Sha1 => "sha1",
+            ChecksumAlgorithm::Sha256 => "sha256",
+            ChecksumAlgorithm::Sha512 => "sha512",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().replace('-', "").as_str() {
+            "md5" => Some(ChecksumAlgorithm::Md5),
+            "sha1" => Some(ChecksumAlgorithm::Sha1),
+            "sha256" => Some(ChecksumAlgorithm::Sha256),
+            "sha512" => Some(ChecksumAlgorithm::Sha512),
+            _ => None,
+        }
+    }
+
+    fn from_hex_len(len: usize) -> Option<Self> {
+        match len {
+            32 => Some(ChecksumAlgorithm::Md5),
+            40 => Some(ChecksumAlgorithm::Sha1),
+            64 => Some(ChecksumAlgorithm::Sha256),
+            128 => Some(ChecksumAlgorithm::Sha512),
+            _ => None,
+        }
+    }
+
+    fn hasher(self) -> Box<dyn DynDigest> {
+        match self {
+            ChecksumAlgorithm::Md5 => Box::new(Md5::new()),
+            ChecksumAlgorithm::Sha1 => Box::new(Sha1::new()),
+            ChecksumAlgorithm::Sha256 => Box::new(Sha256::new()),
+            ChecksumAlgorithm::Sha512 => Box::new(Sha512::new()),
+        }
+    }
+}
+
+/// One file listed in a checksum file.
+#[derive(Debug, Clone)]
+pub struct ChecksumListing {
+    /// The checksum file the listing came from
+    pub source: PathBuf,
+    /// On-disk path of the listed file
+    pub path: PathBuf,
+    pub algorithm: ChecksumAlgorithm,
+    pub expected: String,
+}
+
+/// Recognize checksum files by name: `MD5SUMS`, `SHA256SUMS`, `*.md5`,
+/// `*.sha256`, `checksums.txt`, BagIt `manifest-<alg>.txt`, and so on.
+pub fn is_checksum_file(name: &str) -> bool {
+    let name = name.to_lowercase();
+    name_algorithm_hint(&name).is_some()
+        || matches!(name.as_str(), "checksums.txt" | "checksum.txt" | "checksums" | "checksum")
+}
+
+fn name_algorithm_hint(name: &str) -> Option<ChecksumAlgorithm> {
+    let name = name.to_lowercase();
+    if let Some(alg) = name.strip_prefix("manifest-").and_then(|n| n.strip_suffix(".txt")) {
+        return ChecksumAlgorithm::from_name(alg);
+    }
+    if let Some(alg) = name.strip_suffix("sums").or_else(|| name.strip_suffix("sums.txt")) {
+        return ChecksumAlgorithm::from_name(alg);
+    }
+    if let Some(alg) = name.strip_suffix("sum.txt") {
+        return ChecksumAlgorithm::from_name(alg);
+    }
+    let ext = Path::new(&name).extension()?.to_string_lossy().to_string();
+    ChecksumAlgorithm::from_name(&ext)
+}
+
+/// Parse GNU coreutils (`<hex>  <path>` / `<hex> *<path>`) and BSD
+/// (`SHA256 (<path>) = <hex>`) style lines. Paths are relative to the
+/// directory containing the checksum file.
+pub fn parse_checksum_file(path: &Path) -> Result<Vec<ChecksumListing>> {
+    let file = fs::File::open(path)
+        .with_context(|| format!("Failed to open checksum file: {}", path.display()))?;
+    let base = path.parent().unwrap_or(Path::new(""));
+    let hint = path
+        .file_name()
+        .and_then(|n| name_algorithm_hint(&n.to_string_lossy()));
+
+    let mut listings = Vec::new();
+    for (line_num, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.with_context(|| format!("Failed to read line {} in {}", line_num + 1, path.display()))?;
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let parsed = parse_bsd_line(line).or_else(|| parse_gnu_line(line, hint));
+        match parsed {
+            Some((algorithm, expected, name)) => listings.push(ChecksumListing {
+                source: path.to_path_buf(),
+                path: base.join(name.trim_start_matches("./")),
+                algorithm,
+                expected: expected.to_lowercase(),
+            }),
+            None => eprintln!("Warning: Unrecognized line {} in {}: {}", line_num + 1, path.display(), line),
+        }
+    }
+
+    Ok(listings)
+}
+
+fn parse_bsd_line(line: &str) -> Option<(ChecksumAlgorithm, String, String)> {
+    let (tag, rest) = line.split_once(" (")?;
+    let (name, hex) = rest.rsplit_once(") = ")?;
+    let algorithm = ChecksumAlgorithm::from_name(tag)?;
+    is_hex(hex, algorithm).then(|| (algorithm, hex.to_string(), name.to_string()))
+}
+
+fn parse_gnu_line(line: &str, hint: Option<ChecksumAlgorithm>) -> Option<(ChecksumAlgorithm, String, String)> {
+    // A leading backslash means the file name contains escaped characters
+    let (escaped, line) = match line.strip_prefix('\\') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+    let (hex, rest) = line.split_once(' ')?;
+    let name = rest.strip_prefix(' ').or_else(|| rest.strip_prefix('*')).unwrap_or(rest);
+    let algorithm = hint.or_else(|| ChecksumAlgorithm::from_hex_len(hex.len()))?;
+    if !is_hex(hex, algorithm) || name.is_empty() {
+        return None;
+    }
+    let name = if escaped {
+        name.replace("\\n", "\n").replace("\\\\", "\\")
+    } else {
+        name.to_string()
+    };
+    Some((algorithm, hex.to_string(), name))
+}
+
+fn is_hex(value: &str, algorithm: ChecksumAlgorithm) -> bool {
+    ChecksumAlgorithm::from_hex_len(value.len()) == Some(algorithm) && value.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Compute the listing's digest and our own SHA-256 in a single read.
+/// Returns `(external_digest, sha256)`.
+pub fn hash_with(path: &Path, algorithm: ChecksumAlgorithm, buffer_size: usize) -> Result<(String, String)> {
+    let mut external = algorithm.hasher();
+    let mut sha256 = Sha256::new();
+    let mut buffer = vec![0; buffer_size];
+
+    let mut file = fs::File::open(path)
+        .with_context(|| format!("Failed to open file: {}", path.display()))?;
+
+    loop {
+        let bytes_read = file.read(&mut buffer)?;
+        if bytes_read == 0 {
+            break;
+        }
+        crate::throttle::consume(bytes_read);
+        crate::progress::bytes_hashed(bytes_read);
+        external.update(&buffer[..bytes_read]);
+        if algorithm != ChecksumAlgorithm::Sha256 {
+            Digest::update(&mut sha256, &buffer[..bytes_read]);
+        }
+    }
+
+    let external = hex(&external.finalize());
+    let sha256 = if algorithm == ChecksumAlgorithm::Sha256 {
+        external.clone()
+    } else {
+        format!("{:x}", sha256.finalize())
+    };
+    Ok((external, sha256))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
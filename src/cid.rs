@@ -0,0 +1,266 @@
+//! `--cid-version`: IPFS content identifiers recorded next to the SHA-256
+//! hash, computed as `ipfs add` does with its defaults: 256 KiB chunks in a
+//! balanced UnixFS DAG of at most 174 links per node.
+//!
+//! Version 1 CIDs use raw leaves and base32 (`bafk...` for files of one
+//! chunk, `bafy...` otherwise), matching `ipfs add --cid-version 1`. Version 0
+//! CIDs wrap each chunk in a dag-pb node and use base58 (`Qm...`), matching
+//! plain `ipfs add`. The CID is built while the file is read for its SHA-256
+//! hash, so recording it costs no extra I/O.
+
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::Read;
+
+use crate::{hash_reader, special, xattrs, Error, FileInfo};
+
+const CHUNK_SIZE: usize = 256 * 1024;
+const MAX_LINKS: usize = 174;
+
+/// Multicodec codes
+const RAW: u64 = 0x55;
+const DAG_PB: u64 = 0x70;
+
+/// UnixFS `Data.DataType.File`
+const UNIXFS_FILE: u64 = 2;
+
+const BASE32: &[u8] = b"abcdefghijklmnopqrstuvwxyz234567";
+const BASE58: &[u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+/// The CID version of a recorded CID: version 0 CIDs are base58 multihashes
+/// starting with `Qm`.
+pub(crate) fn version_of(cid: &str) -> u8 {
+    if cid.starts_with("Qm") {
+        0
+    } else {
+        1
+    }
+}
+
+fn varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Append a length-delimited protobuf field.
+fn bytes_field(out: &mut Vec<u8>, tag: u8, bytes: &[u8]) {
+    out.push(tag);
+    varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+/// A child of a DAG node.
+struct Link {
+    cid: Vec<u8>,
+    /// Bytes of the child's blocks, itself included
+    tsize: u64,
+    /// Bytes of file data below the child
+    filesize: u64,
+}
+
+/// UnixFS `Data` message of a file node.
+fn unixfs_file(data: &[u8], filesize: u64, blocksizes: &[u64]) -> Vec<u8> {
+    let mut out = vec![0x08];
+    varint(&mut out, UNIXFS_FILE);
+    if !data.is_empty() {
+        bytes_field(&mut out, 0x12, data);
+    }
+    out.push(0x18);
+    varint(&mut out, filesize);
+    for &size in blocksizes {
+        out.push(0x20);
+        varint(&mut out, size);
+    }
+    out
+}
+
+/// dag-pb `PBNode`, links first as the canonical encoding requires.
+fn pb_node(links: &[Link], data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for link in links {
+        let mut encoded = Vec::new();
+        bytes_field(&mut encoded, 0x0a, &link.cid);
+        // go-ipfs writes the empty name of file links
+        bytes_field(&mut encoded, 0x12, b"");
+        encoded.push(0x18);
+        varint(&mut encoded, link.tsize);
+        bytes_field(&mut out, 0x12, &encoded);
+    }
+    bytes_field(&mut out, 0x0a, data);
+    out
+}
+
+/// Builds the CID of a file from its contents, fed in any number of pieces.
+pub(crate) struct CidBuilder {
+    version: u8,
+    chunk: Vec<u8>,
+    leaves: u64,
+    /// Links waiting for a parent, by depth above the leaves
+    levels: Vec<Vec<Link>>,
+}
+
+impl CidBuilder {
+    pub(crate) fn new(version: u8) -> Self {
+        CidBuilder {
+            version,
+            chunk: Vec::with_capacity(CHUNK_SIZE),
+            leaves: 0,
+            levels: Vec::new(),
+        }
+    }
+
+    /// Binary CID of a block: a bare multihash for version 0.
+    fn cid(&self, codec: u64, block: &[u8]) -> Vec<u8> {
+        let mut cid = Vec::new();
+        if self.version > 0 {
+            varint(&mut cid, 1);
+            varint(&mut cid, codec);
+        }
+        cid.extend_from_slice(&[0x12, 0x20]);
+        cid.extend_from_slice(&Sha256::digest(block));
+        cid
+    }
+
+    pub(crate) fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let take = (CHUNK_SIZE - self.chunk.len()).min(data.len());
+            self.chunk.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.chunk.len() == CHUNK_SIZE {
+                self.add_leaf();
+            }
+        }
+    }
+
+    fn add_leaf(&mut self) {
+        let filesize = self.chunk.len() as u64;
+        let link = if self.version == 0 {
+            let block = pb_node(&[], &unixfs_file(&self.chunk, filesize, &[]));
+            Link { cid: self.cid(DAG_PB, &block), tsize: block.len() as u64, filesize }
+        } else {
+            Link { cid: self.cid(RAW, &self.chunk), tsize: filesize, filesize }
+        };
+        self.chunk.clear();
+        self.leaves += 1;
+        self.push(0, link);
+    }
+
+    /// Add a link at `depth`, first moving a full level into a parent node.
+    fn push(&mut self, depth: usize, link: Link) {
+        if self.levels.len() <= depth {
+            self.levels.push(Vec::new());
+        }
+        if self.levels[depth].len() == MAX_LINKS {
+            let links = std::mem::take(&mut self.levels[depth]);
+            let parent = self.node(links);
+            self.push(depth + 1, parent);
+        }
+        self.levels[depth].push(link);
+    }
+
+    fn node(&self, links: Vec<Link>) -> Link {
+        let blocksizes: Vec<u64> = links.iter().map(|link| link.filesize).collect();
+        let filesize = blocksizes.iter().sum();
+        let block = pb_node(&links, &unixfs_file(&[], filesize, &blocksizes));
+        Link {
+            cid: self.cid(DAG_PB, &block),
+            tsize: block.len() as u64 + links.iter().map(|link| link.tsize).sum::<u64>(),
+            filesize,
+        }
+    }
+
+    /// The CID in its usual text form.
+    pub(crate) fn finish(mut self) -> String {
+        // An empty file is a single empty leaf
+        if !self.chunk.is_empty() || self.leaves == 0 {
+            self.add_leaf();
+        }
+        let mut depth = 0;
+        let root = loop {
+            let top = self.levels.get(depth + 1..).unwrap_or_default().iter().all(Vec::is_empty);
+            let mut links = std::mem::take(&mut self.levels[depth]);
+            if top && links.len() == 1 {
+                break links.remove(0);
+            }
+            if !links.is_empty() {
+                let parent = self.node(links);
+                self.push(depth + 1, parent);
+            }
+            depth += 1;
+        };
+        if self.version == 0 {
+            base58(&root.cid)
+        } else {
+            format!("b{}", base32(&root.cid))
+        }
+    }
+}
+
+/// RFC 4648 base32, lowercase and unpadded as multibase `b` uses it.
+fn base32(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    let (mut buffer, mut bits) = (0u32, 0);
+    for &byte in bytes {
+        buffer = (buffer << 8) | u32::from(byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32[(buffer >> bits) as usize & 31] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32[(buffer << (5 - bits)) as usize & 31] as char);
+    }
+    out
+}
+
+fn base58(bytes: &[u8]) -> String {
+    let mut digits: Vec<u8> = Vec::new();
+    for &byte in bytes {
+        let mut carry = u32::from(byte);
+        for digit in digits.iter_mut() {
+            carry += u32::from(*digit) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+    let zeros = bytes.iter().take_while(|&&byte| byte == 0).count();
+    let mut out = "1".repeat(zeros);
+    out.extend(digits.iter().rev().map(|&digit| BASE58[digit as usize] as char));
+    out
+}
+
+/// Passes reads through while building the CID of everything read.
+struct CidReader<R> {
+    inner: R,
+    builder: CidBuilder,
+}
+
+impl<R: Read> Read for CidReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.builder.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// SHA-256 and CID of a file, from one read of its contents. The hash is
+/// stamped on the file with `--write-xattr`.
+pub(crate) fn hash_with_cid(file_info: &FileInfo, version: u8, buffer_size: usize) -> Result<(String, String)> {
+    if let Some(kind) = special::marker(file_info) {
+        return Err(Error::Config(format!("{} is a special file ({}) and has no CID", file_info.path.display(), kind)).into());
+    }
+    let file = fs::File::open(&file_info.path).map_err(Error::io("Failed to open file", &file_info.path))?;
+    let mut reader = CidReader { inner: file, builder: CidBuilder::new(version) };
+    let hash = hash_reader(&mut reader, buffer_size)?;
+    xattrs::stamp(file_info, &hash);
+    Ok((hash, reader.builder.finish()))
+}
@@ -0,0 +1,218 @@
+use anyhow::Result;
+use rayon::prelude::*;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::report::{open_output, OutputFormat};
+use crate::progress::Progress;
+use crate::{collect_files, get_file_hash, Args, Error, FileInfo};
+
+/// A path present on both sides whose contents differ.
+#[derive(Serialize, Debug, Clone)]
+pub struct DifferingEntry {
+    pub path: PathBuf,
+    pub size_a: u64,
+    pub size_b: u64,
+    /// Hashes are omitted when the sizes alone show the files differ
+    pub hash_a: Option<String>,
+    pub hash_b: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct CompareError {
+    pub path: PathBuf,
+    pub error: String,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct CompareSummary {
+    pub matching: usize,
+    pub differing: usize,
+    pub only_in_a: usize,
+    pub only_in_b: usize,
+    pub errors: usize,
+}
+
+/// Result of comparing two directory trees file by file.
+#[derive(Serialize, Debug, Default)]
+pub struct CompareReport {
+    pub dir_a: PathBuf,
+    pub dir_b: PathBuf,
+    pub identical: bool,
+    pub summary: CompareSummary,
+    pub differing: Vec<DifferingEntry>,
+    pub only_in_a: Vec<PathBuf>,
+    pub only_in_b: Vec<PathBuf>,
+    pub errors: Vec<CompareError>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub matching: Option<Vec<PathBuf>>,
+}
+
+impl CompareReport {
+    pub fn write_text(&self, writer: &mut impl Write) -> Result<()> {
+        for entry in &self.differing {
+            writeln!(writer, "DIFFER: {}", entry.path.display())?;
+        }
+        for path in &self.only_in_a {
+            writeln!(writer, "ONLY IN A: {}", path.display())?;
+        }
+        for path in &self.only_in_b {
+            writeln!(writer, "ONLY IN B: {}", path.display())?;
+        }
+        for error in &self.errors {
+            writeln!(writer, "ERROR: {}: {}", error.path.display(), error.error)?;
+        }
+        for path in self.matching.iter().flatten() {
+            writeln!(writer, "MATCH: {}", path.display())?;
+        }
+        writeln!(
+            writer,
+            "Summary: {} matching, {} differing, {} only in A, {} only in B, {} errors",
+            self.summary.matching, self.summary.differing, self.summary.only_in_a, self.summary.only_in_b, self.summary.errors
+        )?;
+        Ok(())
+    }
+}
+
+fn relative_files(root: &Path, args: &Args) -> Result<HashMap<PathBuf, FileInfo>> {
+    Ok(collect_files(root, args)?
+        .into_iter()
+        .map(|file_info| {
+            let relative = file_info.path.strip_prefix(root).unwrap_or(&file_info.path).to_path_buf();
+            (relative, file_info)
+        })
+        .collect())
+}
+
+enum PairResult {
+    Match,
+    Differ(Option<String>, Option<String>),
+    Error(String),
+}
+
+/// Compare two trees without an intermediate manifest. Files present on both
+/// sides are only hashed when their sizes agree.
+pub fn compare_trees(dir_a: &Path, dir_b: &Path, list_matching: bool, args: &Args) -> Result<CompareReport> {
+    for dir in [dir_a, dir_b] {
+        if !dir.is_dir() {
+            anyhow::bail!("Not a directory: {}", dir.display());
+        }
+    }
+    let (files_a, files_b) = rayon::join(|| relative_files(dir_a, args), || relative_files(dir_b, args));
+    let (files_a, files_b) = (files_a?, files_b?);
+
+    let mut report = CompareReport {
+        dir_a: dir_a.to_path_buf(),
+        dir_b: dir_b.to_path_buf(),
+        ..Default::default()
+    };
+    let mut pairs = Vec::new();
+    for (path, a) in &files_a {
+        match files_b.get(path) {
+            Some(b) => pairs.push((path, a, b)),
+            None => report.only_in_a.push(path.clone()),
+        }
+    }
+    report.only_in_b = files_b.keys().filter(|path| !files_a.contains_key(*path)).cloned().collect();
+
+    let progress = Progress::start(pairs.len() as u64, 0);
+    let results: Vec<(&PathBuf, &FileInfo, &FileInfo, PairResult)> = pairs
+        .into_par_iter()
+        .filter(|_| !args.cancel.is_cancelled())
+        .map(|(path, a, b)| {
+            progress.file_started(path);
+            let result = if a.size != b.size {
+                PairResult::Differ(None, None)
+            } else {
+                let (hash_a, hash_b) = rayon::join(
+                    || get_file_hash(a, args.buffer_size),
+                    || get_file_hash(b, args.buffer_size),
+                );
+                match (hash_a, hash_b) {
+                    (Ok(hash_a), Ok(hash_b)) if hash_a == hash_b => PairResult::Match,
+                    (Ok(hash_a), Ok(hash_b)) => PairResult::Differ(Some(hash_a), Some(hash_b)),
+                    (Err(e), _) | (_, Err(e)) => PairResult::Error(format!("{:#}", e)),
+                }
+            };
+            match result {
+                PairResult::Error(ref message) => progress.error(path, message),
+                _ => progress.file_finished(path),
+            }
+            (path, a, b, result)
+        })
+        .collect();
+    progress.finish("Comparison complete");
+    if args.cancel.is_cancelled() {
+        return Err(Error::Cancelled.into());
+    }
+
+    let mut matching = Vec::new();
+    for (path, a, b, result) in results {
+        match result {
+            PairResult::Match => matching.push(path.clone()),
+            PairResult::Differ(hash_a, hash_b) => report.differing.push(DifferingEntry {
+                path: path.clone(),
+                size_a: a.size,
+                size_b: b.size,
+                hash_a,
+                hash_b,
+            }),
+            PairResult::Error(error) => report.errors.push(CompareError { path: path.clone(), error }),
+        }
+    }
+
+    report.differing.sort_by(|a, b| a.path.cmp(&b.path));
+    report.only_in_a.sort();
+    report.only_in_b.sort();
+    report.errors.sort_by(|a, b| a.path.cmp(&b.path));
+    matching.sort();
+    report.summary = CompareSummary {
+        matching: matching.len(),
+        differing: report.differing.len(),
+        only_in_a: report.only_in_a.len(),
+        only_in_b: report.only_in_b.len(),
+        errors: report.errors.len(),
+    };
+    report.identical = report.differing.is_empty()
+        && report.only_in_a.is_empty()
+        && report.only_in_b.is_empty()
+        && report.errors.is_empty();
+    if list_matching {
+        report.matching = Some(matching);
+    }
+    Ok(report)
+}
+
+pub fn run_compare(
+    dir_a: &Path,
+    dir_b: &Path,
+    format: OutputFormat,
+    output: Option<&Path>,
+    list_matching: bool,
+    args: &Args,
+) -> Result<()> {
+    let report = compare_trees(dir_a, dir_b, list_matching, args)?;
+
+    let mut writer = open_output(output)?;
+    match format {
+        OutputFormat::Text => report.write_text(&mut writer)?,
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut writer, &report)?;
+            writeln!(writer)?;
+        }
+    }
+    writer.flush()?;
+
+    if !report.identical {
+        anyhow::bail!(
+            "Trees differ: {} differing, {} only in A, {} only in B, {} errors",
+            report.summary.differing,
+            report.summary.only_in_a,
+            report.summary.only_in_b,
+            report.summary.errors
+        );
+    }
+    Ok(())
+}
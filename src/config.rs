@@ -0,0 +1,46 @@
+//! Settings read from the TOML file given with `--config`, for options that
+//! do not fit on a command line.
+
+use anyhow::Result;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+use crate::notify::NotifierConfig;
+use crate::Error;
+
+fn default_top_files() -> usize {
+    10
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Notifiers run after validation and update runs
+    #[serde(default)]
+    pub notify: Vec<NotifierConfig>,
+    /// How many problem files a notification lists
+    #[serde(default = "default_top_files")]
+    pub top_files: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            notify: Vec::new(),
+            top_files: default_top_files(),
+        }
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path).map_err(Error::io("Failed to read config file", path))?;
+        let config: Config = toml::from_str(&content)
+            .map_err(|e| Error::Config(format!("Invalid config file {}: {}", path.display(), e)))?;
+        if !config.notify.is_empty() && !cfg!(feature = "notify") {
+            return Err(Error::Config("Notifiers need data-manifest built with the notify feature".into()).into());
+        }
+        Ok(config)
+    }
+}
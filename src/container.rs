@@ -0,0 +1,235 @@
+//! `--descend-archives`: tar, tar.gz, and zip files hashed as directories of
+//! their members, which are recorded as `collection/pkg.tar#inner/path`.
+//!
+//! A container's member list is read once and kept for the rest of the run.
+//! Zip members are hashed one at a time. Tar members can only be reached by
+//! reading the container from the start, so the first member hashed hashes
+//! them all in one pass and the others take their result from it.
+
+use anyhow::{Context, Result};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::{days_from_civil, hash_reader, system_time_nanos, Error, FileInfo};
+
+/// Separates the path of a container from the path of a member inside it.
+const SEPARATOR: char = '#';
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Tar,
+    TarGz,
+    Zip,
+}
+
+fn kind(path: &Path) -> Option<Kind> {
+    let name = path.file_name()?.to_str()?.to_ascii_lowercase();
+    if name.ends_with(".tar") {
+        Some(Kind::Tar)
+    } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        Some(Kind::TarGz)
+    } else if name.ends_with(".zip") {
+        Some(Kind::Zip)
+    } else {
+        None
+    }
+}
+
+/// Whether `path` names a container that `--descend-archives` descends into.
+pub(crate) fn is_container(path: &Path) -> bool {
+    kind(path).is_some()
+}
+
+/// Size and mtime of a container file, so a long-running process notices
+/// when a container it already read has changed.
+type Stamp = (u64, Option<i64>);
+
+fn stamp(container: &Path) -> Result<Stamp> {
+    let metadata = fs::metadata(container).map_err(Error::io("Failed to read container", container))?;
+    Ok((metadata.len(), metadata.modified().ok().and_then(system_time_nanos)))
+}
+
+struct Member {
+    size: u64,
+    /// Nanoseconds since the Unix epoch
+    mtime: Option<i64>,
+}
+
+type Listing = Arc<BTreeMap<String, Member>>;
+
+static LISTINGS: Mutex<BTreeMap<PathBuf, (Stamp, Listing)>> = Mutex::new(BTreeMap::new());
+
+/// Member hashes of tar containers, filled in by the first member hashed.
+type TarHashes = Arc<Mutex<Option<(Stamp, HashMap<String, String>)>>>;
+
+static TAR_HASHES: Mutex<BTreeMap<PathBuf, TarHashes>> = Mutex::new(BTreeMap::new());
+
+fn open_tar(container: &Path, kind: Kind) -> Result<tar::Archive<Box<dyn Read>>> {
+    let file = BufReader::new(fs::File::open(container).map_err(Error::io("Failed to open container", container))?);
+    let reader: Box<dyn Read> = match kind {
+        Kind::TarGz => Box::new(flate2::read::MultiGzDecoder::new(file)),
+        _ => Box::new(file),
+    };
+    Ok(tar::Archive::new(reader))
+}
+
+fn open_zip(container: &Path) -> Result<zip::ZipArchive<BufReader<fs::File>>> {
+    let file = BufReader::new(fs::File::open(container).map_err(Error::io("Failed to open container", container))?);
+    Ok(zip::ZipArchive::new(file)?)
+}
+
+/// A member's path as recorded: relative, without a leading `./`.
+pub(crate) fn member_name(raw: &str) -> Option<String> {
+    let name = raw.trim_start_matches("./").trim_start_matches('/');
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+/// Zip timestamps carry no time zone; they are taken as UTC.
+fn zip_mtime(time: zip::DateTime) -> i64 {
+    let days = days_from_civil(time.year().into(), time.month().into(), time.day().into());
+    let secs = days * 86_400 + i64::from(time.hour()) * 3_600 + i64::from(time.minute()) * 60 + i64::from(time.second());
+    secs * 1_000_000_000
+}
+
+fn read_listing(container: &Path, kind: Kind) -> Result<BTreeMap<String, Member>> {
+    let mut members = BTreeMap::new();
+    if kind == Kind::Zip {
+        let mut archive = open_zip(container)?;
+        for index in 0..archive.len() {
+            let file = archive.by_index_raw(index)?;
+            let Some(name) = member_name(file.name()).filter(|_| file.is_file()) else { continue };
+            members.insert(name, Member {
+                size: file.size(),
+                mtime: file.last_modified().map(zip_mtime),
+            });
+        }
+    } else {
+        let mut archive = open_tar(container, kind)?;
+        for entry in archive.entries()? {
+            let entry = entry?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            let Some(name) = member_name(&entry.path()?.to_string_lossy()) else { continue };
+            members.insert(name, Member {
+                size: entry.size(),
+                mtime: entry.header().mtime().ok().map(|secs| secs as i64 * 1_000_000_000),
+            });
+        }
+    }
+    Ok(members)
+}
+
+/// The regular files in `container`, read at most once per change to it.
+fn listing(container: &Path) -> Result<Listing> {
+    let kind = kind(container).with_context(|| format!("Not a tar or zip file: {}", container.display()))?;
+    let stamp = stamp(container)?;
+    if let Some((read, listing)) = LISTINGS.lock().unwrap().get(container) {
+        if *read == stamp {
+            return Ok(Arc::clone(listing));
+        }
+    }
+    let listing = Arc::new(
+        read_listing(container, kind).with_context(|| format!("Failed to read container {}", container.display()))?,
+    );
+    LISTINGS.lock().unwrap().insert(container.to_path_buf(), (stamp, Arc::clone(&listing)));
+    Ok(listing)
+}
+
+fn member_path(container: &Path, name: &str) -> PathBuf {
+    let mut path = container.as_os_str().to_owned();
+    path.push(SEPARATOR.to_string());
+    path.push(name);
+    PathBuf::from(path)
+}
+
+/// The members of `container` as files to hash, with paths of the form
+/// `container#member`.
+pub(crate) fn members(container: &Path) -> Result<Vec<FileInfo>> {
+    Ok(listing(container)?
+        .iter()
+        .map(|(name, member)| FileInfo {
+            path: member_path(container, name),
+            size: member.size,
+            mtime: member.mtime,
+        })
+        .collect())
+}
+
+/// Split a member path into the container file and the member's path inside
+/// it, or `None` if `path` does not lead into a container.
+pub(crate) fn split_member(path: &Path) -> Option<(PathBuf, String)> {
+    let text = path.to_str()?;
+    text.match_indices(SEPARATOR).find_map(|(index, _)| {
+        let container = Path::new(&text[..index]);
+        let name = &text[index + 1..];
+        (!name.is_empty() && is_container(container) && container.is_file())
+            .then(|| (container.to_path_buf(), name.to_string()))
+    })
+}
+
+/// Size and mtime of the regular file at `path`, or with `descend` of the
+/// container member it names.
+pub(crate) fn stat(path: &Path, descend: bool) -> Option<FileInfo> {
+    match fs::metadata(path) {
+        Ok(metadata) => metadata.is_file().then(|| FileInfo::from_metadata(path.to_path_buf(), &metadata)),
+        Err(_) if descend => {
+            let (container, name) = split_member(path)?;
+            let listing = listing(&container).ok()?;
+            let member = listing.get(&name)?;
+            Some(FileInfo {
+                path: path.to_path_buf(),
+                size: member.size,
+                mtime: member.mtime,
+            })
+        }
+        Err(_) => None,
+    }
+}
+
+/// Whether `path` exists, counting container members with `descend`.
+pub(crate) fn exists(path: &Path, descend: bool) -> bool {
+    path.exists() || (descend && stat(path, true).is_some())
+}
+
+fn hash_tar(container: &Path, kind: Kind, buffer_size: usize) -> Result<HashMap<String, String>> {
+    let mut hashes = HashMap::new();
+    let mut archive = open_tar(container, kind)?;
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let Some(name) = member_name(&entry.path()?.to_string_lossy()) else { continue };
+        hashes.insert(name, hash_reader(&mut entry, buffer_size)?);
+    }
+    Ok(hashes)
+}
+
+/// SHA-256 of the member `name` of `container`.
+pub(crate) fn member_hash(container: &Path, name: &str, buffer_size: usize) -> Result<String> {
+    let kind = kind(container).with_context(|| format!("Not a tar or zip file: {}", container.display()))?;
+    let not_found = || Error::io("Failed to open file", &member_path(container, name))(std::io::ErrorKind::NotFound.into());
+    if kind == Kind::Zip {
+        let mut archive = open_zip(container)?;
+        let mut file = archive.by_name(name).map_err(|_| not_found())?;
+        return hash_reader(&mut file, buffer_size);
+    }
+
+    let slot = Arc::clone(TAR_HASHES.lock().unwrap().entry(container.to_path_buf()).or_default());
+    // Other threads hashing members of the same container wait for this pass
+    let mut hashes = slot.lock().unwrap_or_else(|e| e.into_inner());
+    let stamp = stamp(container)?;
+    let current = match *hashes {
+        Some((read, ref hashes)) if read == stamp => hashes,
+        _ => {
+            let read = hash_tar(container, kind, buffer_size)
+                .with_context(|| format!("Failed to read container {}", container.display()))?;
+            &hashes.insert((stamp, read)).1
+        }
+    };
+    current.get(name).cloned().ok_or_else(|| not_found().into())
+}
@@ -0,0 +1,257 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::report::{open_output, OutputFormat};
+use crate::{load_existing_manifest, ManifestEntry};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DiffEntry {
+    pub path: PathBuf,
+    pub hash: String,
+    pub size: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChangedEntry {
+    pub path: PathBuf,
+    pub old_hash: String,
+    pub new_hash: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RenamedEntry {
+    pub from: PathBuf,
+    pub to: PathBuf,
+    pub hash: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct DiffSummary {
+    pub unchanged: usize,
+    pub added: usize,
+    pub removed: usize,
+    pub changed: usize,
+    pub renamed: usize,
+}
+
+/// Differences between two manifests.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct DiffReport {
+    pub old: PathBuf,
+    pub new: PathBuf,
+    pub summary: DiffSummary,
+    pub added: Vec<DiffEntry>,
+    pub removed: Vec<DiffEntry>,
+    pub changed: Vec<ChangedEntry>,
+    pub renamed: Vec<RenamedEntry>,
+}
+
+/// Drop the leading archive-name component so manifests of differently named
+/// snapshots of the same tree can be compared.
+fn strip_archive_name(entries: HashMap<PathBuf, ManifestEntry>) -> HashMap<PathBuf, ManifestEntry> {
+    entries
+        .into_iter()
+        .map(|(path, entry)| {
+            let stripped: PathBuf = path.components().skip(1).collect();
+            (stripped, entry)
+        })
+        .collect()
+}
+
+pub fn diff_entries(
+    old: &HashMap<PathBuf, ManifestEntry>,
+    new: &HashMap<PathBuf, ManifestEntry>,
+) -> DiffReport {
+    let mut report = DiffReport::default();
+
+    for (path, old_entry) in old {
+        match new.get(path) {
+            Some(new_entry) if new_entry.hash == old_entry.hash => report.summary.unchanged += 1,
+            Some(new_entry) => report.changed.push(ChangedEntry {
+                path: path.clone(),
+                old_hash: old_entry.hash.clone(),
+                new_hash: new_entry.hash.clone(),
+            }),
+            None => report.removed.push(DiffEntry {
+                path: path.clone(),
+                hash: old_entry.hash.clone(),
+                size: old_entry.size,
+            }),
+        }
+    }
+    for (path, new_entry) in new {
+        if !old.contains_key(path) {
+            report.added.push(DiffEntry {
+                path: path.clone(),
+                hash: new_entry.hash.clone(),
+                size: new_entry.size,
+            });
+        }
+    }
+
+    // A removed entry and an added entry with the same content is a rename
+    report.removed.sort_by(|a, b| a.path.cmp(&b.path));
+    report.added.sort_by(|a, b| a.path.cmp(&b.path));
+    let mut removed_by_hash: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (i, entry) in report.removed.iter().enumerate() {
+        removed_by_hash.entry(&entry.hash).or_default().push(i);
+    }
+    let mut renamed_removed = vec![false; report.removed.len()];
+    let mut renamed_added = vec![false; report.added.len()];
+    for (added_index, added) in report.added.iter().enumerate() {
+        let Some(candidates) = removed_by_hash.get_mut(added.hash.as_str()) else { continue };
+        let position = candidates.iter().position(|&i| {
+            let removed = &report.removed[i];
+            removed.size.is_none() || added.size.is_none() || removed.size == added.size
+        });
+        if let Some(position) = position {
+            let removed_index = candidates.remove(position);
+            renamed_removed[removed_index] = true;
+            renamed_added[added_index] = true;
+            report.renamed.push(RenamedEntry {
+                from: report.removed[removed_index].path.clone(),
+                to: added.path.clone(),
+                hash: added.hash.clone(),
+            });
+        }
+    }
+    let mut flags = renamed_removed.into_iter();
+    report.removed.retain(|_| !flags.next().unwrap_or(false));
+    let mut flags = renamed_added.into_iter();
+    report.added.retain(|_| !flags.next().unwrap_or(false));
+
+    report.changed.sort_by(|a, b| a.path.cmp(&b.path));
+    report.renamed.sort_by(|a, b| a.from.cmp(&b.from));
+    report.summary.added = report.added.len();
+    report.summary.removed = report.removed.len();
+    report.summary.changed = report.changed.len();
+    report.summary.renamed = report.renamed.len();
+    report
+}
+
+impl DiffReport {
+    pub fn write_text(&self, writer: &mut impl Write) -> Result<()> {
+        for entry in &self.added {
+            writeln!(writer, "+ {}", entry.path.display())?;
+        }
+        for entry in &self.removed {
+            writeln!(writer, "- {}", entry.path.display())?;
+        }
+        for entry in &self.changed {
+            writeln!(writer, "M {}", entry.path.display())?;
+        }
+        for entry in &self.renamed {
+            writeln!(writer, "R {} -> {}", entry.from.display(), entry.to.display())?;
+        }
+        writeln!(
+            writer,
+            "Summary: {} added, {} removed, {} changed, {} renamed, {} unchanged",
+            self.summary.added, self.summary.removed, self.summary.changed, self.summary.renamed, self.summary.unchanged
+        )?;
+        Ok(())
+    }
+}
+
+pub fn run_diff(
+    old_path: &Path,
+    new_path: &Path,
+    format: OutputFormat,
+    output: Option<&Path>,
+    ignore_archive_name: bool,
+) -> Result<()> {
+    for path in [old_path, new_path] {
+        if !path.exists() {
+            anyhow::bail!("Manifest does not exist: {}", path.display());
+        }
+    }
+    let mut old = load_existing_manifest(old_path)?.entries;
+    let mut new = load_existing_manifest(new_path)?.entries;
+    if ignore_archive_name {
+        old = strip_archive_name(old);
+        new = strip_archive_name(new);
+    }
+
+    let mut report = diff_entries(&old, &new);
+    report.old = old_path.to_path_buf();
+    report.new = new_path.to_path_buf();
+
+    let mut writer = open_output(output)?;
+    match format {
+        OutputFormat::Text => report.write_text(&mut writer)?,
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut writer, &report)?;
+            writeln!(writer)?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(hash: &str, size: Option<u64>) -> ManifestEntry {
+        ManifestEntry {
+            hash: hash.to_string(),
+            size,
+            mtime: None,
+            verified: None,
+            cid: None,
+            xattrs: None,
+            acl: None,
+            link: None,
+            format: None,
+            dev: None,
+            inode: None,
+        }
+    }
+
+    fn manifest(entries: &[(&str, &str, Option<u64>)]) -> HashMap<PathBuf, ManifestEntry> {
+        entries.iter().map(|&(path, hash, size)| (PathBuf::from(path), entry(hash, size))).collect()
+    }
+
+    #[test]
+    fn pairs_removed_and_added_entries_with_the_same_contents_as_renames() {
+        let old = manifest(&[("a", "1", Some(1)), ("b", "2", Some(2)), ("c", "3", Some(3)), ("d", "4", None)]);
+        let new = manifest(&[("a", "1", Some(1)), ("b", "9", Some(2)), ("moved/c", "3", Some(3)), ("e", "5", None), ("f", "4", Some(4))]);
+        let report = diff_entries(&old, &new);
+
+        assert_eq!(report.summary.unchanged, 1);
+        assert_eq!(report.changed.len(), 1);
+        assert_eq!((report.changed[0].old_hash.as_str(), report.changed[0].new_hash.as_str()), ("2", "9"));
+        // Either side without a size still pairs on the hash alone
+        let renames: Vec<_> = report.renamed.iter().map(|r| (r.from.to_str().unwrap(), r.to.to_str().unwrap())).collect();
+        assert_eq!(renames, [("c", "moved/c"), ("d", "f")]);
+        assert_eq!(report.added.iter().map(|e| e.path.to_str().unwrap()).collect::<Vec<_>>(), ["e"]);
+        assert!(report.removed.is_empty());
+        assert_eq!((report.summary.added, report.summary.removed, report.summary.renamed), (1, 0, 2));
+    }
+
+    #[test]
+    fn renames_need_matching_sizes_and_pair_each_entry_once() {
+        let old = manifest(&[("a", "1", Some(1)), ("b", "1", Some(1)), ("c", "2", Some(2))]);
+        let new = manifest(&[("x", "1", Some(1)), ("y", "2", Some(5))]);
+        let report = diff_entries(&old, &new);
+
+        // Two copies removed, one added: only one becomes a rename
+        let renames: Vec<_> = report.renamed.iter().map(|r| (r.from.to_str().unwrap(), r.to.to_str().unwrap())).collect();
+        assert_eq!(renames, [("a", "x")]);
+        let removed: Vec<_> = report.removed.iter().map(|e| e.path.to_str().unwrap()).collect();
+        assert_eq!(removed, ["b", "c"]);
+        assert_eq!(report.added.iter().map(|e| e.path.to_str().unwrap()).collect::<Vec<_>>(), ["y"]);
+    }
+
+    #[test]
+    fn strips_the_archive_name_for_comparison() {
+        let old = strip_archive_name(manifest(&[("snap-1/dir/a", "1", None)]));
+        let new = strip_archive_name(manifest(&[("snap-2/dir/a", "1", None)]));
+        let report = diff_entries(&old, &new);
+        assert_eq!(report.summary.unchanged, 1);
+        assert!(report.added.is_empty() && report.removed.is_empty() && report.renamed.is_empty());
+    }
+}
@@ -0,0 +1,136 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::{load_existing_manifest, special, Manifest};
+use crate::report::{open_output, OutputFormat};
+
+/// A set of manifest entries with identical content.
+#[derive(Serialize, Debug, Clone)]
+pub struct DuplicateGroup {
+    pub hash: String,
+    pub size: Option<u64>,
+    pub paths: Vec<PathBuf>,
+    /// Bytes freed by keeping a single copy, when the size is known
+    pub reclaimable: Option<u64>,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct DuplicateReport {
+    pub manifest: PathBuf,
+    pub groups: usize,
+    pub duplicate_files: usize,
+    pub reclaimable_bytes: u64,
+    /// Groups whose size could not be determined and are not counted in reclaimable_bytes
+    pub unknown_size_groups: usize,
+    pub duplicates: Vec<DuplicateGroup>,
+}
+
+impl DuplicateReport {
+    pub fn write_text(&self, writer: &mut impl Write) -> Result<()> {
+        for group in &self.duplicates {
+            match group.size {
+                Some(size) => writeln!(writer, "{} ({} copies, {} bytes each)", group.hash, group.paths.len(), size)?,
+                None => writeln!(writer, "{} ({} copies)", group.hash, group.paths.len())?,
+            }
+            for path in &group.paths {
+                writeln!(writer, "  {}", path.display())?;
+            }
+        }
+        writeln!(
+            writer,
+            "Summary: {} duplicate groups, {} redundant files, {} reclaimable bytes",
+            self.groups, self.duplicate_files, self.reclaimable_bytes
+        )?;
+        if self.unknown_size_groups > 0 {
+            writeln!(
+                writer,
+                "Warning: {} groups have no recorded size; use an extended manifest or --archive-path to include them",
+                self.unknown_size_groups
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Group manifest entries by hash. Sizes come from the manifest when recorded,
+/// otherwise from the file under `archive_path` if one is given.
+pub fn find_duplicates(manifest_path: &Path, archive_path: Option<&Path>) -> Result<DuplicateReport> {
+    let manifest = load_existing_manifest(manifest_path)?;
+    Ok(DuplicateReport {
+        manifest: manifest_path.to_path_buf(),
+        ..group_duplicates(&manifest, archive_path)
+    })
+}
+
+/// [`find_duplicates`] for a loaded manifest. The report names no manifest.
+pub(crate) fn group_duplicates(manifest: &Manifest, archive_path: Option<&Path>) -> DuplicateReport {
+    let mut by_hash: HashMap<&str, Vec<(&PathBuf, Option<u64>)>> = HashMap::new();
+    // Special files have no contents to duplicate
+    for (path, entry) in manifest.entries.iter().filter(|(_, entry)| !special::is_marker(&entry.hash)) {
+        by_hash.entry(&entry.hash).or_default().push((path, entry.size));
+    }
+
+    let mut report = DuplicateReport::default();
+    for (hash, mut files) in by_hash {
+        if files.len() < 2 {
+            continue;
+        }
+        files.sort();
+        let size = files.iter().find_map(|(_, size)| *size).or_else(|| {
+            let archive_path = archive_path?;
+            files.iter().find_map(|(path, _)| {
+                // Manifest keys start with the archive name
+                let relative: PathBuf = path.components().skip(1).collect();
+                fs::metadata(archive_path.join(relative)).ok().map(|m| m.len())
+            })
+        });
+        let copies = files.len() as u64 - 1;
+        let reclaimable = size.map(|size| size * copies);
+
+        report.duplicate_files += copies as usize;
+        match reclaimable {
+            Some(bytes) => report.reclaimable_bytes += bytes,
+            None => report.unknown_size_groups += 1,
+        }
+        report.duplicates.push(DuplicateGroup {
+            hash: hash.to_string(),
+            size,
+            paths: files.into_iter().map(|(path, _)| path.clone()).collect(),
+            reclaimable,
+        });
+    }
+
+    // Largest savings first
+    report
+        .duplicates
+        .sort_by(|a, b| b.reclaimable.cmp(&a.reclaimable).then_with(|| a.paths.cmp(&b.paths)));
+    report.groups = report.duplicates.len();
+    report
+}
+
+pub fn run_dupes(
+    manifest_path: &Path,
+    archive_path: Option<&Path>,
+    format: OutputFormat,
+    output: Option<&Path>,
+) -> Result<()> {
+    if !manifest_path.exists() {
+        anyhow::bail!("Manifest does not exist: {}", manifest_path.display());
+    }
+    let report = find_duplicates(manifest_path, archive_path)?;
+
+    let mut writer = open_output(output)?;
+    match format {
+        OutputFormat::Text => report.write_text(&mut writer)?,
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut writer, &report)?;
+            writeln!(writer)?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
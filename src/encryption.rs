@@ -0,0 +1,183 @@
+//! age encryption of manifest files, which list every file of an archive:
+//! with `--encrypt-to`, manifests are written encrypted to the given age
+//! recipients, and manifests read anywhere are decrypted with the
+//! identities of `--identity`.
+//!
+//! The recipients and identities are set once per run, like the bandwidth
+//! limit, because manifests are written and read from many places.
+
+use anyhow::Result;
+use std::fs;
+use std::io::{self, BufRead, BufWriter, Read, Write};
+use std::path::Path;
+#[cfg(feature = "age")]
+use std::sync::Mutex;
+
+use crate::{Args, Error};
+
+/// Start of the binary age format
+pub(crate) const AGE_MAGIC: &[u8] = b"age-encryption.org/v1\n";
+
+#[cfg(feature = "age")]
+static RECIPIENTS: Mutex<Vec<age::x25519::Recipient>> = Mutex::new(Vec::new());
+#[cfg(feature = "age")]
+static IDENTITIES: Mutex<Vec<age::x25519::Identity>> = Mutex::new(Vec::new());
+
+/// Whether manifests are being written encrypted.
+#[cfg(feature = "age")]
+fn encrypting() -> bool {
+    !RECIPIENTS.lock().unwrap().is_empty()
+}
+
+#[cfg(not(feature = "age"))]
+fn encrypting() -> bool {
+    false
+}
+
+/// Take the recipients and identities of `args` for the rest of the run,
+/// replacing those of an earlier run in the same process.
+pub(crate) fn configure(args: &Args) -> Result<()> {
+    clear_keys();
+    if args.encrypt_to.is_empty() && args.identity.is_none() {
+        return Ok(());
+    }
+    set_keys(&args.encrypt_to, args.identity.as_deref())?;
+    // Fail before hashing rather than when the manifest is written
+    if !args.validate && args.command.is_none() {
+        refuse_plaintext(&args.output)?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "age")]
+fn clear_keys() {
+    RECIPIENTS.lock().unwrap().clear();
+    IDENTITIES.lock().unwrap().clear();
+}
+
+#[cfg(not(feature = "age"))]
+fn clear_keys() {}
+
+#[cfg(feature = "age")]
+fn set_keys(recipients: &[String], identity: Option<&Path>) -> Result<()> {
+    let recipients = recipients
+        .iter()
+        .map(|recipient| {
+            recipient
+                .parse::<age::x25519::Recipient>()
+                .map_err(|e| Error::Config(format!("Invalid age recipient {}: {}", recipient, e)))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let identities = match identity {
+        Some(path) => age::IdentityFile::from_file(path.to_string_lossy().into_owned())
+            .map_err(|e| Error::Config(format!("Invalid age identity file {}: {}", path.display(), e)))?
+            .into_identities()
+            .into_iter()
+            .map(|entry| match entry {
+                age::IdentityFileEntry::Native(identity) => identity,
+            })
+            .collect(),
+        None => Vec::new(),
+    };
+    *RECIPIENTS.lock().unwrap() = recipients;
+    *IDENTITIES.lock().unwrap() = identities;
+    Ok(())
+}
+
+#[cfg(not(feature = "age"))]
+fn set_keys(_recipients: &[String], _identity: Option<&Path>) -> Result<()> {
+    Err(Error::Config("--encrypt-to and --identity need data-manifest built with the age feature".into()).into())
+}
+
+fn is_encrypted(path: &Path) -> bool {
+    let mut magic = [0u8; AGE_MAGIC.len()];
+    fs::File::open(path).and_then(|mut file| file.read_exact(&mut magic)).is_ok() && magic == AGE_MAGIC
+}
+
+/// Refuse to replace an encrypted manifest with a plaintext one.
+fn refuse_plaintext(manifest_path: &Path) -> Result<()> {
+    if !encrypting() && is_encrypted(manifest_path) {
+        return Err(Error::Config(format!(
+            "{} is encrypted; pass --encrypt-to to keep it encrypted when it is rewritten",
+            manifest_path.display()
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+/// A manifest file being written, encrypted when `--encrypt-to` was given.
+/// [`finish`](Self::finish) must be called to complete it.
+pub(crate) enum ManifestFile {
+    Plain(BufWriter<fs::File>),
+    #[cfg(feature = "age")]
+    Encrypted(age::stream::StreamWriter<BufWriter<fs::File>>),
+}
+
+impl ManifestFile {
+    pub(crate) fn create(path: &Path) -> Result<Self> {
+        refuse_plaintext(path)?;
+        let file = BufWriter::new(fs::File::create(path).map_err(Error::io("Failed to create output file", path))?);
+        #[cfg(feature = "age")]
+        if encrypting() {
+            let recipients = RECIPIENTS.lock().unwrap().iter().map(|r| Box::new(r.clone()) as Box<dyn age::Recipient + Send>).collect();
+            let encryptor = age::Encryptor::with_recipients(recipients).expect("at least one recipient");
+            let writer = encryptor.wrap_output(file).map_err(|e| Error::Config(format!("Failed to encrypt {}: {}", path.display(), e)))?;
+            return Ok(ManifestFile::Encrypted(writer));
+        }
+        Ok(ManifestFile::Plain(file))
+    }
+
+    pub(crate) fn finish(self) -> Result<()> {
+        match self {
+            ManifestFile::Plain(mut file) => file.flush()?,
+            #[cfg(feature = "age")]
+            ManifestFile::Encrypted(writer) => writer.finish()?.flush()?,
+        }
+        Ok(())
+    }
+}
+
+impl Write for ManifestFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            ManifestFile::Plain(file) => file.write(buf),
+            #[cfg(feature = "age")]
+            ManifestFile::Encrypted(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            ManifestFile::Plain(file) => file.flush(),
+            #[cfg(feature = "age")]
+            ManifestFile::Encrypted(writer) => writer.flush(),
+        }
+    }
+}
+
+/// `reader`, which starts with [`AGE_MAGIC`], decrypted with the
+/// `--identity` identities. `path` is only used in error messages.
+#[cfg(feature = "age")]
+pub(crate) fn decrypt(path: &Path, reader: impl BufRead + 'static) -> Result<Box<dyn Read>> {
+    let identities = IDENTITIES.lock().unwrap().clone();
+    if identities.is_empty() {
+        return Err(Error::Config(format!("{} is encrypted; pass its age identity file with --identity", path.display())).into());
+    }
+    let failed = |e: age::DecryptError| Error::Config(format!("Cannot decrypt {}: {}", path.display(), e));
+    let decryptor = match age::Decryptor::new_buffered(reader).map_err(failed)? {
+        age::Decryptor::Recipients(decryptor) => decryptor,
+        age::Decryptor::Passphrase(_) => {
+            return Err(Error::Config(format!("{} is encrypted with a passphrase, not to a recipient", path.display())).into())
+        }
+    };
+    let decrypted = decryptor
+        .decrypt(identities.iter().map(|identity| identity as &dyn age::Identity))
+        .map_err(failed)?;
+    Ok(Box::new(decrypted))
+}
+
+#[cfg(not(feature = "age"))]
+pub(crate) fn decrypt(path: &Path, _reader: impl BufRead + 'static) -> Result<Box<dyn Read>> {
+    Err(Error::Config(format!("{} is age encrypted, but age support was not compiled in", path.display())).into())
+}
@@ -0,0 +1,51 @@
+use std::path::{Path, PathBuf};
+
+/// Errors the library raises for conditions callers may want to handle.
+/// Functions return `anyhow::Result`; recover the kind with
+/// `error.downcast_ref::<data_manifest::Error>()`, which also sees through
+/// added context.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// A file or directory could not be read or written.
+    #[error("{message}: {}", path.display())]
+    Io {
+        message: &'static str,
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    /// A manifest could not be parsed.
+    #[error("Invalid manifest {} (line {line}): {message}", path.display())]
+    Parse { path: PathBuf, line: usize, message: String },
+
+    /// Validation completed but found hash mismatches, missing files, or other
+    /// problems its policy treats as failures. Carries the exit status so
+    /// callers can tell corruption from expected modification.
+    #[error("{message}")]
+    Validation { message: String, exit_code: i32 },
+
+    /// Invalid options or an archive that does not suit them.
+    #[error("{0}")]
+    Config(String),
+
+    /// The operation was stopped through its [`CancellationToken`](crate::CancellationToken).
+    #[error("Operation cancelled")]
+    Cancelled,
+}
+
+impl Error {
+    /// Adapter for `map_err` that records which path an IO operation failed on.
+    pub(crate) fn io(message: &'static str, path: &Path) -> impl FnOnce(std::io::Error) -> Error {
+        let path = path.to_path_buf();
+        move |source| Error::Io { message, path, source }
+    }
+
+    /// Process exit status for this error: the validation outcome, or 1.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Validation { exit_code, .. } => *exit_code,
+            _ => 1,
+        }
+    }
+}
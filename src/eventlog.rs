@@ -0,0 +1,175 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::report::ValidationReport;
+use crate::{now_secs, Manifest};
+
+/// One line of the fixity event log.
+#[derive(Serialize, Debug)]
+struct FixityEvent<'a> {
+    timestamp: i64,
+    host: &'a str,
+    manifest: &'a Path,
+    archive: &'a Path,
+    path: &'a Path,
+    result: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expected_hash: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    actual_hash: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    renamed_from: Option<&'a Path>,
+}
+
+impl<'a> FixityEvent<'a> {
+    fn new(timestamp: i64, host: &'a str, report: &'a ValidationReport, path: &'a Path, result: &'static str) -> Self {
+        FixityEvent {
+            timestamp,
+            host,
+            manifest: &report.manifest,
+            archive: &report.archive,
+            path,
+            result,
+            expected_hash: None,
+            actual_hash: None,
+            renamed_from: None,
+        }
+    }
+}
+
+/// Name of this machine, for attributing events in a log shared between hosts.
+pub fn hostname() -> String {
+    std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| fs::read_to_string("/etc/hostname").ok())
+        .or_else(|| std::env::var("COMPUTERNAME").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Append one JSON line per validation outcome to `log_path`. The file is only
+/// ever opened for appending, so earlier history is never rewritten.
+pub fn append_events(log_path: &Path, report: &ValidationReport, manifest: &Manifest) -> Result<()> {
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_path)
+        .with_context(|| format!("Failed to open event log: {}", log_path.display()))?;
+    let mut writer = BufWriter::new(file);
+    let timestamp = now_secs();
+    let host = hostname();
+
+    let event = |path, result| FixityEvent::new(timestamp, &host, report, path, result);
+    let mut write = |event: FixityEvent| -> Result<()> {
+        serde_json::to_writer(&mut writer, &event)?;
+        writeln!(writer)?;
+        Ok(())
+    };
+
+    for path in &report.valid {
+        let hash = manifest.entries.get(path).map(|e| e.hash.as_str());
+        write(FixityEvent { expected_hash: hash, actual_hash: hash, ..event(path, "valid") })?;
+    }
+    for path in &report.assumed_valid {
+        let hash = manifest.entries.get(path).map(|e| e.hash.as_str());
+        write(FixityEvent { expected_hash: hash, ..event(path, "assumed_valid") })?;
+    }
+    for (result, entries) in [
+        ("mismatch", &report.mismatched),
+        ("metadata_changed", &report.metadata_changed),
+        ("missing", &report.missing),
+        ("new", &report.new),
+    ] {
+        for entry in entries {
+            write(FixityEvent {
+                expected_hash: entry.expected_hash.as_deref(),
+                actual_hash: entry.actual_hash.as_deref(),
+                ..event(&entry.path, result)
+            })?;
+        }
+    }
+    for entry in &report.renamed {
+        write(FixityEvent {
+            expected_hash: Some(&entry.hash),
+            actual_hash: Some(&entry.hash),
+            renamed_from: Some(&entry.from),
+            ..event(&entry.to, "renamed")
+        })?;
+    }
+    for entry in &report.repaired {
+        write(FixityEvent {
+            expected_hash: Some(&entry.hash),
+            actual_hash: Some(&entry.hash),
+            ..event(&entry.path, "repaired")
+        })?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Kind of change made to a manifest entry by update mode.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeAction {
+    Added,
+    Updated,
+    Removed,
+    Renamed,
+}
+
+/// One change to a manifest entry, as recorded in the change journal.
+#[derive(Serialize, Debug, Clone)]
+pub struct ManifestChange {
+    pub action: ChangeAction,
+    pub path: PathBuf,
+    /// Previous path of a renamed entry
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_hash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_hash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct JournalLine<'a> {
+    timestamp: i64,
+    host: &'a str,
+    manifest: &'a Path,
+    #[serde(flatten)]
+    change: &'a ManifestChange,
+}
+
+/// Append the changes made by one update run to the journal at `journal_path`,
+/// one JSON line each. Like the event log, the journal is only appended to.
+pub fn append_journal(journal_path: &Path, manifest_path: &Path, changes: &[ManifestChange]) -> Result<()> {
+    if changes.is_empty() {
+        return Ok(());
+    }
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(journal_path)
+        .with_context(|| format!("Failed to open journal: {}", journal_path.display()))?;
+    let mut writer = BufWriter::new(file);
+    let timestamp = now_secs();
+    let host = hostname();
+    for change in changes {
+        let line = JournalLine {
+            timestamp,
+            host: &host,
+            manifest: manifest_path,
+            change,
+        };
+        serde_json::to_writer(&mut writer, &line)?;
+        writeln!(writer)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
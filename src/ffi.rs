@@ -0,0 +1,161 @@
+//! C ABI for embedding the crate in C and C++ programs. The matching header
+//! is `include/data_manifest.h`.
+//!
+//! Every function returns the command line's exit status: 0 on success, 2 or
+//! 3 when validation fails (see the validation policy), and 1 for any other
+//! error. The message of the last error on the calling thread is available
+//! from [`dm_last_error`].
+
+use std::cell::RefCell;
+use std::ffi::{c_char, c_void, CStr, CString, OsString};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use clap::Parser;
+
+use crate::{run, set_progress_sink, Args, Error, ProgressSink};
+
+/// Called after each file is processed with the number of files done so far,
+/// the number of files in the pass, and the file's path.
+pub type DmProgressFn = Option<extern "C" fn(user_data: *mut c_void, done: u64, total: u64, path: *const c_char)>;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+struct CallbackProgress {
+    callback: extern "C" fn(*mut c_void, u64, u64, *const c_char),
+    // Only passed back to the caller's callback
+    user_data: usize,
+    total: AtomicU64,
+    done: AtomicU64,
+}
+
+impl ProgressSink for CallbackProgress {
+    fn started(&self, total_files: u64, completed: u64) {
+        self.total.store(total_files, Ordering::Relaxed);
+        self.done.store(completed, Ordering::Relaxed);
+    }
+
+    fn file_finished(&self, path: &Path) {
+        let done = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+        let path = CString::new(path.to_string_lossy().as_bytes()).unwrap_or_default();
+        (self.callback)(self.user_data as *mut c_void, done, self.total.load(Ordering::Relaxed), path.as_ptr());
+    }
+
+    fn error(&self, path: &Path, _message: &str) {
+        self.file_finished(path);
+    }
+}
+
+/// Read a required C string argument.
+unsafe fn arg(value: *const c_char, name: &str) -> Result<OsString> {
+    if value.is_null() {
+        return Err(Error::Config(format!("{} must not be null", name)).into());
+    }
+    let value = CStr::from_ptr(value).to_str().with_context(|| format!("{} is not valid UTF-8", name))?;
+    Ok(value.into())
+}
+
+/// Parse the command line `data-manifest -a <archive> -o <manifest> <mode...> <extra...>`
+/// and run it with `progress` installed as the progress sink.
+unsafe fn run_ffi(
+    archive: *const c_char,
+    manifest: *const c_char,
+    mode: &[&str],
+    extra_args: *const *const c_char,
+    extra_count: usize,
+    progress: DmProgressFn,
+    user_data: *mut c_void,
+) -> Result<()> {
+    let mut argv: Vec<OsString> = vec!["data-manifest".into(), "-a".into(), arg(archive, "archive")?, "-o".into(), arg(manifest, "manifest")?];
+    argv.extend(mode.iter().map(OsString::from));
+    if extra_count > 0 {
+        if extra_args.is_null() {
+            return Err(Error::Config("extra_args must not be null".into()).into());
+        }
+        for i in 0..extra_count {
+            argv.push(arg(*extra_args.add(i), "extra argument")?);
+        }
+    }
+    let args = Args::try_parse_from(argv).map_err(|e| Error::Config(e.to_string()))?;
+
+    if let Some(callback) = progress {
+        set_progress_sink(Some(Arc::new(CallbackProgress {
+            callback,
+            user_data: user_data as usize,
+            total: AtomicU64::new(0),
+            done: AtomicU64::new(0),
+        })));
+    }
+    let result = run(&args);
+    if progress.is_some() {
+        set_progress_sink(None);
+    }
+    result
+}
+
+/// Record the outcome of a call and turn it into an exit status.
+fn finish(result: std::thread::Result<Result<()>>) -> i32 {
+    let (status, message) = match result {
+        Ok(Ok(())) => (0, None),
+        Ok(Err(e)) => (e.downcast_ref::<Error>().map_or(1, Error::exit_code), Some(format!("{:#}", e))),
+        Err(_) => (1, Some("data-manifest panicked".to_string())),
+    };
+    LAST_ERROR.with(|last| *last.borrow_mut() = message.and_then(|m| CString::new(m).ok()));
+    status
+}
+
+/// Generate a manifest of `archive` at `manifest`.
+///
+/// `extra_args` holds `extra_count` further command-line options (for example
+/// `"--extended"`); it may be null when `extra_count` is 0. `progress` may be
+/// null. Progress is reported through the process-wide progress sink, so
+/// calls with a callback should not overlap.
+///
+/// # Safety
+///
+/// `archive` and `manifest` must be valid NUL-terminated strings and
+/// `extra_args` must point to `extra_count` of them.
+#[no_mangle]
+pub unsafe extern "C" fn dm_generate_manifest(
+    archive: *const c_char,
+    manifest: *const c_char,
+    extra_args: *const *const c_char,
+    extra_count: usize,
+    progress: DmProgressFn,
+    user_data: *mut c_void,
+) -> i32 {
+    finish(std::panic::catch_unwind(|| {
+        run_ffi(archive, manifest, &[], extra_args, extra_count, progress, user_data)
+    }))
+}
+
+/// Validate `archive` against `manifest`. Arguments are as for
+/// [`dm_generate_manifest`].
+///
+/// # Safety
+///
+/// As for [`dm_generate_manifest`].
+#[no_mangle]
+pub unsafe extern "C" fn dm_validate_manifest(
+    archive: *const c_char,
+    manifest: *const c_char,
+    extra_args: *const *const c_char,
+    extra_count: usize,
+    progress: DmProgressFn,
+    user_data: *mut c_void,
+) -> i32 {
+    finish(std::panic::catch_unwind(|| {
+        run_ffi(archive, manifest, &["--validate"], extra_args, extra_count, progress, user_data)
+    }))
+}
+
+/// The message of the last failed call on this thread, or null if the last
+/// call succeeded. The string stays valid until the next call on this thread.
+#[no_mangle]
+pub extern "C" fn dm_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(std::ptr::null(), |m| m.as_ptr()))
+}
@@ -0,0 +1,317 @@
+//! Google Cloud Storage archives, addressed as `gs://bucket/prefix`, read
+//! through the JSON API.
+//!
+//! The access token is `GOOGLE_OAUTH_ACCESS_TOKEN`, or is obtained from
+//! `gcloud auth print-access-token`, or from the metadata server when running
+//! on Google Cloud. `STORAGE_EMULATOR_HOST` selects an emulator such as
+//! fake-gcs-server, which needs no token.
+
+use anyhow::{Context, Result};
+use base64::Engine;
+use serde::Deserialize;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::remote::{object_key, parse_timestamp, split_url, uri_encode, FetchRange, RangeOptions, RangeReader};
+use crate::{Args, Error, FileInfo, FileSource};
+
+/// Access tokens last an hour; refresh well before that
+const TOKEN_LIFETIME: Duration = Duration::from_secs(45 * 60);
+
+const METADATA_TOKEN_URL: &str = "http://metadata.google.internal/computeMetadata/v1/instance/service-accounts/default/token";
+
+enum TokenSource {
+    /// Emulators accept unauthenticated requests
+    None,
+    Fixed(String),
+    Gcloud,
+    Metadata,
+}
+
+/// Bearer tokens for requests, refreshed when they get old.
+struct Tokens {
+    source: TokenSource,
+    current: Mutex<Option<(String, Instant)>>,
+}
+
+impl Tokens {
+    fn from_env(emulated: bool) -> Self {
+        let mut current = None;
+        let source = if emulated {
+            TokenSource::None
+        } else if let Ok(token) = std::env::var("GOOGLE_OAUTH_ACCESS_TOKEN") {
+            TokenSource::Fixed(token)
+        } else if let Ok(token) = gcloud_token() {
+            current = Some((token, Instant::now()));
+            TokenSource::Gcloud
+        } else {
+            TokenSource::Metadata
+        };
+        Tokens {
+            source,
+            current: Mutex::new(current),
+        }
+    }
+
+    fn get(&self, agent: &ureq::Agent) -> Result<Option<String>> {
+        match self.source {
+            TokenSource::None => Ok(None),
+            TokenSource::Fixed(ref token) => Ok(Some(token.clone())),
+            TokenSource::Gcloud => self.cached(gcloud_token).map(Some),
+            TokenSource::Metadata => self.cached(|| metadata_token(agent)).map(Some),
+        }
+    }
+
+    fn cached(&self, fetch: impl FnOnce() -> Result<String>) -> Result<String> {
+        let mut current = self.current.lock().unwrap_or_else(|e| e.into_inner());
+        match *current {
+            Some((ref token, fetched)) if fetched.elapsed() < TOKEN_LIFETIME => Ok(token.clone()),
+            _ => {
+                let token = fetch()?;
+                *current = Some((token.clone(), Instant::now()));
+                Ok(token)
+            }
+        }
+    }
+}
+
+fn gcloud_token() -> Result<String> {
+    let output = std::process::Command::new("gcloud")
+        .args(["auth", "print-access-token"])
+        .stderr(std::process::Stdio::null())
+        .output()
+        .map_err(|_| Error::Config("No GOOGLE_OAUTH_ACCESS_TOKEN and gcloud is not installed".into()))?;
+    if !output.status.success() {
+        return Err(Error::Config("gcloud auth print-access-token failed; run gcloud auth login".into()).into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn metadata_token(agent: &ureq::Agent) -> Result<String> {
+    #[derive(Deserialize)]
+    struct Token {
+        access_token: String,
+    }
+    let body = agent
+        .get(METADATA_TOKEN_URL)
+        .set("Metadata-Flavor", "Google")
+        .timeout(Duration::from_secs(5))
+        .call()
+        .map_err(|_| Error::Config("No Google Cloud credentials: set GOOGLE_OAUTH_ACCESS_TOKEN or install gcloud".into()))?
+        .into_string()?;
+    let token: Token = serde_json::from_str(&body).context("Invalid metadata server token response")?;
+    Ok(token.access_token)
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ObjectList {
+    #[serde(default)]
+    items: Vec<ListedObject>,
+    next_page_token: Option<String>,
+}
+
+/// An object as listed, with the checksums GCS computed when it was written.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ListedObject {
+    pub name: String,
+    /// The JSON API sends 64-bit integers as strings
+    #[serde(deserialize_with = "string_number")]
+    pub size: u64,
+    pub updated: Option<String>,
+    /// Base64 MD5 of the content; composite objects have none
+    pub md5_hash: Option<String>,
+    /// Base64 big-endian CRC32C of the content
+    pub crc32c: Option<String>,
+}
+
+fn string_number<'de, D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<u64, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    value.parse().map_err(serde::de::Error::custom)
+}
+
+/// Decode a base64 `md5Hash` into hex.
+pub(crate) fn decode_md5(value: &str) -> Option<String> {
+    let bytes = base64::engine::general_purpose::STANDARD.decode(value).ok()?;
+    Some(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Decode a base64 big-endian `crc32c`.
+pub(crate) fn decode_crc32c(value: &str) -> Option<u32> {
+    let bytes: [u8; 4] = base64::engine::general_purpose::STANDARD.decode(value).ok()?.try_into().ok()?;
+    Some(u32::from_be_bytes(bytes))
+}
+
+/// A client for one bucket.
+pub(crate) struct GcsClient {
+    agent: ureq::Agent,
+    bucket: String,
+    endpoint: String,
+    tokens: Tokens,
+}
+
+impl GcsClient {
+    pub fn new(bucket: &str) -> Result<Self> {
+        let emulator = std::env::var("STORAGE_EMULATOR_HOST").ok().map(|host| {
+            let host = host.trim_end_matches('/');
+            if host.contains("://") { host.to_string() } else { format!("http://{}", host) }
+        });
+        Ok(GcsClient {
+            agent: ureq::AgentBuilder::new().build(),
+            bucket: bucket.to_string(),
+            tokens: Tokens::from_env(emulator.is_some()),
+            endpoint: emulator.unwrap_or_else(|| "https://storage.googleapis.com".to_string()),
+        })
+    }
+
+    fn location(&self, name: &str) -> String {
+        format!("gs://{}/{}", self.bucket, name)
+    }
+
+    /// An authorized request to the JSON API. `object` is empty for the
+    /// bucket's object collection.
+    fn request(&self, method: &str, object: &str, query: &[(&str, &str)]) -> Result<ureq::Request> {
+        let mut url = format!("{}/storage/v1/b/{}/o", self.endpoint, uri_encode(&self.bucket, false));
+        if !object.is_empty() {
+            url.push('/');
+            url.push_str(&uri_encode(object, false));
+        }
+        let mut request = self.agent.request(method, &url);
+        for (k, v) in query {
+            request = request.query(k, v);
+        }
+        if let Some(token) = self.tokens.get(&self.agent)? {
+            request = request.set("authorization", &format!("Bearer {}", token));
+        }
+        Ok(request)
+    }
+
+    /// Every object under `prefix`.
+    pub fn list(&self, prefix: &str, args: &Args) -> Result<Vec<ListedObject>> {
+        let mut objects = Vec::new();
+        let mut token: Option<String> = None;
+        loop {
+            let mut query = vec![("prefix", prefix), ("fields", "items(name,size,updated,md5Hash,crc32c),nextPageToken")];
+            if let Some(ref token) = token {
+                query.push(("pageToken", token.as_str()));
+            }
+            let body = self
+                .request("GET", "", &query)?
+                .call()
+                .map_err(|e| request_error(e, &self.location(prefix)))?
+                .into_string()?;
+            let page: ObjectList = serde_json::from_str(&body).context("Invalid object listing")?;
+            objects.extend(page.items);
+            match page.next_page_token {
+                Some(next) if !args.cancel.is_cancelled() => token = Some(next),
+                _ => break,
+            }
+        }
+        Ok(objects)
+    }
+
+    /// Bytes `start..end` of `name`.
+    fn get_range(&self, name: &str, start: u64, end: u64) -> io::Result<Vec<u8>> {
+        let range = format!("bytes={}-{}", start, end - 1);
+        let response = self
+            .request("GET", name, &[("alt", "media")])
+            .map_err(|e| io::Error::other(format!("{:#}", e)))?
+            .set("range", &range)
+            .call()
+            .map_err(|e| io_error(e, &self.location(name)))?;
+        let mut data = Vec::with_capacity((end - start) as usize);
+        response.into_reader().read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    fn object_size(&self, name: &str) -> io::Result<u64> {
+        let body = self
+            .request("GET", name, &[("fields", "name,size")])
+            .map_err(|e| io::Error::other(format!("{:#}", e)))?
+            .call()
+            .map_err(|e| io_error(e, &self.location(name)))?
+            .into_string()?;
+        let object: ListedObject = serde_json::from_str(&body).map_err(io::Error::other)?;
+        Ok(object.size)
+    }
+}
+
+/// Turn a failed request into an error that includes the service's message.
+fn request_error(error: ureq::Error, what: &str) -> anyhow::Error {
+    match error {
+        ureq::Error::Status(code, response) => {
+            let body = response.into_string().unwrap_or_default();
+            let message = serde_json::from_str::<serde_json::Value>(&body)
+                .ok()
+                .and_then(|v| v["error"]["message"].as_str().map(str::to_string))
+                .unwrap_or_else(|| body.trim().to_string());
+            anyhow::anyhow!("Request for {} failed with HTTP {}: {}", what, code, message)
+        }
+        e => anyhow::Error::new(e).context(format!("Request for {} failed", what)),
+    }
+}
+
+/// Like [`request_error`], keeping "not found" recognisable for validation.
+fn io_error(error: ureq::Error, what: &str) -> io::Error {
+    match error {
+        ureq::Error::Status(404, _) => io::Error::new(io::ErrorKind::NotFound, format!("{} not found", what)),
+        e => io::Error::other(format!("{:#}", request_error(e, what))),
+    }
+}
+
+/// Objects under a `gs://bucket/prefix` URL, read with concurrent range GETs.
+pub struct GcsSource {
+    client: Arc<GcsClient>,
+    prefix: String,
+    ranges: RangeOptions,
+    /// Sizes from the listing, so objects can be read without a metadata request
+    sizes: Mutex<std::collections::HashMap<PathBuf, u64>>,
+}
+
+impl GcsSource {
+    pub fn open(url: &str, args: &Args) -> Result<Self> {
+        let (bucket, prefix) = split_url(url)?;
+        Ok(GcsSource {
+            client: Arc::new(GcsClient::new(bucket)?),
+            prefix: prefix.to_string(),
+            ranges: RangeOptions::from_args(args)?,
+            sizes: Default::default(),
+        })
+    }
+}
+
+impl FileSource for GcsSource {
+    fn files(&self, args: &Args) -> Result<Vec<FileInfo>> {
+        let list_prefix = if self.prefix.is_empty() { String::new() } else { format!("{}/", self.prefix) };
+        let mut files = Vec::new();
+        let mut sizes = self.sizes.lock().unwrap();
+        for object in self.client.list(&list_prefix, args)? {
+            // Folder placeholders created by the console
+            if object.name.ends_with('/') {
+                continue;
+            }
+            let path = PathBuf::from(&object.name[list_prefix.len()..]);
+            sizes.insert(path.clone(), object.size);
+            files.push(FileInfo {
+                path,
+                size: object.size,
+                mtime: object.updated.as_deref().and_then(parse_timestamp),
+            });
+        }
+        Ok(files)
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send>> {
+        let name = object_key(&self.prefix, path);
+        let size = match self.sizes.lock().unwrap().get(path) {
+            Some(&size) => size,
+            None => self.client.object_size(&name)?,
+        };
+        let client = Arc::clone(&self.client);
+        let fetch: FetchRange = Arc::new(move |start, end| client.get_range(&name, start, end));
+        Ok(Box::new(RangeReader::new(fetch, size, self.ranges)))
+    }
+}
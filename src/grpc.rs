@@ -0,0 +1,225 @@
+//! The gRPC job API of `data-manifest serve --grpc`, defined in
+//! `proto/data_manifest.proto`. It shares its jobs with the HTTP API and,
+//! like it, requires `DATA_MANIFEST_SERVE_TOKEN` as a bearer token unless
+//! the service was started with `--no-token`.
+
+// tonic::Status is the error type of every handler and interceptor
+#![allow(clippy::result_large_err)]
+
+use anyhow::{Context, Result};
+use std::io::Read;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream, UnboundedReceiverStream};
+use tonic::{Request, Response, Status};
+
+use crate::jobs::{self, Job, JobEvent, JobRequest, Jobs};
+use crate::{CancellationToken, Error};
+
+mod proto {
+    tonic::include_proto!("data_manifest.v1");
+}
+
+use proto::job_event::Event;
+use proto::job_service_server::{JobService, JobServiceServer};
+
+const MANIFEST_CHUNK_SIZE: usize = 1 << 20;
+
+fn status_code(status: jobs::Status) -> i32 {
+    let status = match status {
+        jobs::Status::Queued => proto::Status::Queued,
+        jobs::Status::Running => proto::Status::Running,
+        jobs::Status::Passed => proto::Status::Passed,
+        jobs::Status::Failed => proto::Status::Failed,
+        jobs::Status::Error => proto::Status::Error,
+        jobs::Status::Cancelled => proto::Status::Cancelled,
+    };
+    status as i32
+}
+
+fn job_message(job: &Job) -> proto::Job {
+    let status = job.status();
+    let mode = match status.request.mode {
+        jobs::Mode::Generate => proto::Mode::Generate,
+        jobs::Mode::Update => proto::Mode::Update,
+        jobs::Mode::Validate => proto::Mode::Validate,
+    };
+    proto::Job {
+        id: status.id,
+        mode: mode as i32,
+        archive: status.request.archive.clone(),
+        manifest: status.request.manifest.display().to_string(),
+        archive_name: status.request.archive_name.clone().unwrap_or_default(),
+        options: status.request.options.clone(),
+        status: status_code(status.status),
+        submitted: status.submitted,
+        started: status.started.unwrap_or(0),
+        finished: status.finished.unwrap_or(0),
+        total_files: status.total_files,
+        files_done: status.files_done,
+        errors: status.errors,
+        bytes_hashed: status.bytes_hashed,
+        error: status.error.unwrap_or_default(),
+    }
+}
+
+fn event_message(event: &JobEvent) -> proto::JobEvent {
+    let event = match *event {
+        JobEvent::Started { total_files, completed } => Event::Started(proto::Started { total_files, completed }),
+        JobEvent::FileFinished { path } => Event::FileFinished(proto::FileFinished {
+            path: path.display().to_string(),
+        }),
+        JobEvent::FileError { path, message } => Event::FileError(proto::FileError {
+            path: path.display().to_string(),
+            message: message.to_string(),
+        }),
+        JobEvent::Finished { status, error } => Event::Finished(proto::Finished {
+            status: status_code(status),
+            error: error.unwrap_or_default().to_string(),
+        }),
+    };
+    proto::JobEvent { event: Some(event) }
+}
+
+struct Service {
+    jobs: Arc<Jobs>,
+}
+
+impl Service {
+    fn job(&self, id: u64) -> Result<Arc<Job>, Status> {
+        self.jobs.get(id).ok_or_else(|| Status::not_found(format!("No job {}", id)))
+    }
+}
+
+#[tonic::async_trait]
+impl JobService for Service {
+    async fn submit_job(&self, request: Request<proto::SubmitJobRequest>) -> Result<Response<proto::Job>, Status> {
+        let request = request.into_inner();
+        let mode = match proto::Mode::try_from(request.mode) {
+            Ok(proto::Mode::Generate) => jobs::Mode::Generate,
+            Ok(proto::Mode::Update) => jobs::Mode::Update,
+            Ok(proto::Mode::Validate) => jobs::Mode::Validate,
+            Err(_) => return Err(Status::invalid_argument(format!("Unknown mode {}", request.mode))),
+        };
+        let job_request = JobRequest {
+            mode,
+            archive: request.archive,
+            manifest: request.manifest.into(),
+            archive_name: Some(request.archive_name).filter(|name| !name.is_empty()),
+            options: request.options,
+        };
+        let job = self.jobs.submit(job_request).map_err(Status::invalid_argument)?;
+        Ok(Response::new(job_message(&job)))
+    }
+
+    async fn get_job(&self, request: Request<proto::JobId>) -> Result<Response<proto::Job>, Status> {
+        let job = self.job(request.into_inner().id)?;
+        Ok(Response::new(job_message(&job)))
+    }
+
+    async fn list_jobs(&self, _request: Request<proto::ListJobsRequest>) -> Result<Response<proto::ListJobsResponse>, Status> {
+        let jobs = self.jobs.list().iter().map(|job| job_message(job)).collect();
+        Ok(Response::new(proto::ListJobsResponse { jobs }))
+    }
+
+    async fn cancel_job(&self, request: Request<proto::JobId>) -> Result<Response<proto::Job>, Status> {
+        let job = self.job(request.into_inner().id)?;
+        job.cancel();
+        Ok(Response::new(job_message(&job)))
+    }
+
+    type WatchJobStream = UnboundedReceiverStream<Result<proto::JobEvent, Status>>;
+
+    async fn watch_job(&self, request: Request<proto::JobId>) -> Result<Response<Self::WatchJobStream>, Status> {
+        let job = self.job(request.into_inner().id)?;
+        // Unbounded, so a slow client never holds up hashing
+        let (sender, receiver) = mpsc::unbounded_channel();
+        job.subscribe(Box::new(move |event| sender.send(Ok(event_message(event))).is_ok()));
+        Ok(Response::new(UnboundedReceiverStream::new(receiver)))
+    }
+
+    async fn get_result(&self, request: Request<proto::JobId>) -> Result<Response<proto::JobResult>, Status> {
+        let job = self.job(request.into_inner().id)?;
+        let message = job_message(&job);
+        if message.finished == 0 {
+            return Err(Status::failed_precondition("The job has not finished"));
+        }
+        let report_json = job.report().map(|report| report.to_string()).unwrap_or_default();
+        Ok(Response::new(proto::JobResult {
+            job: Some(message),
+            report_json,
+        }))
+    }
+
+    type GetManifestStream = ReceiverStream<Result<proto::ManifestChunk, Status>>;
+
+    async fn get_manifest(&self, request: Request<proto::JobId>) -> Result<Response<Self::GetManifestStream>, Status> {
+        let job = self.job(request.into_inner().id)?;
+        if job.is_running() {
+            return Err(Status::failed_precondition("The job is still running"));
+        }
+        let mut file = job.open_manifest().map_err(|_| Status::not_found("The job left no manifest"))?;
+        let (sender, receiver) = mpsc::channel(4);
+        tokio::task::spawn_blocking(move || {
+            let mut buffer = vec![0; MANIFEST_CHUNK_SIZE];
+            loop {
+                let chunk = match file.read(&mut buffer) {
+                    Ok(0) => return,
+                    Ok(n) => Ok(proto::ManifestChunk { data: buffer[..n].to_vec() }),
+                    Err(e) => Err(Status::internal(format!("Failed to read the manifest: {}", e))),
+                };
+                let failed = chunk.is_err();
+                if sender.blocking_send(chunk).is_err() || failed {
+                    return;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+}
+
+/// Serve the gRPC job API on `listen` from a background thread until
+/// `cancel` is cancelled.
+pub(crate) fn spawn(listen: &str, jobs: Arc<Jobs>, token: Option<String>, cancel: CancellationToken) -> Result<()> {
+    let addr: SocketAddr = listen
+        .parse()
+        .map_err(|_| Error::Config(format!("Invalid --grpc address (expected host:port): {}", listen)))?;
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .context("Failed to start the gRPC runtime")?;
+    let listener = runtime
+        .block_on(tokio::net::TcpListener::bind(addr))
+        .map_err(|e| Error::Config(format!("Failed to listen on {}: {}", listen, e)))?;
+
+    let expected = token.map(|token| format!("Bearer {}", token));
+    let check_token = move |request: Request<()>| match expected {
+        Some(ref expected) if !request.metadata().get("authorization").and_then(|v| v.to_str().ok()).is_some_and(|v| crate::serve::token_matches(v, expected)) => {
+            Err(Status::unauthenticated("Missing or invalid bearer token"))
+        }
+        _ => Ok(request),
+    };
+    let service = JobServiceServer::with_interceptor(Service { jobs }, check_token);
+    let shutdown = async move {
+        while !cancel.is_cancelled() {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+    };
+    std::thread::Builder::new()
+        .name("grpc".into())
+        .spawn(move || {
+            let served = runtime.block_on(
+                tonic::transport::Server::builder()
+                    .add_service(service)
+                    .serve_with_incoming_shutdown(TcpListenerStream::new(listener), shutdown),
+            );
+            if let Err(e) = served {
+                eprintln!("gRPC server failed: {}", e);
+            }
+        })
+        .context("Failed to start the gRPC server")?;
+    println!("Serving gRPC on {}", listen);
+    Ok(())
+}
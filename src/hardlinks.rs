@@ -0,0 +1,95 @@
+//! Hard links: several paths naming the same file, as in Maildir trees
+//! where a message is linked into many folders. Generate and update read the
+//! contents of such a file once and record the hash for each of its paths.
+//!
+//! With `--hardlink-groups`, a `link` column numbers the groups of paths
+//! linked to each other within the archive, in path order, so that the
+//! links can be recreated when restoring from the manifest; `-` marks files
+//! with a single path. `--update` renumbers the groups of a manifest that
+//! records them.
+//!
+//! Links are recognized by device and inode number on Unix. Other platforms
+//! read each path.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use crate::{manifest_key, FileInfo};
+
+/// Device and inode number of a file with more than one link.
+#[cfg(unix)]
+fn link_id(file_info: &FileInfo) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    // Container members do not exist on disk and are skipped
+    let metadata = std::fs::metadata(&file_info.path).ok()?;
+    (metadata.nlink() > 1).then(|| (metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn link_id(_file_info: &FileInfo) -> Option<(u64, u64)> {
+    None
+}
+
+/// Split `files` into those to hash and further links to a file among
+/// them, each paired with the file whose hash it shares.
+pub(crate) fn split(files: Vec<&FileInfo>) -> (Vec<&FileInfo>, Vec<(&FileInfo, &FileInfo)>) {
+    let mut first = HashMap::new();
+    let mut to_hash = Vec::with_capacity(files.len());
+    let mut links = Vec::new();
+    for file_info in files {
+        match link_id(file_info) {
+            Some(id) => match first.get(&id) {
+                Some(&target) => links.push((file_info, target)),
+                None => {
+                    first.insert(id, file_info);
+                    to_hash.push(file_info);
+                }
+            },
+            None => to_hash.push(file_info),
+        }
+    }
+    (to_hash, links)
+}
+
+/// The entries of further `links`, taken from the entries in `hashed` of
+/// the files they link to. Links to files that failed to hash are left out.
+pub(crate) fn link_entries<'a, T: Clone>(
+    hashed: impl IntoIterator<Item = (&'a PathBuf, T)>,
+    links: &[(&FileInfo, &FileInfo)],
+    archive_path: &Path,
+    archive_name: &str,
+) -> Vec<(PathBuf, T)> {
+    if links.is_empty() {
+        return Vec::new();
+    }
+    let hashed: HashMap<&PathBuf, T> = hashed.into_iter().collect();
+    links
+        .iter()
+        .filter_map(|(link, target)| {
+            let entry = hashed.get(&manifest_key(&target.path, archive_path, archive_name))?;
+            Some((manifest_key(&link.path, archive_path, archive_name), entry.clone()))
+        })
+        .collect()
+}
+
+/// The number of the link group of each of `files` that is linked to
+/// another of them, by manifest path. Groups are numbered from 1 in the
+/// order of their first paths.
+pub(crate) fn groups(files: &[FileInfo], archive_path: &Path, archive_name: &str) -> HashMap<PathBuf, u64> {
+    let mut by_id: HashMap<(u64, u64), Vec<PathBuf>> = HashMap::new();
+    for file_info in files {
+        if let Some(id) = link_id(file_info) {
+            by_id.entry(id).or_default().push(manifest_key(&file_info.path, archive_path, archive_name));
+        }
+    }
+    let ordered: BTreeMap<PathBuf, Vec<PathBuf>> = by_id
+        .into_values()
+        .filter(|paths| paths.len() > 1)
+        .map(|paths| (paths.iter().min().cloned().unwrap_or_default(), paths))
+        .collect();
+    ordered
+        .into_values()
+        .zip(1..)
+        .flat_map(|(paths, group)| paths.into_iter().map(move |path| (path, group)))
+        .collect()
+}
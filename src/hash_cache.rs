@@ -0,0 +1,141 @@
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::{get_file_hash, xattrs, FileInfo};
+
+/// Identity of a file's content as far as the filesystem can tell without
+/// reading it: device, inode, size, and mtime in nanoseconds.
+type CacheKey = (u64, u64, u64, i64);
+
+/// Hashes computed by earlier runs, so unchanged files need not be re-read.
+struct HashCache {
+    path: PathBuf,
+    entries: Mutex<HashMap<CacheKey, String>>,
+    dirty: std::sync::atomic::AtomicBool,
+}
+
+/// The cache of the current run, if any
+static CACHE: RwLock<Option<Arc<HashCache>>> = RwLock::new(None);
+
+fn current() -> Option<Arc<HashCache>> {
+    CACHE.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Whether a hash cache is in use.
+#[cfg(test)]
+pub(crate) fn enabled() -> bool {
+    current().is_some()
+}
+
+/// `$XDG_CACHE_HOME/data-manifest/hash-cache.tsv`, falling back to `~/.cache`.
+pub fn default_path() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+    Some(base.join("data-manifest").join("hash-cache.tsv"))
+}
+
+/// Load the cache file (if it exists) and enable cached hashing for this run,
+/// replacing the cache of an earlier run.
+pub fn enable(path: &Path) -> Result<()> {
+    disable();
+    let mut entries = HashMap::new();
+    if path.exists() {
+        let file = fs::File::open(path)
+            .with_context(|| format!("Failed to open hash cache: {}", path.display()))?;
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            let parts: Vec<&str> = line.split('\t').collect();
+            let [dev, inode, size, mtime, hash] = parts[..] else { continue };
+            let (Ok(dev), Ok(inode), Ok(size), Ok(mtime)) = (dev.parse(), inode.parse(), size.parse(), mtime.parse())
+            else {
+                continue;
+            };
+            entries.insert((dev, inode, size, mtime), hash.to_string());
+        }
+    }
+    *CACHE.write().unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(HashCache {
+        path: path.to_path_buf(),
+        entries: Mutex::new(entries),
+        dirty: std::sync::atomic::AtomicBool::new(false),
+    }));
+    Ok(())
+}
+
+/// Hash without a cache for this run.
+pub fn disable() {
+    *CACHE.write().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+#[cfg(unix)]
+fn cache_key(file_info: &FileInfo) -> Option<CacheKey> {
+    use std::os::unix::fs::MetadataExt;
+    let metadata = fs::metadata(&file_info.path).ok()?;
+    Some((metadata.dev(), metadata.ino(), file_info.size, file_info.mtime?))
+}
+
+#[cfg(not(unix))]
+fn cache_key(_file_info: &FileInfo) -> Option<CacheKey> {
+    None
+}
+
+/// Hash a file, reusing the hash stamped on it with `--trust-xattr`, or a
+/// cached hash when its device, inode, size, and mtime are unchanged. Falls
+/// through to a plain hash when no cache is enabled. Other hashes are
+/// stamped on the file with `--write-xattr`. Validation never uses this: its
+/// job is to re-read the data. Returns the hash and whether it came from a
+/// cache rather than the file contents.
+pub fn cached_file_hash(file_info: &FileInfo, buffer_size: usize) -> Result<(String, bool)> {
+    if let Some(hash) = xattrs::stamped_hash(file_info) {
+        return Ok((hash, true));
+    }
+    let (hash, from_cache) = lookup_file_hash(file_info, buffer_size)?;
+    xattrs::stamp(file_info, &hash);
+    Ok((hash, from_cache))
+}
+
+fn lookup_file_hash(file_info: &FileInfo, buffer_size: usize) -> Result<(String, bool)> {
+    let Some(cache) = current() else {
+        return Ok((get_file_hash(file_info, buffer_size)?, false));
+    };
+    let Some(key) = cache_key(file_info) else {
+        return Ok((get_file_hash(file_info, buffer_size)?, false));
+    };
+    if let Some(hash) = cache.entries.lock().unwrap_or_else(|e| e.into_inner()).get(&key) {
+        return Ok((hash.clone(), true));
+    }
+    let hash = get_file_hash(file_info, buffer_size)?;
+    cache.entries.lock().unwrap_or_else(|e| e.into_inner()).insert(key, hash.clone());
+    cache.dirty.store(true, std::sync::atomic::Ordering::Relaxed);
+    Ok((hash, false))
+}
+
+/// Write the cache back if this run added anything. The file is replaced
+/// atomically so a crash never leaves a truncated cache.
+pub fn save() -> Result<()> {
+    let Some(cache) = current() else { return Ok(()) };
+    if !cache.dirty.load(std::sync::atomic::Ordering::Relaxed) {
+        return Ok(());
+    }
+    if let Some(parent) = cache.path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create cache directory: {}", parent.display()))?;
+    }
+    let temp = cache.path.with_extension("tsv.tmp");
+    let file = fs::File::create(&temp)
+        .with_context(|| format!("Failed to write hash cache: {}", temp.display()))?;
+    let mut writer = BufWriter::new(file);
+    let entries = cache.entries.lock().unwrap_or_else(|e| e.into_inner());
+    for ((dev, inode, size, mtime), hash) in entries.iter() {
+        writeln!(writer, "{}\t{}\t{}\t{}\t{}", dev, inode, size, mtime, hash)?;
+    }
+    writer.flush()?;
+    drop(writer);
+    fs::rename(&temp, &cache.path)
+        .with_context(|| format!("Failed to replace hash cache: {}", cache.path.display()))?;
+    Ok(())
+}
@@ -0,0 +1,264 @@
+//! `--report html`: a self-contained HTML page summarizing a generation or
+//! validation run, for readers who do not work with manifests or JSON. It
+//! holds summary cards, tables of the failures, and a rollup of the files
+//! in each top-level directory of the archive. Styles are inline and there
+//! are no scripts, so the page can be attached to an audit as it is.
+//!
+//! The page is written next to the manifest, with an `.html` extension.
+
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::report::{xml_escape as escape, ValidationReport};
+use crate::{format_utc, now_secs, Error, ManifestEntry};
+
+const STYLE: &str = "
+body { font-family: system-ui, sans-serif; margin: 2em auto; max-width: 72em; color: #222; }
+h1 { margin-bottom: 0.2em; }
+.meta { color: #666; margin-top: 0; }
+.cards { display: flex; flex-wrap: wrap; gap: 1em; margin: 1.5em 0; }
+.card { border: 1px solid #ddd; border-radius: 6px; padding: 0.8em 1.2em; min-width: 9em; }
+.card .value { font-size: 1.6em; font-weight: bold; }
+.card .label { color: #666; }
+.card.bad { border-color: #c62828; background: #fdecea; }
+.card.good { border-color: #2e7d32; background: #edf7ed; }
+table { border-collapse: collapse; width: 100%; margin-bottom: 2em; }
+th, td { text-align: left; padding: 0.3em 0.6em; border-bottom: 1px solid #eee; }
+th { background: #f5f5f5; }
+td.number, th.number { text-align: right; }
+td.path, td.hash { font-family: ui-monospace, monospace; word-break: break-all; }
+";
+
+/// Where the HTML report of the manifest at `manifest_path` is written.
+pub(crate) fn report_path(manifest_path: &Path) -> Result<PathBuf> {
+    let path = manifest_path.with_extension("html");
+    if path == manifest_path {
+        return Err(Error::Config(format!("--report html would overwrite the manifest: {}", manifest_path.display())).into());
+    }
+    Ok(path)
+}
+
+/// The top-level directory of the archive a manifest path lies in, or the
+/// archive itself for files in its root.
+fn directory(path: &Path) -> String {
+    let components: Vec<_> = path.components().collect();
+    let depth = if components.len() > 2 { 2 } else { 1 };
+    let directory: PathBuf = components.iter().take(depth).collect();
+    format!("{}/", directory.display())
+}
+
+struct Page {
+    writer: BufWriter<fs::File>,
+    path: PathBuf,
+}
+
+impl Page {
+    fn create(path: &Path, title: &str, subject: &str) -> Result<Self> {
+        let file = fs::File::create(path).with_context(|| format!("Failed to create HTML report: {}", path.display()))?;
+        let mut page = Page { writer: BufWriter::new(file), path: path.to_path_buf() };
+        writeln!(page.writer, "<!DOCTYPE html>")?;
+        writeln!(page.writer, "<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">")?;
+        writeln!(page.writer, "<title>{}: {}</title>", escape(title), escape(subject))?;
+        writeln!(page.writer, "<style>{}</style>\n</head>\n<body>", STYLE)?;
+        writeln!(page.writer, "<h1>{}</h1>", escape(title))?;
+        writeln!(page.writer, "<p class=\"meta\">{} &middot; {} UTC</p>", escape(subject), format_utc(now_secs()))?;
+        Ok(page)
+    }
+
+    /// Summary cards of `(label, value, class)`; the class is `good`, `bad`,
+    /// or empty.
+    fn cards(&mut self, cards: &[(&str, String, &str)]) -> Result<()> {
+        writeln!(self.writer, "<div class=\"cards\">")?;
+        for (label, value, class) in cards {
+            writeln!(
+                self.writer,
+                "<div class=\"card {}\"><div class=\"value\">{}</div><div class=\"label\">{}</div></div>",
+                class,
+                escape(value),
+                escape(label)
+            )?;
+        }
+        writeln!(self.writer, "</div>")?;
+        Ok(())
+    }
+
+    /// A table under `heading`, left out when there are no rows. Columns
+    /// whose heading starts with `#` hold numbers; `Path`, `From`, `To`, and
+    /// `Directory` columns hold paths, and those ending in `hash` hashes.
+    fn table(&mut self, heading: &str, columns: &[&str], rows: &[Vec<String>]) -> Result<()> {
+        if rows.is_empty() {
+            return Ok(());
+        }
+        let classes: Vec<&str> = columns
+            .iter()
+            .map(|column| match *column {
+                column if column.starts_with('#') => "number",
+                "Path" | "From" | "To" | "Directory" => "path",
+                column if column.to_lowercase().ends_with("hash") => "hash",
+                _ => "",
+            })
+            .collect();
+        writeln!(self.writer, "<h2>{} ({})</h2>\n<table>\n<tr>", escape(heading), rows.len())?;
+        for (column, class) in columns.iter().zip(&classes) {
+            writeln!(self.writer, "<th class=\"{}\">{}</th>", class, escape(column.trim_start_matches('#')))?;
+        }
+        writeln!(self.writer, "</tr>")?;
+        for row in rows {
+            write!(self.writer, "<tr>")?;
+            for (value, class) in row.iter().zip(&classes) {
+                write!(self.writer, "<td class=\"{}\">{}</td>", class, escape(value))?;
+            }
+            writeln!(self.writer, "</tr>")?;
+        }
+        writeln!(self.writer, "</table>")?;
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<PathBuf> {
+        writeln!(self.writer, "</body>\n</html>")?;
+        self.writer.flush()?;
+        Ok(self.path)
+    }
+}
+
+fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+/// Write the report of a generated manifest: the entries written, the
+/// files that failed to hash with their errors, and the special files found.
+/// Returns the path written.
+pub(crate) fn write_generate_report(
+    manifest_path: &Path,
+    archive_path: &Path,
+    entries: &[(PathBuf, ManifestEntry)],
+    failures: &[(PathBuf, String)],
+    special_files: usize,
+    elapsed: Duration,
+) -> Result<PathBuf> {
+    let mut page = Page::create(
+        &report_path(manifest_path)?,
+        "Manifest generation report",
+        &format!("{} from {}", manifest_path.display(), archive_path.display()),
+    )?;
+
+    let total_bytes: u64 = entries.iter().filter_map(|(_, entry)| entry.size).sum();
+    let error_class = if failures.is_empty() { "good" } else { "bad" };
+    page.cards(&[
+        ("Files recorded", entries.len().to_string(), ""),
+        ("Bytes", total_bytes.to_string(), ""),
+        ("Errors", failures.len().to_string(), error_class),
+        ("Special files", special_files.to_string(), ""),
+        ("Duration", format!("{:.2?}", elapsed), ""),
+    ])?;
+
+    let failures: Vec<Vec<String>> = failures
+        .iter()
+        .map(|(path, error)| vec![path.display().to_string(), error.clone()])
+        .collect();
+    page.table("Files that failed to hash", &["Path", "Error"], &failures)?;
+
+    let mut rollup: BTreeMap<String, (usize, u64)> = BTreeMap::new();
+    for (path, entry) in entries {
+        let totals = rollup.entry(directory(path)).or_default();
+        totals.0 += 1;
+        totals.1 += entry.size.unwrap_or(0);
+    }
+    let rollup: Vec<Vec<String>> = rollup
+        .into_iter()
+        .map(|(directory, (files, bytes))| vec![directory, files.to_string(), bytes.to_string()])
+        .collect();
+    page.table("Directories", &["Directory", "#Files", "#Bytes"], &rollup)?;
+    page.finish()
+}
+
+/// Write the report of a finished validation: the outcome, the files in
+/// each problem category, and the outcome per directory. Returns the path
+/// written.
+pub(crate) fn write_validation_report(manifest_path: &Path, report: &ValidationReport) -> Result<PathBuf> {
+    let mut page = Page::create(
+        &report_path(manifest_path)?,
+        "Validation report",
+        &format!("{} against {}", report.archive.display(), manifest_path.display()),
+    )?;
+
+    let summary = &report.summary;
+    let count_class = |count: usize, fails: bool| if count > 0 && fails { "bad" } else { "" };
+    let (outcome, outcome_class) = if report.passed { ("Passed", "good") } else { ("Failed", "bad") };
+    page.cards(&[
+        ("Result", outcome.to_string(), outcome_class),
+        ("Valid", (summary.valid + summary.assumed_valid).to_string(), ""),
+        ("Mismatched", summary.invalid.to_string(), count_class(summary.invalid, true)),
+        ("Corruption suspected", summary.corruption_suspected.to_string(), count_class(summary.corruption_suspected, true)),
+        ("Metadata changed", summary.metadata_changed.to_string(), count_class(summary.metadata_changed, true)),
+        ("Missing", summary.missing.to_string(), count_class(summary.missing, report.policy.fail_on_missing)),
+        ("New", summary.new.to_string(), count_class(summary.new, report.policy.fail_on_new)),
+        ("Renamed", summary.renamed.to_string(), count_class(summary.renamed, report.policy.fail_on_renamed)),
+        ("Repaired", summary.repaired.to_string(), ""),
+    ])?;
+
+    let mismatched: Vec<Vec<String>> = report
+        .mismatched
+        .iter()
+        .map(|entry| {
+            vec![
+                entry.path.display().to_string(),
+                entry.mismatch_kind.map(|kind| kind.describe().to_string()).unwrap_or_default(),
+                optional(entry.expected_hash.as_ref()),
+                optional(entry.actual_hash.as_ref()),
+            ]
+        })
+        .collect();
+    page.table("Mismatched files", &["Path", "Kind", "Expected hash", "Actual hash"], &mismatched)?;
+    for (heading, entries) in [
+        ("Metadata changed", &report.metadata_changed),
+        ("Missing files", &report.missing),
+        ("New files", &report.new),
+    ] {
+        let rows: Vec<Vec<String>> = entries
+            .iter()
+            .map(|entry| vec![entry.path.display().to_string(), optional(entry.size), optional(entry.expected_hash.as_ref())])
+            .collect();
+        page.table(heading, &["Path", "#Size", "Expected hash"], &rows)?;
+    }
+    let renamed: Vec<Vec<String>> = report
+        .renamed
+        .iter()
+        .map(|entry| vec![entry.from.display().to_string(), entry.to.display().to_string(), entry.hash.clone()])
+        .collect();
+    page.table("Renamed or moved files", &["From", "To", "Hash"], &renamed)?;
+    let repaired: Vec<Vec<String>> = report
+        .repaired
+        .iter()
+        .map(|entry| vec![entry.path.display().to_string(), entry.reason.clone(), entry.source.display().to_string()])
+        .collect();
+    page.table("Repaired files", &["Path", "Reason", "Source"], &repaired)?;
+
+    // Valid, mismatched, missing, and new files per directory
+    let mut rollup: BTreeMap<String, [usize; 4]> = BTreeMap::new();
+    let categories = [
+        report.valid.iter().chain(&report.assumed_valid).collect::<Vec<_>>(),
+        report.mismatched.iter().map(|entry| &entry.path).collect(),
+        report.missing.iter().map(|entry| &entry.path).collect(),
+        report.new.iter().map(|entry| &entry.path).collect(),
+    ];
+    for (index, paths) in categories.iter().enumerate() {
+        for path in paths {
+            rollup.entry(directory(path)).or_default()[index] += 1;
+        }
+    }
+    let rollup: Vec<Vec<String>> = rollup
+        .into_iter()
+        .map(|(directory, counts)| {
+            let mut row = vec![directory];
+            row.extend(counts.iter().map(|count| count.to_string()));
+            row
+        })
+        .collect();
+    page.table("Directories", &["Directory", "#Valid", "#Mismatched", "#Missing", "#New"], &rollup)?;
+    page.finish()
+}
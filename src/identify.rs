@@ -0,0 +1,57 @@
+//! `--identify`: the format of each file, recorded as a MIME type in a
+//! `format` column of the extended manifest, so that the pass computing
+//! fixity also gives a format inventory of the archive.
+//!
+//! Formats are identified by magic bytes at the start of the file, as
+//! `file --mime-type` does. Files no signature matches are `text/plain` if
+//! their start is UTF-8 text and `application/octet-stream` otherwise;
+//! empty files are `inode/x-empty`. PRONOM identifiers need a signature
+//! file and are left to tools such as DROID or Siegfried.
+
+use anyhow::Result;
+use std::fs::File;
+use std::io::Read;
+
+use crate::{container, special, Error, FileInfo};
+
+/// How much of the start of a file is looked at
+const HEADER_SIZE: u64 = 8192;
+
+/// The format of a file, or `None` for container members and for the
+/// special files and directories recorded without contents.
+pub(crate) fn record(file_info: &FileInfo, descend_archives: bool) -> Result<Option<String>> {
+    if descend_archives && container::split_member(&file_info.path).is_some() {
+        return Ok(None);
+    }
+    if special::marker(file_info).is_some() {
+        return Ok(None);
+    }
+    let mut header = Vec::new();
+    File::open(&file_info.path)
+        .and_then(|file| file.take(HEADER_SIZE).read_to_end(&mut header))
+        .map_err(Error::io("Failed to read file", &file_info.path))?;
+    Ok(Some(identify(&header).to_string()))
+}
+
+/// The MIME type of contents starting with `header`.
+fn identify(header: &[u8]) -> &'static str {
+    if header.is_empty() {
+        return "inode/x-empty";
+    }
+    if let Some(kind) = infer::get(header) {
+        return kind.mime_type();
+    }
+    if !header.contains(&0) && is_utf8_prefix(header) {
+        "text/plain"
+    } else {
+        "application/octet-stream"
+    }
+}
+
+/// Whether `header` is UTF-8, allowing a character cut off at the end.
+fn is_utf8_prefix(header: &[u8]) -> bool {
+    match std::str::from_utf8(header) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none(),
+    }
+}
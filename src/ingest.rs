@@ -0,0 +1,96 @@
+use anyhow::Result;
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::progress::Progress;
+use crate::remote::{default_archive_name, open_destination, Destination, HashingReader};
+use crate::{acquire_lock, collect_files, manifest_key, write_manifest, Args, Error, FileInfo, HashAlgorithm, ManifestEntry};
+
+/// Upload one file while hashing it, so it is read exactly once.
+fn ingest_file(destination: &dyn Destination, file_info: &FileInfo, source: &Path) -> Result<String> {
+    let file = fs::File::open(&file_info.path).map_err(Error::io("Failed to open file", &file_info.path))?;
+    let mut reader = HashingReader::new(file, HashAlgorithm::Sha256);
+    let relative = file_info.path.strip_prefix(source).unwrap_or(&file_info.path);
+    destination.upload(relative, &mut reader, file_info.size)?;
+    Ok(reader.finish())
+}
+
+/// Copy the files under `source` to the object storage at `url` and write a
+/// manifest of what was uploaded. Entries are keyed as a manifest generated
+/// from `url` would key them, so the upload can later be validated in place.
+pub fn run_ingest(source: &Path, url: &str, manifest_path: &Path, archive_name: Option<&str>, args: &Args) -> Result<()> {
+    let source = fs::canonicalize(source).map_err(Error::io("Source path does not exist", source))?;
+    let archive_name = match archive_name {
+        Some(name) => name.to_string(),
+        None => default_archive_name(url)?,
+    };
+    let destination = open_destination(url, args)?;
+    let _lock = acquire_lock(manifest_path, args)?;
+
+    println!("Scanning source: {}", source.display());
+    let files = collect_files(&source, args)?;
+    if args.cancel.is_cancelled() {
+        return Err(Error::Cancelled.into());
+    }
+    println!("Found {} files", files.len());
+    println!("Uploading to: {}", url);
+
+    let progress = Progress::start(files.len() as u64, 0);
+    let results: Vec<(&FileInfo, Result<String>)> = files
+        .par_iter()
+        .filter(|_| !args.cancel.is_cancelled())
+        .map(|file_info| {
+            progress.file_started(&file_info.path);
+            let result = ingest_file(destination.as_ref(), file_info, &source);
+            progress.file_done(&file_info.path, &result);
+            (file_info, result)
+        })
+        .collect();
+    progress.finish("Complete");
+
+    // Everything uploaded so far is recorded, even when cancelled or failing,
+    // so the manifest describes what actually reached the destination
+    let mut entries: HashMap<PathBuf, ManifestEntry> = HashMap::new();
+    let mut error_count = 0;
+    for (file_info, result) in results {
+        match result {
+            Ok(hash) => {
+                let entry = ManifestEntry {
+                    hash,
+                    size: Some(file_info.size),
+                    mtime: file_info.mtime,
+                    verified: None,
+                    cid: None,
+                    xattrs: None,
+                    acl: None,
+                    link: None,
+                    format: None,
+                    dev: None,
+                    inode: None,
+                };
+                entries.insert(manifest_key(&file_info.path, &source, &archive_name), entry);
+            }
+            Err(e) => {
+                eprintln!("Error uploading {}: {:#}", file_info.path.display(), e);
+                error_count += 1;
+            }
+        }
+    }
+    write_manifest(manifest_path, &entries, args.writes_extended())?;
+    println!("Manifest written to: {}", manifest_path.display());
+    println!("Uploaded and verified: {} files", entries.len());
+
+    if args.cancel.is_cancelled() {
+        return Err(Error::Cancelled.into());
+    }
+    if error_count > 0 {
+        return Err(Error::Validation {
+            message: format!("Ingest incomplete: {} files failed to upload or verify", error_count),
+            exit_code: 1,
+        }
+        .into());
+    }
+    Ok(())
+}
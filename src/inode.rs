@@ -0,0 +1,45 @@
+//! `--inodes`: the device and inode number of each file, recorded in `dev`
+//! and `inode` columns of the extended manifest. A rename keeps both, so
+//! `--update` recognizes a file that was renamed and edited in between,
+//! which has a new hash and would otherwise be taken for one removed file
+//! and one added. Paths with the same pair are hard links to one file, so
+//! the links can be worked out from the manifest later.
+//!
+//! Inode numbers are reused once a file is deleted, so a new file can be
+//! taken for a renamed one. They are only compared when the hashes of the
+//! removed and added entries do not match. Restores and copies to another
+//! filesystem give files new numbers; validation does not check them.
+
+use anyhow::Result;
+
+use crate::{Error, FileInfo, ManifestEntry};
+
+/// The device and inode number of a file, or `None` for container members,
+/// which have none of their own.
+#[cfg(unix)]
+pub(crate) fn record(file_info: &FileInfo, descend_archives: bool) -> Result<Option<(u64, u64)>> {
+    use std::os::unix::fs::MetadataExt;
+    if descend_archives && crate::container::split_member(&file_info.path).is_some() {
+        return Ok(None);
+    }
+    let metadata = std::fs::metadata(&file_info.path).map_err(Error::io("Failed to read metadata", &file_info.path))?;
+    Ok(Some((metadata.dev(), metadata.ino())))
+}
+
+#[cfg(not(unix))]
+pub(crate) fn record(_file_info: &FileInfo, _descend_archives: bool) -> Result<Option<(u64, u64)>> {
+    Err(Error::Config("--inodes is not supported on this platform".into()).into())
+}
+
+/// Set the `dev` and `inode` columns of `entry` for `file_info`.
+pub(crate) fn stamp(entry: &mut ManifestEntry, file_info: &FileInfo, descend_archives: bool) -> Result<()> {
+    let id = record(file_info, descend_archives)?;
+    entry.dev = id.map(|(dev, _)| dev);
+    entry.inode = id.map(|(_, inode)| inode);
+    Ok(())
+}
+
+/// The device and inode number recorded in `entry`, if any.
+pub(crate) fn of(entry: &ManifestEntry) -> Option<(u64, u64)> {
+    entry.dev.zip(entry.inode)
+}
@@ -0,0 +1,440 @@
+//! `inventory-check`: a manifest cross-referenced with an S3 Inventory
+//! report, so a bucket replica can be audited from the dump S3 writes daily
+//! or weekly instead of listing every object live.
+//!
+//! Reports are read from local copies: the inventory's `manifest.json`, which
+//! names its data files and their schema, or the CSV (optionally gzipped) and
+//! Parquet data files themselves. Data files are streamed a row at a time;
+//! only the manifest being checked is held in memory.
+
+use anyhow::{Context, Result};
+use md5::{Digest, Md5};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{BufRead, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::reader::decompress;
+use crate::remote::{percent_decode, split_url, without_archive_name};
+use crate::report::{open_output, OutputFormat};
+use crate::source::select_files;
+use crate::{load_existing_manifest, Args, Error, FileInfo};
+
+/// Columns of a CSV inventory when no `manifest.json` gives them.
+pub const DEFAULT_SCHEMA: &str = "Bucket, Key, Size, LastModifiedDate, ETag";
+
+/// A manifest entry whose object has a different size.
+#[derive(Serialize, Debug, Clone)]
+pub struct SizeMismatch {
+    pub path: PathBuf,
+    pub expected: u64,
+    pub actual: u64,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct InventorySummary {
+    pub objects: u64,
+    pub matched: usize,
+    pub mismatched: usize,
+    pub missing: usize,
+    pub only_inventory: usize,
+}
+
+/// Result of reconciling a manifest with the objects an inventory lists
+/// under a bucket prefix.
+#[derive(Serialize, Debug, Default)]
+pub struct InventoryCheckReport {
+    pub url: String,
+    pub inventory: Vec<PathBuf>,
+    pub passed: bool,
+    pub summary: InventorySummary,
+    pub mismatched: Vec<SizeMismatch>,
+    /// Manifest entries with no object in the inventory
+    pub missing: Vec<PathBuf>,
+    /// Objects in the inventory with no manifest entry
+    pub only_inventory: Vec<PathBuf>,
+}
+
+impl InventoryCheckReport {
+    pub fn write_text(&self, writer: &mut impl Write) -> Result<()> {
+        for entry in &self.mismatched {
+            writeln!(writer, "SIZE MISMATCH: {} (expected {}, found {})", entry.path.display(), entry.expected, entry.actual)?;
+        }
+        for path in &self.missing {
+            writeln!(writer, "MISSING: {}", path.display())?;
+        }
+        for path in &self.only_inventory {
+            writeln!(writer, "ONLY IN INVENTORY: {}", path.display())?;
+        }
+        writeln!(
+            writer,
+            "Summary: {} objects listed, {} matched, {} mismatched, {} missing, {} only in inventory",
+            self.summary.objects, self.summary.matched, self.summary.mismatched, self.summary.missing, self.summary.only_inventory
+        )?;
+        Ok(())
+    }
+}
+
+/// The `manifest.json` S3 writes alongside each inventory report.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct InventoryManifest {
+    source_bucket: String,
+    file_format: String,
+    #[serde(default)]
+    file_schema: String,
+    files: Vec<InventoryFile>,
+}
+
+#[derive(Deserialize)]
+struct InventoryFile {
+    key: String,
+    #[serde(rename = "MD5checksum")]
+    md5_checksum: Option<String>,
+}
+
+/// Inventory column names compared without case or underscores, so the CSV
+/// `IsDeleteMarker` and the Parquet `is_delete_marker` are the same column.
+fn column_name(name: &str) -> String {
+    name.trim().to_ascii_lowercase().replace('_', "")
+}
+
+/// Positions of the columns a CSV inventory is read by.
+#[derive(Clone)]
+struct Schema {
+    bucket: Option<usize>,
+    key: usize,
+    size: Option<usize>,
+    is_latest: Option<usize>,
+    is_delete_marker: Option<usize>,
+}
+
+impl Schema {
+    fn parse(schema: &str) -> Result<Self> {
+        let names: Vec<String> = schema.split(',').map(column_name).collect();
+        let position = |name: &str| names.iter().position(|n| n == name);
+        Ok(Schema {
+            bucket: position("bucket"),
+            key: position("key").ok_or_else(|| Error::Config(format!("Inventory schema has no Key column: {}", schema)))?,
+            size: position("size"),
+            is_latest: position("islatest"),
+            is_delete_marker: position("isdeletemarker"),
+        })
+    }
+}
+
+enum DataFile {
+    Csv(PathBuf, Schema),
+    Parquet(PathBuf),
+}
+
+/// An object as an inventory row lists it.
+struct Object {
+    bucket: Option<String>,
+    key: String,
+    size: Option<u64>,
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Where the local copy of an inventory data file is: in the `data/`
+/// directory next to the dated directory holding `manifest.json`, as S3
+/// lays the report out, or next to `manifest.json` itself.
+fn local_data_file(manifest_path: &Path, key: &str) -> Result<PathBuf> {
+    let dir = manifest_path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let name = key.rsplit('/').next().unwrap_or(key);
+    [dir.parent().map(|p| p.join("data").join(name)), Some(dir.join("data").join(name)), Some(dir.join(name))]
+        .into_iter()
+        .flatten()
+        .find(|path| path.is_file())
+        .ok_or_else(|| {
+            Error::Config(format!(
+                "Inventory file {} listed in {} was not found; download the report's data/ directory too",
+                key,
+                manifest_path.display()
+            ))
+            .into()
+        })
+}
+
+/// Confirm a data file is the one `manifest.json` describes, so a truncated
+/// download is not mistaken for missing objects.
+fn check_md5(path: &Path, expected: &str) -> Result<()> {
+    let mut file = fs::File::open(path).map_err(Error::io("Failed to open inventory file", path))?;
+    let mut hasher = Md5::new();
+    let mut buffer = vec![0; 1024 * 1024];
+    loop {
+        let n = file.read(&mut buffer).map_err(Error::io("Failed to read inventory file", path))?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+    if !hex(&hasher.finalize()).eq_ignore_ascii_case(expected) {
+        return Err(Error::Config(format!("Inventory file {} does not match the MD5 in its manifest.json", path.display())).into());
+    }
+    Ok(())
+}
+
+/// The data files of the inventory reports or data files in `paths`.
+fn data_files(paths: &[PathBuf], schema: &str, bucket: &str) -> Result<Vec<DataFile>> {
+    let mut files = Vec::new();
+    for path in paths {
+        let name = path.file_name().map(|n| n.to_string_lossy().to_ascii_lowercase()).unwrap_or_default();
+        if name.ends_with(".json") {
+            let text = fs::read_to_string(path).map_err(Error::io("Failed to read inventory manifest", path))?;
+            let manifest: InventoryManifest = serde_json::from_str(&text)
+                .with_context(|| format!("Not an S3 Inventory manifest.json: {}", path.display()))?;
+            if manifest.source_bucket != bucket {
+                return Err(Error::Config(format!(
+                    "{} is an inventory of bucket {}, not {}",
+                    path.display(),
+                    manifest.source_bucket,
+                    bucket
+                ))
+                .into());
+            }
+            let format = manifest.file_format.to_ascii_lowercase();
+            let schema = match format.as_str() {
+                "csv" => Some(Schema::parse(&manifest.file_schema)?),
+                "parquet" => None,
+                _ => {
+                    return Err(Error::Config(format!(
+                        "{} is a {} inventory; only CSV and Parquet inventories can be read",
+                        path.display(),
+                        manifest.file_format
+                    ))
+                    .into())
+                }
+            };
+            for file in &manifest.files {
+                let local = local_data_file(path, &file.key)?;
+                if let Some(ref md5) = file.md5_checksum {
+                    check_md5(&local, md5)?;
+                }
+                files.push(match schema {
+                    Some(ref schema) => DataFile::Csv(local, schema.clone()),
+                    None => DataFile::Parquet(local),
+                });
+            }
+        } else if name.ends_with(".parquet") {
+            files.push(DataFile::Parquet(path.clone()));
+        } else {
+            files.push(DataFile::Csv(path.clone(), Schema::parse(schema)?));
+        }
+    }
+    Ok(files)
+}
+
+/// Split a CSV line into fields, removing quotes and unescaping `""`.
+fn csv_fields(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+/// Keys in CSV inventories are form-encoded, with `+` for a space.
+fn decode_key(key: &str) -> String {
+    percent_decode(&key.replace('+', " "))
+}
+
+fn read_csv(path: &Path, schema: &Schema, visit: &mut impl FnMut(Object)) -> Result<()> {
+    let file = fs::File::open(path).map_err(Error::io("Failed to open inventory file", path))?;
+    for (index, line) in decompress(path, file)?.lines().enumerate() {
+        let line = line.map_err(Error::io("Failed to read inventory file", path))?;
+        if line.is_empty() {
+            continue;
+        }
+        let fields = csv_fields(&line);
+        let field = |column: Option<usize>| column.and_then(|i| fields.get(i)).map(String::as_str).filter(|v| !v.is_empty());
+        // Versioned inventories also list noncurrent versions and delete markers
+        if field(schema.is_delete_marker) == Some("true") || field(schema.is_latest) == Some("false") {
+            continue;
+        }
+        let parse_error = |message: &str| Error::Parse {
+            path: path.to_path_buf(),
+            line: index + 1,
+            message: message.to_string(),
+        };
+        let key = field(Some(schema.key)).ok_or_else(|| parse_error("Row has no key"))?;
+        let size = match field(schema.size) {
+            Some(size) => Some(size.parse().map_err(|_| parse_error("Invalid size"))?),
+            None => None,
+        };
+        visit(Object {
+            bucket: field(schema.bucket).map(str::to_string),
+            key: decode_key(key),
+            size,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(feature = "parquet")]
+fn read_parquet(path: &Path, visit: &mut impl FnMut(Object)) -> Result<()> {
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use parquet::record::Field;
+
+    let file = fs::File::open(path).map_err(Error::io("Failed to open inventory file", path))?;
+    let reader = SerializedFileReader::new(file).with_context(|| format!("Failed to read Parquet file {}", path.display()))?;
+    for row in reader.get_row_iter(None)? {
+        let row = row.with_context(|| format!("Failed to read Parquet file {}", path.display()))?;
+        let mut object = Object { bucket: None, key: String::new(), size: None };
+        let mut current = true;
+        for (name, value) in row.get_column_iter() {
+            match (column_name(name).as_str(), value) {
+                ("bucket", Field::Str(bucket)) => object.bucket = Some(bucket.clone()),
+                ("key", Field::Str(key)) => object.key = key.clone(),
+                ("size", Field::Long(size)) => object.size = Some(*size as u64),
+                ("islatest", Field::Bool(false)) | ("isdeletemarker", Field::Bool(true)) => current = false,
+                _ => {}
+            }
+        }
+        if current && !object.key.is_empty() {
+            visit(object);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "parquet"))]
+fn read_parquet(path: &Path, _visit: &mut impl FnMut(Object)) -> Result<()> {
+    Err(Error::Config(format!(
+        "{} is a Parquet inventory; reading it needs data-manifest built with the parquet feature",
+        path.display()
+    ))
+    .into())
+}
+
+/// Reconcile the entries of `manifest` with the objects the inventory
+/// reports or data files in `inventory` list under `url`.
+pub fn check_inventory(url: &str, inventory: &[PathBuf], manifest: &Path, schema: &str, args: &Args) -> Result<InventoryCheckReport> {
+    let (bucket, prefix) = split_url(url)?;
+    let prefix = if prefix.is_empty() { String::new() } else { format!("{}/", prefix) };
+    if !manifest.exists() {
+        return Err(Error::Config(format!("Manifest does not exist: {}", manifest.display())).into());
+    }
+    // Object key below the prefix to the recorded size
+    let expected: HashMap<String, Option<u64>> = load_existing_manifest(manifest)?
+        .entries
+        .into_iter()
+        .map(|(path, entry)| (without_archive_name(&path).to_string_lossy().replace('\\', "/"), entry.size))
+        .collect();
+    let files = data_files(inventory, schema, bucket)?;
+
+    let mut report = InventoryCheckReport {
+        url: url.to_string(),
+        inventory: inventory.to_vec(),
+        ..Default::default()
+    };
+    let mut seen = HashSet::new();
+    let mut only_inventory = Vec::new();
+    let mut visit = |object: Object| {
+        if object.bucket.as_deref().is_some_and(|b| b != bucket) {
+            return;
+        }
+        let Some(relative) = object.key.strip_prefix(&prefix) else { return };
+        if relative.is_empty() || relative.ends_with('/') {
+            return;
+        }
+        report.summary.objects += 1;
+        match expected.get(relative) {
+            Some(size) => {
+                if !seen.insert(relative.to_string()) {
+                    return;
+                }
+                match (*size, object.size) {
+                    (Some(expected), Some(actual)) if expected != actual => report.mismatched.push(SizeMismatch {
+                        path: PathBuf::from(relative),
+                        expected,
+                        actual,
+                    }),
+                    _ => report.summary.matched += 1,
+                }
+            }
+            None => only_inventory.push(FileInfo {
+                path: PathBuf::from(relative),
+                size: object.size.unwrap_or(0),
+                mtime: None,
+            }),
+        }
+    };
+    for file in &files {
+        if args.cancel.is_cancelled() {
+            return Err(Error::Cancelled.into());
+        }
+        match file {
+            DataFile::Csv(path, schema) => {
+                status!("Reading inventory: {}", path.display());
+                read_csv(path, schema, &mut visit)?;
+            }
+            DataFile::Parquet(path) => {
+                status!("Reading inventory: {}", path.display());
+                read_parquet(path, &mut visit)?;
+            }
+        }
+    }
+
+    report.missing = expected.keys().filter(|key| !seen.contains(*key)).map(PathBuf::from).collect();
+    // Objects the local walk would have excluded are not "only in inventory"
+    report.only_inventory = select_files(only_inventory, args)?.into_iter().map(|f| f.path).collect();
+    report.mismatched.sort_by(|a, b| a.path.cmp(&b.path));
+    report.missing.sort();
+    report.only_inventory.sort();
+    report.summary.mismatched = report.mismatched.len();
+    report.summary.missing = report.missing.len();
+    report.summary.only_inventory = report.only_inventory.len();
+    report.passed = report.mismatched.is_empty() && report.missing.is_empty();
+    Ok(report)
+}
+
+pub fn run_inventory_check(
+    url: &str,
+    inventory: &[PathBuf],
+    manifest: &Path,
+    schema: &str,
+    format: OutputFormat,
+    output: Option<&Path>,
+    args: &Args,
+) -> Result<()> {
+    let report = check_inventory(url, inventory, manifest, schema, args)?;
+
+    let mut writer = open_output(output)?;
+    match format {
+        OutputFormat::Text => report.write_text(&mut writer)?,
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut writer, &report)?;
+            writeln!(writer)?;
+        }
+    }
+    writer.flush()?;
+
+    if !report.passed {
+        return Err(Error::Validation {
+            message: format!(
+                "Inventory check failed: {} mismatched, {} missing",
+                report.summary.mismatched, report.summary.missing
+            ),
+            exit_code: 1,
+        }
+        .into());
+    }
+    Ok(())
+}
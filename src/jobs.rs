@@ -0,0 +1,444 @@
+//! The job queue behind `data-manifest serve`: generation, update, and
+//! validation jobs submitted over HTTP or gRPC, run one at a time since
+//! progress reporting and the settings of a run, such as the hash cache and
+//! the bandwidth limit, are process-wide. Each job's run sets them anew, and
+//! hashes in a pool of its own when it sets `--threads`.
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+
+use crate::progress::{set_progress_sink, ProgressSink};
+use crate::{now_secs, report, Args, CancellationToken, Error};
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Mode {
+    Generate,
+    Update,
+    Validate,
+}
+
+/// A job as submitted.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub(crate) struct JobRequest {
+    pub mode: Mode,
+    /// Archive path or remote archive URL, as for --archive-path
+    pub archive: String,
+    /// Manifest to write or validate, on the server below the service's
+    /// `--root`
+    pub manifest: PathBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archive_name: Option<String>,
+    /// Further command-line options, e.g. `["--extended", "--exclude", "*.tmp"]`,
+    /// from [`JOB_OPTIONS`]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub options: Vec<String>,
+}
+
+/// The options a job may set. Options that name further files on the
+/// server, run commands, or contact other services are left to whoever
+/// starts the service.
+const JOB_OPTIONS: &[&str] = &[
+    "--buffer-size",
+    "--bwlimit",
+    "--threads",
+    "--strict",
+    "--allow-new",
+    "--allow-missing",
+    "--sample",
+    "--sample-count",
+    "--seed",
+    "--budget",
+    "--record-verified",
+    "--path",
+    "--changed-only",
+    "--quick",
+    "--two-phase",
+    "--keep-missing",
+    "--paranoid",
+    "--since",
+    "--backups",
+    "--report",
+    "--report-top",
+    "--extended",
+    "--metadata",
+    "--xattr-name",
+    "--cid-version",
+    "--hardlink-groups",
+    "--identify",
+    "--inodes",
+    "--only-ext",
+    "--skip-ext",
+    "--max-depth",
+    "--follow-symlinks",
+    "--one-file-system",
+    "--ads",
+    "--special",
+    "--empty-dirs",
+    "--normalize-paths",
+    "--case-insensitive",
+    "--descend-archives",
+    "--exclude",
+    "--no-default-excludes",
+    "--exclude-hidden",
+    "--exclude-system",
+    "--exclude-backups",
+    "--verify-checksum-files",
+    "--remote-concurrency",
+    "--part-size",
+];
+
+impl JobRequest {
+    /// The options the job runs with, writing or validating `manifest`,
+    /// parsed as the command line would be.
+    fn args(&self, manifest: &Path) -> Result<Args, String> {
+        let mut argv = vec!["data-manifest".to_string(), format!("--archive-path={}", self.archive)];
+        argv.push(format!("--output={}", manifest.display()));
+        match self.mode {
+            Mode::Generate => {}
+            Mode::Update => argv.push("--update".to_string()),
+            Mode::Validate => argv.push("--validate".to_string()),
+        }
+        if let Some(ref name) = self.archive_name {
+            argv.push(format!("--archive-name={}", name));
+        }
+        for option in self.options.iter().filter(|option| option.starts_with('-')) {
+            let name = option.split_once('=').map_or(option.as_str(), |(name, _)| name);
+            if !JOB_OPTIONS.contains(&name) {
+                return Err(format!("{} cannot be set for a job", name));
+            }
+        }
+        argv.extend(self.options.iter().cloned());
+        let args = Args::try_parse_from(argv).map_err(|e| e.to_string().trim().to_string())?;
+        if args.command.is_some() {
+            return Err("options cannot select a subcommand".into());
+        }
+        Ok(args)
+    }
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Status {
+    Queued,
+    Running,
+    Passed,
+    /// Validation found problems
+    Failed,
+    Error,
+    Cancelled,
+}
+
+impl Status {
+    #[cfg_attr(not(feature = "grpc"), allow(dead_code))]
+    fn is_finished(self) -> bool {
+        !matches!(self, Status::Queued | Status::Running)
+    }
+}
+
+/// Something that happened to a job, as sent to its subscribers.
+#[cfg_attr(not(feature = "grpc"), allow(dead_code))]
+pub(crate) enum JobEvent<'a> {
+    /// A pass over `total_files` files began, `completed` of them already done
+    Started { total_files: u64, completed: u64 },
+    FileFinished { path: &'a Path },
+    FileError { path: &'a Path, message: &'a str },
+    /// Always the last event
+    Finished { status: Status, error: Option<&'a str> },
+}
+
+/// Receives a job's events for as long as it returns `true`.
+type Subscriber = Box<dyn Fn(&JobEvent) -> bool + Send>;
+
+#[derive(Default)]
+struct JobProgress {
+    total_files: AtomicU64,
+    files_done: AtomicU64,
+    errors: AtomicU64,
+    bytes_hashed: AtomicU64,
+}
+
+struct JobState {
+    status: Status,
+    started: Option<i64>,
+    finished: Option<i64>,
+    error: Option<String>,
+    /// The validation report, update summary, or counts of a generated manifest
+    report: Option<serde_json::Value>,
+}
+
+pub(crate) struct Job {
+    pub id: u64,
+    pub request: JobRequest,
+    /// The manifest of the request, resolved below the service's root
+    manifest: PathBuf,
+    /// The service's root, canonicalized
+    root: PathBuf,
+    submitted: i64,
+    /// Taken by the worker when the job starts
+    args: Mutex<Option<Args>>,
+    cancel: CancellationToken,
+    state: Mutex<JobState>,
+    progress: JobProgress,
+    subscribers: Mutex<Vec<Subscriber>>,
+}
+
+/// A snapshot of a job and its progress.
+#[derive(Serialize)]
+pub(crate) struct JobStatus<'a> {
+    pub id: u64,
+    #[serde(flatten)]
+    pub request: &'a JobRequest,
+    pub status: Status,
+    pub submitted: i64,
+    pub started: Option<i64>,
+    pub finished: Option<i64>,
+    pub total_files: u64,
+    pub files_done: u64,
+    pub errors: u64,
+    pub bytes_hashed: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Job {
+    pub fn status(&self) -> JobStatus<'_> {
+        let state = self.state.lock().unwrap();
+        JobStatus {
+            id: self.id,
+            request: &self.request,
+            status: state.status,
+            submitted: self.submitted,
+            started: state.started,
+            finished: state.finished,
+            total_files: self.progress.total_files.load(Ordering::Relaxed),
+            files_done: self.progress.files_done.load(Ordering::Relaxed),
+            errors: self.progress.errors.load(Ordering::Relaxed),
+            bytes_hashed: self.progress.bytes_hashed.load(Ordering::Relaxed),
+            error: state.error.clone(),
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.state.lock().unwrap().status == Status::Running
+    }
+
+    /// Open the job's manifest, unless it has since been replaced by a link
+    /// leading out of the service's root.
+    pub fn open_manifest(&self) -> io::Result<fs::File> {
+        if !fs::canonicalize(&self.manifest)?.starts_with(&self.root) {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "The manifest is outside the root"));
+        }
+        fs::File::open(&self.manifest)
+    }
+
+    /// The report of a finished job.
+    pub fn report(&self) -> Option<serde_json::Value> {
+        self.state.lock().unwrap().report.clone()
+    }
+
+    /// Cancel the job if it has not finished. A running job records its
+    /// outcome once the run stops.
+    pub fn cancel(&self) {
+        let mut state = self.state.lock().unwrap();
+        match state.status {
+            Status::Queued => {
+                state.status = Status::Cancelled;
+                state.finished = Some(now_secs());
+                self.emit(&JobEvent::Finished {
+                    status: Status::Cancelled,
+                    error: None,
+                });
+                self.subscribers.lock().unwrap().clear();
+            }
+            Status::Running => self.cancel.cancel(),
+            _ => {}
+        }
+    }
+
+    /// Send the job's events to `subscriber` from now on. A finished job only
+    /// sends its `Finished` event.
+    #[cfg_attr(not(feature = "grpc"), allow(dead_code))]
+    pub fn subscribe(&self, subscriber: Subscriber) {
+        let state = self.state.lock().unwrap();
+        if state.status.is_finished() {
+            subscriber(&JobEvent::Finished {
+                status: state.status,
+                error: state.error.as_deref(),
+            });
+        } else {
+            self.subscribers.lock().unwrap().push(subscriber);
+        }
+    }
+
+    fn emit(&self, event: &JobEvent) {
+        self.subscribers.lock().unwrap().retain(|subscriber| subscriber(event));
+    }
+}
+
+/// Feeds the progress of the running job into its counters and subscribers.
+struct JobSink(Arc<Job>);
+
+impl ProgressSink for JobSink {
+    fn started(&self, total_files: u64, completed: u64) {
+        self.0.progress.total_files.store(total_files, Ordering::Relaxed);
+        self.0.progress.files_done.store(completed, Ordering::Relaxed);
+        self.0.emit(&JobEvent::Started { total_files, completed });
+    }
+
+    fn bytes_hashed(&self, bytes: u64) {
+        self.0.progress.bytes_hashed.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    fn file_finished(&self, path: &Path) {
+        self.0.progress.files_done.fetch_add(1, Ordering::Relaxed);
+        self.0.emit(&JobEvent::FileFinished { path });
+    }
+
+    fn error(&self, path: &Path, message: &str) {
+        self.0.progress.errors.fetch_add(1, Ordering::Relaxed);
+        self.0.emit(&JobEvent::FileError { path, message });
+    }
+}
+
+/// The path of `manifest` below `root`, or why it is refused: it leads out
+/// of `root`, directly or through a link, or its directory does not exist.
+fn resolve_manifest(root: &Path, manifest: &Path) -> Result<PathBuf, String> {
+    let outside = || format!("The manifest must be below {}", root.display());
+    if manifest.components().any(|component| component == Component::ParentDir) {
+        return Err(outside());
+    }
+    let path = root.join(manifest);
+    let (Some(directory), Some(name)) = (path.parent(), path.file_name()) else {
+        return Err(outside());
+    };
+    let directory = fs::canonicalize(directory).map_err(|e| format!("Manifest directory {}: {}", directory.display(), e))?;
+    let path = directory.join(name);
+    let target = match fs::canonicalize(&path) {
+        Ok(target) => target,
+        Err(_) => path.clone(),
+    };
+    if !directory.starts_with(root) || !target.starts_with(root) {
+        return Err(outside());
+    }
+    Ok(path)
+}
+
+/// Submitted jobs, kept until the service stops.
+pub(crate) struct Jobs {
+    /// Canonical directory the manifests of jobs are kept in
+    root: PathBuf,
+    jobs: Mutex<BTreeMap<u64, Arc<Job>>>,
+    next_id: AtomicU64,
+    queue: Mutex<mpsc::Sender<Arc<Job>>>,
+}
+
+impl Jobs {
+    /// Start the worker that runs submitted jobs, whose manifests are kept
+    /// below `root`.
+    pub fn start(root: &Path) -> Result<Arc<Self>> {
+        let root = fs::canonicalize(root).map_err(Error::io("Failed to open the job root", root))?;
+        let (queue, receiver) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("jobs".into())
+            .spawn(move || run_jobs(receiver))
+            .context("Failed to start the job worker")?;
+        Ok(Arc::new(Jobs {
+            root,
+            jobs: Default::default(),
+            next_id: AtomicU64::new(0),
+            queue: Mutex::new(queue),
+        }))
+    }
+
+    /// Queue `request`, or say why its options are invalid.
+    pub fn submit(&self, request: JobRequest) -> Result<Arc<Job>, String> {
+        let manifest = resolve_manifest(&self.root, &request.manifest)?;
+        let mut args = request.args(&manifest)?;
+        let cancel = CancellationToken::new();
+        args.cancel = cancel.clone();
+        let job = Arc::new(Job {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed) + 1,
+            request,
+            manifest,
+            root: self.root.clone(),
+            submitted: now_secs(),
+            args: Mutex::new(Some(args)),
+            cancel,
+            state: Mutex::new(JobState {
+                status: Status::Queued,
+                started: None,
+                finished: None,
+                error: None,
+                report: None,
+            }),
+            progress: JobProgress::default(),
+            subscribers: Default::default(),
+        });
+        self.jobs.lock().unwrap().insert(job.id, Arc::clone(&job));
+        let _ = self.queue.lock().unwrap().send(Arc::clone(&job));
+        Ok(job)
+    }
+
+    pub fn get(&self, id: u64) -> Option<Arc<Job>> {
+        self.jobs.lock().unwrap().get(&id).cloned()
+    }
+
+    pub fn list(&self) -> Vec<Arc<Job>> {
+        self.jobs.lock().unwrap().values().cloned().collect()
+    }
+
+    /// Cancel every job that has not finished.
+    pub fn cancel_all(&self) {
+        for job in self.jobs.lock().unwrap().values() {
+            job.cancel();
+        }
+    }
+}
+
+/// Run queued jobs in submission order.
+fn run_jobs(queue: mpsc::Receiver<Arc<Job>>) {
+    for job in queue {
+        let Some(args) = job.args.lock().unwrap().take() else { continue };
+        {
+            let mut state = job.state.lock().unwrap();
+            if state.status != Status::Queued {
+                continue;
+            }
+            state.status = Status::Running;
+            state.started = Some(now_secs());
+        }
+        println!("Job {}: {:?} {}", job.id, job.request.mode, job.request.archive);
+        set_progress_sink(Some(Arc::new(JobSink(Arc::clone(&job)))));
+        let result = crate::run(&args);
+        set_progress_sink(None);
+
+        let mut state = job.state.lock().unwrap();
+        state.finished = Some(now_secs());
+        state.report = report::take_summary();
+        state.status = match result {
+            _ if job.cancel.is_cancelled() => Status::Cancelled,
+            Ok(()) => Status::Passed,
+            Err(ref e) => {
+                state.error = Some(format!("{:#}", e));
+                match e.downcast_ref::<Error>() {
+                    Some(Error::Validation { .. }) => Status::Failed,
+                    _ => Status::Error,
+                }
+            }
+        };
+        println!("Job {}: {:?}", job.id, state.status);
+        job.emit(&JobEvent::Finished {
+            status: state.status,
+            error: state.error.as_deref(),
+        });
+        job.subscribers.lock().unwrap().clear();
+    }
+}
@@ -0,0 +1,170 @@
+use anyhow::{Context, Result};
+use std::fs::{self, TryLockError};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::eventlog::hostname;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// A `<manifest>.lock` sidecar held for the duration of a run that writes the
+/// manifest. The run holds an advisory lock (flock, LockFileEx) on the file,
+/// which the operating system releases if the run dies, so a lock left by a
+/// crashed run never blocks the next one. The file is removed when the lock
+/// is dropped.
+pub struct ManifestLock {
+    path: PathBuf,
+    file: fs::File,
+}
+
+impl Drop for ManifestLock {
+    fn drop(&mut self) {
+        // A lock file replaced by --force belongs to the run that forced it.
+        // It is removed before the lock is released, so a run waiting on it
+        // sees that it is gone and takes a new one.
+        if is_lock_file(&self.file, &self.path) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Path of the lock sidecar for `manifest_path`.
+pub fn lock_path(manifest_path: &Path) -> PathBuf {
+    let mut name = manifest_path.file_name().unwrap_or_default().to_os_string();
+    name.push(".lock");
+    manifest_path.with_file_name(name)
+}
+
+/// Whether `file` is still the file at `path`, not one removed since.
+#[cfg(unix)]
+fn is_lock_file(file: &fs::File, path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (file.metadata(), fs::metadata(path)) {
+        (Ok(held), Ok(current)) => held.dev() == current.dev() && held.ino() == current.ino(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn is_lock_file(_file: &fs::File, path: &Path) -> bool {
+    path.exists()
+}
+
+/// Take the lock for `manifest_path`. If another run holds it, fail
+/// immediately unless `wait` is given, in which case poll until it is released
+/// (giving up after the timeout, if any). `force` removes an existing lock
+/// instead of honouring it.
+pub fn acquire(manifest_path: &Path, wait: Option<Option<Duration>>, force: bool) -> Result<ManifestLock> {
+    let path = lock_path(manifest_path);
+    let started = Instant::now();
+    let mut announced = false;
+    loop {
+        let mut file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .with_context(|| format!("Failed to create lock file: {}", path.display()))?;
+        match file.try_lock() {
+            // The file may have been removed by the run that held it just
+            // before it released the lock; then lock the file that replaces it
+            Ok(()) if !is_lock_file(&file, &path) => continue,
+            Ok(()) => {
+                file.set_len(0)?;
+                writeln!(file, "pid={}", std::process::id())?;
+                writeln!(file, "host={}", hostname())?;
+                writeln!(file, "started={}", crate::format_utc(crate::now_secs()))?;
+                return Ok(ManifestLock { path, file });
+            }
+            Err(TryLockError::WouldBlock) => {}
+            Err(TryLockError::Error(e)) => {
+                return Err(e).with_context(|| format!("Failed to lock: {}", path.display()));
+            }
+        }
+        drop(file);
+
+        let holder = fs::read_to_string(&path).unwrap_or_default();
+        if force {
+            eprintln!("Warning: Removing existing lock file: {}", path.display());
+            match fs::remove_file(&path) {
+                Ok(()) => continue,
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e).with_context(|| format!("Failed to remove lock file: {}", path.display())),
+            }
+        }
+
+        let holder = holder.lines().collect::<Vec<_>>().join(", ");
+        let Some(timeout) = wait else {
+            anyhow::bail!(
+                "Manifest is locked by another run ({}); use --wait to wait for it or --force to remove {}",
+                holder,
+                path.display()
+            );
+        };
+        if timeout.is_some_and(|timeout| started.elapsed() >= timeout) {
+            anyhow::bail!("Timed out waiting for manifest lock held by another run ({})", holder);
+        }
+        if !announced {
+            eprintln!("Waiting for manifest lock held by another run ({})", holder);
+            announced = true;
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("data-manifest-lock-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("manifest.txt")
+    }
+
+    #[test]
+    fn takes_over_a_lock_left_by_a_dead_run() {
+        let manifest = manifest("stale");
+        fs::write(lock_path(&manifest), "pid=4294967295\nhost=elsewhere\nstarted=2020-01-01T00:00:00\n").unwrap();
+
+        let lock = acquire(&manifest, None, false).unwrap();
+        let holder = fs::read_to_string(lock_path(&manifest)).unwrap();
+        assert!(holder.starts_with(&format!("pid={}\n", std::process::id())));
+        drop(lock);
+        assert!(!lock_path(&manifest).exists());
+        fs::remove_dir_all(manifest.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn refuses_a_held_lock_unless_forced() {
+        let manifest = manifest("held");
+        let first = acquire(&manifest, None, false).unwrap();
+        assert!(acquire(&manifest, None, false).is_err());
+        assert!(acquire(&manifest, Some(Some(Duration::from_millis(10))), false).is_err());
+
+        let forced = acquire(&manifest, None, true).unwrap();
+        // The run that was overridden leaves the new lock file in place
+        drop(first);
+        assert!(lock_path(&manifest).exists());
+        drop(forced);
+        assert!(!lock_path(&manifest).exists());
+        fs::remove_dir_all(manifest.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn waiter_takes_the_lock_once_it_is_released() {
+        let manifest = manifest("wait");
+        let first = acquire(&manifest, None, false).unwrap();
+        let waiter = {
+            let manifest = manifest.clone();
+            std::thread::spawn(move || acquire(&manifest, Some(None), false).map(drop))
+        };
+        std::thread::sleep(Duration::from_millis(100));
+        drop(first);
+        waiter.join().unwrap().unwrap();
+        assert!(!lock_path(&manifest).exists());
+        fs::remove_dir_all(manifest.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,63 @@
+//! Long and UNC archive paths on Windows. Archives on file servers are often
+//! nested deeper than the 260 characters of `MAX_PATH`, and names such as
+//! `aux.txt` or `notes.` written by other systems are renamed by the classic
+//! Win32 path syntax. The archive root is therefore turned into an
+//! extended-length path, `\\?\D:\archive` or `\\?\UNC\server\share\archive`,
+//! so that every path derived from it while walking, hashing, and validating
+//! reaches the file as named. Manifest keys are relative to the root and do
+//! not change.
+
+use anyhow::Result;
+use std::path::{Component, Path, PathBuf, Prefix};
+
+#[cfg(windows)]
+use crate::Error;
+
+/// `path` as an absolute extended-length path on Windows; other platforms
+/// have no such limit and keep it as given.
+#[cfg(windows)]
+pub(crate) fn extended_length(path: &Path) -> Result<PathBuf> {
+    // canonicalize returns the \\?\ and \\?\UNC\ forms
+    Ok(std::fs::canonicalize(path).map_err(Error::io("Archive path does not exist", path))?)
+}
+
+#[cfg(not(windows))]
+pub(crate) fn extended_length(path: &Path) -> Result<PathBuf> {
+    Ok(path.to_path_buf())
+}
+
+/// `path` without the `\\?\` prefix of an extended-length path, as other
+/// programs write it.
+#[cfg(windows)]
+pub(crate) fn without_verbatim(path: &Path) -> PathBuf {
+    let text = path.to_string_lossy();
+    if let Some(unc) = text.strip_prefix(r"\\?\UNC\") {
+        PathBuf::from(format!(r"\\{}", unc))
+    } else if let Some(local) = text.strip_prefix(r"\\?\") {
+        PathBuf::from(local)
+    } else {
+        path.to_path_buf()
+    }
+}
+
+#[cfg(not(windows))]
+pub(crate) fn without_verbatim(path: &Path) -> PathBuf {
+    path.to_path_buf()
+}
+
+/// The archive name used when none is given: the last component of the
+/// archive path, the share of a UNC share root, or the letter of a drive
+/// root.
+pub(crate) fn default_archive_name(path: &Path) -> String {
+    if let Some(name) = path.file_name() {
+        return name.to_string_lossy().into_owned();
+    }
+    if let Some(Component::Prefix(prefix)) = path.components().next() {
+        match prefix.kind() {
+            Prefix::UNC(_, share) | Prefix::VerbatimUNC(_, share) => return share.to_string_lossy().into_owned(),
+            Prefix::Disk(letter) | Prefix::VerbatimDisk(letter) => return char::from(letter).to_string(),
+            _ => {}
+        }
+    }
+    path.as_os_str().to_string_lossy().into_owned()
+}
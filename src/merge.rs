@@ -0,0 +1,370 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::{load_existing_manifest, require_sha256, write_manifest, ManifestEntry};
+
+/// How `merge` resolves a path listed with different hashes in several inputs.
+#[derive(clap::ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Fail and list the conflicting paths
+    Error,
+    /// Keep the entry with the newest mtime (or from the most recently modified manifest)
+    PreferNewest,
+    /// Keep the entry from the first manifest that lists the path
+    PreferFirst,
+}
+
+/// An entry together with the time used to rank it under `PreferNewest`.
+struct Candidate {
+    entry: ManifestEntry,
+    source: usize,
+    time: i64,
+}
+
+/// Combine two entries with the same hash, keeping whatever metadata either one has.
+fn combine(kept: &mut ManifestEntry, other: &ManifestEntry) {
+    kept.size = kept.size.or(other.size);
+    kept.mtime = kept.mtime.or(other.mtime);
+    kept.verified = kept.verified.max(other.verified);
+}
+
+/// Merge `inputs` into `output`, resolving conflicting paths with `policy`.
+pub fn run_merge(inputs: &[PathBuf], output: &Path, policy: ConflictPolicy) -> Result<()> {
+    let mut merged: HashMap<PathBuf, Candidate> = HashMap::new();
+    let mut conflicts: Vec<(PathBuf, usize, usize)> = Vec::new();
+    let mut extended = false;
+
+    for (index, input) in inputs.iter().enumerate() {
+        if !input.exists() {
+            anyhow::bail!("Manifest does not exist: {}", input.display());
+        }
+        let manifest = load_existing_manifest(input)?;
+        require_sha256(&manifest, input)?;
+        extended |= manifest.extended;
+        // Entries without an mtime are ranked by when their manifest was written
+        let manifest_time = std::fs::metadata(input)?.modified().ok().and_then(crate::system_time_nanos).unwrap_or(0);
+        println!("Loaded {} entries from {}", manifest.entries.len(), input.display());
+
+        for (path, entry) in manifest.entries {
+            let time = entry.mtime.unwrap_or(manifest_time);
+            let Some(existing) = merged.get_mut(&path) else {
+                merged.insert(path, Candidate { entry, source: index, time });
+                continue;
+            };
+            if existing.entry.hash == entry.hash {
+                combine(&mut existing.entry, &entry);
+                continue;
+            }
+            conflicts.push((path.clone(), existing.source, index));
+            if policy == ConflictPolicy::PreferNewest && time > existing.time {
+                *existing = Candidate { entry, source: index, time };
+            }
+        }
+    }
+
+    conflicts.sort();
+    let mut paths_resolved = 0;
+    if !conflicts.is_empty() {
+        if policy == ConflictPolicy::Error {
+            for (path, first, second) in &conflicts {
+                eprintln!(
+                    "Conflict: {} differs between {} and {}",
+                    path.display(),
+                    inputs[*first].display(),
+                    inputs[*second].display()
+                );
+            }
+            anyhow::bail!(
+                "{} paths have conflicting hashes; choose a policy with --on-conflict",
+                conflicts.len()
+            );
+        }
+        let mut paths: Vec<&PathBuf> = conflicts.iter().map(|(path, _, _)| path).collect();
+        paths.dedup();
+        paths_resolved = paths.len();
+        for path in paths {
+            let kept = &merged[path];
+            eprintln!("Warning: Conflicting hashes for {}; kept entry from {}", path.display(), inputs[kept.source].display());
+        }
+    }
+
+    let entries: HashMap<PathBuf, ManifestEntry> = merged.into_iter().map(|(path, candidate)| (path, candidate.entry)).collect();
+    write_manifest(output, &entries, extended)?;
+    println!("Merged manifest written to: {}", output.display());
+    println!("Total entries: {}", entries.len());
+    println!("Conflicting paths resolved: {}", paths_resolved);
+    Ok(())
+}
+
+/// How a path diverged between the two sides of a three-way merge.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictKind {
+    /// Changed on both sides to different hashes
+    BothModified,
+    /// Added on both sides with different hashes
+    BothAdded,
+    /// Changed on one side and removed on the other
+    ModifiedAndRemoved,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct MergeConflict {
+    pub path: PathBuf,
+    pub kind: ConflictKind,
+    pub base_hash: Option<String>,
+    pub ours_hash: Option<String>,
+    pub theirs_hash: Option<String>,
+    /// Which version ended up in the merged manifest: "base", "ours", "theirs", or "removed"
+    pub resolution: &'static str,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ConflictReport {
+    pub base: PathBuf,
+    pub ours: PathBuf,
+    pub theirs: PathBuf,
+    pub merged_entries: usize,
+    pub conflicts: Vec<MergeConflict>,
+}
+
+fn same_hash(a: Option<&ManifestEntry>, b: Option<&ManifestEntry>) -> bool {
+    a.map(|e| &e.hash) == b.map(|e| &e.hash)
+}
+
+/// Merge two manifests that diverged from `base`. Paths changed on only one
+/// side take that side's version; paths changed differently on both sides are
+/// conflicts resolved by `policy` and listed in the conflict report. With
+/// `ConflictPolicy::Error` conflicting paths keep their base version and the
+/// merge fails after writing its outputs.
+pub fn run_three_way_merge(
+    base_path: &Path,
+    ours_path: &Path,
+    theirs_path: &Path,
+    output: &Path,
+    policy: ConflictPolicy,
+    conflicts_path: Option<&Path>,
+) -> Result<()> {
+    let mut manifests = Vec::new();
+    for input in [base_path, ours_path, theirs_path] {
+        if !input.exists() {
+            anyhow::bail!("Manifest does not exist: {}", input.display());
+        }
+        let manifest = load_existing_manifest(input)?;
+        require_sha256(&manifest, input)?;
+        manifests.push(manifest);
+    }
+    let ours_time = std::fs::metadata(ours_path)?.modified().ok().and_then(crate::system_time_nanos).unwrap_or(0);
+    let theirs_time = std::fs::metadata(theirs_path)?.modified().ok().and_then(crate::system_time_nanos).unwrap_or(0);
+    let extended = manifests.iter().any(|m| m.extended);
+    let [base, ours, theirs] = [&manifests[0].entries, &manifests[1].entries, &manifests[2].entries];
+
+    let mut paths: Vec<&PathBuf> = base.keys().chain(ours.keys()).chain(theirs.keys()).collect();
+    paths.sort();
+    paths.dedup();
+
+    let mut entries: HashMap<PathBuf, ManifestEntry> = HashMap::new();
+    let mut conflicts = Vec::new();
+    for path in paths {
+        let (b, o, t) = (base.get(path), ours.get(path), theirs.get(path));
+        let chosen = if same_hash(o, t) {
+            o.map(|o| {
+                let mut merged = o.clone();
+                if let Some(t) = t {
+                    combine(&mut merged, t);
+                }
+                merged
+            })
+        } else if same_hash(b, o) {
+            t.cloned()
+        } else if same_hash(b, t) {
+            o.cloned()
+        } else {
+            let kind = match (b, o, t) {
+                (None, _, _) => ConflictKind::BothAdded,
+                (Some(_), Some(_), Some(_)) => ConflictKind::BothModified,
+                _ => ConflictKind::ModifiedAndRemoved,
+            };
+            let take_ours = match policy {
+                ConflictPolicy::Error => None,
+                ConflictPolicy::PreferFirst => Some(true),
+                // A modification always wins over a removal
+                ConflictPolicy::PreferNewest => Some(match (o, t) {
+                    (Some(o), Some(t)) => o.mtime.unwrap_or(ours_time) >= t.mtime.unwrap_or(theirs_time),
+                    (o, _) => o.is_some(),
+                }),
+            };
+            let (chosen, resolution) = match take_ours {
+                None => (b.cloned(), "base"),
+                Some(true) => (o.cloned(), "ours"),
+                Some(false) => (t.cloned(), "theirs"),
+            };
+            conflicts.push(MergeConflict {
+                path: path.clone(),
+                kind,
+                base_hash: b.map(|e| e.hash.clone()),
+                ours_hash: o.map(|e| e.hash.clone()),
+                theirs_hash: t.map(|e| e.hash.clone()),
+                resolution: if chosen.is_some() { resolution } else { "removed" },
+            });
+            chosen
+        };
+        if let Some(entry) = chosen {
+            entries.insert(path.clone(), entry);
+        }
+    }
+
+    write_manifest(output, &entries, extended)?;
+    println!("Merged manifest written to: {}", output.display());
+    println!("Total entries: {}", entries.len());
+    let conflict_count = conflicts.len();
+    println!("Conflicts: {}", conflict_count);
+    for conflict in &conflicts {
+        eprintln!(
+            "Conflict ({:?}): {} (resolution: {})",
+            conflict.kind,
+            conflict.path.display(),
+            conflict.resolution
+        );
+    }
+
+    if let Some(conflicts_path) = conflicts_path {
+        let report = ConflictReport {
+            base: base_path.to_path_buf(),
+            ours: ours_path.to_path_buf(),
+            theirs: theirs_path.to_path_buf(),
+            merged_entries: entries.len(),
+            conflicts,
+        };
+        let file = std::fs::File::create(conflicts_path)
+            .with_context(|| format!("Failed to create conflict report: {}", conflicts_path.display()))?;
+        let mut writer = std::io::BufWriter::new(file);
+        serde_json::to_writer_pretty(&mut writer, &report)?;
+        writeln!(writer)?;
+        println!("Conflict report written to: {}", conflicts_path.display());
+    }
+
+    if policy == ConflictPolicy::Error && conflict_count > 0 {
+        anyhow::bail!(
+            "{} paths were changed differently on both sides and kept at their base version; choose a policy with --on-conflict",
+            conflict_count
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash(c: char) -> String {
+        c.to_string().repeat(64)
+    }
+
+    /// Write a manifest of `(path, hash character, mtime)` entries into `dir`.
+    fn write(dir: &Path, name: &str, entries: &[(&str, char, i64)]) -> PathBuf {
+        let entries = entries
+            .iter()
+            .map(|&(path, c, mtime)| {
+                let entry = ManifestEntry {
+                    hash: hash(c),
+                    size: Some(1),
+                    mtime: Some(mtime),
+                    verified: None,
+                    cid: None,
+                    xattrs: None,
+                    acl: None,
+                    link: None,
+                    format: None,
+                    dev: None,
+                    inode: None,
+                };
+                (PathBuf::from(path), entry)
+            })
+            .collect();
+        let path = dir.join(name);
+        write_manifest(&path, &entries, true).unwrap();
+        path
+    }
+
+    fn hashes(manifest: &Path) -> Vec<(String, String)> {
+        let mut entries: Vec<_> = load_existing_manifest(manifest)
+            .unwrap()
+            .entries
+            .into_iter()
+            .map(|(path, entry)| (path.to_string_lossy().into_owned(), entry.hash))
+            .collect();
+        entries.sort();
+        entries
+    }
+
+    fn expect(entries: &[(&str, char)]) -> Vec<(String, String)> {
+        entries.iter().map(|&(path, c)| (path.to_string(), hash(c))).collect()
+    }
+
+    #[test]
+    fn conflicting_paths_follow_the_policy() {
+        let _guard = crate::lock_run();
+        let dir = std::env::temp_dir().join(format!("data-manifest-merge-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let first = write(&dir, "first.txt", &[("a/same", '1', 5), ("a/both", '2', 10), ("a/only-first", '3', 5)]);
+        let second = write(&dir, "second.txt", &[("a/same", '1', 5), ("a/both", '4', 20), ("a/only-second", '5', 5)]);
+        let inputs = [first, second];
+        let output = dir.join("merged.txt");
+
+        assert!(run_merge(&inputs, &output, ConflictPolicy::Error).is_err());
+        assert!(!output.exists());
+
+        run_merge(&inputs, &output, ConflictPolicy::PreferFirst).unwrap();
+        assert_eq!(hashes(&output), expect(&[("a/both", '2'), ("a/only-first", '3'), ("a/only-second", '5'), ("a/same", '1')]));
+
+        run_merge(&inputs, &output, ConflictPolicy::PreferNewest).unwrap();
+        assert_eq!(hashes(&output), expect(&[("a/both", '4'), ("a/only-first", '3'), ("a/only-second", '5'), ("a/same", '1')]));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn three_way_merge_classifies_conflicts() {
+        let _guard = crate::lock_run();
+        let dir = std::env::temp_dir().join(format!("data-manifest-merge3-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let base = write(&dir, "base.txt", &[("a/kept", '1', 5), ("a/ours", '2', 5), ("a/both", '3', 5), ("a/gone", '4', 5), ("a/same", '5', 5)]);
+        let ours = write(&dir, "ours.txt", &[("a/kept", '1', 5), ("a/ours", '6', 5), ("a/both", '7', 5), ("a/gone", '8', 5), ("a/same", '9', 5), ("a/new", 'a', 5)]);
+        let theirs = write(&dir, "theirs.txt", &[("a/kept", '1', 5), ("a/ours", '2', 5), ("a/both", 'b', 5), ("a/same", '9', 5), ("a/new", 'c', 5)]);
+        let output = dir.join("merged.txt");
+        let report = dir.join("conflicts.json");
+
+        assert!(run_three_way_merge(&base, &ours, &theirs, &output, ConflictPolicy::Error, Some(&report)).is_err());
+        // Conflicting paths keep their base version; one-sided and identical changes are taken
+        assert_eq!(
+            hashes(&output),
+            expect(&[("a/both", '3'), ("a/gone", '4'), ("a/kept", '1'), ("a/ours", '6'), ("a/same", '9')])
+        );
+        let report: serde_json::Value = serde_json::from_reader(std::fs::File::open(&report).unwrap()).unwrap();
+        let conflicts: Vec<_> = report["conflicts"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|c| (c["path"].as_str().unwrap(), c["kind"].as_str().unwrap(), c["resolution"].as_str().unwrap()))
+            .collect();
+        assert_eq!(
+            conflicts,
+            [("a/both", "both_modified", "base"), ("a/gone", "modified_and_removed", "base"), ("a/new", "both_added", "removed")]
+        );
+
+        // Taking our side keeps our modification over their removal
+        run_three_way_merge(&base, &ours, &theirs, &output, ConflictPolicy::PreferFirst, None).unwrap();
+        assert_eq!(
+            hashes(&output),
+            expect(&[("a/both", '7'), ("a/gone", '8'), ("a/kept", '1'), ("a/new", 'a'), ("a/ours", '6'), ("a/same", '9')])
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
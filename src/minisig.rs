@@ -0,0 +1,169 @@
+//! minisign signatures of manifests, for machines without a GPG keyring:
+//! `--minisign-key` signs the written manifest with a minisign secret key
+//! file into `<manifest>.minisig` and records the key ID in the manifest
+//! header, and `--minisign-pubkey` checks that signature before validation
+//! or update trusts the manifest.
+//!
+//! Keys and signatures are those of the `minisign` tool, so `minisign -G`
+//! creates a key pair and `minisign -V` verifies manifests too.
+
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::{rewrites_manifest, Args, Error};
+
+/// ID of the key manifests are being signed with, for their headers
+static KEY_ID: Mutex<Option<String>> = Mutex::new(None);
+
+/// The key ID to record in the header of a manifest being written.
+pub(crate) fn header_key_id() -> Option<String> {
+    KEY_ID.lock().unwrap().clone()
+}
+
+/// Where the minisign signature of `manifest_path` is kept.
+pub fn signature_path(manifest_path: &Path) -> PathBuf {
+    let mut path = manifest_path.as_os_str().to_owned();
+    path.push(".minisig");
+    PathBuf::from(path)
+}
+
+/// Run `run` and sign the manifest it wrote with `args.minisign_key`,
+/// including one rewritten by validation. The key is loaded first, so a
+/// wrong password fails before any hashing. Without a key, a validation that
+/// may rewrite a signed manifest is refused, as for GPG signatures.
+pub(crate) fn run_minisigned(args: &Args, run: impl FnOnce(&Args) -> Result<()>) -> Result<()> {
+    let Some(ref key_path) = args.minisign_key else {
+        let signature = signature_path(&args.output);
+        if args.validate && rewrites_manifest(args) && signature.exists() {
+            return Err(Error::Config(format!(
+                "{} is signed with minisign and validation may rewrite it; pass --minisign-key to sign it again",
+                args.output.display()
+            ))
+            .into());
+        }
+        run(args)?;
+        if !args.validate && args.output.exists() && signature.exists() {
+            eprintln!(
+                "Warning: {} was rewritten but not signed; {} no longer matches it (pass --minisign-key)",
+                args.output.display(),
+                signature.display()
+            );
+        }
+        return Ok(());
+    };
+    signed(key_path, args, run)
+}
+
+#[cfg(feature = "minisign")]
+fn signed(key_path: &Path, args: &Args, run: impl FnOnce(&Args) -> Result<()>) -> Result<()> {
+    use std::fs;
+
+    let key = load_secret_key(key_path)?;
+    *KEY_ID.lock().unwrap() = Some(key_id(key.keynum()));
+    let before = crate::file_state(&args.output);
+    let result = run(args);
+    *KEY_ID.lock().unwrap() = None;
+    result?;
+    let rewritten = if args.validate {
+        crate::file_state(&args.output) != before
+    } else {
+        !args.cancel.is_cancelled()
+    };
+    if !rewritten || !args.output.exists() {
+        return Ok(());
+    }
+
+    let signature = signature_path(&args.output);
+    let file = fs::File::open(&args.output).map_err(Error::io("Failed to open manifest file", &args.output))?;
+    let name = args.output.file_name().unwrap_or_default().to_string_lossy();
+    let trusted_comment = format!("timestamp:{}\tfile:{}", crate::now_secs(), name);
+    let signature_box = minisign::sign(None, &key, file, Some(&trusted_comment), None)
+        .map_err(|e| Error::Config(format!("Failed to sign {}: {}", args.output.display(), e)))?;
+    fs::write(&signature, signature_box.into_string()).map_err(Error::io("Failed to write signature", &signature))?;
+    status!("Signature written to: {}", signature.display());
+    Ok(())
+}
+
+#[cfg(not(feature = "minisign"))]
+fn signed(_key_path: &Path, _args: &Args, _run: impl FnOnce(&Args) -> Result<()>) -> Result<()> {
+    Err(Error::Config("--minisign-key needs data-manifest built with the minisign feature".into()).into())
+}
+
+/// minisign's form of a key ID: the little-endian number in hex.
+#[cfg(feature = "minisign")]
+fn key_id(keynum: &[u8]) -> String {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&keynum[..8]);
+    format!("{:016X}", u64::from_le_bytes(bytes))
+}
+
+/// Load a secret key file. Keys without a password load as they are; the
+/// password of others comes from `DATA_MANIFEST_MINISIGN_PASSWORD` or a
+/// prompt.
+#[cfg(feature = "minisign")]
+fn load_secret_key(path: &Path) -> Result<minisign::SecretKey> {
+    let text = std::fs::read_to_string(path).map_err(Error::io("Failed to read minisign secret key", path))?;
+    let invalid = |e: minisign::PError| Error::Config(format!("Cannot use minisign secret key {}: {}", path.display(), e));
+    if let Ok(key) = minisign::SecretKey::from_box(text.clone().into(), Some(String::new())) {
+        return Ok(key);
+    }
+    let password = std::env::var("DATA_MANIFEST_MINISIGN_PASSWORD").ok();
+    Ok(minisign::SecretKey::from_box(text.into(), password).map_err(invalid)?)
+}
+
+/// Check the minisign signature of `manifest_path` with `public_key`: a
+/// public key file, or the key itself as `minisign -P` takes it. Without a
+/// public key there is nothing to check against.
+pub(crate) fn verify_manifest(manifest_path: &Path, public_key: Option<&str>) -> Result<()> {
+    let Some(public_key) = public_key else {
+        return Ok(());
+    };
+    verify(manifest_path, public_key)
+}
+
+#[cfg(feature = "minisign")]
+fn verify(manifest_path: &Path, public_key: &str) -> Result<()> {
+    use std::fs;
+
+    let invalid = |message: String| -> anyhow::Error { Error::Validation { message, exit_code: 1 }.into() };
+    let key = if Path::new(public_key).is_file() {
+        minisign::PublicKey::from_file(public_key)
+    } else {
+        minisign::PublicKey::from_base64(public_key)
+    }
+    .map_err(|e| Error::Config(format!("Invalid minisign public key {}: {}", public_key, e)))?;
+    let expected = key_id(key.keynum());
+
+    let signature = signature_path(manifest_path);
+    if !signature.exists() {
+        return Err(invalid(format!("{} is not signed: {} does not exist", manifest_path.display(), signature.display())));
+    }
+    // The recorded key ID names the right public key before any checking
+    let mut reader = crate::ManifestReader::open(manifest_path)?;
+    reader.next().transpose()?;
+    if let Some(ref recorded) = reader.header().minisign_key {
+        if *recorded != expected {
+            return Err(invalid(format!(
+                "{} was signed with minisign key {}, not {}",
+                manifest_path.display(),
+                recorded,
+                expected
+            )));
+        }
+    }
+
+    let signature_box = minisign::SignatureBox::from_file(&signature)
+        .map_err(|e| invalid(format!("Invalid signature {}: {}", signature.display(), e)))?;
+    let file = fs::File::open(manifest_path).map_err(Error::io("Failed to open manifest file", manifest_path))?;
+    minisign::verify(&key, &signature_box, file, true, false, false).map_err(|e| {
+        invalid(format!("The signature {} of {} is not valid: {}", signature.display(), manifest_path.display(), e))
+    })?;
+    status!("Good minisign signature from key {}", expected);
+    Ok(())
+}
+
+#[cfg(not(feature = "minisign"))]
+fn verify(_manifest_path: &Path, _public_key: &str) -> Result<()> {
+    Err(Error::Config("--minisign-pubkey needs data-manifest built with the minisign feature".into()).into())
+}
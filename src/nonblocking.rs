@@ -0,0 +1,93 @@
+//! Async wrappers for services running on a tokio runtime. Each function runs
+//! the corresponding blocking operation on tokio's blocking thread pool, where
+//! file hashing still fans out over the rayon pool. Like [`crate::run`], the
+//! manifest modes apply the settings in their `Args` (hash cache, bandwidth
+//! limit, ...) and wait for each other, as those settings are process-wide.
+
+use anyhow::{Context, Result};
+use std::path::PathBuf;
+
+use crate::{Args, Manifest, ManifestBuilder, ValidationReport};
+
+async fn run_blocking<T: Send + 'static>(job: impl FnOnce() -> Result<T> + Send + 'static) -> Result<T> {
+    tokio::task::spawn_blocking(job).await.context("Manifest job panicked or was cancelled")?
+}
+
+/// Async version of [`crate::generate_manifest`].
+pub async fn generate_manifest(archive_path: PathBuf, manifest_path: PathBuf, archive_name: String, args: Args) -> Result<()> {
+    run_blocking(move || {
+        let _guard = crate::lock_run();
+        crate::configure_run(&args)?;
+        crate::generate_manifest(&archive_path, &manifest_path, &archive_name, &args)
+    })
+    .await
+}
+
+/// Async version of [`crate::update_manifest`].
+pub async fn update_manifest(archive_path: PathBuf, manifest_path: PathBuf, archive_name: String, args: Args) -> Result<()> {
+    run_blocking(move || {
+        let _guard = crate::lock_run();
+        crate::configure_run(&args)?;
+        crate::update_manifest(&archive_path, &manifest_path, &archive_name, &args)
+    })
+    .await
+}
+
+/// Async version of [`crate::validate_manifest`].
+pub async fn validate_manifest(archive_path: PathBuf, manifest_path: PathBuf, archive_name: String, args: Args) -> Result<()> {
+    run_blocking(move || {
+        let _guard = crate::lock_run();
+        crate::configure_run(&args)?;
+        crate::validate_manifest(&archive_path, &manifest_path, &archive_name, &args)
+    })
+    .await
+}
+
+/// Async version of [`ManifestBuilder::build`].
+pub async fn build(builder: ManifestBuilder) -> Result<Manifest> {
+    run_blocking(move || builder.build()).await
+}
+
+/// Async version of [`Manifest::validate`]. Takes the manifest by value and
+/// returns it alongside the report.
+pub async fn validate(manifest: Manifest, root: PathBuf) -> Result<(Manifest, ValidationReport)> {
+    run_blocking(move || {
+        let report = manifest.validate(&root)?;
+        Ok((manifest, report))
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use std::fs;
+
+    #[test]
+    fn validate_does_not_inherit_settings_of_an_earlier_run() {
+        let root = std::env::temp_dir().join(format!("data-manifest-async-{}", std::process::id()));
+        let archive = root.join("archive");
+        fs::create_dir_all(&archive).unwrap();
+        fs::write(archive.join("a.txt"), "a").unwrap();
+        let manifest = root.join("manifest.txt");
+        let [archive_arg, manifest_arg, cache_arg] =
+            [&archive, &manifest, &root.join("cache.tsv")].map(|path| path.to_string_lossy().to_string());
+
+        crate::run(&Args::parse_from([
+            "data-manifest", "-a", &archive_arg, "-o", &manifest_arg, "--bwlimit", "1G", "--hash-cache", &cache_arg,
+        ]))
+        .unwrap();
+        let args = Args::parse_from(["data-manifest", "-a", &archive_arg, "-o", &manifest_arg, "--validate"]);
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let result = runtime.block_on(validate_manifest(archive, manifest, "archive".to_string(), args));
+        let (limit, cached) = {
+            let _guard = crate::lock_run();
+            (crate::throttle::limit(), crate::hash_cache::enabled())
+        };
+        fs::remove_dir_all(&root).unwrap();
+        result.unwrap();
+        assert_eq!(limit, None);
+        assert!(!cached);
+    }
+}
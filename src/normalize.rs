@@ -0,0 +1,54 @@
+//! `--normalize-paths`: Unicode normalization of manifest paths. macOS
+//! file systems hand out names decomposed (NFD), while Linux and Windows keep
+//! them as they were written, usually composed (NFC), so the same accented
+//! file name differs byte for byte between systems. With a normalization
+//! form, the paths of new entries and of entries read from manifests are
+//! normalized, and entries match the files whatever form their names have on
+//! disk.
+//!
+//! The form is set once per run, like the bandwidth limit, because manifest
+//! paths are made and read in many places.
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU8, Ordering};
+use unicode_normalization::UnicodeNormalization;
+
+/// Unicode normalization form of manifest paths.
+#[derive(clap::ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PathNormalization {
+    /// Composed, as Linux and Windows usually store names
+    Nfc,
+    /// Decomposed, as macOS stores names
+    Nfd,
+    /// Paths as the filesystem gives them
+    None,
+}
+
+static FORM: AtomicU8 = AtomicU8::new(PathNormalization::None as u8);
+
+/// Normalize manifest paths to `form` for the rest of the run.
+pub(crate) fn set_form(form: PathNormalization) {
+    FORM.store(form as u8, Ordering::Relaxed);
+}
+
+fn form() -> PathNormalization {
+    match FORM.load(Ordering::Relaxed) {
+        x if x == PathNormalization::Nfc as u8 => PathNormalization::Nfc,
+        x if x == PathNormalization::Nfd as u8 => PathNormalization::Nfd,
+        _ => PathNormalization::None,
+    }
+}
+
+/// Whether manifest paths are being normalized.
+pub(crate) fn normalizing() -> bool {
+    form() != PathNormalization::None
+}
+
+/// A manifest path in the normalization form of the run.
+pub(crate) fn manifest_path(path: String) -> PathBuf {
+    PathBuf::from(match form() {
+        PathNormalization::Nfc => path.nfc().collect(),
+        PathNormalization::Nfd => path.nfd().collect(),
+        PathNormalization::None => path,
+    })
+}
@@ -0,0 +1,216 @@
+//! Notifications after validation and update runs, sent by the notifiers
+//! configured in the `--config` file:
+//!
+//! ```toml
+//! top_files = 10
+//!
+//! [[notify]]
+//! type = "slack"
+//! webhook = "https://hooks.slack.com/services/..."
+//!
+//! [[notify]]
+//! type = "smtp"
+//! server = "smtp.example.org"
+//! username = "fixity"
+//! password_env = "SMTP_PASSWORD"
+//! from = "fixity@example.org"
+//! to = ["archive-ops@example.org"]
+//! on = "always"
+//! ```
+
+use anyhow::Result;
+use serde::Deserialize;
+use serde_json::Value;
+use std::fmt::Write;
+
+use crate::config::Config;
+use crate::report::{last_summary, summary_counts};
+use crate::{archive_label, eventlog, run_mode, Args, Error};
+
+/// Which runs a notifier reports.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum NotifyOn {
+    /// Every run
+    Always,
+    /// Runs that failed validation or ended with an error
+    #[default]
+    Failure,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpTls {
+    /// Plain SMTP, for a relay on the local host or network
+    None,
+    /// Upgrade the connection with STARTTLS (port 587 by default)
+    #[default]
+    Starttls,
+    /// TLS from the start (port 465 by default)
+    Tls,
+}
+
+#[cfg_attr(not(feature = "notify"), allow(dead_code))]
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum NotifierConfig {
+    /// A Slack incoming webhook
+    Slack {
+        webhook: String,
+        #[serde(default)]
+        on: NotifyOn,
+    },
+    /// Email through an SMTP server
+    Smtp {
+        server: String,
+        port: Option<u16>,
+        #[serde(default)]
+        tls: SmtpTls,
+        username: Option<String>,
+        /// Environment variable holding the password, so it stays out of the file
+        password_env: Option<String>,
+        from: String,
+        to: Vec<String>,
+        #[serde(default)]
+        on: NotifyOn,
+    },
+}
+
+/// Problem lists of the validation report and the update summary, in the
+/// order they are listed.
+const PROBLEM_LISTS: &[(&str, &str)] = &[
+    ("mismatched", "mismatched"),
+    ("missing", "missing"),
+    ("metadata_changed", "metadata changed"),
+    ("changed", "changed"),
+    ("removed", "removed"),
+    ("new", "new"),
+    ("added", "added"),
+    ("renamed", "renamed"),
+];
+
+#[cfg_attr(not(feature = "notify"), allow(dead_code))]
+struct Notification {
+    subject: String,
+    body: String,
+}
+
+fn format_notification(args: &Args, result: &Result<()>, top_files: usize) -> Notification {
+    let outcome = match result {
+        Ok(()) => "passed",
+        Err(e) if matches!(e.downcast_ref::<Error>(), Some(Error::Validation { .. })) => "FAILED",
+        Err(_) => "ERROR",
+    };
+    let archive = archive_label(args);
+    let subject = format!("data-manifest {} {} on {}: {}", run_mode(args), archive, eventlog::hostname(), outcome);
+
+    let mut body = String::new();
+    let _ = writeln!(body, "Archive: {}", archive);
+    let _ = writeln!(body, "Manifest: {}", args.output.display());
+    if let Err(e) = result {
+        let _ = writeln!(body, "{:#}", e);
+    }
+    let summary = last_summary().unwrap_or(Value::Null);
+    let counts = summary_counts(&summary);
+    if !counts.is_empty() {
+        let _ = writeln!(body, "Summary: {}", counts.join(", "));
+    }
+
+    let problems: Vec<(&str, String)> = PROBLEM_LISTS
+        .iter()
+        .flat_map(|&(key, label)| {
+            let entries = summary.get(key).and_then(Value::as_array).map(Vec::as_slice).unwrap_or_default();
+            entries.iter().filter_map(move |entry| {
+                let path = entry.get("path").or_else(|| entry.get("to"))?.as_str()?;
+                Some((label, path.to_string()))
+            })
+        })
+        .collect();
+    if !problems.is_empty() {
+        let heading = if args.validate { "Problem files" } else { "Changed files" };
+        let _ = writeln!(body, "\n{} ({} of {}):", heading, problems.len().min(top_files), problems.len());
+        for (label, path) in problems.iter().take(top_files) {
+            let _ = writeln!(body, "  {:<17} {}", label, path);
+        }
+    }
+    Notification { subject, body }
+}
+
+/// Send the notifications configured for this run's outcome. Failures to
+/// notify are reported but do not change the outcome of the run.
+pub(crate) fn notify(config: &Config, args: &Args, result: &Result<()>) {
+    if config.notify.is_empty() || !(args.validate || args.update) || args.cancel.is_cancelled() {
+        return;
+    }
+    let notification = format_notification(args, result, config.top_files);
+    for notifier in &config.notify {
+        let (on, kind) = match notifier {
+            NotifierConfig::Slack { on, .. } => (*on, "Slack"),
+            NotifierConfig::Smtp { on, .. } => (*on, "email"),
+        };
+        if on == NotifyOn::Failure && result.is_ok() {
+            continue;
+        }
+        match send(notifier, &notification) {
+            Ok(()) => status!("Sent {} notification", kind),
+            Err(e) => eprintln!("Failed to send {} notification: {:#}", kind, e),
+        }
+    }
+}
+
+#[cfg(feature = "notify")]
+#[derive(serde::Serialize)]
+struct SlackMessage<'a> {
+    text: &'a str,
+}
+
+#[cfg(feature = "notify")]
+fn send(notifier: &NotifierConfig, notification: &Notification) -> Result<()> {
+    use anyhow::Context;
+    use lettre::message::header::ContentType;
+    use lettre::transport::smtp::authentication::Credentials;
+    use lettre::{SmtpTransport, Transport};
+
+    match notifier {
+        NotifierConfig::Slack { webhook, .. } => {
+            let text = format!("*{}*\n```{}```", notification.subject, notification.body.trim_end());
+            let body = serde_json::to_string(&SlackMessage { text: &text })?;
+            ureq::post(webhook)
+                .set("content-type", "application/json")
+                .send_string(&body)
+                .context("Slack webhook request failed")?;
+        }
+        NotifierConfig::Smtp { server, port, tls, username, password_env, from, to, .. } => {
+            let mut message = lettre::Message::builder()
+                .from(from.parse().with_context(|| format!("Invalid from address {}", from))?)
+                .subject(&notification.subject)
+                .header(ContentType::TEXT_PLAIN);
+            for to in to {
+                message = message.to(to.parse().with_context(|| format!("Invalid to address {}", to))?);
+            }
+            let message = message.body(notification.body.clone())?;
+            let mut transport = match tls {
+                SmtpTls::None => SmtpTransport::builder_dangerous(server),
+                SmtpTls::Starttls => SmtpTransport::starttls_relay(server)?,
+                SmtpTls::Tls => SmtpTransport::relay(server)?,
+            };
+            if let Some(port) = port {
+                transport = transport.port(*port);
+            }
+            if let Some(username) = username {
+                let password = match password_env {
+                    Some(name) => std::env::var(name).map_err(|_| Error::Config(format!("{} is not set", name)))?,
+                    None => String::new(),
+                };
+                transport = transport.credentials(Credentials::new(username.clone(), password));
+            }
+            transport.build().send(&message).with_context(|| format!("Sending mail through {} failed", server))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "notify"))]
+fn send(_notifier: &NotifierConfig, _notification: &Notification) -> Result<()> {
+    Err(Error::Config("Notifiers need data-manifest built with the notify feature".into()).into())
+}
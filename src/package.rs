@@ -0,0 +1,194 @@
+//! `package`: a directory written into one tar file, optionally zstd
+//! compressed, whose first member is the manifest of the files after it.
+//!
+//! The manifest has to be written before the files it describes, so every
+//! file is hashed up front and hashed again as it is copied into the tar. A
+//! file that changed in between fails the package instead of leaving a
+//! manifest that does not match its contents.
+
+use anyhow::Result;
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::progress::Progress;
+use crate::remote::HashingReader;
+use crate::{
+    collect_files, hash_entry, manifest_key, now_secs, write_manifest_to, Args, Error, FileInfo, HashAlgorithm,
+    ManifestEntry,
+};
+
+/// Name of the manifest member, ahead of the `<archive name>/...` tree it
+/// describes.
+pub const MANIFEST_MEMBER: &str = "manifest.txt";
+
+/// zstd level for `--zstd`, the library's default trade-off of speed and size
+#[cfg(feature = "zstd")]
+const ZSTD_LEVEL: i32 = 3;
+
+/// The package file, compressed or not.
+enum Output {
+    Plain(BufWriter<fs::File>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::Encoder<'static, BufWriter<fs::File>>),
+}
+
+impl Output {
+    fn create(path: &Path, zstd: bool) -> Result<Self> {
+        let file = BufWriter::new(fs::File::create(path).map_err(Error::io("Failed to create package", path))?);
+        if !zstd {
+            return Ok(Output::Plain(file));
+        }
+        #[cfg(feature = "zstd")]
+        return Ok(Output::Zstd(zstd::Encoder::new(file, ZSTD_LEVEL)?));
+        #[cfg(not(feature = "zstd"))]
+        Err(Error::Config("--zstd needs data-manifest built with the zstd feature".into()).into())
+    }
+
+    /// Complete the compressed stream and flush everything to disk.
+    #[cfg_attr(not(feature = "zstd"), allow(clippy::infallible_destructuring_match))]
+    fn finish(self) -> Result<()> {
+        let file = match self {
+            Output::Plain(file) => file,
+            #[cfg(feature = "zstd")]
+            Output::Zstd(encoder) => encoder.finish()?,
+        };
+        file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        Ok(())
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Output::Plain(file) => file.write(buf),
+            #[cfg(feature = "zstd")]
+            Output::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Output::Plain(file) => file.flush(),
+            #[cfg(feature = "zstd")]
+            Output::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Write the files under `source` into the tar file `output`, preceded by
+/// their manifest as [`MANIFEST_MEMBER`]. Files are stored as
+/// `<archive_name>/<relative path>`, the paths the manifest records, so the
+/// extracted tree validates against it directly. The package is removed
+/// again if it cannot be completed.
+pub fn run_package(source: &Path, output: &Path, zstd: bool, archive_name: Option<&str>, args: &Args) -> Result<()> {
+    if zstd && !cfg!(feature = "zstd") {
+        return Err(Error::Config("--zstd needs data-manifest built with the zstd feature".into()).into());
+    }
+    if args.descend_archives {
+        return Err(Error::Config("package stores container files as they are; drop --descend-archives".into()).into());
+    }
+    let source = fs::canonicalize(source).map_err(Error::io("Source path does not exist", source))?;
+    let archive_name = match archive_name {
+        Some(name) => name.to_string(),
+        None => source.file_name().unwrap_or(source.as_os_str()).to_string_lossy().to_string(),
+    };
+
+    status!("Scanning source: {}", source.display());
+    let mut files = collect_files(&source, args)?;
+    if args.cancel.is_cancelled() {
+        return Err(Error::Cancelled.into());
+    }
+    // A package written inside the source must not include itself
+    let package_path = output
+        .parent()
+        .map(|parent| if parent.as_os_str().is_empty() { Path::new(".") } else { parent })
+        .and_then(|parent| fs::canonicalize(parent).ok())
+        .zip(output.file_name())
+        .map(|(parent, name)| parent.join(name));
+    files.retain(|file_info| Some(&file_info.path) != package_path.as_ref());
+    status!("Found {} files", files.len());
+
+    let progress = Progress::start(files.len() as u64, 0);
+    let entries: HashMap<PathBuf, ManifestEntry> = files
+        .par_iter()
+        .map(|file_info| {
+            if args.cancel.is_cancelled() {
+                return Err(Error::Cancelled.into());
+            }
+            progress.file_started(&file_info.path);
+            let result = hash_entry(file_info, &source, &archive_name, args);
+            progress.file_done(&file_info.path, &result);
+            result
+        })
+        .collect::<Result<_>>()?;
+    progress.finish("Hashing complete");
+
+    status!("Writing package: {}", output.display());
+    let mut keyed: Vec<_> = files
+        .iter()
+        .map(|file_info| (manifest_key(&file_info.path, &source, &archive_name), file_info))
+        .collect();
+    keyed.sort_by(|a, b| a.0.cmp(&b.0));
+    let written = write_package(output, zstd, &keyed, &entries, args);
+    if written.is_err() {
+        let _ = fs::remove_file(output);
+    }
+    let bytes = written?;
+
+    status!("Package written to: {}", output.display());
+    status!("Packaged: {} files, {} bytes", entries.len(), bytes);
+    Ok(())
+}
+
+/// Write the manifest and then every file, returning the bytes of file data.
+fn write_package(
+    output: &Path,
+    zstd: bool,
+    files: &[(PathBuf, &FileInfo)],
+    entries: &HashMap<PathBuf, ManifestEntry>,
+    args: &Args,
+) -> Result<u64> {
+    let mut builder = tar::Builder::new(Output::create(output, zstd)?);
+
+    let mut manifest = Vec::new();
+    write_manifest_to(&mut manifest, entries, args.writes_extended())?;
+    let mut header = tar::Header::new_gnu();
+    header.set_size(manifest.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(now_secs() as u64);
+    builder.append_data(&mut header, MANIFEST_MEMBER, manifest.as_slice())?;
+
+    let progress = Progress::start(files.len() as u64, 0);
+    let mut bytes = 0;
+    for (key, file_info) in files {
+        if args.cancel.is_cancelled() {
+            return Err(Error::Cancelled.into());
+        }
+        progress.file_started(&file_info.path);
+        let file = fs::File::open(&file_info.path).map_err(Error::io("Failed to open file", &file_info.path))?;
+        let metadata = file.metadata().map_err(Error::io("Failed to read metadata", &file_info.path))?;
+        let expected = &entries[key];
+        let mut header = tar::Header::new_gnu();
+        header.set_metadata(&metadata);
+        // The header promises exactly the hashed size; a file that grew or
+        // shrank is caught by the size or the hash
+        header.set_size(file_info.size);
+        let mut reader = HashingReader::new(file.take(file_info.size), HashAlgorithm::Sha256);
+        builder.append_data(&mut header, key, &mut reader)?;
+        if reader.finish() != expected.hash || metadata.len() != file_info.size {
+            return Err(Error::Config(format!(
+                "{} changed while it was being packaged; run package again",
+                file_info.path.display()
+            ))
+            .into());
+        }
+        bytes += file_info.size;
+        progress.file_finished(&file_info.path);
+    }
+    progress.finish("Package complete");
+    builder.into_inner()?.finish()?;
+    Ok(bytes)
+}
@@ -0,0 +1,160 @@
+use indicatif::{ProgressBar, ProgressStyle};
+use std::fmt::Display;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::{system_log, timing};
+
+/// Receives progress notifications while files are hashed. Every method has
+/// an empty default so implementations only override what they render.
+/// Methods are called concurrently from the hashing threads.
+pub trait ProgressSink: Send + Sync {
+    /// A pass over `total_files` files begins; `completed` of them were already
+    /// done by an earlier, resumed run.
+    fn started(&self, _total_files: u64, _completed: u64) {}
+
+    fn file_started(&self, _path: &Path) {}
+
+    /// A chunk of `bytes` was read and hashed.
+    fn bytes_hashed(&self, _bytes: u64) {}
+
+    /// A file was processed (hashed, or skipped because it did not need to be).
+    fn file_finished(&self, _path: &Path) {}
+
+    /// A file could not be processed. No `file_finished` follows.
+    fn error(&self, _path: &Path, _message: &str) {}
+
+    /// The pass is complete.
+    fn finished(&self, _message: &str) {}
+}
+
+static SINK: RwLock<Option<Arc<dyn ProgressSink>>> = RwLock::new(None);
+
+/// Whether the installed sink is the `--progress` bar put up by a run.
+static RUN_BAR: AtomicBool = AtomicBool::new(false);
+
+/// Install the sink that receives progress from every later operation, or
+/// remove it with `None`.
+pub fn set_progress_sink(sink: Option<Arc<dyn ProgressSink>>) {
+    let mut current = SINK.write().unwrap_or_else(|e| e.into_inner());
+    *current = sink;
+    RUN_BAR.store(false, Ordering::Relaxed);
+}
+
+/// Put up a progress bar for a run with `--progress`, or take down the one
+/// an earlier run put up. Sinks installed with [`set_progress_sink`] stay.
+pub(crate) fn configure(show_bar: bool) {
+    let mut current = SINK.write().unwrap_or_else(|e| e.into_inner());
+    if show_bar {
+        *current = Some(Arc::new(IndicatifProgress::default()));
+        RUN_BAR.store(true, Ordering::Relaxed);
+    } else if RUN_BAR.swap(false, Ordering::Relaxed) {
+        *current = None;
+    }
+}
+
+fn current_sink() -> Option<Arc<dyn ProgressSink>> {
+    SINK.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Report bytes read by a hashing loop to the installed sink.
+pub(crate) fn bytes_hashed(bytes: usize) {
+    timing::bytes_read(bytes);
+    if let Some(sink) = current_sink() {
+        sink.bytes_hashed(bytes as u64);
+    }
+}
+
+/// One pass over a set of files, reporting to the sink installed when it started.
+pub(crate) struct Progress {
+    sink: Option<Arc<dyn ProgressSink>>,
+}
+
+impl Progress {
+    pub fn start(total_files: u64, completed: u64) -> Self {
+        let sink = current_sink();
+        if let Some(ref sink) = sink {
+            sink.started(total_files, completed);
+        }
+        Progress { sink }
+    }
+
+    pub fn file_started(&self, path: &Path) {
+        timing::file_started();
+        if let Some(ref sink) = self.sink {
+            sink.file_started(path);
+        }
+    }
+
+    pub fn file_finished(&self, path: &Path) {
+        timing::file_finished();
+        if let Some(ref sink) = self.sink {
+            sink.file_finished(path);
+        }
+    }
+
+    pub fn error(&self, path: &Path, error: &dyn Display) {
+        timing::file_finished();
+        let message = format!("{:#}", error);
+        system_log::file_error(path, &message);
+        if let Some(ref sink) = self.sink {
+            sink.error(path, &message);
+        }
+    }
+
+    /// Report the outcome of processing `path`.
+    pub fn file_done<T, E: Display>(&self, path: &Path, result: &std::result::Result<T, E>) {
+        match result {
+            Ok(_) => self.file_finished(path),
+            Err(e) => self.error(path, e),
+        }
+    }
+
+    pub fn finish(self, message: &str) {
+        if let Some(ref sink) = self.sink {
+            sink.finished(message);
+        }
+    }
+}
+
+/// The command line's `--progress` bar: one tick per file.
+#[derive(Default)]
+pub struct IndicatifProgress {
+    bar: Mutex<Option<ProgressBar>>,
+}
+
+impl IndicatifProgress {
+    fn tick(&self) {
+        if let Some(ref bar) = *self.bar.lock().unwrap_or_else(|e| e.into_inner()) {
+            bar.inc(1);
+        }
+    }
+}
+
+impl ProgressSink for IndicatifProgress {
+    fn started(&self, total_files: u64, completed: u64) {
+        let bar = ProgressBar::new(total_files);
+        bar.set_style(
+            ProgressStyle::default_bar()
+                .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({eta})")
+                .progress_chars("#>-"),
+        );
+        bar.set_position(completed);
+        *self.bar.lock().unwrap_or_else(|e| e.into_inner()) = Some(bar);
+    }
+
+    fn file_finished(&self, _path: &Path) {
+        self.tick();
+    }
+
+    fn error(&self, _path: &Path, _message: &str) {
+        self.tick();
+    }
+
+    fn finished(&self, message: &str) {
+        if let Some(bar) = self.bar.lock().unwrap_or_else(|e| e.into_inner()).take() {
+            bar.finish_with_message(message.to_string());
+        }
+    }
+}
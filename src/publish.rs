@@ -0,0 +1,99 @@
+//! Pushing finished runs to a central fixity registry: `--publish <URL>`
+//! POSTs the manifest and the run's summary as one JSON document, with
+//! `DATA_MANIFEST_PUBLISH_TOKEN` as a bearer token when it is set.
+
+use anyhow::Result;
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+
+use crate::report::last_summary;
+use crate::{archive_label, eventlog, now_secs, run_mode, Args, Error};
+
+/// The document sent to the registry.
+#[derive(Serialize)]
+struct Publication<'a> {
+    host: String,
+    /// Archive path or URL
+    archive: String,
+    mode: &'static str,
+    started: i64,
+    finished: i64,
+    /// `passed`, `failed` when validation found problems, or `error`
+    outcome: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    manifest_path: &'a Path,
+    /// The manifest text; absent when the run left no manifest
+    manifest: Option<String>,
+    summary: Option<serde_json::Value>,
+}
+
+/// Run `run` and publish its outcome to `url`. Failed runs are published
+/// too; a failed publication fails an otherwise successful run.
+pub(crate) fn run_published(url: &str, args: &Args, run: impl FnOnce(&Args) -> Result<()>) -> Result<()> {
+    if !cfg!(feature = "publish") {
+        return Err(Error::Config("--publish needs data-manifest built with the publish feature".into()).into());
+    }
+    let started = now_secs();
+    let result = run(args);
+    if args.cancel.is_cancelled() {
+        return result;
+    }
+
+    let (outcome, error) = match result {
+        Ok(()) => ("passed", None),
+        Err(ref e) => match e.downcast_ref::<Error>() {
+            Some(Error::Validation { .. }) => ("failed", Some(format!("{:#}", e))),
+            _ => ("error", Some(format!("{:#}", e))),
+        },
+    };
+    let publication = Publication {
+        host: eventlog::hostname(),
+        archive: archive_label(args),
+        mode: run_mode(args),
+        started,
+        finished: now_secs(),
+        outcome,
+        error,
+        manifest_path: &args.output,
+        manifest: fs::read_to_string(&args.output).ok(),
+        summary: last_summary(),
+    };
+    match (result, send(url, &publication)) {
+        (Ok(()), sent) => sent,
+        (Err(e), Ok(())) => Err(e),
+        (Err(e), Err(publish_error)) => {
+            eprintln!("Failed to publish the results: {:#}", publish_error);
+            Err(e)
+        }
+    }
+}
+
+#[cfg(feature = "publish")]
+fn send(url: &str, publication: &Publication) -> Result<()> {
+    let body = serde_json::to_string(publication)?;
+    let mut request = ureq::post(url).set("content-type", "application/json");
+    if let Ok(token) = std::env::var("DATA_MANIFEST_PUBLISH_TOKEN") {
+        request = request.set("authorization", &format!("Bearer {}", token));
+    }
+    match request.send_string(&body) {
+        Ok(_) => {
+            status!("Results published to: {}", url);
+            Ok(())
+        }
+        Err(ureq::Error::Status(code, response)) => Err(Error::Config(format!(
+            "Publishing to {} failed with HTTP {} {}",
+            url,
+            code,
+            response.status_text()
+        ))
+        .into()),
+        Err(e) => Err(anyhow::Error::new(e).context(format!("Failed to publish to {}", url))),
+    }
+}
+
+#[cfg(not(feature = "publish"))]
+fn send(_url: &str, _publication: &Publication) -> Result<()> {
+    Err(Error::Config("--publish needs data-manifest built with the publish feature".into()).into())
+}
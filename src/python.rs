@@ -0,0 +1,189 @@
+//! The `data_manifest` Python extension module, built with maturin from the
+//! `python` feature.
+//!
+//! ```python
+//! import data_manifest
+//!
+//! manifest = data_manifest.generate("/data/archive", exclude=["*.tmp"],
+//!                                   progress=lambda done, total, path: print(done, total))
+//! manifest.write("manifest.txt")
+//! report = data_manifest.Manifest.load("manifest.txt").validate("/mnt/replica/archive")
+//! assert report["passed"]
+//! ```
+
+// The code generated by pyo3's macros converts PyErr into itself
+#![allow(clippy::useless_conversion)]
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use pyo3::exceptions::{PyOSError, PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use serde::Serialize;
+
+use crate::{load_existing_manifest, set_progress_sink, Error, HashAlgorithm, Manifest, ProgressSink};
+
+fn to_py_err(e: anyhow::Error) -> PyErr {
+    let message = format!("{:#}", e);
+    match e.downcast_ref::<Error>() {
+        Some(Error::Io { .. }) => PyOSError::new_err(message),
+        Some(Error::Config(_)) => PyValueError::new_err(message),
+        _ => PyRuntimeError::new_err(message),
+    }
+}
+
+/// Convert a report into Python objects through its JSON form, so Python sees
+/// the same structure as `--report-file` writes.
+fn to_py_object(py: Python<'_>, value: &impl Serialize) -> PyResult<PyObject> {
+    let json = serde_json::to_string(value).map_err(|e| PyRuntimeError::new_err(e.to_string()))?;
+    Ok(py.import_bound("json")?.call_method1("loads", (json,))?.unbind())
+}
+
+/// Calls a Python `progress(done, total, path)` callable after each file.
+struct CallbackProgress {
+    callback: PyObject,
+    total: AtomicU64,
+    done: AtomicU64,
+}
+
+impl ProgressSink for CallbackProgress {
+    fn started(&self, total_files: u64, completed: u64) {
+        self.total.store(total_files, Ordering::Relaxed);
+        self.done.store(completed, Ordering::Relaxed);
+    }
+
+    fn file_finished(&self, path: &Path) {
+        let done = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+        let total = self.total.load(Ordering::Relaxed);
+        Python::with_gil(|py| {
+            if let Err(e) = self.callback.call1(py, (done, total, path.to_string_lossy())) {
+                // Exceptions cannot cross the hashing threads; report them like Python does for callbacks
+                e.print(py);
+            }
+        });
+    }
+
+    fn error(&self, path: &Path, _message: &str) {
+        self.file_finished(path);
+    }
+}
+
+/// Run `job` without holding the GIL, reporting progress to `progress` if given.
+fn with_progress<T: Send>(py: Python<'_>, progress: Option<PyObject>, job: impl FnOnce() -> anyhow::Result<T> + Send) -> PyResult<T> {
+    let has_progress = progress.is_some();
+    if let Some(callback) = progress {
+        set_progress_sink(Some(Arc::new(CallbackProgress {
+            callback,
+            total: AtomicU64::new(0),
+            done: AtomicU64::new(0),
+        })));
+    }
+    let result = py.allow_threads(job);
+    if has_progress {
+        set_progress_sink(None);
+    }
+    result.map_err(to_py_err)
+}
+
+/// A manifest held in memory.
+#[pyclass(name = "Manifest", module = "data_manifest")]
+struct PyManifest {
+    inner: Manifest,
+}
+
+#[pymethods]
+impl PyManifest {
+    /// Read a manifest file (plain, gzip, or zstd).
+    #[staticmethod]
+    fn load(path: PathBuf) -> PyResult<Self> {
+        if !path.exists() {
+            return Err(PyOSError::new_err(format!("Manifest does not exist: {}", path.display())));
+        }
+        let inner = load_existing_manifest(&path).map_err(to_py_err)?;
+        Ok(PyManifest { inner })
+    }
+
+    fn write(&self, path: PathBuf) -> PyResult<()> {
+        self.inner.write(path).map_err(to_py_err)
+    }
+
+    /// Re-hash every entry under `root` and return the validation report as a dict.
+    #[pyo3(signature = (root, progress=None))]
+    fn validate(&self, py: Python<'_>, root: PathBuf, progress: Option<PyObject>) -> PyResult<PyObject> {
+        let report = with_progress(py, progress, || self.inner.validate(&root))?;
+        to_py_object(py, &report)
+    }
+
+    /// Entries added, removed, changed, or renamed in `other`, as a dict.
+    fn diff(&self, py: Python<'_>, other: &PyManifest) -> PyResult<PyObject> {
+        to_py_object(py, &self.inner.diff(&other.inner))
+    }
+
+    #[getter]
+    fn algorithm(&self) -> &'static str {
+        self.inner.algorithm.name()
+    }
+
+    #[getter]
+    fn extended(&self) -> bool {
+        self.inner.extended
+    }
+
+    /// Map of recorded path to hash.
+    #[getter]
+    fn entries<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let entries = PyDict::new_bound(py);
+        for (path, entry) in &self.inner.entries {
+            entries.set_item(path.to_string_lossy(), &entry.hash)?;
+        }
+        Ok(entries)
+    }
+
+    fn __len__(&self) -> usize {
+        self.inner.entries.len()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("<Manifest {} entries, {}>", self.inner.entries.len(), self.inner.algorithm.name())
+    }
+}
+
+/// Walk and hash `root`, returning a `Manifest`. `progress(done, total, path)`
+/// is called after each file.
+#[pyfunction]
+#[pyo3(signature = (root, *, algorithm="sha256", archive_name=None, exclude=Vec::new(), extended=false, threads=None, progress=None))]
+#[allow(clippy::too_many_arguments)]
+fn generate(
+    py: Python<'_>,
+    root: PathBuf,
+    algorithm: &str,
+    archive_name: Option<String>,
+    exclude: Vec<String>,
+    extended: bool,
+    threads: Option<usize>,
+    progress: Option<PyObject>,
+) -> PyResult<PyManifest> {
+    let algorithm = HashAlgorithm::from_name(algorithm)
+        .ok_or_else(|| PyValueError::new_err(format!("Unsupported hash algorithm: {}", algorithm)))?;
+    let mut builder = Manifest::builder().root(root).algorithm(algorithm).extended(extended);
+    if let Some(name) = archive_name {
+        builder = builder.archive_name(name);
+    }
+    if let Some(threads) = threads {
+        builder = builder.threads(threads);
+    }
+    for pattern in exclude {
+        builder = builder.exclude(pattern);
+    }
+    let inner = with_progress(py, progress, || builder.build())?;
+    Ok(PyManifest { inner })
+}
+
+#[pymodule]
+fn data_manifest(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyManifest>()?;
+    m.add_function(wrap_pyfunction!(generate, m)?)?;
+    Ok(())
+}
@@ -0,0 +1,65 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::Path;
+
+use crate::archive_file_path;
+use crate::report::{QuarantineEntry, ValidationReport};
+
+/// How corrupted files are placed in the quarantine tree.
+#[derive(clap::ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QuarantineMode {
+    /// Move the file out of the archive
+    Move,
+    /// Hard-link the file, leaving the original in place
+    Link,
+}
+
+fn move_file(source: &Path, target: &Path) -> Result<()> {
+    if fs::rename(source, target).is_ok() {
+        return Ok(());
+    }
+    // Quarantine may be on another filesystem
+    fs::copy(source, target)
+        .with_context(|| format!("Failed to copy {} to {}", source.display(), target.display()))?;
+    fs::remove_file(source).with_context(|| format!("Failed to remove {}", source.display()))?;
+    Ok(())
+}
+
+/// Place every mismatched file under `quarantine_path`, preserving its path
+/// relative to the archive root.
+pub fn quarantine_mismatched(
+    report: &mut ValidationReport,
+    archive_path: &Path,
+    quarantine_path: &Path,
+    archive_name: &str,
+    mode: QuarantineMode,
+) -> Result<()> {
+    for entry in &report.mismatched {
+        let source = archive_file_path(&entry.path, archive_path, archive_name);
+        let target = archive_file_path(&entry.path, quarantine_path, archive_name);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+        }
+        if target.exists() {
+            eprintln!("Warning: Not quarantining {}: {} already exists", entry.path.display(), target.display());
+            continue;
+        }
+        let result = match mode {
+            QuarantineMode::Move => move_file(&source, &target),
+            QuarantineMode::Link => fs::hard_link(&source, &target)
+                .with_context(|| format!("Failed to link {} to {}", source.display(), target.display())),
+        };
+        match result {
+            Ok(()) => {
+                status!("Quarantined {} to {}", entry.path.display(), target.display());
+                report.quarantined.push(QuarantineEntry {
+                    path: entry.path.clone(),
+                    location: target,
+                });
+            }
+            Err(e) => eprintln!("Warning: Could not quarantine {}: {:#}", entry.path.display(), e),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,138 @@
+//! Any storage rclone can reach, addressed as `rclone://remote/path` for the
+//! rclone path `remote:path`.
+//!
+//! Remotes come from the user's rclone configuration (`rclone config`, or
+//! `RCLONE_CONFIG` and the `RCLONE_*` environment variables), so every
+//! backend rclone supports can be hashed the same way. Files are listed with
+//! `rclone lsjson` and read with `rclone cat`.
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdout, Command, Stdio};
+
+use crate::remote::{object_key, parse_timestamp, split_url};
+use crate::{Args, Error, FileInfo, FileSource};
+
+/// rclone exits with these when a directory or file does not exist
+const EXIT_DIR_NOT_FOUND: i32 = 3;
+const EXIT_FILE_NOT_FOUND: i32 = 4;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListedFile {
+    path: String,
+    size: i64,
+    mod_time: Option<String>,
+    #[serde(default)]
+    is_dir: bool,
+}
+
+/// Streams a file from `rclone cat`, reporting a failed exit as an error
+/// once the output ends.
+struct CatReader {
+    child: Child,
+    stdout: ChildStdout,
+    target: String,
+}
+
+impl Read for CatReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.stdout.read(buf)?;
+        if n > 0 || buf.is_empty() {
+            return Ok(n);
+        }
+        let status = self.child.wait()?;
+        if status.success() {
+            return Ok(0);
+        }
+        let mut stderr = String::new();
+        if let Some(mut pipe) = self.child.stderr.take() {
+            let _ = pipe.read_to_string(&mut stderr);
+        }
+        let kind = match status.code() {
+            Some(EXIT_DIR_NOT_FOUND | EXIT_FILE_NOT_FOUND) => io::ErrorKind::NotFound,
+            _ => io::ErrorKind::Other,
+        };
+        let message = stderr.lines().last().unwrap_or_default().trim().to_string();
+        Err(io::Error::new(kind, format!("rclone cat {} failed ({}): {}", self.target, status, message)))
+    }
+}
+
+impl Drop for CatReader {
+    fn drop(&mut self) {
+        // Stop a transfer that was abandoned part way
+        if let Ok(None) = self.child.try_wait() {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+/// The files below an `rclone://remote/path` URL.
+pub struct RcloneSource {
+    /// `remote:path`
+    root: String,
+}
+
+impl RcloneSource {
+    pub fn open(url: &str, _args: &Args) -> Result<Self> {
+        let (remote, path) = split_url(url)?;
+        Ok(RcloneSource {
+            root: format!("{}:{}", remote, path),
+        })
+    }
+
+    fn target(&self, path: &Path) -> String {
+        match self.root.strip_suffix(':') {
+            Some(remote) => format!("{}:{}", remote, object_key("", path)),
+            None => format!("{}/{}", self.root, object_key("", path)),
+        }
+    }
+}
+
+fn rclone() -> Command {
+    let mut command = Command::new("rclone");
+    command.stdin(Stdio::null());
+    command
+}
+
+impl FileSource for RcloneSource {
+    fn files(&self, args: &Args) -> Result<Vec<FileInfo>> {
+        let mut command = rclone();
+        command.args(["lsjson", "--recursive", "--files-only", "--no-mimetype"]);
+        if let Some(max_depth) = args.max_depth {
+            command.args(["--max-depth", &max_depth.to_string()]);
+        }
+        if args.follow_symlinks {
+            command.arg("--copy-links");
+        }
+        let output = command
+            .arg(&self.root)
+            .stderr(Stdio::inherit())
+            .output()
+            .map_err(|e| Error::Config(format!("Failed to run rclone: {}", e)))?;
+        if !output.status.success() {
+            return Err(Error::Config(format!("rclone lsjson {} failed ({})", self.root, output.status)).into());
+        }
+        let listed: Vec<ListedFile> = serde_json::from_slice(&output.stdout).context("Invalid rclone lsjson output")?;
+        Ok(listed
+            .into_iter()
+            .filter(|file| !file.is_dir)
+            .map(|file| FileInfo {
+                path: PathBuf::from(file.path),
+                // Some backends report -1 for sizes they do not know
+                size: file.size.max(0) as u64,
+                mtime: file.mod_time.as_deref().and_then(parse_timestamp),
+            })
+            .collect())
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send>> {
+        let target = self.target(path);
+        let mut child = rclone().arg("cat").arg(&target).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+        let stdout = child.stdout.take().expect("piped stdout");
+        Ok(Box::new(CatReader { child, stdout, target }))
+    }
+}
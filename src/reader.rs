@@ -0,0 +1,223 @@
+use anyhow::Result;
+use std::fs;
+use std::io::{BufRead, BufReader, Lines, Read};
+use std::path::{Path, PathBuf};
+
+use crate::encryption::{decrypt, AGE_MAGIC};
+use crate::{normalize, parse_mtime, Error, HashAlgorithm, ManifestEntry, ManifestHeader, EXTENDED_HEADER};
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+#[cfg(feature = "zstd")]
+fn zstd_decoder(path: &Path, reader: impl BufRead + 'static) -> Result<Box<dyn BufRead>> {
+    let decoder = zstd::Decoder::with_buffer(reader).map_err(Error::io("Failed to read file", path))?;
+    Ok(Box::new(BufReader::new(decoder)))
+}
+
+#[cfg(not(feature = "zstd"))]
+fn zstd_decoder(path: &Path, _reader: impl BufRead + 'static) -> Result<Box<dyn BufRead>> {
+    Err(Error::Config(format!("{} is zstd compressed, but zstd support was not compiled in", path.display())).into())
+}
+
+/// `reader` decompressed if its contents start with a gzip or zstd header,
+/// and decrypted first if they are age encrypted. `path` is only used in
+/// error messages.
+pub(crate) fn decompress(path: &Path, reader: impl Read + 'static) -> Result<Box<dyn BufRead>> {
+    let mut reader = BufReader::new(reader);
+    let magic = reader.fill_buf().map_err(Error::io("Failed to read file", path))?;
+    Ok(if magic.starts_with(GZIP_MAGIC) {
+        Box::new(BufReader::new(flate2::bufread::MultiGzDecoder::new(reader)))
+    } else if magic.starts_with(ZSTD_MAGIC) {
+        zstd_decoder(path, reader)?
+    } else if magic.starts_with(AGE_MAGIC) {
+        // Encrypted manifests may be compressed too
+        return decompress(path, decrypt(path, reader)?);
+    } else {
+        Box::new(reader)
+    })
+}
+
+/// Reads manifest entries one at a time, so arbitrarily large manifests can
+/// be processed in constant memory. Gzip and zstd compressed manifests are
+/// detected from their contents and decompressed on the fly.
+///
+/// Header lines precede all entries, so [`header`](Self::header) is final
+/// once the first entry has been read.
+///
+/// ```no_run
+/// use data_manifest::ManifestReader;
+///
+/// let mut total = 0u64;
+/// for entry in ManifestReader::open("manifest.txt.gz".as_ref())? {
+///     let (_path, entry) = entry?;
+///     total += entry.size.unwrap_or(0);
+/// }
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub struct ManifestReader {
+    path: PathBuf,
+    lines: Lines<Box<dyn BufRead>>,
+    line_num: usize,
+    header: ManifestHeader,
+}
+
+impl ManifestReader {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = fs::File::open(path).map_err(Error::io("Failed to open manifest file", path))?;
+        Self::from_reader(path, file)
+    }
+
+    /// Read a manifest from `reader` instead of a file. `path` is only used
+    /// in error messages.
+    pub fn from_reader(path: &Path, reader: impl Read + 'static) -> Result<Self> {
+        let reader = decompress(path, reader)?;
+        Ok(ManifestReader {
+            path: path.to_path_buf(),
+            lines: reader.lines(),
+            line_num: 0,
+            // Basic manifests have no header and two columns: <hash> <path>
+            header: ManifestHeader::default(),
+        })
+    }
+
+    /// The header lines read so far.
+    pub fn header(&self) -> &ManifestHeader {
+        &self.header
+    }
+
+    fn parse_error(&self, message: String) -> anyhow::Error {
+        Error::Parse {
+            path: self.path.clone(),
+            line: self.line_num,
+            message,
+        }
+        .into()
+    }
+
+    /// Apply a header or comment line.
+    fn parse_header(&mut self, line: &str) -> Result<()> {
+        if line == EXTENDED_HEADER {
+            self.header.extended = true;
+        } else if let Some(name) = line.strip_prefix("# algorithm:") {
+            self.header.algorithm = HashAlgorithm::from_name(name.trim())
+                .ok_or_else(|| self.parse_error(format!("Unsupported hash algorithm: {}", name.trim())))?;
+        } else if let Some(key_id) = line.strip_prefix("# minisign-key:") {
+            self.header.minisign_key = Some(key_id.trim().to_string());
+        } else if let Some(names) = line.strip_prefix("# columns:") {
+            self.header.columns = names.split_whitespace().map(str::to_string).collect();
+            if self.header.columns.last().map(String::as_str) != Some("path") || !self.header.columns.iter().any(|c| c == "hash") {
+                return Err(self.parse_error(format!("Invalid column header: {}", line)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Parse an entry line, or `None` if it has too few columns.
+    fn parse_entry(&self, line: &str) -> Option<(PathBuf, ManifestEntry)> {
+        // The path is always the last column and may contain spaces
+        let parts: Vec<&str> = line.splitn(self.header.columns.len(), ' ').collect();
+        if parts.len() != self.header.columns.len() {
+            return None;
+        }
+
+        let mut entry = ManifestEntry {
+            hash: String::new(),
+            size: None,
+            mtime: None,
+            verified: None,
+            cid: None,
+            xattrs: None,
+            acl: None,
+            link: None,
+            format: None,
+            dev: None,
+            inode: None,
+        };
+        let mut path = PathBuf::new();
+        for (column, value) in self.header.columns.iter().zip(parts) {
+            match column.as_str() {
+                "hash" => entry.hash = value.to_string(),
+                "size" => entry.size = value.parse().ok(),
+                "mtime" => entry.mtime = parse_mtime(value),
+                "verified" => entry.verified = value.parse().ok(),
+                "cid" => entry.cid = (value != "-").then(|| value.to_string()),
+                "xattrs" => entry.xattrs = (value != "-").then(|| value.to_string()),
+                "acl" => entry.acl = (value != "-").then(|| value.to_string()),
+                "link" => entry.link = value.parse().ok(),
+                "format" => entry.format = (value != "-").then(|| value.to_string()),
+                "dev" => entry.dev = value.parse().ok(),
+                "inode" => entry.inode = value.parse().ok(),
+                "path" => path = normalize::manifest_path(separators(value)),
+                // Columns written by newer versions are ignored
+                _ => {}
+            }
+        }
+        Some((path, entry))
+    }
+}
+
+/// A manifest path with `/` separators. Older versions wrote paths with `\`
+/// separators on Windows. Every path starts with the archive name and a
+/// separator, so a path without any `/` is one of those, and Windows names
+/// cannot contain `/`; in other paths a `\` is part of a (Unix) file name.
+fn separators(value: &str) -> String {
+    if value.contains('/') {
+        value.to_string()
+    } else {
+        value.replace('\\', "/")
+    }
+}
+
+impl Iterator for ManifestReader {
+    type Item = Result<(PathBuf, ManifestEntry)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(e) => return Some(Err(Error::io("Failed to read manifest file", &self.path)(e).into())),
+            };
+            self.line_num += 1;
+            let line = line.trim();
+
+            if line.is_empty() {
+                continue;
+            }
+            if line.starts_with('#') {
+                if let Err(e) = self.parse_header(line) {
+                    return Some(Err(e));
+                }
+                continue;
+            }
+            match self.parse_entry(line) {
+                Some(entry) => return Some(Ok(entry)),
+                None => eprintln!("Warning: Invalid line {} in manifest: {}", self.line_num, line),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+    fn read(manifest: String) -> Vec<PathBuf> {
+        let reader = ManifestReader::from_reader(Path::new("manifest.txt"), std::io::Cursor::new(manifest)).unwrap();
+        reader.map(|entry| entry.unwrap().0).collect()
+    }
+
+    #[test]
+    fn keeps_backslashes_in_unix_file_names() {
+        let paths = read(format!("{HASH} archive/dir/a\\b.txt\n{HASH} archive/c\\\\d\n"));
+        assert_eq!(paths, [PathBuf::from("archive/dir/a\\b.txt"), PathBuf::from("archive/c\\\\d")]);
+    }
+
+    #[test]
+    fn converts_windows_separators() {
+        let paths = read(format!("{HASH} archive\\dir\\file.txt\n"));
+        assert_eq!(paths, [PathBuf::from("archive/dir/file.txt")]);
+    }
+}
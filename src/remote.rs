@@ -0,0 +1,520 @@
+use anyhow::Result;
+use rayon::prelude::*;
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use crate::progress::Progress;
+use crate::source::select_files;
+use crate::{
+    acquire_lock, classify_mismatch, load_existing_manifest, mtime_changed, manifest_key, minisig, require_sha256, signature, special, throttle, write_manifest, Args, Error, FileInfo, FileSource,
+    HashAlgorithm, Hasher, Manifest, ManifestEntry, MetadataKind, ReportEntry, ValidationPolicy, ValidationReport,
+};
+
+/// Whether `--archive-path` names a remote archive (`scheme://...`) rather
+/// than a local directory.
+pub fn is_remote(path: &Path) -> bool {
+    path.to_str().is_some_and(|p| p.contains("://"))
+}
+
+/// The file source for a remote archive URL.
+pub fn open_source(url: &str, args: &Args) -> Result<Box<dyn FileSource>> {
+    let (scheme, _) = url.split_once("://").unwrap_or((url, ""));
+    match scheme {
+        #[cfg(feature = "s3")]
+        "s3" => Ok(Box::new(crate::s3::S3Source::open(url, args)?)),
+        #[cfg(not(feature = "s3"))]
+        "s3" => Err(not_compiled(scheme, "s3")),
+        #[cfg(feature = "azure")]
+        "az" => Ok(Box::new(crate::azure::AzureSource::open(url, args)?)),
+        #[cfg(not(feature = "azure"))]
+        "az" => Err(not_compiled(scheme, "azure")),
+        #[cfg(feature = "gcs")]
+        "gs" => Ok(Box::new(crate::gcs::GcsSource::open(url, args)?)),
+        #[cfg(not(feature = "gcs"))]
+        "gs" => Err(not_compiled(scheme, "gcs")),
+        #[cfg(feature = "webdav")]
+        "http" | "https" => Ok(Box::new(crate::webdav::WebDavSource::open(url, args)?)),
+        #[cfg(not(feature = "webdav"))]
+        "http" | "https" => Err(not_compiled(scheme, "webdav")),
+        "rclone" => Ok(Box::new(crate::rclone::RcloneSource::open(url, args)?)),
+        "sftp" => Ok(Box::new(crate::sftp::SftpSource::open(url, args)?)),
+        _ => Err(Error::Config(format!("Unsupported archive URL scheme: {}://", scheme)).into()),
+    }
+}
+
+/// Object storage that files can be uploaded to.
+pub(crate) trait Destination: Send + Sync {
+    /// Upload `size` bytes read from `reader` to `path` below the destination
+    /// and confirm that the checksum the service stored matches what was
+    /// sent.
+    fn upload(&self, path: &Path, reader: &mut dyn Read, size: u64) -> Result<()>;
+}
+
+/// The upload destination for a remote URL.
+#[cfg_attr(not(any(feature = "s3", feature = "azure")), allow(unused_variables))]
+pub(crate) fn open_destination(url: &str, args: &Args) -> Result<Box<dyn Destination>> {
+    let (scheme, _) = url.split_once("://").unwrap_or((url, ""));
+    match scheme {
+        #[cfg(feature = "s3")]
+        "s3" => Ok(Box::new(crate::s3::S3Destination::open(url, args)?)),
+        #[cfg(not(feature = "s3"))]
+        "s3" => Err(not_compiled(scheme, "s3")),
+        #[cfg(feature = "azure")]
+        "az" => Ok(Box::new(crate::azure::AzureDestination::open(url, args)?)),
+        #[cfg(not(feature = "azure"))]
+        "az" => Err(not_compiled(scheme, "azure")),
+        _ => Err(Error::Config(format!("Unsupported destination URL scheme: {}://", scheme)).into()),
+    }
+}
+
+#[cfg_attr(all(feature = "s3", feature = "azure", feature = "gcs", feature = "webdav"), allow(dead_code))]
+fn not_compiled(scheme: &str, feature: &str) -> anyhow::Error {
+    Error::Config(format!("{}:// archives need data-manifest built with the {} feature", scheme, feature)).into()
+}
+
+/// Split `scheme://host/path` into the host (bucket, container, ...) and the
+/// path below it without leading or trailing slashes.
+pub(crate) fn split_url(url: &str) -> Result<(&str, &str)> {
+    let rest = url.split_once("://").map(|(_, rest)| rest).unwrap_or(url);
+    let (host, path) = rest.split_once('/').unwrap_or((rest, ""));
+    if host.is_empty() {
+        return Err(Error::Config(format!("Archive URL has no bucket or host: {}", url)).into());
+    }
+    Ok((host, path.trim_matches('/')))
+}
+
+/// Default archive name of a remote URL: its last path segment, or the bucket
+/// or host when there is no path.
+pub(crate) fn default_archive_name(url: &str) -> Result<String> {
+    let (host, path) = split_url(url)?;
+    Ok(path.rsplit('/').next().filter(|s| !s.is_empty()).unwrap_or(host).to_string())
+}
+
+/// Percent-encode everything except unreserved characters (and `/` when
+/// `keep_slash`), as request signing requires.
+#[cfg_attr(not(any(feature = "s3", feature = "azure", feature = "gcs", feature = "webdav")), allow(dead_code))]
+pub(crate) fn uri_encode(value: &str, keep_slash: bool) -> String {
+    let mut encoded = String::new();
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => encoded.push(byte as char),
+            b'/' if keep_slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Decode `%XX` escapes; invalid escapes are kept as they are.
+pub(crate) fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok()).and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Strip the archive name from a manifest key.
+pub(crate) fn without_archive_name(key: &Path) -> PathBuf {
+    let mut components = key.components();
+    match components.next() {
+        Some(Component::Normal(_)) if components.clone().next().is_some() => components.as_path().to_path_buf(),
+        _ => key.to_path_buf(),
+    }
+}
+
+/// The key of `path` below `prefix`.
+pub(crate) fn object_key(prefix: &str, path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    if prefix.is_empty() {
+        path
+    } else {
+        format!("{}/{}", prefix, path)
+    }
+}
+
+/// Parse an RFC 3339 timestamp such as `2024-06-01T02:00:00.123Z` or
+/// `2024-06-01T04:00:00.123+02:00` into nanoseconds since the Unix epoch.
+pub(crate) fn parse_timestamp(value: &str) -> Option<i64> {
+    let (value, offset) = match value.strip_suffix('Z') {
+        Some(value) => (value, 0),
+        None => match value.len().checked_sub(6).and_then(|i| Some((value.get(..i)?, value.get(i..)?))) {
+            Some((value, zone)) if zone.starts_with(['+', '-']) && zone.as_bytes()[3] == b':' => {
+                let hours: i64 = zone[1..3].parse().ok()?;
+                let minutes: i64 = zone[4..].parse().ok()?;
+                let offset = (hours * 3600 + minutes * 60) * if zone.starts_with('-') { -1 } else { 1 };
+                (value, offset)
+            }
+            _ => (value, 0),
+        },
+    };
+    let (value, frac) = value.split_once('.').unwrap_or((value, "0"));
+    let frac = format!("{:0<9}", frac);
+    let nanos: i64 = frac.get(..9)?.parse().ok()?;
+    (crate::parse_utc(value)? - offset).checked_mul(1_000_000_000)?.checked_add(nanos)
+}
+
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// `Wed, 16 Oct 2024 01:00:00 GMT` for a time in seconds since the Unix epoch.
+#[cfg_attr(not(feature = "azure"), allow(dead_code))]
+pub(crate) fn http_date(secs: i64) -> String {
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    let utc = crate::format_utc(secs);
+    let (date, time) = utc.split_once('T').unwrap_or((&utc, "00:00:00"));
+    let date: Vec<usize> = date.split('-').filter_map(|p| p.parse().ok()).collect();
+    let [year, month, day] = date[..] else { return utc };
+    let weekday = WEEKDAYS[secs.div_euclid(86_400).rem_euclid(7) as usize];
+    format!("{}, {:02} {} {} {} GMT", weekday, day, MONTHS[month - 1], year, time)
+}
+
+/// Parse an HTTP date such as `Wed, 16 Oct 2024 01:00:00 GMT` into
+/// nanoseconds since the Unix epoch.
+#[cfg_attr(not(any(feature = "azure", feature = "webdav")), allow(dead_code))]
+pub(crate) fn parse_http_date(value: &str) -> Option<i64> {
+    let mut parts = value.split_whitespace().skip(1);
+    let (day, month, year, time) = (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+    let month = MONTHS.iter().position(|m| m.eq_ignore_ascii_case(month))? + 1;
+    crate::parse_utc(&format!("{}-{}-{}T{}", year, month, day, time))?.checked_mul(1_000_000_000)
+}
+
+/// Fetches the byte range `start..end` of a remote file.
+pub(crate) type FetchRange = Arc<dyn Fn(u64, u64) -> io::Result<Vec<u8>> + Send + Sync>;
+
+/// How remote files are split into range requests.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct RangeOptions {
+    pub part_size: u64,
+    pub concurrency: usize,
+}
+
+impl RangeOptions {
+    /// `--part-size` and `--remote-concurrency`.
+    pub fn from_args(args: &Args) -> Result<Self> {
+        Ok(RangeOptions {
+            part_size: throttle::parse_size(&args.part_size)?.max(1),
+            concurrency: args.remote_concurrency.max(1),
+        })
+    }
+}
+
+/// Reads a remote file of known size in order while fetching up to
+/// `concurrency` parts of it at once.
+pub(crate) struct RangeReader {
+    fetch: FetchRange,
+    size: u64,
+    part_size: u64,
+    concurrency: usize,
+    next_offset: u64,
+    parts: VecDeque<io::Cursor<Vec<u8>>>,
+}
+
+impl RangeReader {
+    pub fn new(fetch: FetchRange, size: u64, options: RangeOptions) -> Self {
+        RangeReader {
+            fetch,
+            size,
+            part_size: options.part_size,
+            concurrency: options.concurrency,
+            next_offset: 0,
+            parts: VecDeque::new(),
+        }
+    }
+
+    /// Fetch the next window of parts concurrently.
+    fn fill(&mut self) -> io::Result<()> {
+        let mut ranges = Vec::new();
+        while ranges.len() < self.concurrency && self.next_offset < self.size {
+            let end = (self.next_offset + self.part_size).min(self.size);
+            ranges.push((self.next_offset, end));
+            self.next_offset = end;
+        }
+        let fetch = &self.fetch;
+        let parts: Vec<io::Result<Vec<u8>>> = std::thread::scope(|scope| {
+            let handles: Vec<_> = ranges.iter().map(|&(start, end)| scope.spawn(move || fetch(start, end))).collect();
+            handles
+                .into_iter()
+                .map(|h| h.join().unwrap_or_else(|_| Err(io::Error::other("Range request panicked"))))
+                .collect()
+        });
+        for (part, (start, end)) in parts.into_iter().zip(ranges) {
+            let part = part?;
+            if part.len() as u64 != end - start {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!("Range {}-{} returned {} bytes", start, end, part.len()),
+                ));
+            }
+            self.parts.push_back(io::Cursor::new(part));
+        }
+        Ok(())
+    }
+}
+
+impl Read for RangeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if let Some(part) = self.parts.front_mut() {
+                let n = part.read(buf)?;
+                if n > 0 {
+                    return Ok(n);
+                }
+                self.parts.pop_front();
+                continue;
+            }
+            if self.next_offset >= self.size {
+                return Ok(0);
+            }
+            self.fill()?;
+        }
+    }
+}
+
+/// Hashes everything read through it, so a file can be hashed while it is
+/// being copied elsewhere.
+pub(crate) struct HashingReader<R> {
+    inner: R,
+    hasher: Hasher,
+}
+
+impl<R: Read> HashingReader<R> {
+    pub fn new(inner: R, algorithm: HashAlgorithm) -> Self {
+        HashingReader {
+            inner,
+            hasher: algorithm.hasher(),
+        }
+    }
+
+    pub fn finish(self) -> String {
+        self.hasher.finish()
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        crate::progress::bytes_hashed(n);
+        Ok(n)
+    }
+}
+
+/// Hash every selected file of `source` in parallel, keyed as a local scan
+/// would key them.
+fn hash_source(source: &dyn FileSource, archive_name: &str, args: &Args) -> Result<HashMap<PathBuf, ManifestEntry>> {
+    let files = select_files(source.files(args)?, args)?;
+    if args.cancel.is_cancelled() {
+        return Err(Error::Cancelled.into());
+    }
+    println!("Found {} files", files.len());
+
+    let progress = Progress::start(files.len() as u64, 0);
+    let entries = files
+        .par_iter()
+        .map(|file_info| {
+            if args.cancel.is_cancelled() {
+                return Err(Error::Cancelled.into());
+            }
+            progress.file_started(&file_info.path);
+            let hash = source
+                .open(&file_info.path)
+                .map_err(|e| Error::io("Failed to open file", &file_info.path)(e).into())
+                .and_then(|reader| HashAlgorithm::Sha256.hash_reader(&mut throttle::Throttled(reader), args.buffer_size));
+            progress.file_done(&file_info.path, &hash);
+            let entry = ManifestEntry {
+                hash: hash?,
+                size: Some(file_info.size),
+                mtime: file_info.mtime,
+                verified: None,
+                cid: None,
+                xattrs: None,
+                acl: None,
+                link: None,
+                format: None,
+                dev: None,
+                inode: None,
+            };
+            Ok((manifest_key(&file_info.path, Path::new(""), archive_name), entry))
+        })
+        .collect::<Result<HashMap<_, _>>>();
+    progress.finish("Complete");
+    entries
+}
+
+/// Generate or validate a manifest of the remote archive at `url`.
+pub fn run_remote(url: &str, args: &Args) -> Result<()> {
+    if args.update || args.verify_checksum_files {
+        return Err(Error::Config("Remote archives support generating and validating manifests only".into()).into());
+    }
+    let archive_name = match args.archive_name {
+        Some(ref name) => name.clone(),
+        None => default_archive_name(url)?,
+    };
+    // ssh:// archives are hashed by an agent on the host instead of being read
+    let source = match url.split_once("://") {
+        Some(("ssh", _)) => None,
+        _ => Some(open_source(url, args)?),
+    };
+    let hash = || match source {
+        Some(ref source) => hash_source(source.as_ref(), &archive_name, args),
+        None => crate::agent::hash_over_ssh(url, &archive_name, args),
+    };
+    let _lock = acquire_lock(&args.output, args)?;
+
+    if args.validate {
+        return validate_remote(hash, url, &args.output, args);
+    }
+
+    println!("Scanning archive: {}", url);
+    let entries = hash()?;
+    write_manifest(&args.output, &entries, args.writes_extended())?;
+    println!("Manifest written to: {}", args.output.display());
+    println!("Total files: {}", entries.len());
+    crate::report::record_summary(&crate::report::GenerateSummary {
+        files: entries.len(),
+        errors: 0,
+        special_files: 0,
+        timings: None,
+    });
+    Ok(())
+}
+
+/// Re-hash the remote archive and compare it with the manifest. Renames,
+/// repairs, and the local-only validation modes do not apply.
+fn validate_remote(
+    hash: impl FnOnce() -> Result<HashMap<PathBuf, ManifestEntry>>,
+    url: &str,
+    manifest_path: &Path,
+    args: &Args,
+) -> Result<()> {
+    status!("Validating manifest: {}", manifest_path.display());
+    if !manifest_path.exists() {
+        return Err(Error::Config(format!("Manifest does not exist: {}", manifest_path.display())).into());
+    }
+    signature::verify_manifest(manifest_path, args.sign.as_deref())?;
+    minisig::verify_manifest(manifest_path, args.minisign_pubkey.as_deref())?;
+    let manifest = load_existing_manifest(manifest_path)?;
+    require_sha256(&manifest, manifest_path)?;
+    let actual = hash()?;
+    validate_entries(&manifest, manifest_path, &actual, Path::new(url), args)
+}
+
+/// Compare hashed entries with `manifest`, report the results as validation
+/// mode does, and fail if the validation policy is not met.
+pub(crate) fn validate_entries(
+    manifest: &Manifest,
+    manifest_path: &Path,
+    actual: &HashMap<PathBuf, ManifestEntry>,
+    archive: &Path,
+    args: &Args,
+) -> Result<()> {
+    let policy = ValidationPolicy {
+        fail_on_new: args.strict && !args.allow_new,
+        fail_on_missing: !args.allow_missing,
+        fail_on_renamed: args.strict,
+    };
+    let mut report = ValidationReport::new(manifest_path, archive, policy);
+    let check_mtime = args.metadata.contains(&MetadataKind::Mtime);
+    if args.metadata.iter().any(|kind| matches!(kind, MetadataKind::Xattr | MetadataKind::Acl)) {
+        eprintln!("Warning: Extended attributes and ACLs cannot be read from {}; they are not checked", archive.display());
+    }
+    // Object stores and remote listings have no directories to check
+    for (key, expected) in manifest.entries.iter().filter(|(_, expected)| expected.hash != special::DIR) {
+        let entry = |actual: Option<&ManifestEntry>| ReportEntry {
+            path: key.clone(),
+            expected_hash: Some(expected.hash.clone()),
+            actual_hash: actual.map(|a| a.hash.clone()),
+            size: actual.and_then(|a| a.size).or(expected.size),
+            mtime: actual.and_then(|a| a.mtime).or(expected.mtime),
+            mismatch_kind: None,
+        };
+        match actual.get(key) {
+            Some(found) if found.hash == expected.hash && check_mtime && mtime_changed(expected, found.mtime) => {
+                report.metadata_changed.push(entry(Some(found)));
+            }
+            Some(found) if found.hash == expected.hash => report.valid.push(key.clone()),
+            Some(found) => {
+                let file_info = FileInfo {
+                    path: key.clone(),
+                    size: found.size.unwrap_or(0),
+                    mtime: found.mtime,
+                };
+                report.mismatched.push(ReportEntry {
+                    mismatch_kind: classify_mismatch(expected, &file_info),
+                    ..entry(Some(found))
+                });
+            }
+            None => report.missing.push(entry(None)),
+        }
+    }
+    for (key, found) in actual {
+        if !manifest.entries.contains_key(key) {
+            report.new.push(ReportEntry {
+                path: key.clone(),
+                expected_hash: None,
+                actual_hash: Some(found.hash.clone()),
+                size: found.size,
+                mtime: found.mtime,
+                mismatch_kind: None,
+            });
+        }
+    }
+    report.finish();
+
+    for entry in &report.mismatched {
+        match entry.mismatch_kind {
+            Some(kind) => status!("Hash mismatch ({}): {}", kind.describe(), entry.path.display()),
+            None => status!("Hash mismatch: {}", entry.path.display()),
+        }
+    }
+    for entry in &report.metadata_changed {
+        status!("Timestamp changed: {}", entry.path.display());
+    }
+    for entry in &report.missing {
+        status!("Missing file: {}", entry.path.display());
+    }
+    for entry in &report.new {
+        status!("New file found: {}", entry.path.display());
+    }
+    let summary = &report.summary;
+    status!("Validation results:");
+    status!("  Valid files: {}", summary.valid);
+    status!("  Invalid files: {}", summary.invalid);
+    status!("  New files: {}", summary.new);
+    status!("  Missing files: {}", summary.missing);
+    if summary.metadata_changed > 0 {
+        status!("  Metadata changed: {}", summary.metadata_changed);
+    }
+
+    if let Some(ref report_file) = args.report_file {
+        report.write_json(report_file)?;
+        status!("Report written to: {}", report_file.display());
+    }
+    crate::report::record_summary(&report);
+    if !report.passed {
+        return Err(Error::Validation {
+            message: format!(
+                "Validation failed: {} invalid files ({} modified, {} corruption suspected), {} metadata changes, {} missing files, {} new files",
+                summary.invalid, summary.modified, summary.corruption_suspected, summary.metadata_changed, summary.missing, summary.new
+            ),
+            exit_code: report.exit_code(),
+        }
+        .into());
+    }
+    status!("Validation successful!");
+    Ok(())
+}
@@ -0,0 +1,126 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::time::SystemTime;
+
+use crate::report::{RepairEntry, ReportEntry, ValidationReport};
+use crate::{archive_file_path, get_file_hash, Error, FileInfo};
+
+fn hash_path(path: &Path, buffer_size: usize) -> Result<String> {
+    let metadata = fs::metadata(path)?;
+    get_file_hash(&FileInfo::from_metadata(path.to_path_buf(), &metadata), buffer_size)
+}
+
+/// Copy `source` next to `target`, check the copy against `expected_hash`, and
+/// only then rename it into place, so a failed repair never leaves a partial file.
+fn restore(source: &Path, target: &Path, expected_hash: &str, buffer_size: usize) -> Result<()> {
+    let parent = target.parent().context("Repair target has no parent directory")?;
+    fs::create_dir_all(parent)
+        .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+    let file_name = target.file_name().context("Repair target has no file name")?;
+    let temp = parent.join(format!(".{}.repair", file_name.to_string_lossy()));
+
+    fs::copy(source, &temp)
+        .with_context(|| format!("Failed to copy {} to {}", source.display(), temp.display()))?;
+    let copied_hash = hash_path(&temp, buffer_size);
+    if copied_hash.as_deref().ok() != Some(expected_hash) {
+        let _ = fs::remove_file(&temp);
+        anyhow::bail!("Copied file does not match the manifest hash");
+    }
+    fs::rename(&temp, target)
+        .with_context(|| format!("Failed to move {} into place", temp.display()))?;
+    Ok(())
+}
+
+/// Restore mismatched and missing files from a replica laid out like the
+/// archive. A replica copy is only used when its hash matches the manifest.
+/// Repaired entries move from `mismatched`/`missing` into `repaired`.
+pub fn repair_from_replica(
+    report: &mut ValidationReport,
+    archive_path: &Path,
+    replica_path: &Path,
+    archive_name: &str,
+    buffer_size: usize,
+    log_path: Option<&Path>,
+) -> Result<()> {
+    let mut log = match log_path {
+        Some(path) => Some(
+            fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("Failed to open repair log: {}", path.display()))?,
+        ),
+        None => None,
+    };
+
+    let mut repair = |entry: &ReportEntry, reason: &'static str| -> Result<bool> {
+        let Some(ref expected) = entry.expected_hash else {
+            return Ok(false);
+        };
+        let target = archive_file_path(&entry.path, archive_path, archive_name);
+        let source = archive_file_path(&entry.path, replica_path, archive_name);
+
+        let outcome = match hash_path(&source, buffer_size) {
+            Err(e) => Err(format!("replica copy unreadable: {:#}", e)),
+            Ok(hash) if &hash != expected => Err(format!("replica copy also differs (got {})", hash)),
+            Ok(_) => restore(&source, &target, expected, buffer_size).map_err(|e| format!("{:#}", e)),
+        };
+        let (status, detail) = match outcome {
+            Ok(()) => {
+                status!("Repaired {} file {} from {}", reason, entry.path.display(), source.display());
+                ("repaired", String::new())
+            }
+            Err(ref message) => {
+                eprintln!("Warning: Could not repair {}: {}", entry.path.display(), message);
+                ("failed", message.clone())
+            }
+        };
+        if let Some(ref mut log) = log {
+            let timestamp = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default();
+            writeln!(
+                log,
+                "{}\t{}\t{}\t{}\t{}\t{}",
+                timestamp, status, reason, entry.path.display(), source.display(), detail
+            )?;
+        }
+        Ok(outcome.is_ok())
+    };
+
+    let mut repaired = Vec::new();
+    for (reason, entries) in [("mismatched", &mut report.mismatched), ("missing", &mut report.missing)] {
+        let mut remaining = Vec::new();
+        for entry in entries.drain(..) {
+            if repair(&entry, reason)? {
+                repaired.push(RepairEntry {
+                    source: archive_file_path(&entry.path, replica_path, archive_name),
+                    hash: entry.expected_hash.clone().unwrap_or_default(),
+                    path: entry.path,
+                    reason: reason.to_string(),
+                });
+            } else {
+                remaining.push(entry);
+            }
+        }
+        *entries = remaining;
+    }
+    report.repaired.extend(repaired);
+    Ok(())
+}
+
+/// The replica must be a different directory from the archive itself.
+pub fn check_replica(archive_path: &Path, replica_path: &Path) -> Result<()> {
+    if !replica_path.is_dir() {
+        return Err(Error::Config(format!("Replica path is not a directory: {}", replica_path.display())).into());
+    }
+    let replica = fs::canonicalize(replica_path).map_err(Error::io("Failed to resolve replica path", replica_path))?;
+    let archive = fs::canonicalize(archive_path).map_err(Error::io("Failed to resolve archive path", archive_path))?;
+    if replica == archive {
+        return Err(Error::Config(format!("Replica path is the archive itself: {}", replica_path.display())).into());
+    }
+    Ok(())
+}
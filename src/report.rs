@@ -0,0 +1,715 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::eventlog::{ChangeAction, ManifestChange};
+use crate::timing::Timings;
+use crate::SCHEMA_VERSION;
+
+/// A single problem path found during validation.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReportEntry {
+    pub path: PathBuf,
+    pub expected_hash: Option<String>,
+    pub actual_hash: Option<String>,
+    pub size: Option<u64>,
+    /// Modification time in nanoseconds since the Unix epoch: the file's current
+    /// mtime, or the recorded one for missing files
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mtime: Option<i64>,
+    /// Why a hash mismatch happened, when the manifest records size and mtime.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mismatch_kind: Option<MismatchKind>,
+}
+
+/// Hash mismatches are either expected edits (size or mtime changed too) or
+/// silent corruption (identical metadata, different content).
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MismatchKind {
+    Modified,
+    CorruptionSuspected,
+}
+
+impl MismatchKind {
+    pub fn describe(self) -> &'static str {
+        match self {
+            MismatchKind::Modified => "modified: size or mtime changed",
+            MismatchKind::CorruptionSuspected => "corruption suspected: size and mtime unchanged",
+        }
+    }
+}
+
+/// A missing manifest entry matched to a new file with identical content.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RenameEntry {
+    pub from: PathBuf,
+    pub to: PathBuf,
+    pub hash: String,
+    pub size: Option<u64>,
+}
+
+/// A mismatched or missing file restored from a replica copy whose hash
+/// matched the manifest.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RepairEntry {
+    pub path: PathBuf,
+    pub reason: String,
+    pub hash: String,
+    pub source: PathBuf,
+}
+
+/// A mismatched file placed in the quarantine tree (--quarantine).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct QuarantineEntry {
+    pub path: PathBuf,
+    pub location: PathBuf,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct ValidationSummary {
+    pub valid: usize,
+    pub invalid: usize,
+    pub modified: usize,
+    pub corruption_suspected: usize,
+    pub metadata_changed: usize,
+    pub new: usize,
+    pub missing: usize,
+    pub renamed: usize,
+    pub repaired: usize,
+    pub assumed_valid: usize,
+    /// Sockets, FIFOs, and device nodes found while walking the archive
+    pub special_files: usize,
+}
+
+/// Per-phase counts for two-phase validation: a metadata scan of every file
+/// followed by full hashing of the suspicious ones.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct PhaseSummary {
+    pub metadata_checked: usize,
+    pub metadata_unchanged: usize,
+    pub metadata_changed: usize,
+    pub previously_flagged: usize,
+    pub hashed: usize,
+}
+
+/// Which categories of problems cause validation to fail. Hash mismatches
+/// always fail.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct ValidationPolicy {
+    pub fail_on_new: bool,
+    pub fail_on_missing: bool,
+    pub fail_on_renamed: bool,
+}
+
+impl Default for ValidationPolicy {
+    fn default() -> Self {
+        ValidationPolicy {
+            fail_on_new: false,
+            fail_on_missing: true,
+            fail_on_renamed: false,
+        }
+    }
+}
+
+/// Everything a validation run found, in a form suitable for machine consumption.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct ValidationReport {
+    /// Version of the published report schema; 0 for reports written before it existed
+    pub schema_version: u32,
+    pub manifest: PathBuf,
+    pub archive: PathBuf,
+    pub policy: ValidationPolicy,
+    pub passed: bool,
+    pub summary: ValidationSummary,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub phases: Option<PhaseSummary>,
+    pub mismatched: Vec<ReportEntry>,
+    /// Files whose size or mtime differ from the manifest (--quick mode).
+    pub metadata_changed: Vec<ReportEntry>,
+    pub missing: Vec<ReportEntry>,
+    pub new: Vec<ReportEntry>,
+    pub renamed: Vec<RenameEntry>,
+    /// Files restored from a replica (--repair); no longer counted as mismatched or missing.
+    pub repaired: Vec<RepairEntry>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub quarantined: Vec<QuarantineEntry>,
+    /// Paths that validated successfully; only used for per-file output formats.
+    #[serde(skip)]
+    pub valid: Vec<PathBuf>,
+    /// Paths skipped by --changed-only because size and mtime were unchanged.
+    #[serde(skip)]
+    pub assumed_valid: Vec<PathBuf>,
+    /// Special files found while walking the archive.
+    #[serde(skip)]
+    pub special_files: usize,
+    /// Where the time of the run went
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timings: Option<Timings>,
+}
+
+pub fn load_flagged_paths(path: &Path) -> Result<HashSet<PathBuf>> {
+    let file = fs::File::open(path)
+        .with_context(|| format!("Failed to open previous report: {}", path.display()))?;
+    let report: ValidationReport = serde_json::from_reader(std::io::BufReader::new(file))
+        .with_context(|| format!("Invalid report file: {}", path.display()))?;
+    Ok(report
+        .mismatched
+        .into_iter()
+        .chain(report.metadata_changed)
+        .map(|e| e.path)
+        .collect())
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct UpdateCounts {
+    pub unchanged: usize,
+    pub skipped: usize,
+    pub updated: usize,
+    pub added: usize,
+    pub removed: usize,
+    pub renamed: usize,
+    pub kept_missing: usize,
+    /// Sockets, FIFOs, and device nodes found while walking the archive
+    pub special_files: usize,
+}
+
+/// What an update run changed, for reviewers to sanity-check.
+#[derive(Serialize, Debug, Default)]
+pub struct UpdateSummary {
+    pub manifest: PathBuf,
+    pub archive: PathBuf,
+    pub summary: UpdateCounts,
+    pub added: Vec<ManifestChange>,
+    pub changed: Vec<ManifestChange>,
+    pub removed: Vec<ManifestChange>,
+    pub renamed: Vec<ManifestChange>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timings: Option<Timings>,
+}
+
+impl UpdateSummary {
+    /// Sort `changes` into the added, changed, and removed lists.
+    pub fn new(manifest: &Path, archive: &Path, counts: UpdateCounts, changes: &[ManifestChange]) -> Self {
+        let mut summary = UpdateSummary {
+            manifest: manifest.to_path_buf(),
+            archive: archive.to_path_buf(),
+            summary: counts,
+            ..Default::default()
+        };
+        for change in changes {
+            let list = match change.action {
+                ChangeAction::Added => &mut summary.added,
+                ChangeAction::Updated => &mut summary.changed,
+                ChangeAction::Removed => &mut summary.removed,
+                ChangeAction::Renamed => &mut summary.renamed,
+            };
+            list.push(change.clone());
+        }
+        summary
+    }
+
+    pub fn print(&self) {
+        for (title, changes) in [("Added", &self.added), ("Changed", &self.changed), ("Removed", &self.removed)] {
+            if changes.is_empty() {
+                continue;
+            }
+            println!("{} ({}):", title, changes.len());
+            for change in changes {
+                println!("  {}", change.path.display());
+            }
+        }
+        if !self.renamed.is_empty() {
+            println!("Renamed ({}):", self.renamed.len());
+            for change in &self.renamed {
+                let from = change.from.as_deref().unwrap_or(Path::new("?"));
+                println!("  {} -> {}", from.display(), change.path.display());
+            }
+        }
+    }
+
+    pub fn write_json(&self, path: &Path) -> Result<()> {
+        let file = fs::File::create(path)
+            .with_context(|| format!("Failed to create summary file: {}", path.display()))?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writeln!(writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Quote a CSV field if it contains a delimiter, quote, or line break.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// How validation results are grouped into JUnit test cases.
+#[derive(clap::ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JunitGroup {
+    File,
+    Directory,
+}
+
+/// Reports written after a run, besides its summary.
+#[derive(clap::ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReportKind {
+    /// A self-contained HTML page next to the manifest
+    Html,
+    /// The largest files and a histogram of file sizes, printed
+    Sizes,
+}
+
+/// Inline annotation syntax to emit alongside validation problems.
+#[derive(clap::ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Annotate {
+    Github,
+}
+
+/// Output format for reports written by subcommands.
+#[derive(clap::ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+/// Open the given output file, or stdout when none is given.
+pub fn open_output(path: Option<&Path>) -> Result<Box<dyn Write>> {
+    Ok(match path {
+        Some(path) => Box::new(BufWriter::new(
+            fs::File::create(path).with_context(|| format!("Failed to create output file: {}", path.display()))?,
+        )),
+        None => Box::new(std::io::stdout().lock()),
+    })
+}
+
+/// Outcome of a single JUnit test case.
+enum CaseResult<'a> {
+    Pass,
+    Failure(&'a str, String),
+}
+
+impl ValidationReport {
+    pub fn new(manifest: &Path, archive: &Path, policy: ValidationPolicy) -> Self {
+        ValidationReport {
+            schema_version: SCHEMA_VERSION,
+            manifest: manifest.to_path_buf(),
+            archive: archive.to_path_buf(),
+            policy,
+            ..Default::default()
+        }
+    }
+
+    /// Sort entry lists, fill in the summary counts, and apply the policy.
+    pub fn finish(&mut self) {
+        self.valid.sort();
+        self.assumed_valid.sort();
+        self.mismatched.sort_by(|a, b| a.path.cmp(&b.path));
+        self.metadata_changed.sort_by(|a, b| a.path.cmp(&b.path));
+        self.missing.sort_by(|a, b| a.path.cmp(&b.path));
+        self.new.sort_by(|a, b| a.path.cmp(&b.path));
+        self.renamed.sort_by(|a, b| a.from.cmp(&b.from));
+        self.repaired.sort_by(|a, b| a.path.cmp(&b.path));
+        self.summary = ValidationSummary {
+            valid: self.valid.len(),
+            invalid: self.mismatched.len(),
+            modified: self.count_mismatches(MismatchKind::Modified),
+            corruption_suspected: self.count_mismatches(MismatchKind::CorruptionSuspected),
+            metadata_changed: self.metadata_changed.len(),
+            new: self.new.len(),
+            missing: self.missing.len(),
+            renamed: self.renamed.len(),
+            repaired: self.repaired.len(),
+            assumed_valid: self.assumed_valid.len(),
+            special_files: self.special_files,
+        };
+        self.passed = self.summary.invalid == 0 && !self.fails_without_mismatches();
+    }
+
+    fn count_mismatches(&self, kind: MismatchKind) -> usize {
+        self.mismatched.iter().filter(|e| e.mismatch_kind == Some(kind)).count()
+    }
+
+    /// Process exit status for a finished report: 0 when it passed, 3 when any
+    /// file is suspected corrupt, 2 when every mismatch is a modification and
+    /// nothing else failed, and 1 otherwise.
+    pub fn exit_code(&self) -> i32 {
+        if self.passed {
+            0
+        } else if self.summary.corruption_suspected > 0 {
+            3
+        } else if self.summary.modified == self.summary.invalid && !self.fails_without_mismatches() {
+            2
+        } else {
+            1
+        }
+    }
+
+    fn fails_without_mismatches(&self) -> bool {
+        self.summary.metadata_changed > 0
+            || (self.policy.fail_on_missing && self.summary.missing > 0)
+            || (self.policy.fail_on_new && self.summary.new > 0)
+            || (self.policy.fail_on_renamed && self.summary.renamed > 0)
+    }
+
+    /// Write one CSV row per problem file. Nothing is written when there are no
+    /// problems; returns whether the file was written.
+    pub fn write_failures_csv(&self, path: &Path) -> Result<bool> {
+        let mut rows: Vec<(&ReportEntry, &str)> = Vec::new();
+        for entry in &self.mismatched {
+            let category = match entry.mismatch_kind {
+                Some(MismatchKind::Modified) => "modified",
+                Some(MismatchKind::CorruptionSuspected) => "corruption_suspected",
+                None => "mismatch",
+            };
+            rows.push((entry, category));
+        }
+        for (category, entries) in [
+            ("metadata_changed", &self.metadata_changed),
+            ("missing", &self.missing),
+            ("new", &self.new),
+        ] {
+            rows.extend(entries.iter().map(|e| (e, category)));
+        }
+        if rows.is_empty() && self.renamed.is_empty() {
+            return Ok(false);
+        }
+
+        let file = fs::File::create(path)
+            .with_context(|| format!("Failed to create CSV file: {}", path.display()))?;
+        let mut writer = BufWriter::new(file);
+        writeln!(writer, "path,expected_hash,actual_hash,size,mtime,category")?;
+        for (entry, category) in rows {
+            writeln!(
+                writer,
+                "{},{},{},{},{},{}",
+                csv_field(&entry.path.display().to_string()),
+                entry.expected_hash.as_deref().unwrap_or_default(),
+                entry.actual_hash.as_deref().unwrap_or_default(),
+                entry.size.map(|s| s.to_string()).unwrap_or_default(),
+                entry.mtime.map(crate::format_mtime).unwrap_or_default(),
+                category
+            )?;
+        }
+        for entry in &self.renamed {
+            writeln!(
+                writer,
+                "{},{},{},{},,renamed",
+                csv_field(&format!("{} -> {}", entry.from.display(), entry.to.display())),
+                entry.hash,
+                entry.hash,
+                entry.size.map(|s| s.to_string()).unwrap_or_default(),
+            )?;
+        }
+        writer.flush()?;
+        Ok(true)
+    }
+
+    pub fn write_json(&self, path: &Path) -> Result<()> {
+        let file = fs::File::create(path)
+            .with_context(|| format!("Failed to create report file: {}", path.display()))?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writeln!(writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl ValidationReport {
+    pub fn write_junit(&self, path: &Path, group: JunitGroup) -> Result<()> {
+        let file = fs::File::create(path)
+            .with_context(|| format!("Failed to create JUnit file: {}", path.display()))?;
+        let mut writer = BufWriter::new(file);
+
+        // (classname, name, result)
+        let mut cases: Vec<(String, String, CaseResult)> = Vec::new();
+        match group {
+            JunitGroup::File => {
+                for path in self.valid.iter().chain(&self.assumed_valid) {
+                    cases.push((parent_dir(path), path.display().to_string(), CaseResult::Pass));
+                }
+                for entry in &self.repaired {
+                    cases.push((parent_dir(&entry.path), entry.path.display().to_string(), CaseResult::Pass));
+                }
+                for entry in &self.mismatched {
+                    cases.push((
+                        parent_dir(&entry.path),
+                        entry.path.display().to_string(),
+                        CaseResult::Failure("mismatch", describe("mismatch", entry)),
+                    ));
+                }
+                for (kind, entries, fails) in [
+                    ("metadata", &self.metadata_changed, true),
+                    ("missing", &self.missing, self.policy.fail_on_missing),
+                    ("new", &self.new, self.policy.fail_on_new),
+                ] {
+                    for entry in entries {
+                        let result = if fails {
+                            CaseResult::Failure(kind, describe(kind, entry))
+                        } else {
+                            CaseResult::Pass
+                        };
+                        cases.push((parent_dir(&entry.path), entry.path.display().to_string(), result));
+                    }
+                }
+                for entry in &self.renamed {
+                    let result = if self.policy.fail_on_renamed {
+                        CaseResult::Failure("renamed", describe_rename(entry))
+                    } else {
+                        CaseResult::Pass
+                    };
+                    cases.push((parent_dir(&entry.to), entry.to.display().to_string(), result));
+                }
+                cases.sort_by(|a, b| a.1.cmp(&b.1));
+            }
+            JunitGroup::Directory => {
+                let mut dirs: BTreeMap<String, Vec<String>> = BTreeMap::new();
+                for path in self.valid.iter().chain(&self.assumed_valid) {
+                    dirs.entry(parent_dir(path)).or_default();
+                }
+                for entry in &self.repaired {
+                    dirs.entry(parent_dir(&entry.path)).or_default();
+                }
+                for (kind, entries, fails) in [
+                    ("mismatch", &self.mismatched, true),
+                    ("metadata", &self.metadata_changed, true),
+                    ("missing", &self.missing, self.policy.fail_on_missing),
+                    ("new", &self.new, self.policy.fail_on_new),
+                ] {
+                    for entry in entries {
+                        let problems = dirs.entry(parent_dir(&entry.path)).or_default();
+                        if fails {
+                            problems.push(describe(kind, entry));
+                        }
+                    }
+                }
+                for entry in &self.renamed {
+                    let problems = dirs.entry(parent_dir(&entry.to)).or_default();
+                    if self.policy.fail_on_renamed {
+                        problems.push(describe_rename(entry));
+                    }
+                }
+                for (dir, problems) in dirs {
+                    let result = if problems.is_empty() {
+                        CaseResult::Pass
+                    } else {
+                        CaseResult::Failure("validation", problems.join("\n"))
+                    };
+                    cases.push((dir.clone(), dir, result));
+                }
+            }
+        }
+
+        let failures = cases.iter().filter(|c| matches!(c.2, CaseResult::Failure(..))).count();
+        let suite_name = xml_escape(&self.manifest.display().to_string());
+
+        writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(
+            writer,
+            r#"<testsuites name="data-manifest" tests="{}" failures="{}">"#,
+            cases.len(),
+            failures
+        )?;
+        writeln!(
+            writer,
+            r#"  <testsuite name="{}" tests="{}" failures="{}">"#,
+            suite_name,
+            cases.len(),
+            failures
+        )?;
+        for (classname, name, result) in &cases {
+            let open = format!(
+                r#"    <testcase classname="{}" name="{}""#,
+                xml_escape(classname),
+                xml_escape(name)
+            );
+            match result {
+                CaseResult::Pass => writeln!(writer, "{}/>", open)?,
+                CaseResult::Failure(kind, message) => {
+                    writeln!(writer, "{}>", open)?;
+                    writeln!(
+                        writer,
+                        r#"      <failure type="{}" message="{}">{}</failure>"#,
+                        kind,
+                        xml_escape(message.lines().next().unwrap_or_default()),
+                        xml_escape(message)
+                    )?;
+                    writeln!(writer, "    </testcase>")?;
+                }
+            }
+        }
+        writeln!(writer, "  </testsuite>")?;
+        writeln!(writer, "</testsuites>")?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+fn parent_dir(path: &Path) -> String {
+    path.parent()
+        .map(|p| p.display().to_string())
+        .unwrap_or_default()
+}
+
+fn describe(kind: &str, entry: &ReportEntry) -> String {
+    match kind {
+        "mismatch" => format!(
+            "hash mismatch for {}: expected {}, got {}",
+            entry.path.display(),
+            entry.expected_hash.as_deref().unwrap_or("-"),
+            entry.actual_hash.as_deref().unwrap_or("-")
+        ),
+        "metadata" => format!("size or mtime of {} changed since the manifest was written", entry.path.display()),
+        "new" => format!("new file {} is not in the manifest", entry.path.display()),
+        _ => format!("missing file {}", entry.path.display()),
+    }
+}
+
+fn describe_rename(entry: &RenameEntry) -> String {
+    format!("{} was renamed or moved to {}", entry.from.display(), entry.to.display())
+}
+
+pub fn xml_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Format a GitHub Actions workflow command such as
+/// `::error file=path,title=Hash mismatch::expected ..., got ...`.
+pub fn github_annotation(level: &str, file: &Path, title: &str, message: &str) -> String {
+    format!(
+        "::{} file={},title={}::{}",
+        level,
+        escape_github_property(&file.display().to_string()),
+        escape_github_property(title),
+        escape_github_data(message)
+    )
+}
+
+/// The path to annotate for the file at `path` with manifest key `key`:
+/// relative to `$GITHUB_WORKSPACE`, where GitHub looks for annotated files,
+/// or the manifest key for files outside the workspace.
+pub(crate) fn annotation_path(path: &Path, key: &Path) -> PathBuf {
+    let workspace = std::env::var_os("GITHUB_WORKSPACE").map(PathBuf::from);
+    workspace_path(path, key, workspace.as_deref())
+}
+
+fn workspace_path(path: &Path, key: &Path, workspace: Option<&Path>) -> PathBuf {
+    let absolute = |path: &Path| match path.is_absolute() {
+        true => Some(crate::longpath::without_verbatim(path)),
+        false => std::env::current_dir().ok().map(|dir| dir.join(path)),
+    };
+    // Symlinks, such as /tmp on macOS, are resolved where the paths exist
+    let forms = |path: &Path| {
+        let canonical = fs::canonicalize(path).ok().map(|path| crate::longpath::without_verbatim(&path));
+        [absolute(path), canonical].into_iter().flatten().collect::<Vec<_>>()
+    };
+    let Some(workspace) = workspace else {
+        return key.to_path_buf();
+    };
+    let workspaces = forms(workspace);
+    let relative = forms(path).into_iter().find_map(|path| {
+        workspaces.iter().find_map(|workspace| path.strip_prefix(workspace).ok().map(Path::to_path_buf))
+    });
+    match relative {
+        // GitHub expects `/` separators on every runner
+        Some(relative) if cfg!(windows) => PathBuf::from(relative.to_string_lossy().replace('\\', "/")),
+        Some(relative) => relative,
+        None => key.to_path_buf(),
+    }
+}
+
+fn escape_github_data(s: &str) -> String {
+    s.replace('%', "%25").replace('\r', "%0D").replace('\n', "%0A")
+}
+
+fn escape_github_property(s: &str) -> String {
+    escape_github_data(s).replace(':', "%3A").replace(',', "%2C")
+}
+
+/// The summary of the current run, for `--publish`, notifiers, and `serve`:
+/// the validation report, the update summary, or the counts of a generated
+/// manifest.
+static LAST_SUMMARY: Mutex<Option<serde_json::Value>> = Mutex::new(None);
+
+/// Keep `summary` as the summary of the current run.
+pub(crate) fn record_summary(summary: &impl Serialize) {
+    *LAST_SUMMARY.lock().unwrap() = serde_json::to_value(summary).ok();
+}
+
+pub(crate) fn last_summary() -> Option<serde_json::Value> {
+    LAST_SUMMARY.lock().unwrap().clone()
+}
+
+/// The summary of the last run, if any, leaving none.
+pub(crate) fn take_summary() -> Option<serde_json::Value> {
+    LAST_SUMMARY.lock().unwrap().take()
+}
+
+/// The nonzero counts of a recorded summary, as `"<name> <count>"`.
+pub(crate) fn summary_counts(summary: &serde_json::Value) -> Vec<String> {
+    let Some(counts) = summary.get("summary").and_then(serde_json::Value::as_object) else { return Vec::new() };
+    counts
+        .iter()
+        .filter(|(_, count)| count.as_u64().is_some_and(|count| count > 0))
+        .map(|(name, count)| format!("{} {}", name.replace('_', " "), count))
+        .collect()
+}
+
+/// Counts recorded for a newly generated manifest.
+#[derive(Serialize)]
+pub(crate) struct GenerateSummary {
+    pub files: usize,
+    pub errors: usize,
+    pub special_files: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timings: Option<Timings>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn github_annotation_escapes_properties_and_data() {
+        let line = github_annotation("error", Path::new("dir/a:b,c%.txt"), "Hash: mismatch, bad", "50% done\r\nnext: a, b");
+        assert_eq!(
+            line,
+            "::error file=dir/a%3Ab%2Cc%25.txt,title=Hash%3A mismatch%2C bad::50%25 done%0D%0Anext: a, b"
+        );
+    }
+
+    #[test]
+    fn annotations_name_files_relative_to_the_workspace() {
+        let key = Path::new("archive/dir/a.txt");
+        let workspace = std::env::temp_dir().join("data-manifest-workspace");
+        let inside = workspace.join("data").join("dir").join("a.txt");
+        assert_eq!(workspace_path(&inside, key, Some(&workspace)), PathBuf::from("data/dir/a.txt"));
+        // Outside the workspace, or without one, the manifest key is used
+        let outside = std::env::temp_dir().join("elsewhere").join("a.txt");
+        assert_eq!(workspace_path(&outside, key, Some(&workspace)), key);
+        assert_eq!(workspace_path(&inside, key, None), key);
+    }
+}
@@ -0,0 +1,569 @@
+//! Amazon S3 (and S3-compatible) archives, addressed as `s3://bucket/prefix`.
+//!
+//! Credentials come from `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY`, and
+//! `AWS_SESSION_TOKEN`, or from the `AWS_PROFILE` (default `default`) section
+//! of `~/.aws/credentials`. The region is `AWS_REGION` or `AWS_DEFAULT_REGION`
+//! (default `us-east-1`). `AWS_ENDPOINT_URL` selects an S3-compatible service,
+//! addressed with path-style URLs.
+
+use anyhow::{Context, Result};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::remote::{object_key, parse_timestamp, split_url, uri_encode, Destination, FetchRange, RangeOptions, RangeReader};
+use crate::{format_utc, now_secs, Args, Error, FileInfo, FileSource};
+
+/// SHA-256 of an empty payload.
+pub(crate) const EMPTY_SHA256: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+#[derive(Clone)]
+pub(crate) struct Credentials {
+    access_key: String,
+    secret_key: String,
+    session_token: Option<String>,
+}
+
+impl Credentials {
+    fn from_env() -> Result<Self> {
+        if let (Ok(access_key), Ok(secret_key)) = (std::env::var("AWS_ACCESS_KEY_ID"), std::env::var("AWS_SECRET_ACCESS_KEY")) {
+            return Ok(Credentials {
+                access_key,
+                secret_key,
+                session_token: std::env::var("AWS_SESSION_TOKEN").ok(),
+            });
+        }
+        let path = std::env::var_os("AWS_SHARED_CREDENTIALS_FILE")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".aws/credentials")))
+            .ok_or_else(|| Error::Config("No AWS credentials found".into()))?;
+        let profile = std::env::var("AWS_PROFILE").unwrap_or_else(|_| "default".to_string());
+        let contents = std::fs::read_to_string(&path)
+            .map_err(|_| Error::Config(format!("No AWS credentials in the environment or {}", path.display())))?;
+        Self::from_profile(&contents, &profile)
+            .ok_or_else(|| Error::Config(format!("No credentials for profile {} in {}", profile, path.display())).into())
+    }
+
+    /// Read one `[profile]` section of an AWS credentials file.
+    fn from_profile(contents: &str, profile: &str) -> Option<Self> {
+        let mut in_section = false;
+        let (mut access_key, mut secret_key, mut session_token) = (None, None, None);
+        for line in contents.lines().map(str::trim) {
+            if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                in_section = section.trim() == profile;
+            } else if let Some((key, value)) = line.split_once('=').filter(|_| in_section) {
+                let value = Some(value.trim().to_string());
+                match key.trim() {
+                    "aws_access_key_id" => access_key = value,
+                    "aws_secret_access_key" => secret_key = value,
+                    "aws_session_token" => session_token = value,
+                    _ => {}
+                }
+            }
+        }
+        Some(Credentials {
+            access_key: access_key?,
+            secret_key: secret_key?,
+            session_token,
+        })
+    }
+}
+
+fn hmac_sha256(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// `20240601T020000Z` for a time in seconds since the Unix epoch.
+fn amz_date(secs: i64) -> String {
+    format_utc(secs).replace(['-', ':'], "") + "Z"
+}
+
+/// The parts of a request covered by its signature.
+struct SignedRequest<'a> {
+    method: &'a str,
+    canonical_uri: &'a str,
+    query: &'a [(String, String)],
+    /// Must include `host`, `x-amz-content-sha256`, and `x-amz-date`
+    headers: &'a [(String, String)],
+    payload_hash: &'a str,
+}
+
+/// Build the SigV4 `Authorization` header for `request` sent at `amz_date`.
+fn authorization(credentials: &Credentials, region: &str, amz_date: &str, request: &SignedRequest) -> String {
+    let SignedRequest { method, canonical_uri, query, headers, payload_hash } = *request;
+    let mut query: Vec<String> = query.iter().map(|(k, v)| format!("{}={}", uri_encode(k, false), uri_encode(v, false))).collect();
+    query.sort();
+    let mut headers: Vec<(String, String)> = headers.iter().map(|(k, v)| (k.to_ascii_lowercase(), v.trim().to_string())).collect();
+    headers.sort();
+    let canonical_headers: String = headers.iter().map(|(k, v)| format!("{}:{}\n", k, v)).collect();
+    let signed_headers = headers.iter().map(|(k, _)| k.as_str()).collect::<Vec<_>>().join(";");
+    let canonical_request = format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        method,
+        canonical_uri,
+        query.join("&"),
+        canonical_headers,
+        signed_headers,
+        payload_hash
+    );
+
+    let date = &amz_date[..8];
+    let scope = format!("{}/{}/s3/aws4_request", date, region);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        hex(&Sha256::digest(canonical_request.as_bytes()))
+    );
+    let key = hmac_sha256(format!("AWS4{}", credentials.secret_key).as_bytes(), date);
+    let key = hmac_sha256(&key, region);
+    let key = hmac_sha256(&key, "s3");
+    let key = hmac_sha256(&key, "aws4_request");
+    let signature = hex(&hmac_sha256(&key, &string_to_sign));
+    format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        credentials.access_key, scope, signed_headers, signature
+    )
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListBucketResult {
+    #[serde(default)]
+    contents: Vec<ListedObject>,
+    #[serde(default)]
+    is_truncated: bool,
+    next_continuation_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub(crate) struct ListedObject {
+    pub key: String,
+    pub size: u64,
+    pub last_modified: Option<String>,
+    /// Quoted MD5 of a single-part upload, or `"<md5 of part md5s>-<parts>"`
+    #[serde(rename = "ETag")]
+    pub etag: Option<String>,
+}
+
+/// A signed-request client for one bucket.
+#[derive(Clone)]
+pub(crate) struct S3Client {
+    agent: ureq::Agent,
+    bucket: String,
+    region: String,
+    endpoint: Option<String>,
+    credentials: Credentials,
+}
+
+impl S3Client {
+    pub fn new(bucket: &str) -> Result<Self> {
+        let region = std::env::var("AWS_REGION")
+            .or_else(|_| std::env::var("AWS_DEFAULT_REGION"))
+            .unwrap_or_else(|_| "us-east-1".to_string());
+        let endpoint = std::env::var("AWS_ENDPOINT_URL_S3")
+            .or_else(|_| std::env::var("AWS_ENDPOINT_URL"))
+            .ok()
+            .map(|e| e.trim_end_matches('/').to_string());
+        Ok(S3Client {
+            agent: ureq::AgentBuilder::new().build(),
+            bucket: bucket.to_string(),
+            region,
+            endpoint,
+            credentials: Credentials::from_env()?,
+        })
+    }
+
+    /// The request URL, host, and canonical URI of `key`.
+    fn location(&self, key: &str) -> (String, String, String) {
+        match self.endpoint {
+            Some(ref endpoint) => {
+                let host = endpoint.split_once("://").map(|(_, h)| h).unwrap_or(endpoint).to_string();
+                let uri = format!("/{}/{}", uri_encode(&self.bucket, false), uri_encode(key, true));
+                (format!("{}{}", endpoint, uri), host, uri)
+            }
+            None => {
+                let host = format!("{}.s3.{}.amazonaws.com", self.bucket, self.region);
+                let uri = format!("/{}", uri_encode(key, true));
+                (format!("https://{}{}", host, uri), host, uri)
+            }
+        }
+    }
+
+    /// A signed request for `key`. `headers` are signed too.
+    pub fn request(
+        &self,
+        method: &str,
+        key: &str,
+        query: &[(&str, &str)],
+        headers: &[(&str, String)],
+        payload_hash: &str,
+    ) -> ureq::Request {
+        let (url, host, uri) = self.location(key);
+        let date = amz_date(now_secs());
+        let query: Vec<(String, String)> = query.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        let mut signed: Vec<(String, String)> = vec![
+            ("host".into(), host),
+            ("x-amz-content-sha256".into(), payload_hash.to_string()),
+            ("x-amz-date".into(), date.clone()),
+        ];
+        if let Some(ref token) = self.credentials.session_token {
+            signed.push(("x-amz-security-token".into(), token.clone()));
+        }
+        signed.extend(headers.iter().map(|(k, v)| (k.to_string(), v.clone())));
+        let request = SignedRequest {
+            method,
+            canonical_uri: &uri,
+            query: &query,
+            headers: &signed,
+            payload_hash,
+        };
+        let auth = authorization(&self.credentials, &self.region, &date, &request);
+
+        let mut request = self.agent.request(method, &url);
+        for (k, v) in &query {
+            request = request.query(k, v);
+        }
+        for (k, v) in signed.iter().filter(|(k, _)| k != "host") {
+            request = request.set(k, v);
+        }
+        request.set("authorization", &auth)
+    }
+
+    /// Every object under `prefix`.
+    pub fn list(&self, prefix: &str, args: &Args) -> Result<Vec<ListedObject>> {
+        let mut objects = Vec::new();
+        let mut token: Option<String> = None;
+        loop {
+            let mut query = vec![("list-type", "2"), ("prefix", prefix)];
+            if let Some(ref token) = token {
+                query.push(("continuation-token", token.as_str()));
+            }
+            let body = self
+                .request("GET", "", &query, &[], EMPTY_SHA256)
+                .call()
+                .map_err(|e| request_error(e, &format!("s3://{}/{}", self.bucket, prefix)))?
+                .into_string()?;
+            let page: ListBucketResult = quick_xml::de::from_str(&body).context("Invalid ListObjectsV2 response")?;
+            objects.extend(page.contents);
+            match page.next_continuation_token.filter(|_| page.is_truncated) {
+                Some(next) if !args.cancel.is_cancelled() => token = Some(next),
+                _ => break,
+            }
+        }
+        Ok(objects)
+    }
+
+    /// Bytes `start..end` of `key`.
+    pub fn get_range(&self, key: &str, start: u64, end: u64) -> io::Result<Vec<u8>> {
+        let range = format!("bytes={}-{}", start, end - 1);
+        let response = self
+            .request("GET", key, &[], &[("range", range)], EMPTY_SHA256)
+            .call()
+            .map_err(|e| io_error(e, key))?;
+        let mut data = Vec::with_capacity((end - start) as usize);
+        response.into_reader().read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    /// Upload `data` to `key` in one request, with its SHA-256 checksum for S3
+    /// to verify and store.
+    pub fn put_object(&self, key: &str, data: &[u8]) -> Result<()> {
+        let digest = Sha256::digest(data);
+        self.request("PUT", key, &[], &[("x-amz-checksum-sha256", base64(&digest))], &hex(&digest))
+            .send_bytes(data)
+            .map_err(|e| request_error(e, key))?;
+        Ok(())
+    }
+
+    /// Start a multipart upload of `key` with SHA-256 part checksums.
+    pub fn create_multipart_upload(&self, key: &str) -> Result<String> {
+        let body = self
+            .request("POST", key, &[("uploads", "")], &[("x-amz-checksum-algorithm", "SHA256".to_string())], EMPTY_SHA256)
+            .call()
+            .map_err(|e| request_error(e, key))?
+            .into_string()?;
+        let result: InitiateMultipartUploadResult =
+            quick_xml::de::from_str(&body).context("Invalid CreateMultipartUpload response")?;
+        Ok(result.upload_id)
+    }
+
+    /// Upload part `number` (from 1) of a multipart upload.
+    pub fn upload_part(&self, key: &str, upload_id: &str, number: usize, data: &[u8]) -> Result<UploadedPart> {
+        let digest = Sha256::digest(data);
+        let number_str = number.to_string();
+        let response = self
+            .request(
+                "PUT",
+                key,
+                &[("partNumber", &number_str), ("uploadId", upload_id)],
+                &[("x-amz-checksum-sha256", base64(&digest))],
+                &hex(&digest),
+            )
+            .send_bytes(data)
+            .map_err(|e| request_error(e, &format!("{} part {}", key, number)))?;
+        Ok(UploadedPart {
+            number,
+            etag: response.header("etag").unwrap_or_default().to_string(),
+            sha256: digest.to_vec(),
+        })
+    }
+
+    pub fn complete_multipart_upload(&self, key: &str, upload_id: &str, parts: &[UploadedPart]) -> Result<()> {
+        let mut body = String::from("<CompleteMultipartUpload>");
+        for part in parts {
+            body.push_str(&format!(
+                "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag><ChecksumSHA256>{}</ChecksumSHA256></Part>",
+                part.number,
+                xml_escape(&part.etag),
+                base64(&part.sha256)
+            ));
+        }
+        body.push_str("</CompleteMultipartUpload>");
+        let response = self
+            .request("POST", key, &[("uploadId", upload_id)], &[], &hex(&Sha256::digest(body.as_bytes())))
+            .send_string(&body)
+            .map_err(|e| request_error(e, key))?
+            .into_string()?;
+        // Completion can fail after the 200 status has been sent
+        if response.contains("<Error>") {
+            anyhow::bail!("Completing the upload of {} failed: {}", key, response.trim());
+        }
+        Ok(())
+    }
+
+    pub fn abort_multipart_upload(&self, key: &str, upload_id: &str) -> Result<()> {
+        self.request("DELETE", key, &[("uploadId", upload_id)], &[], EMPTY_SHA256)
+            .call()
+            .map_err(|e| request_error(e, key))?;
+        Ok(())
+    }
+
+    /// The SHA-256 checksum stored with `key`, base64 encoded. Multipart
+    /// uploads store a composite checksum ending in `-<parts>`.
+    pub fn stored_checksum(&self, key: &str) -> Result<Option<String>> {
+        let response = self
+            .request("HEAD", key, &[], &[("x-amz-checksum-mode", "ENABLED".to_string())], EMPTY_SHA256)
+            .call()
+            .map_err(|e| request_error(e, key))?;
+        Ok(response.header("x-amz-checksum-sha256").map(str::to_string))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct InitiateMultipartUploadResult {
+    upload_id: String,
+}
+
+/// A part of a multipart upload, as needed to complete it.
+pub(crate) struct UploadedPart {
+    number: usize,
+    etag: String,
+    sha256: Vec<u8>,
+}
+
+fn base64(bytes: &[u8]) -> String {
+    use base64::Engine;
+    base64::engine::general_purpose::STANDARD.encode(bytes)
+}
+
+fn xml_escape(value: &str) -> String {
+    value.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Turn a failed request into an error that includes the service's message.
+pub(crate) fn request_error(error: ureq::Error, what: &str) -> anyhow::Error {
+    match error {
+        ureq::Error::Status(code, response) => {
+            let body = response.into_string().unwrap_or_default();
+            anyhow::anyhow!("Request for {} failed with HTTP {}: {}", what, code, body.trim())
+        }
+        e => anyhow::Error::new(e).context(format!("Request for {} failed", what)),
+    }
+}
+
+/// Like [`request_error`], keeping "not found" recognisable for validation.
+pub(crate) fn io_error(error: ureq::Error, what: &str) -> io::Error {
+    match error {
+        ureq::Error::Status(404, _) => io::Error::new(io::ErrorKind::NotFound, format!("{} not found", what)),
+        e => io::Error::other(format!("{:#}", request_error(e, what))),
+    }
+}
+
+/// Objects under an `s3://bucket/prefix` URL, read with concurrent range GETs.
+pub struct S3Source {
+    client: Arc<S3Client>,
+    prefix: String,
+    ranges: RangeOptions,
+    /// Sizes from the listing, so files can be read without a HEAD request
+    sizes: std::sync::Mutex<std::collections::HashMap<PathBuf, u64>>,
+}
+
+impl S3Source {
+    pub fn open(url: &str, args: &Args) -> Result<Self> {
+        let (bucket, prefix) = split_url(url)?;
+        Ok(S3Source {
+            client: Arc::new(S3Client::new(bucket)?),
+            prefix: prefix.to_string(),
+            ranges: RangeOptions::from_args(args)?,
+            sizes: Default::default(),
+        })
+    }
+
+}
+
+impl FileSource for S3Source {
+    fn files(&self, args: &Args) -> Result<Vec<FileInfo>> {
+        let list_prefix = if self.prefix.is_empty() { String::new() } else { format!("{}/", self.prefix) };
+        let mut files = Vec::new();
+        let mut sizes = self.sizes.lock().unwrap();
+        for object in self.client.list(&list_prefix, args)? {
+            // Zero-byte "directory" markers created by consoles are not files
+            if object.key.ends_with('/') {
+                continue;
+            }
+            let path = PathBuf::from(&object.key[list_prefix.len()..]);
+            sizes.insert(path.clone(), object.size);
+            files.push(FileInfo {
+                path,
+                size: object.size,
+                mtime: object.last_modified.as_deref().and_then(parse_timestamp),
+            });
+        }
+        Ok(files)
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send>> {
+        let key = object_key(&self.prefix, path);
+        let size = match self.sizes.lock().unwrap().get(path) {
+            Some(&size) => size,
+            None => {
+                let response = self.client.request("HEAD", &key, &[], &[], EMPTY_SHA256).call().map_err(|e| io_error(e, &key))?;
+                response.header("content-length").and_then(|l| l.parse().ok()).unwrap_or(0)
+            }
+        };
+        let client = Arc::clone(&self.client);
+        let fetch: FetchRange = Arc::new(move |start, end| client.get_range(&key, start, end));
+        Ok(Box::new(RangeReader::new(fetch, size, self.ranges)))
+    }
+}
+
+/// S3 rejects smaller parts except the last
+const MIN_PART_SIZE: u64 = 5 * 1024 * 1024;
+const MAX_PARTS: u64 = 10_000;
+
+/// Uploads below an `s3://bucket/prefix` URL.
+pub struct S3Destination {
+    client: Arc<S3Client>,
+    bucket: String,
+    prefix: String,
+    ranges: RangeOptions,
+}
+
+impl S3Destination {
+    pub fn open(url: &str, args: &Args) -> Result<Self> {
+        let (bucket, prefix) = split_url(url)?;
+        Ok(S3Destination {
+            client: Arc::new(S3Client::new(bucket)?),
+            bucket: bucket.to_string(),
+            prefix: prefix.to_string(),
+            ranges: RangeOptions::from_args(args)?,
+        })
+    }
+
+    /// Upload in parts, `concurrency` at a time, returning the composite
+    /// checksum S3 should store.
+    fn upload_multipart(&self, key: &str, reader: &mut dyn Read, part_size: u64) -> Result<String> {
+        let upload_id = self.client.create_multipart_upload(key)?;
+        let result = self.upload_parts(key, &upload_id, reader, part_size);
+        match result {
+            Ok(parts) => {
+                self.client.complete_multipart_upload(key, &upload_id, &parts)?;
+                let digests: Vec<u8> = parts.iter().flat_map(|p| p.sha256.iter().copied()).collect();
+                Ok(format!("{}-{}", base64(&Sha256::digest(&digests)), parts.len()))
+            }
+            Err(e) => {
+                // Abandoned parts are billed until the upload is aborted
+                if let Err(abort) = self.client.abort_multipart_upload(key, &upload_id) {
+                    eprintln!("Failed to abort upload {} of {}: {:#}", upload_id, key, abort);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    fn upload_parts(&self, key: &str, upload_id: &str, reader: &mut dyn Read, part_size: u64) -> Result<Vec<UploadedPart>> {
+        let mut parts = Vec::new();
+        loop {
+            let mut window = Vec::new();
+            while window.len() < self.ranges.concurrency {
+                let mut data = Vec::new();
+                reader.take(part_size).read_to_end(&mut data)?;
+                if data.is_empty() {
+                    break;
+                }
+                window.push(data);
+            }
+            if window.is_empty() {
+                return Ok(parts);
+            }
+            let first = parts.len() + 1;
+            let client = &self.client;
+            let uploaded: Vec<Result<UploadedPart>> = std::thread::scope(|scope| {
+                let handles: Vec<_> = window
+                    .iter()
+                    .enumerate()
+                    .map(|(i, data)| scope.spawn(move || client.upload_part(key, upload_id, first + i, data)))
+                    .collect();
+                handles
+                    .into_iter()
+                    .map(|h| h.join().unwrap_or_else(|_| Err(anyhow::anyhow!("Part upload panicked"))))
+                    .collect()
+            });
+            for part in uploaded {
+                parts.push(part?);
+            }
+        }
+    }
+}
+
+impl Destination for S3Destination {
+    fn upload(&self, path: &Path, reader: &mut dyn Read, size: u64) -> Result<()> {
+        let key = object_key(&self.prefix, path);
+        let url = format!("s3://{}/{}", self.bucket, key);
+        let part_size = self.ranges.part_size.max(MIN_PART_SIZE).max(size.div_ceil(MAX_PARTS));
+        let expected = if size <= part_size {
+            let mut data = Vec::new();
+            reader.read_to_end(&mut data)?;
+            self.client.put_object(&key, &data)?;
+            base64(&Sha256::digest(&data))
+        } else {
+            self.upload_multipart(&key, reader, part_size)?
+        };
+
+        match self.client.stored_checksum(&key)? {
+            Some(stored) if stored == expected => Ok(()),
+            Some(stored) => Err(Error::Validation {
+                message: format!("Stored checksum of {} is {}, expected {}", url, stored, expected),
+                exit_code: 1,
+            }
+            .into()),
+            None => Err(Error::Validation {
+                message: format!("{} has no stored SHA-256 checksum", url),
+                exit_code: 1,
+            }
+            .into()),
+        }
+    }
+}
@@ -0,0 +1,30 @@
+use anyhow::Result;
+
+/// Version of the JSON manifest and report formats. Bumped whenever a field is
+/// removed or changes meaning; new optional fields do not change it.
+pub const SCHEMA_VERSION: u32 = 1;
+
+const MANIFEST_SCHEMA: &str = include_str!("../schemas/manifest.schema.json");
+const REPORT_SCHEMA: &str = include_str!("../schemas/report.schema.json");
+
+/// JSON format described by a published schema.
+#[derive(clap::ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SchemaKind {
+    /// A manifest serialized as JSON by the library
+    Manifest,
+    /// The validation report written by --report-file
+    Report,
+}
+
+/// The JSON Schema of `kind`, as published in the `schemas` directory.
+pub fn schema(kind: SchemaKind) -> &'static str {
+    match kind {
+        SchemaKind::Manifest => MANIFEST_SCHEMA,
+        SchemaKind::Report => REPORT_SCHEMA,
+    }
+}
+
+pub fn run_schema(kind: SchemaKind) -> Result<()> {
+    print!("{}", schema(kind));
+    Ok(())
+}
@@ -0,0 +1,189 @@
+//! A small fixity service: `data-manifest serve` accepts generation, update,
+//! and validation jobs over HTTP (and gRPC, see [`crate::grpc`]), reports
+//! their progress, and serves the resulting manifests and reports.
+//!
+//! - `POST /jobs` submits a job (see [`JobRequest`]) and returns its id
+//! - `GET /jobs` lists jobs; `GET /jobs/{id}` shows one with its progress
+//! - `GET /jobs/{id}/manifest` and `GET /jobs/{id}/report` fetch the results
+//! - `DELETE /jobs/{id}` cancels a job
+//!
+//! The jobs themselves are run by [`crate::jobs`], with their manifests
+//! kept below `--root`. Requests must carry `DATA_MANIFEST_SERVE_TOKEN` as
+//! a bearer token; the service only runs without one when `--no-token` is
+//! given.
+
+use anyhow::Result;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tiny_http::{Header, Method, Request, Response, ResponseBox, Server};
+
+use crate::jobs::{Job, JobRequest, Jobs};
+use crate::{Args, Error};
+
+/// Requests are handled by this many worker threads.
+const WORKERS: usize = 8;
+/// Requests waiting for a worker beyond this many are turned away with 503.
+const QUEUE: usize = 64;
+
+/// Whether `presented` equals `expected`, taking the same time wherever
+/// they differ so the token cannot be guessed byte by byte.
+pub(crate) fn token_matches(presented: &str, expected: &str) -> bool {
+    let (presented, expected) = (Sha256::digest(presented.as_bytes()), Sha256::digest(expected.as_bytes()));
+    presented.iter().zip(expected.iter()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+fn json_response(code: u16, body: &impl Serialize) -> ResponseBox {
+    let body = serde_json::to_string_pretty(body).unwrap_or_default();
+    Response::from_string(body)
+        .with_status_code(code)
+        .with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
+        .boxed()
+}
+
+fn error_response(code: u16, message: &str) -> ResponseBox {
+    #[derive(Serialize)]
+    struct ErrorBody<'a> {
+        error: &'a str,
+    }
+    json_response(code, &ErrorBody { error: message })
+}
+
+fn submit(request: &mut Request, jobs: &Jobs) -> ResponseBox {
+    let mut body = String::new();
+    if let Err(e) = request.as_reader().read_to_string(&mut body) {
+        return error_response(400, &format!("Failed to read the request: {}", e));
+    }
+    let job_request: JobRequest = match serde_json::from_str(&body) {
+        Ok(job_request) => job_request,
+        Err(e) => return error_response(400, &format!("Invalid job: {}", e)),
+    };
+    match jobs.submit(job_request) {
+        Ok(job) => json_response(202, &job.status()),
+        Err(e) => error_response(400, &e),
+    }
+}
+
+fn manifest(job: &Job) -> ResponseBox {
+    if job.is_running() {
+        return error_response(409, "The job is still running");
+    }
+    match job.open_manifest() {
+        Ok(file) => Response::from_file(file)
+            .with_header(Header::from_bytes("Content-Type", "text/plain; charset=utf-8").unwrap())
+            .boxed(),
+        Err(_) => error_response(404, "The job left no manifest"),
+    }
+}
+
+fn report(job: &Job) -> ResponseBox {
+    match job.report() {
+        Some(report) => json_response(200, &report),
+        None => error_response(404, "The job has no report yet"),
+    }
+}
+
+fn handle(mut request: Request, jobs: &Jobs, token: Option<&str>) {
+    let authorized = match token {
+        Some(token) => {
+            let expected = format!("Bearer {}", token);
+            request.headers().iter().any(|h| h.field.equiv("Authorization") && token_matches(h.value.as_str(), &expected))
+        }
+        None => true,
+    };
+    let url = request.url().split('?').next().unwrap_or("").to_string();
+    let segments: Vec<&str> = url.split('/').filter(|s| !s.is_empty()).collect();
+    let response = match (request.method(), segments.as_slice()) {
+        _ if !authorized => error_response(401, "Missing or invalid bearer token"),
+        (Method::Post, ["jobs"]) => submit(&mut request, jobs),
+        (Method::Get, ["jobs"]) => {
+            let jobs = jobs.list();
+            json_response(200, &jobs.iter().map(|job| job.status()).collect::<Vec<_>>())
+        }
+        (method, ["jobs", id, rest @ ..]) => match (id.parse().ok().and_then(|id| jobs.get(id)), method, rest) {
+            (None, _, _) => error_response(404, "No such job"),
+            (Some(job), Method::Get, []) => json_response(200, &job.status()),
+            (Some(job), Method::Delete, []) => {
+                job.cancel();
+                json_response(200, &job.status())
+            }
+            (Some(job), Method::Get, ["manifest"]) => manifest(&job),
+            (Some(job), Method::Get, ["report"]) => report(&job),
+            _ => error_response(404, "Not found"),
+        },
+        _ => error_response(404, "Not found"),
+    };
+    let _ = request.respond(response);
+}
+
+/// Serve the job API on `listen`, and the gRPC API on `grpc` if given, for
+/// jobs with manifests below `root`, until `args.cancel` is cancelled.
+pub fn run_serve(listen: &str, grpc: Option<&str>, root: &Path, no_token: bool, args: &Args) -> Result<()> {
+    #[cfg(not(feature = "grpc"))]
+    if grpc.is_some() {
+        return Err(Error::Config("--grpc needs data-manifest built with the grpc feature".into()).into());
+    }
+    let token = std::env::var("DATA_MANIFEST_SERVE_TOKEN").ok().filter(|token| !token.is_empty());
+    if token.is_none() && !no_token {
+        return Err(Error::Config("Set DATA_MANIFEST_SERVE_TOKEN, or pass --no-token to serve without one".into()).into());
+    }
+    let jobs = Jobs::start(root)?;
+    let server = Server::http(listen).map_err(|e| Error::Config(format!("Failed to listen on {}: {}", listen, e)))?;
+    #[cfg(feature = "grpc")]
+    if let Some(grpc) = grpc {
+        crate::grpc::spawn(grpc, Arc::clone(&jobs), token.clone(), args.cancel.clone())?;
+    }
+    println!("Listening on http://{}", listen);
+
+    let (queue, requests) = mpsc::sync_channel::<Request>(QUEUE);
+    let requests = Arc::new(Mutex::new(requests));
+    let workers: Vec<_> = (0..WORKERS)
+        .map(|_| {
+            let (requests, jobs, token) = (Arc::clone(&requests), Arc::clone(&jobs), token.clone());
+            std::thread::spawn(move || work(&requests, &jobs, token.as_deref()))
+        })
+        .collect();
+
+    let result = (|| {
+        while !args.cancel.is_cancelled() {
+            let Some(request) = server.recv_timeout(Duration::from_millis(500))? else { continue };
+            if let Err(TrySendError::Full(request)) = queue.try_send(request) {
+                let _ = request.respond(error_response(503, "Server busy, try again later"));
+            }
+        }
+        Ok(())
+    })();
+    drop(queue);
+    for worker in workers {
+        let _ = worker.join();
+    }
+    jobs.cancel_all();
+    result
+}
+
+/// Handle queued requests until the queue is closed.
+fn work(requests: &Mutex<Receiver<Request>>, jobs: &Jobs, token: Option<&str>) {
+    loop {
+        let request = match requests.lock().unwrap_or_else(|e| e.into_inner()).recv() {
+            Ok(request) => request,
+            Err(_) => return,
+        };
+        handle(request, jobs, token);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_match_only_when_equal() {
+        assert!(token_matches("Bearer secret", "Bearer secret"));
+        assert!(!token_matches("Bearer secreT", "Bearer secret"));
+        assert!(!token_matches("Bearer secret2", "Bearer secret"));
+        assert!(!token_matches("", "Bearer secret"));
+    }
+}
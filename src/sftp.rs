@@ -0,0 +1,524 @@
+//! Archives on hosts reachable over SSH, addressed as
+//! `sftp://[user@]host[:port]/path`, read with the SFTP protocol.
+//!
+//! The connection is made by the system `ssh` client, so keys, agents,
+//! `~/.ssh/config` aliases, and jump hosts work as they do for `sftp`. Nothing
+//! has to be installed on the remote host beyond its SFTP server. `/path` is
+//! absolute; `/~/path` is relative to the login directory.
+
+use anyhow::Result;
+use rayon::prelude::*;
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+use crate::remote::{object_key, FetchRange, RangeOptions, RangeReader};
+use crate::{Args, Error, FileInfo, FileSource};
+
+const SSH_FXP_INIT: u8 = 1;
+const SSH_FXP_VERSION: u8 = 2;
+const SSH_FXP_OPEN: u8 = 3;
+const SSH_FXP_CLOSE: u8 = 4;
+const SSH_FXP_READ: u8 = 5;
+const SSH_FXP_OPENDIR: u8 = 11;
+const SSH_FXP_READDIR: u8 = 12;
+const SSH_FXP_STAT: u8 = 17;
+const SSH_FXP_STATUS: u8 = 101;
+const SSH_FXP_HANDLE: u8 = 102;
+const SSH_FXP_DATA: u8 = 103;
+const SSH_FXP_NAME: u8 = 104;
+const SSH_FXP_ATTRS: u8 = 105;
+
+const SSH_FX_EOF: u32 = 1;
+const SSH_FX_NO_SUCH_FILE: u32 = 2;
+const SSH_FX_PERMISSION_DENIED: u32 = 3;
+
+const SSH_FXF_READ: u32 = 1;
+
+const ATTR_SIZE: u32 = 0x1;
+const ATTR_UIDGID: u32 = 0x2;
+const ATTR_PERMISSIONS: u32 = 0x4;
+const ATTR_ACMODTIME: u32 = 0x8;
+const ATTR_EXTENDED: u32 = 0x8000_0000;
+
+const S_IFMT: u32 = 0o170000;
+const S_IFREG: u32 = 0o100000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFLNK: u32 = 0o120000;
+
+/// Every server must accept reads of this size
+const READ_SIZE: u64 = 32 * 1024;
+/// Reads in flight per range, as the OpenSSH client keeps
+const MAX_OUTSTANDING: usize = 64;
+
+/// Appends SFTP wire types to a request body.
+#[derive(Default)]
+struct Body(Vec<u8>);
+
+impl Body {
+    fn u32(mut self, value: u32) -> Self {
+        self.0.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    fn u64(mut self, value: u64) -> Self {
+        self.0.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    fn bytes(self, value: &[u8]) -> Self {
+        let mut body = self.u32(value.len() as u32);
+        body.0.extend_from_slice(value);
+        body
+    }
+}
+
+/// Reads SFTP wire types from a response body.
+struct Fields<'a>(&'a [u8]);
+
+impl<'a> Fields<'a> {
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.0.len() < n {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Truncated SFTP packet"));
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn bytes(&mut self) -> io::Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    fn attrs(&mut self) -> io::Result<Attrs> {
+        let flags = self.u32()?;
+        let mut attrs = Attrs::default();
+        if flags & ATTR_SIZE != 0 {
+            attrs.size = Some(self.u64()?);
+        }
+        if flags & ATTR_UIDGID != 0 {
+            self.take(8)?;
+        }
+        if flags & ATTR_PERMISSIONS != 0 {
+            attrs.permissions = Some(self.u32()?);
+        }
+        if flags & ATTR_ACMODTIME != 0 {
+            self.take(4)?;
+            attrs.mtime = Some(self.u32()?);
+        }
+        if flags & ATTR_EXTENDED != 0 {
+            for _ in 0..self.u32()? {
+                self.bytes()?;
+                self.bytes()?;
+            }
+        }
+        Ok(attrs)
+    }
+}
+
+#[derive(Default, Clone, Copy)]
+struct Attrs {
+    size: Option<u64>,
+    permissions: Option<u32>,
+    /// Seconds since the Unix epoch
+    mtime: Option<u32>,
+}
+
+impl Attrs {
+    fn file_type(&self) -> Option<u32> {
+        self.permissions.map(|p| p & S_IFMT)
+    }
+
+    fn file_info(&self, path: PathBuf) -> FileInfo {
+        FileInfo {
+            path,
+            size: self.size.unwrap_or(0),
+            mtime: self.mtime.map(|secs| i64::from(secs) * 1_000_000_000),
+        }
+    }
+}
+
+type Response = (u8, Vec<u8>);
+
+/// Senders for the requests awaiting a response, by request id.
+type Pending = Arc<Mutex<Option<HashMap<u32, Sender<Response>>>>>;
+
+/// One SFTP session over an `ssh` child process. Requests from any thread
+/// are multiplexed over it and matched to their responses by id.
+struct Session {
+    host: String,
+    child: Mutex<Child>,
+    stdin: Mutex<ChildStdin>,
+    /// Waiting requests; `None` once the connection has closed
+    pending: Pending,
+    next_id: AtomicU32,
+}
+
+impl Session {
+    fn connect(host: &str, port: Option<&str>) -> Result<Self> {
+        let mut command = Command::new("ssh");
+        command.args(["-o", "BatchMode=yes"]);
+        if let Some(port) = port {
+            command.args(["-p", port]);
+        }
+        let mut child = command
+            .args(["-s", "--", host, "sftp"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| Error::Config(format!("Failed to run ssh: {}", e)))?;
+        let mut stdin = child.stdin.take().expect("piped stdin");
+        let mut stdout = child.stdout.take().expect("piped stdout");
+
+        let failed = || Error::Config(format!("SFTP connection to {} failed", host));
+        write_packet(&mut stdin, SSH_FXP_INIT, &Body::default().u32(3).0).map_err(|_| failed())?;
+        match read_packet(&mut stdout) {
+            Ok((SSH_FXP_VERSION, _)) => {}
+            _ => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(failed().into());
+            }
+        }
+
+        let pending: Pending = Arc::new(Mutex::new(Some(HashMap::new())));
+        let dispatch = Arc::clone(&pending);
+        std::thread::spawn(move || {
+            while let Ok((kind, body)) = read_packet(&mut stdout) {
+                let Some(id) = body.get(..4).map(|id| u32::from_be_bytes(id.try_into().unwrap())) else {
+                    break;
+                };
+                let waiter = dispatch.lock().unwrap().as_mut().and_then(|p| p.remove(&id));
+                if let Some(waiter) = waiter {
+                    let _ = waiter.send((kind, body[4..].to_vec()));
+                }
+            }
+            // Wake everything still waiting
+            dispatch.lock().unwrap().take();
+        });
+
+        Ok(Session {
+            host: host.to_string(),
+            child: Mutex::new(child),
+            stdin: Mutex::new(stdin),
+            pending,
+            next_id: AtomicU32::new(0),
+        })
+    }
+
+    fn closed(&self) -> io::Error {
+        io::Error::new(io::ErrorKind::ConnectionAborted, format!("SFTP connection to {} closed", self.host))
+    }
+
+    /// Send a request; its response arrives on the returned receiver.
+    fn send(&self, kind: u8, body: Body) -> io::Result<Receiver<Response>> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = mpsc::channel();
+        match self.pending.lock().unwrap().as_mut() {
+            Some(pending) => pending.insert(id, sender),
+            None => return Err(self.closed()),
+        };
+        let body = Body::default().u32(id).0.into_iter().chain(body.0).collect::<Vec<_>>();
+        write_packet(&mut *self.stdin.lock().unwrap(), kind, &body).map_err(|_| self.closed())?;
+        Ok(receiver)
+    }
+
+    fn wait(&self, receiver: Receiver<Response>) -> io::Result<Response> {
+        receiver.recv().map_err(|_| self.closed())
+    }
+
+    fn call(&self, kind: u8, body: Body) -> io::Result<Response> {
+        let receiver = self.send(kind, body)?;
+        self.wait(receiver)
+    }
+
+    fn open_handle(&self, kind: u8, body: Body, path: &str) -> io::Result<Vec<u8>> {
+        match self.call(kind, body)? {
+            (SSH_FXP_HANDLE, body) => Ok(Fields(&body).bytes()?.to_vec()),
+            response => Err(unexpected(response, path)),
+        }
+    }
+
+    fn close(&self, handle: &[u8]) {
+        // Nothing useful can be done if closing fails
+        let _ = self.send(SSH_FXP_CLOSE, Body::default().bytes(handle));
+    }
+
+    fn stat(&self, path: &str) -> io::Result<Attrs> {
+        match self.call(SSH_FXP_STAT, Body::default().bytes(path.as_bytes()))? {
+            (SSH_FXP_ATTRS, body) => Fields(&body).attrs(),
+            response => Err(unexpected(response, path)),
+        }
+    }
+
+    /// Names and attributes of the entries of directory `path`.
+    fn read_dir(&self, path: &str) -> io::Result<Vec<(String, Attrs)>> {
+        let handle = self.open_handle(SSH_FXP_OPENDIR, Body::default().bytes(path.as_bytes()), path)?;
+        let mut entries = Vec::new();
+        let result = loop {
+            match self.call(SSH_FXP_READDIR, Body::default().bytes(&handle)) {
+                Ok((SSH_FXP_NAME, body)) => {
+                    let mut fields = Fields(&body);
+                    for _ in 0..fields.u32()? {
+                        let name = String::from_utf8_lossy(fields.bytes()?).into_owned();
+                        fields.bytes()?;
+                        let attrs = fields.attrs()?;
+                        if name != "." && name != ".." {
+                            entries.push((name, attrs));
+                        }
+                    }
+                }
+                Ok(response) if is_eof(&response) => break Ok(entries),
+                Ok(response) => break Err(unexpected(response, path)),
+                Err(e) => break Err(e),
+            }
+        };
+        self.close(&handle);
+        result
+    }
+
+    /// Bytes `start..end` of the open file `handle`, read as pipelined
+    /// requests of `READ_SIZE`.
+    fn read_range(&self, handle: &[u8], path: &str, start: u64, end: u64) -> io::Result<Vec<u8>> {
+        let read = |offset: u64, len: u64| {
+            self.send(SSH_FXP_READ, Body::default().bytes(handle).u64(offset).u32(len as u32))
+        };
+        let receive = |receiver| match self.wait(receiver)? {
+            (SSH_FXP_DATA, body) => Ok(Fields(&body).bytes()?.to_vec()),
+            response if is_eof(&response) => Ok(Vec::new()),
+            response => Err(unexpected(response, path)),
+        };
+        let eof = || io::Error::new(io::ErrorKind::UnexpectedEof, format!("{} is shorter than listed", path));
+
+        let mut data = Vec::with_capacity((end - start) as usize);
+        let mut requests = VecDeque::new();
+        let mut next = start;
+        loop {
+            while requests.len() < MAX_OUTSTANDING && next < end {
+                let len = READ_SIZE.min(end - next);
+                requests.push_back((next, len, read(next, len)?));
+                next += len;
+            }
+            let Some((offset, len, receiver)) = requests.pop_front() else {
+                return Ok(data);
+            };
+            let chunk = receive(receiver)?;
+            if chunk.is_empty() {
+                return Err(eof());
+            }
+            // Servers may return less than asked for; fetch the rest before
+            // moving on so the data stays in order
+            let mut done = chunk.len() as u64;
+            data.extend_from_slice(&chunk);
+            while done < len {
+                let chunk = receive(read(offset + done, len - done)?)?;
+                if chunk.is_empty() {
+                    return Err(eof());
+                }
+                done += chunk.len() as u64;
+                data.extend_from_slice(&chunk);
+            }
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        let mut child = self.child.lock().unwrap();
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+}
+
+fn write_packet(writer: &mut impl Write, kind: u8, body: &[u8]) -> io::Result<()> {
+    let mut packet = Vec::with_capacity(body.len() + 5);
+    packet.extend_from_slice(&(body.len() as u32 + 1).to_be_bytes());
+    packet.push(kind);
+    packet.extend_from_slice(body);
+    writer.write_all(&packet)?;
+    writer.flush()
+}
+
+fn read_packet(reader: &mut impl Read) -> io::Result<Response> {
+    let mut len = [0; 4];
+    reader.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len == 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Empty SFTP packet"));
+    }
+    let mut packet = vec![0; len];
+    reader.read_exact(&mut packet)?;
+    let body = packet.split_off(1);
+    Ok((packet[0], body))
+}
+
+fn is_eof(response: &Response) -> bool {
+    response.0 == SSH_FXP_STATUS && Fields(&response.1).u32().ok() == Some(SSH_FX_EOF)
+}
+
+/// The error for a response other than the expected one, keeping "not found"
+/// recognisable for validation.
+fn unexpected((kind, body): Response, path: &str) -> io::Error {
+    if kind != SSH_FXP_STATUS {
+        return io::Error::new(io::ErrorKind::InvalidData, format!("Unexpected SFTP response {} for {}", kind, path));
+    }
+    let mut fields = Fields(&body);
+    let code = fields.u32().unwrap_or(0);
+    let message = fields.bytes().map(|m| String::from_utf8_lossy(m).into_owned()).unwrap_or_default();
+    let kind = match code {
+        SSH_FX_NO_SUCH_FILE => io::ErrorKind::NotFound,
+        SSH_FX_PERMISSION_DENIED => io::ErrorKind::PermissionDenied,
+        _ => io::ErrorKind::Other,
+    };
+    io::Error::new(kind, format!("{}: {} (SFTP status {})", path, message, code))
+}
+
+/// Split `sftp://[user@]host[:port]/path` (or `ssh://`) into the `ssh`
+/// destination, the port, and the remote path. An empty port, as in
+/// `ssh://host:/path`, is ignored.
+pub(crate) fn parse_ssh_url(url: &str) -> Result<(String, Option<&str>, String)> {
+    let rest = url.split_once("://").map(|(_, rest)| rest).unwrap_or(url);
+    let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+    // IPv6 addresses are bracketed: user@[::1]:2222
+    let (host, port) = match authority.split_once(']') {
+        Some((host, port)) => (host.replacen('[', "", 1), port.strip_prefix(':')),
+        None => match authority.split_once(':') {
+            Some((host, port)) => (host.to_string(), Some(port)),
+            None => (authority.to_string(), None),
+        },
+    };
+    let port = port.filter(|p| !p.is_empty());
+    if host.is_empty() || host.ends_with('@') {
+        return Err(Error::Config(format!("Archive URL has no host: {}", url)).into());
+    }
+    // ssh would take these for options, such as -oProxyCommand=...
+    if host.starts_with('-') {
+        return Err(Error::Config(format!("Archive URL host cannot start with '-': {}", url)).into());
+    }
+    if port.is_some_and(|port| !port.bytes().all(|b| b.is_ascii_digit())) {
+        return Err(Error::Config(format!("Archive URL has an invalid port: {}", url)).into());
+    }
+    let path = path.trim_end_matches('/');
+    let path = match path.strip_prefix('~') {
+        Some(relative) => relative.trim_start_matches('/').to_string(),
+        None => format!("/{}", path),
+    };
+    Ok((host, port, path))
+}
+
+/// Files below a directory on an SFTP server.
+pub struct SftpSource {
+    session: Arc<Session>,
+    root: String,
+    ranges: RangeOptions,
+    /// Sizes from the listing, so files can be read without a STAT request
+    sizes: Mutex<HashMap<PathBuf, u64>>,
+}
+
+impl SftpSource {
+    pub fn open(url: &str, args: &Args) -> Result<Self> {
+        let (host, port, root) = parse_ssh_url(url)?;
+        Ok(SftpSource {
+            session: Arc::new(Session::connect(&host, port)?),
+            root,
+            ranges: RangeOptions::from_args(args)?,
+            sizes: Default::default(),
+        })
+    }
+
+    fn remote_path(&self, path: &Path) -> String {
+        match self.root.as_str() {
+            "" => object_key("", path),
+            "/" => format!("/{}", object_key("", path)),
+            root => object_key(root, path),
+        }
+    }
+}
+
+impl FileSource for SftpSource {
+    fn files(&self, args: &Args) -> Result<Vec<FileInfo>> {
+        let root = if self.root.is_empty() { "." } else { self.root.as_str() };
+        let mut files = Vec::new();
+        // Directories are listed a level at a time, each level in parallel
+        let mut level = vec![PathBuf::new()];
+        while !level.is_empty() && !args.cancel.is_cancelled() {
+            let listings = level
+                .par_iter()
+                .map(|dir| {
+                    let remote = if dir.as_os_str().is_empty() { root.to_string() } else { self.remote_path(dir) };
+                    let entries = self.session.read_dir(&remote).map_err(Error::io("Failed to read directory", Path::new(&remote)))?;
+                    Ok((dir, remote, entries))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let mut next = Vec::new();
+            for (dir, remote, entries) in listings {
+                for (name, mut attrs) in entries {
+                    let path = dir.join(&name);
+                    if attrs.file_type() == Some(S_IFLNK) {
+                        if !args.follow_symlinks {
+                            continue;
+                        }
+                        // Only links to files are followed; links to
+                        // directories could form cycles
+                        match self.session.stat(&format!("{}/{}", remote, name)) {
+                            Ok(target) if target.file_type() == Some(S_IFREG) => attrs = target,
+                            _ => continue,
+                        }
+                    }
+                    match attrs.file_type() {
+                        Some(S_IFDIR) => next.push(path),
+                        Some(S_IFREG) | None => files.push(attrs.file_info(path)),
+                        Some(_) => {}
+                    }
+                }
+            }
+            level = next;
+        }
+        let mut sizes = self.sizes.lock().unwrap();
+        sizes.extend(files.iter().map(|f| (f.path.clone(), f.size)));
+        Ok(files)
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send>> {
+        let remote = self.remote_path(path);
+        let listed = self.sizes.lock().unwrap().get(path).copied();
+        let size = match listed {
+            Some(size) => size,
+            None => self.session.stat(&remote)?.size.unwrap_or(0),
+        };
+        let body = Body::default().bytes(remote.as_bytes()).u32(SSH_FXF_READ).u32(0);
+        let handle = FileHandle {
+            session: Arc::clone(&self.session),
+            handle: self.session.open_handle(SSH_FXP_OPEN, body, &remote)?,
+            path: remote,
+        };
+        let fetch: FetchRange = Arc::new(move |start, end| handle.session.read_range(&handle.handle, &handle.path, start, end));
+        Ok(Box::new(RangeReader::new(fetch, size, self.ranges)))
+    }
+}
+
+/// An open remote file, closed when the reader is dropped.
+struct FileHandle {
+    session: Arc<Session>,
+    handle: Vec<u8>,
+    path: String,
+}
+
+impl Drop for FileHandle {
+    fn drop(&mut self) {
+        self.session.close(&self.handle);
+    }
+}
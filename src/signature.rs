@@ -0,0 +1,165 @@
+//! Detached GPG signatures of manifests: `--sign KEYID` writes
+//! `<manifest>.asc` after generation and update, and validation checks the
+//! signature before it trusts the hashes in the manifest.
+//!
+//! Signing and verification run `gpg`, so keys, the agent, and the trust
+//! model are the user's GnuPG configuration.
+
+use anyhow::Result;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::{file_state, rewrites_manifest, Args, Error};
+
+/// Where the detached signature of `manifest_path` is kept.
+pub fn signature_path(manifest_path: &Path) -> PathBuf {
+    let mut path = manifest_path.as_os_str().to_owned();
+    path.push(".asc");
+    PathBuf::from(path)
+}
+
+fn gpg() -> Command {
+    let mut command = Command::new("gpg");
+    command.arg("--batch");
+    command
+}
+
+/// Run `run` and sign the manifest it wrote with `args.sign`, including one
+/// rewritten by validation (`--fix`, `--record-verified`, `--budget`).
+/// Without a key, warn when an existing signature no longer covers the
+/// rewritten manifest; a validation that may rewrite a signed manifest is
+/// refused instead, as the next check of the signature would fail.
+pub(crate) fn run_signed(args: &Args, run: impl FnOnce(&Args) -> Result<()>) -> Result<()> {
+    let signature = signature_path(&args.output);
+    if args.validate && rewrites_manifest(args) && args.sign.is_none() && signature.exists() {
+        return Err(Error::Config(format!(
+            "{} is signed and validation may rewrite it; pass --sign to sign it again",
+            args.output.display()
+        ))
+        .into());
+    }
+    let before = file_state(&args.output);
+    run(args)?;
+    let rewritten = if args.validate {
+        file_state(&args.output) != before
+    } else {
+        !args.cancel.is_cancelled()
+    };
+    if !rewritten || !args.output.exists() {
+        return Ok(());
+    }
+    let Some(ref key) = args.sign else {
+        if signature.exists() {
+            eprintln!(
+                "Warning: {} was rewritten but not signed; {} no longer matches it (pass --sign)",
+                args.output.display(),
+                signature.display()
+            );
+        }
+        return Ok(());
+    };
+
+    status!("Signing manifest with key {}", key);
+    let status = gpg()
+        .args(["--yes", "--armor", "--local-user", key, "--detach-sign", "--output"])
+        .arg(&signature)
+        .arg(&args.output)
+        .status()
+        .map_err(|e| Error::Config(format!("Failed to run gpg: {}", e)))?;
+    if !status.success() {
+        let _ = fs::remove_file(&signature);
+        return Err(Error::Config(format!("gpg could not sign {} with key {} ({})", args.output.display(), key, status)).into());
+    }
+    status!("Signature written to: {}", signature.display());
+    Ok(())
+}
+
+/// Check the detached signature of `manifest_path`, if it has one. With a
+/// `signer`, the signature is required and must be made by that key (a long
+/// key ID, fingerprint, or email address).
+pub(crate) fn verify_manifest(manifest_path: &Path, signer: Option<&str>) -> Result<()> {
+    let signature = signature_path(manifest_path);
+    if !signature.exists() {
+        return match signer {
+            Some(_) => Err(validation_error(format!("{} is not signed: {} does not exist", manifest_path.display(), signature.display()))),
+            None => Ok(()),
+        };
+    }
+
+    let output = gpg()
+        .args(["--status-fd", "1", "--verify"])
+        .arg(&signature)
+        .arg(manifest_path)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| Error::Config(format!("Failed to run gpg: {}", e)))?;
+    let status = String::from_utf8_lossy(&output.stdout);
+    let good = status_line(&status, "GOODSIG");
+    let (Some(good), true) = (good, output.status.success()) else {
+        let reason = String::from_utf8_lossy(&output.stderr);
+        let reason = reason.lines().rev().find(|line| !line.trim().is_empty()).unwrap_or_default().trim();
+        return Err(validation_error(format!(
+            "The signature {} of {} is not valid: {}",
+            signature.display(),
+            manifest_path.display(),
+            reason
+        )));
+    };
+
+    // GOODSIG <long key ID> <user ID>
+    let (key_id, user_id) = good.split_once(' ').unwrap_or((good, ""));
+    if let Some(expected) = signer {
+        // VALIDSIG <fingerprint> ... <primary key fingerprint>
+        let fingerprints: Vec<&str> = status_line(&status, "VALIDSIG")
+            .map(|fields| {
+                let fields: Vec<&str> = fields.split(' ').collect();
+                [fields.first(), fields.last()].into_iter().flatten().copied().collect()
+            })
+            .unwrap_or_default();
+        if !signed_by(expected, key_id, &fingerprints, user_id) {
+            return Err(validation_error(format!(
+                "{} is signed by {} ({}), not by {}",
+                manifest_path.display(),
+                key_id,
+                user_id,
+                expected
+            )));
+        }
+    }
+    status!("Good signature from {} ({})", user_id, key_id);
+    Ok(())
+}
+
+fn validation_error(message: String) -> anyhow::Error {
+    Error::Validation { message, exit_code: 1 }.into()
+}
+
+/// The fields after `[GNUPG:] <keyword>` in gpg's status output.
+fn status_line<'a>(status: &'a str, keyword: &str) -> Option<&'a str> {
+    status.lines().find_map(|line| {
+        let rest = line.strip_prefix("[GNUPG:] ")?.strip_prefix(keyword)?;
+        rest.strip_prefix(' ')
+    })
+}
+
+/// Whether `expected` names the signing key exactly: its long key ID or its
+/// fingerprint (with or without `0x` and spaces), or the email address of
+/// the signer's user ID. Short key IDs and parts of names are too easily
+/// matched by another key to be accepted.
+fn signed_by(expected: &str, key_id: &str, fingerprints: &[&str], user_id: &str) -> bool {
+    let hex: String = expected.trim_start_matches("0x").chars().filter(|c| !c.is_whitespace()).collect();
+    if hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return match hex.len() {
+            // A long key ID is the end of a version 4 fingerprint
+            16 => std::iter::once(&key_id)
+                .chain(fingerprints)
+                .any(|id| id.len() >= 16 && hex.eq_ignore_ascii_case(&id[id.len() - 16..])),
+            40 | 64 => fingerprints.iter().any(|fingerprint| hex.eq_ignore_ascii_case(fingerprint)),
+            _ => false,
+        };
+    }
+    let email = |text: &str| text.rsplit_once('<').and_then(|(_, rest)| rest.split_once('>')).map(|(email, _)| email.to_string());
+    let expected = email(expected).unwrap_or_else(|| expected.to_string());
+    email(user_id).is_some_and(|signer| expected.contains('@') && signer.eq_ignore_ascii_case(&expected))
+}
@@ -0,0 +1,99 @@
+//! `--report sizes`: the largest files found by the walk of a run and a
+//! histogram of file sizes, for the capacity questions that follow a
+//! fixity check. Sizes come from the walk, so the report costs no reads.
+
+use std::path::Path;
+
+use crate::{manifest_key, FileInfo};
+
+/// Upper bounds of the histogram buckets after the one for empty files,
+/// in bytes; the last bucket holds everything larger.
+const BOUNDS: &[u64] = &[
+    1 << 10,
+    10 << 10,
+    100 << 10,
+    1 << 20,
+    10 << 20,
+    100 << 20,
+    1 << 30,
+    10 << 30,
+    100 << 30,
+];
+
+/// Width of the longest histogram bar
+const BAR_WIDTH: usize = 40;
+
+/// `bytes` in binary units, e.g. `1.5 GiB`.
+fn format_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["KiB", "MiB", "GiB", "TiB", "PiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+/// The label of the histogram bucket ending below `bound`, starting at
+/// `lower`.
+fn bucket_label(lower: u64, bound: Option<u64>) -> String {
+    let short = |bytes: u64| format_size(bytes).replace(".0 ", " ");
+    match bound {
+        Some(bound) => format!("{} - {}", short(lower), short(bound)),
+        None => format!(">= {}", short(lower)),
+    }
+}
+
+/// The report lines for `files`: the `top` largest, then the histogram.
+pub(crate) fn report(files: &[FileInfo], archive_path: &Path, archive_name: &str, top: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut largest: Vec<&FileInfo> = files.iter().collect();
+    largest.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.path.cmp(&b.path)));
+    largest.truncate(top);
+    lines.push(format!("Largest files ({} of {}):", largest.len(), files.len()));
+    for file_info in largest {
+        lines.push(format!(
+            "  {:>10}  {}",
+            format_size(file_info.size),
+            manifest_key(&file_info.path, archive_path, archive_name).display()
+        ));
+    }
+
+    // Empty files, then one bucket per bound and one past the last
+    let mut buckets = vec![(0usize, 0u64); BOUNDS.len() + 2];
+    for file_info in files {
+        let index = match file_info.size {
+            0 => 0,
+            size => 1 + BOUNDS.iter().position(|&bound| size < bound).unwrap_or(BOUNDS.len()),
+        };
+        buckets[index].0 += 1;
+        buckets[index].1 += file_info.size;
+    }
+    let labels: Vec<String> = std::iter::once("empty".to_string())
+        .chain((0..=BOUNDS.len()).map(|i| {
+            let lower = if i == 0 { 1 } else { BOUNDS[i - 1] };
+            bucket_label(lower, BOUNDS.get(i).copied())
+        }))
+        .collect();
+    let width = labels.iter().map(String::len).max().unwrap_or(0);
+    let most = buckets.iter().map(|&(count, _)| count).max().unwrap_or(0).max(1);
+    lines.push("File sizes:".to_string());
+    for (label, (count, bytes)) in labels.iter().zip(buckets) {
+        // Buckets with any files get a bar of at least one mark
+        let bar = (count * BAR_WIDTH).div_ceil(most);
+        let line = format!(
+            "  {:<width$}  {:>9} files  {:>10}  {}",
+            label,
+            count,
+            format_size(bytes),
+            "#".repeat(bar),
+            width = width
+        );
+        lines.push(line.trim_end().to_string());
+    }
+    lines
+}
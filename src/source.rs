@@ -0,0 +1,82 @@
+use anyhow::Result;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::{collect_files, extension_allowed, Args, ExcludeSet, FileInfo};
+
+/// Where the files of a tree come from.
+///
+/// The local filesystem is one implementation; object stores, archive files,
+/// or remote agents can implement this to be hashed by [`ManifestBuilder`]
+/// and checked by [`Manifest::validate_source`] like a local directory.
+///
+/// Paths are relative to the root of the tree and use `/` as separator.
+///
+/// [`ManifestBuilder`]: crate::ManifestBuilder
+/// [`Manifest::validate_source`]: crate::Manifest::validate_source
+pub trait FileSource: Send + Sync {
+    /// All files in the tree. Sources should honour the traversal options in
+    /// `args` that apply to them and stop early when `args.cancel` is
+    /// cancelled; exclusion and extension filters are applied by the caller.
+    fn files(&self, args: &Args) -> Result<Vec<FileInfo>>;
+
+    /// Open a file for reading. A file that does not exist must yield an
+    /// error of kind [`io::ErrorKind::NotFound`].
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send>>;
+}
+
+/// Files in a directory on the local filesystem.
+#[derive(Clone, Debug)]
+pub struct LocalFileSource {
+    root: PathBuf,
+}
+
+impl LocalFileSource {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        LocalFileSource { root: root.into() }
+    }
+}
+
+impl FileSource for LocalFileSource {
+    fn files(&self, args: &Args) -> Result<Vec<FileInfo>> {
+        let mut files = collect_files(&self.root, args)?;
+        for file in &mut files {
+            if let Ok(relative) = file.path.strip_prefix(&self.root) {
+                file.path = relative.to_path_buf();
+            }
+        }
+        Ok(files)
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send>> {
+        let path = self.root.join(path);
+        // Directories open fine on Unix but are not files of the tree
+        if !path.is_file() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("Not a file: {}", path.display())));
+        }
+        Ok(Box::new(fs::File::open(path)?))
+    }
+}
+
+/// Keep the files not removed by the exclusion and extension options. A file
+/// is excluded when it or any directory above it matches.
+pub(crate) fn select_files(files: Vec<FileInfo>, args: &Args) -> Result<Vec<FileInfo>> {
+    let excludes = ExcludeSet::from_args(args)?;
+    Ok(files
+        .into_iter()
+        .filter(|file| extension_allowed(&file.path, args) && !file.path.ancestors().any(|p| excludes.is_excluded(p)))
+        .collect())
+}
+
+/// A source shared by clones of a [`ManifestBuilder`](crate::ManifestBuilder).
+#[derive(Clone)]
+pub(crate) struct SharedSource(pub(crate) Arc<dyn FileSource>);
+
+impl fmt::Debug for SharedSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("FileSource")
+    }
+}
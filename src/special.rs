@@ -0,0 +1,114 @@
+//! `--special`: sockets, FIFOs, and device nodes found in the archive. They
+//! have no contents to hash, and reading a FIFO blocks until something
+//! writes to it, so by default they are skipped with a warning. With
+//! `--special record` they are recorded with their kind in place of a hash
+//! (`fifo`, `socket`, `char-device`, or `block-device`), and validation
+//! checks that the path is still a special file of that kind. With
+//! `--special error` the run stops at the first one.
+//!
+//! The special files found by a walk are counted for the summary of the run.
+//!
+//! Empty directories have no contents either. With `--empty-dirs` they are
+//! recorded as `dir` entries, so the shape of the tree is kept.
+
+use anyhow::Result;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{Error, FileInfo};
+
+/// What to do with special files.
+#[derive(clap::ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpecialPolicy {
+    /// Leave them out of the manifest, with a warning
+    Skip,
+    /// Record their kind in place of a hash
+    Record,
+    /// Fail the run
+    Error,
+}
+
+/// The kinds recorded in place of a hash
+const KINDS: &[&str] = &["fifo", "socket", "char-device", "block-device"];
+
+/// Recorded in place of the hash of an empty directory
+pub(crate) const DIR: &str = "dir";
+
+static FOUND: AtomicUsize = AtomicUsize::new(0);
+
+/// The kind of a special file, or `None` for files, directories, and
+/// symlinks.
+#[cfg(unix)]
+fn kind(file_type: fs::FileType) -> Option<&'static str> {
+    use std::os::unix::fs::FileTypeExt;
+    if file_type.is_fifo() {
+        Some("fifo")
+    } else if file_type.is_socket() {
+        Some("socket")
+    } else if file_type.is_char_device() {
+        Some("char-device")
+    } else if file_type.is_block_device() {
+        Some("block-device")
+    } else {
+        None
+    }
+}
+
+#[cfg(not(unix))]
+fn kind(_file_type: fs::FileType) -> Option<&'static str> {
+    None
+}
+
+/// Apply `policy` to a walked directory entry. Returns whether the entry
+/// is a special file to record.
+pub(crate) fn check(path: &Path, file_type: fs::FileType, policy: SpecialPolicy) -> Result<bool> {
+    let Some(kind) = kind(file_type) else {
+        return Ok(false);
+    };
+    FOUND.fetch_add(1, Ordering::Relaxed);
+    match policy {
+        SpecialPolicy::Skip => {
+            eprintln!("Warning: Skipping special file {} ({}; pass --special record to record it)", path.display(), kind);
+            Ok(false)
+        }
+        SpecialPolicy::Record => Ok(true),
+        SpecialPolicy::Error => Err(Error::Config(format!("Special file in archive: {} ({})", path.display(), kind)).into()),
+    }
+}
+
+/// The number of special files found since the last call.
+pub(crate) fn take_found() -> usize {
+    FOUND.swap(0, Ordering::Relaxed)
+}
+
+/// Whether `path` is a directory without any entries.
+pub(crate) fn is_empty_dir(path: &Path) -> bool {
+    fs::read_dir(path).is_ok_and(|mut entries| entries.next().is_none())
+}
+
+/// The kind recorded in place of the hash of a special file or empty
+/// directory. Both are walked with no size, so only empty files are looked
+/// at.
+pub(crate) fn marker(file_info: &FileInfo) -> Option<&'static str> {
+    if file_info.size != 0 {
+        return None;
+    }
+    let file_type = fs::metadata(&file_info.path).ok()?.file_type();
+    if file_type.is_dir() {
+        return Some(DIR);
+    }
+    kind(file_type)
+}
+
+/// Whether `hash` is the kind of a special file or `dir` rather than a
+/// hash.
+pub(crate) fn is_marker(hash: &str) -> bool {
+    hash == DIR || KINDS.contains(&hash)
+}
+
+/// The summary line for `count` special files, if any were found.
+pub(crate) fn summary(count: usize, policy: SpecialPolicy) -> Option<String> {
+    let action = if policy == SpecialPolicy::Record { "recorded" } else { "skipped" };
+    (count > 0).then(|| format!("Special files: {} ({})", count, action))
+}
@@ -0,0 +1,159 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::dupes::group_duplicates;
+use crate::report::{open_output, OutputFormat};
+use crate::{load_existing_manifest, special};
+
+/// Files and bytes with one extension.
+#[derive(Serialize, Debug, Clone)]
+pub struct ExtensionStats {
+    /// Lowercased extension without the dot; empty for files without one
+    pub extension: String,
+    pub files: usize,
+    /// Total size of the files whose size is recorded
+    pub bytes: u64,
+}
+
+/// A path and its depth: 1 in the archive root, 2 in a directory in it,
+/// and so on.
+#[derive(Serialize, Debug, Clone)]
+pub struct DeepPath {
+    pub depth: usize,
+    pub path: PathBuf,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct StatsReport {
+    pub manifest: PathBuf,
+    pub entries: usize,
+    /// Total size of the entries, when the manifest records sizes
+    pub total_bytes: Option<u64>,
+    /// Entries without a recorded size, not counted in total_bytes
+    pub unknown_size_entries: usize,
+    /// Special files and empty directories, which have no contents
+    pub special_entries: usize,
+    /// Extensions by number of files, most first
+    pub extensions: Vec<ExtensionStats>,
+    pub max_depth: usize,
+    /// The deepest paths, deepest first
+    pub deepest: Vec<DeepPath>,
+    pub duplicate_groups: usize,
+    pub duplicate_files: usize,
+    pub reclaimable_bytes: u64,
+}
+
+impl StatsReport {
+    pub fn write_text(&self, writer: &mut impl Write) -> Result<()> {
+        writeln!(writer, "Manifest: {}", self.manifest.display())?;
+        writeln!(writer, "Entries: {}", self.entries)?;
+        match self.total_bytes {
+            Some(bytes) if self.unknown_size_entries > 0 => writeln!(
+                writer,
+                "Total size: {} bytes ({} entries without a recorded size)",
+                bytes, self.unknown_size_entries
+            )?,
+            Some(bytes) => writeln!(writer, "Total size: {} bytes", bytes)?,
+            None => writeln!(writer, "Total size: unknown (the manifest records no sizes; use --extended)")?,
+        }
+        if self.special_entries > 0 {
+            writeln!(writer, "Special files and empty directories: {}", self.special_entries)?;
+        }
+        writeln!(writer, "Extensions:")?;
+        let width = self.extensions.iter().map(|e| e.extension.len()).max().unwrap_or(0).max(6);
+        for extension in &self.extensions {
+            let name = if extension.extension.is_empty() { "(none)" } else { &extension.extension };
+            writeln!(writer, "  {:<width$} {:>8} files {:>16} bytes", name, extension.files, extension.bytes, width = width)?;
+        }
+        writeln!(writer, "Deepest paths (max depth {}):", self.max_depth)?;
+        for deep in &self.deepest {
+            writeln!(writer, "  {:>3}  {}", deep.depth, deep.path.display())?;
+        }
+        writeln!(
+            writer,
+            "Duplicates: {} groups, {} redundant files, {} reclaimable bytes",
+            self.duplicate_groups, self.duplicate_files, self.reclaimable_bytes
+        )?;
+        Ok(())
+    }
+}
+
+/// Entry count, sizes, extensions, depths, and duplicates of the manifest at
+/// `manifest_path`, listing the `top` deepest paths.
+pub fn manifest_stats(manifest_path: &Path, top: usize) -> Result<StatsReport> {
+    let manifest = load_existing_manifest(manifest_path)?;
+
+    let mut report = StatsReport {
+        manifest: manifest_path.to_path_buf(),
+        entries: manifest.entries.len(),
+        ..Default::default()
+    };
+    let mut total_bytes = None;
+    let mut extensions: HashMap<String, ExtensionStats> = HashMap::new();
+    let mut depths = Vec::with_capacity(manifest.entries.len());
+    for (path, entry) in &manifest.entries {
+        match entry.size {
+            Some(size) => *total_bytes.get_or_insert(0) += size,
+            None => report.unknown_size_entries += 1,
+        }
+        // Manifest keys start with the archive name. Files in the archive
+        // root have depth 1, as with --max-depth
+        depths.push((path.components().count().saturating_sub(1), path));
+        if special::is_marker(&entry.hash) {
+            report.special_entries += 1;
+            continue;
+        }
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let stats = extensions.entry(extension.clone()).or_insert_with(|| ExtensionStats {
+            extension,
+            files: 0,
+            bytes: 0,
+        });
+        stats.files += 1;
+        stats.bytes += entry.size.unwrap_or(0);
+    }
+    report.total_bytes = total_bytes;
+
+    report.extensions = extensions.into_values().collect();
+    report
+        .extensions
+        .sort_by(|a, b| b.files.cmp(&a.files).then_with(|| a.extension.cmp(&b.extension)));
+
+    depths.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(b.1)));
+    report.max_depth = depths.first().map_or(0, |(depth, _)| *depth);
+    report.deepest = depths
+        .into_iter()
+        .take(top)
+        .map(|(depth, path)| DeepPath { depth, path: path.clone() })
+        .collect();
+
+    let duplicates = group_duplicates(&manifest, None);
+    report.duplicate_groups = duplicates.groups;
+    report.duplicate_files = duplicates.duplicate_files;
+    report.reclaimable_bytes = duplicates.reclaimable_bytes;
+    Ok(report)
+}
+
+pub fn run_stats(manifest_path: &Path, top: usize, format: OutputFormat, output: Option<&Path>) -> Result<()> {
+    if !manifest_path.exists() {
+        anyhow::bail!("Manifest does not exist: {}", manifest_path.display());
+    }
+    let report = manifest_stats(manifest_path, top)?;
+
+    let mut writer = open_output(output)?;
+    match format {
+        OutputFormat::Text => report.write_text(&mut writer)?,
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut writer, &report)?;
+            writeln!(writer)?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
@@ -0,0 +1,177 @@
+//! `--log syslog` and `--log journald`: per-file errors, validation problems,
+//! and a summary of each run sent to the system log with matching priorities.
+//!
+//! Syslog messages go to the local daemon's socket (`/dev/log`) in the
+//! traditional `<PRI>tag[pid]: message` form. Journald messages use its native
+//! protocol and carry `ARCHIVE_PATH`, `MANIFEST_PATH`, `FILE_PATH`, and
+//! `OUTCOME` fields that can be matched with `journalctl`.
+
+use anyhow::Result;
+use serde_json::Value;
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::report::{last_summary, summary_counts};
+use crate::{archive_label, run_mode, Args, Error};
+
+#[derive(clap::ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogTarget {
+    /// The local syslog daemon
+    Syslog,
+    /// The systemd journal (Linux)
+    Journald,
+}
+
+/// Syslog priorities, as used by both targets.
+#[derive(Clone, Copy)]
+enum Priority {
+    Err = 3,
+    Warning = 4,
+    Notice = 5,
+    Info = 6,
+}
+
+const IDENTIFIER: &str = "data-manifest";
+
+/// "user-level messages", the facility openlog(3) defaults to
+const FACILITY_USER: u8 = 1;
+
+#[cfg(unix)]
+const SYSLOG_SOCKETS: &[&str] = &["/dev/log", "/var/run/syslog", "/var/run/log"];
+
+#[cfg(unix)]
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Validation report lists logged one entry at a time, at WARNING.
+const PROBLEM_LISTS: &[(&str, &str)] = &[
+    ("mismatched", "mismatched"),
+    ("missing", "missing"),
+    ("metadata_changed", "metadata changed"),
+];
+
+#[cfg_attr(not(unix), allow(dead_code))]
+struct SystemLog {
+    target: LogTarget,
+    #[cfg(unix)]
+    socket: std::os::unix::net::UnixDatagram,
+    archive: String,
+    manifest: String,
+}
+
+static LOG: Mutex<Option<SystemLog>> = Mutex::new(None);
+
+/// Send later log messages to `target`, for the archive and manifest of `args`.
+#[cfg(unix)]
+pub(crate) fn open(target: LogTarget, args: &Args) -> Result<()> {
+    use std::os::unix::net::UnixDatagram;
+
+    let paths = match target {
+        LogTarget::Syslog => SYSLOG_SOCKETS,
+        LogTarget::Journald if cfg!(target_os = "linux") => &[JOURNALD_SOCKET][..],
+        LogTarget::Journald => return Err(Error::Config("--log journald is only available on Linux".into()).into()),
+    };
+    let socket = UnixDatagram::unbound()?;
+    if !paths.iter().any(|path| socket.connect(path).is_ok()) {
+        return Err(Error::Config(format!("Cannot reach the system log at {}", paths.join(" or "))).into());
+    }
+    *LOG.lock().unwrap_or_else(|e| e.into_inner()) = Some(SystemLog {
+        target,
+        socket,
+        archive: archive_label(args),
+        manifest: args.output.display().to_string(),
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn open(_target: LogTarget, _args: &Args) -> Result<()> {
+    Err(Error::Config("--log is only available on Unix systems".into()).into())
+}
+
+/// Stop sending log messages, as for a run without `--log`.
+pub(crate) fn close() {
+    *LOG.lock().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+/// Append a journald field, in the binary form if the value spans lines.
+fn journal_field(datagram: &mut Vec<u8>, key: &str, value: &str) {
+    datagram.extend_from_slice(key.as_bytes());
+    if value.contains('\n') {
+        datagram.push(b'\n');
+        datagram.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        datagram.push(b'=');
+    }
+    datagram.extend_from_slice(value.as_bytes());
+    datagram.push(b'\n');
+}
+
+/// Send `message` to the system log if one is open. `fields` only reach the
+/// journal; syslog messages must carry everything in their text.
+fn log(priority: Priority, message: &str, fields: &[(&str, &str)]) {
+    let guard = LOG.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(ref log) = *guard else { return };
+    let datagram = match log.target {
+        LogTarget::Syslog => {
+            let pri = FACILITY_USER * 8 + priority as u8;
+            format!("<{}>{}[{}]: {}", pri, IDENTIFIER, std::process::id(), message).into_bytes()
+        }
+        LogTarget::Journald => {
+            let mut datagram = Vec::new();
+            journal_field(&mut datagram, "MESSAGE", message);
+            journal_field(&mut datagram, "PRIORITY", &(priority as u8).to_string());
+            journal_field(&mut datagram, "SYSLOG_IDENTIFIER", IDENTIFIER);
+            if !log.archive.is_empty() {
+                journal_field(&mut datagram, "ARCHIVE_PATH", &log.archive);
+            }
+            journal_field(&mut datagram, "MANIFEST_PATH", &log.manifest);
+            for (key, value) in fields {
+                journal_field(&mut datagram, key, value);
+            }
+            datagram
+        }
+    };
+    // Logging is best effort; a full or restarted daemon must not fail the run
+    #[cfg(unix)]
+    let _ = log.socket.send(&datagram);
+    #[cfg(not(unix))]
+    let _ = datagram;
+}
+
+/// Log a file that could not be processed.
+pub(crate) fn file_error(path: &Path, message: &str) {
+    let path = path.display().to_string();
+    log(Priority::Err, &format!("{}: {}", path, message), &[("FILE_PATH", &path)]);
+}
+
+/// Log the validation problems and the outcome of a finished archive run.
+pub(crate) fn run_finished(args: &Args, result: &Result<()>) {
+    if LOG.lock().unwrap_or_else(|e| e.into_inner()).is_none() {
+        return;
+    }
+    let summary = last_summary().unwrap_or(Value::Null);
+    if args.validate {
+        for &(key, label) in PROBLEM_LISTS {
+            let entries = summary.get(key).and_then(Value::as_array).map(Vec::as_slice).unwrap_or_default();
+            for path in entries.iter().filter_map(|entry| entry.get("path")?.as_str()) {
+                log(Priority::Warning, &format!("{}: {}", path, label), &[("FILE_PATH", path), ("OUTCOME", key)]);
+            }
+        }
+    }
+
+    let (priority, outcome) = match result {
+        _ if args.cancel.is_cancelled() => (Priority::Notice, "cancelled"),
+        Ok(()) => (Priority::Info, "passed"),
+        Err(e) if matches!(e.downcast_ref::<Error>(), Some(Error::Validation { .. })) => (Priority::Warning, "failed"),
+        Err(_) => (Priority::Err, "error"),
+    };
+    let mut message = format!("{} {}: {}", run_mode(args), archive_label(args), outcome);
+    let counts = summary_counts(&summary);
+    if !counts.is_empty() {
+        message.push_str(&format!(" ({})", counts.join(", ")));
+    }
+    if let Err(e) = result {
+        message.push_str(&format!(": {:#}", e));
+    }
+    log(priority, &message, &[("OUTCOME", outcome)]);
+}
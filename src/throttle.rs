@@ -0,0 +1,81 @@
+use anyhow::Result;
+use std::io::Read;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::Error;
+
+/// Aggregate read bandwidth limit shared by every hashing thread.
+struct RateLimiter {
+    bytes_per_sec: u64,
+    /// The earliest time at which the bytes read so far are "paid for"
+    next_free: Instant,
+}
+
+/// The limit of the current run, if any
+static LIMITER: Mutex<Option<RateLimiter>> = Mutex::new(None);
+
+/// Parse a byte count such as "200M", "1.5G" or "512k". Units are powers of
+/// 1024; a trailing "B" or "/s" is accepted.
+pub fn parse_size(value: &str) -> Result<u64> {
+    let trimmed = value.trim().trim_end_matches("/s").trim_end_matches(['B', 'b']);
+    let split = trimmed.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(trimmed.len());
+    let (number, unit) = trimmed.split_at(split);
+    let number: f64 = number.parse().map_err(|_| Error::Config(format!("Invalid size: {}", value)))?;
+    let multiplier: u64 = match unit.to_ascii_uppercase().as_str() {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => return Err(Error::Config(format!("Invalid size unit in {} (use K, M, G or T)", value)).into()),
+    };
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// Limit the combined read rate of all file hashing to `bytes_per_sec`, or
+/// lift the limit with `None`. Replaces the limit of an earlier run.
+pub fn set_limit(bytes_per_sec: Option<u64>) {
+    *LIMITER.lock().unwrap_or_else(|e| e.into_inner()) = bytes_per_sec.map(|bytes_per_sec| RateLimiter {
+        bytes_per_sec: bytes_per_sec.max(1),
+        next_free: Instant::now(),
+    });
+}
+
+/// The limit in force, in bytes per second.
+#[cfg(test)]
+pub(crate) fn limit() -> Option<u64> {
+    LIMITER.lock().unwrap_or_else(|e| e.into_inner()).as_ref().map(|limiter| limiter.bytes_per_sec)
+}
+
+/// Account for `bytes` just read, sleeping as needed to stay under the limit.
+pub fn consume(bytes: usize) {
+    let wait = {
+        let mut limiter = LIMITER.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(limiter) = limiter.as_mut() else { return };
+        let cost = Duration::from_secs_f64(bytes as f64 / limiter.bytes_per_sec as f64);
+        let now = Instant::now();
+        // Idle time does not accumulate into a burst allowance
+        if limiter.next_free < now {
+            limiter.next_free = now;
+        }
+        limiter.next_free += cost;
+        limiter.next_free - now
+    };
+    // Allow a small amount of credit so short reads don't sleep constantly
+    if wait > Duration::from_millis(10) {
+        std::thread::sleep(wait);
+    }
+}
+
+/// A reader whose reads count against the bandwidth limit of the run, for
+/// hashing loops that do not account for it themselves.
+pub(crate) struct Throttled<R>(pub R);
+
+impl<R: Read> Read for Throttled<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let bytes_read = self.0.read(buf)?;
+        consume(bytes_read);
+        Ok(bytes_read)
+    }
+}
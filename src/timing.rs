@@ -0,0 +1,164 @@
+//! Where the time of a run went: walking the archive, hashing, and writing
+//! the results, with the read throughput while hashing and how busy each
+//! hashing thread was. Threads that are busy for most of the hash time
+//! while throughput is low point at the storage or, for fast storage, the
+//! CPU; threads that are mostly idle point at the tool itself.
+//!
+//! Bytes read and per-thread busy time are counted from the hashing loops
+//! and the progress of each file, like the bandwidth limit, in statics for
+//! the current run.
+
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+static BYTES: AtomicU64 = AtomicU64::new(0);
+static BUSY: Mutex<Option<HashMap<usize, Duration>>> = Mutex::new(None);
+
+thread_local! {
+    static STARTED: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+/// Count `bytes` read while hashing.
+pub(crate) fn bytes_read(bytes: usize) {
+    BYTES.fetch_add(bytes as u64, Ordering::Relaxed);
+}
+
+/// A file starts being processed on this thread.
+pub(crate) fn file_started() {
+    STARTED.with(|started| started.set(Some(Instant::now())));
+}
+
+/// The file started on this thread is done; its time is counted for the
+/// thread. Files finished without being started, because they were
+/// skipped, are not.
+pub(crate) fn file_finished() {
+    let Some(started) = STARTED.with(Cell::take) else {
+        return;
+    };
+    // Threads outside the pool hash one file at a time, as thread 0
+    let thread = rayon::current_thread_index().unwrap_or(0);
+    let mut busy = BUSY.lock().unwrap_or_else(|e| e.into_inner());
+    *busy.get_or_insert_with(HashMap::new).entry(thread).or_default() += started.elapsed();
+}
+
+/// Bytes read and busy time per thread so far.
+fn counted() -> (u64, HashMap<usize, Duration>) {
+    let busy = BUSY.lock().unwrap_or_else(|e| e.into_inner()).clone().unwrap_or_default();
+    (BYTES.load(Ordering::Relaxed), busy)
+}
+
+/// Time spent in each phase of a run, and hashing throughput.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct Timings {
+    /// Seconds spent walking the archive
+    pub walk_secs: f64,
+    /// Seconds spent hashing
+    pub hash_secs: f64,
+    /// Seconds spent after hashing: writing the manifest and reports, or
+    /// pairing renames and repairing in validation
+    pub write_secs: f64,
+    /// Bytes read while hashing; files taken from the hash cache or a
+    /// trusted stamp are not read
+    pub bytes_hashed: u64,
+    /// Bytes hashed per second of hash time, in MB/s (10^6 bytes)
+    pub mb_per_sec: f64,
+    /// Share of the hash time each hashing thread was busy, from 0 to 1
+    pub thread_utilization: Vec<f64>,
+}
+
+impl Timings {
+    /// The lines printed at the end of a run.
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = vec![
+            format!(
+                "Time: walk {:.2}s, hash {:.2}s, write {:.2}s",
+                self.walk_secs, self.hash_secs, self.write_secs
+            ),
+            format!("Throughput: {:.1} MB/s ({} bytes hashed)", self.mb_per_sec, self.bytes_hashed),
+        ];
+        if !self.thread_utilization.is_empty() {
+            let percent: Vec<String> = self
+                .thread_utilization
+                .iter()
+                .map(|share| format!("{:.0}%", share * 100.0))
+                .collect();
+            let mean = self.thread_utilization.iter().sum::<f64>() / self.thread_utilization.len() as f64;
+            lines.push(format!(
+                "Thread utilization: {:.0}% mean ({})",
+                mean * 100.0,
+                percent.join(" ")
+            ));
+        }
+        lines
+    }
+}
+
+/// Measures the phases of a run. Counting starts over when it is started.
+pub(crate) struct Timer {
+    start: Instant,
+    walked: Option<Instant>,
+    /// When hashing ended, with the bytes read and busy time until then
+    hashed: Option<(Instant, u64, HashMap<usize, Duration>)>,
+}
+
+impl Timer {
+    pub fn start() -> Self {
+        BYTES.store(0, Ordering::Relaxed);
+        *BUSY.lock().unwrap_or_else(|e| e.into_inner()) = None;
+        Timer { start: Instant::now(), walked: None, hashed: None }
+    }
+
+    /// The archive has been walked; hashing begins.
+    pub fn walked(&mut self) {
+        self.walked = Some(Instant::now());
+    }
+
+    /// Hashing is done; writing begins. Files read afterwards, such as new
+    /// files hashed to pair renames, are not counted.
+    pub fn hashed(&mut self) {
+        let (bytes, busy) = counted();
+        self.hashed = Some((Instant::now(), bytes, busy));
+    }
+
+    /// The timings of the run so far.
+    pub fn finish(&self) -> Timings {
+        let now = Instant::now();
+        let walked = self.walked.unwrap_or(self.start);
+        let (hashed, bytes_hashed, busy) = match self.hashed {
+            Some((hashed, bytes, ref busy)) => (hashed.max(walked), bytes, busy.clone()),
+            None => {
+                let (bytes, busy) = counted();
+                (now, bytes, busy)
+            }
+        };
+        let hash = hashed - walked;
+        let threads = rayon::current_num_threads().max(busy.keys().max().map_or(0, |&thread| thread + 1));
+        let thread_utilization = if hash.is_zero() || busy.is_empty() {
+            Vec::new()
+        } else {
+            (0..threads)
+                .map(|thread| {
+                    let share = busy.get(&thread).copied().unwrap_or_default().as_secs_f64() / hash.as_secs_f64();
+                    share.min(1.0)
+                })
+                .collect()
+        };
+        Timings {
+            walk_secs: (walked - self.start).as_secs_f64(),
+            hash_secs: hash.as_secs_f64(),
+            write_secs: (now - hashed).as_secs_f64(),
+            bytes_hashed,
+            mb_per_sec: if hash.is_zero() {
+                0.0
+            } else {
+                bytes_hashed as f64 / 1_000_000.0 / hash.as_secs_f64()
+            },
+            thread_utilization,
+        }
+    }
+}
@@ -0,0 +1,425 @@
+//! Confirm that uploads to object storage are intact from what the bucket
+//! already knows about each object, without downloading it: the ETag S3
+//! derives from the MD5 of the uploaded parts, the MD5 or CRC32C GCS records,
+//! or the SHA-256 checksum stored with an S3 object.
+
+use anyhow::Result;
+use base64::Engine;
+use md5::{Digest, Md5};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::progress::Progress;
+use crate::remote::{split_url, without_archive_name};
+use crate::report::{open_output, OutputFormat};
+use crate::source::select_files;
+use crate::{collect_files, load_existing_manifest, require_sha256, throttle, Args, Error, FileInfo};
+
+const MIB: u64 = 1024 * 1024;
+
+/// An object whose stored checksum does not match the local copy.
+#[derive(Serialize, Debug, Clone)]
+pub struct ChecksumMismatch {
+    pub path: PathBuf,
+    pub expected: String,
+    pub actual: String,
+}
+
+/// An object that could not be checked without downloading it.
+#[derive(Serialize, Debug, Clone)]
+pub struct Unverifiable {
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct CheckSummary {
+    pub matched: usize,
+    pub mismatched: usize,
+    pub missing: usize,
+    pub only_remote: usize,
+    pub unverifiable: usize,
+}
+
+/// Result of reconciling local files or a manifest with the objects under a
+/// bucket prefix.
+#[derive(Serialize, Debug, Default)]
+pub struct UploadCheckReport {
+    pub url: String,
+    pub passed: bool,
+    pub summary: CheckSummary,
+    pub mismatched: Vec<ChecksumMismatch>,
+    /// Local files or manifest entries with no object
+    pub missing: Vec<PathBuf>,
+    /// Objects with no local file or manifest entry
+    pub only_remote: Vec<PathBuf>,
+    pub unverifiable: Vec<Unverifiable>,
+}
+
+impl UploadCheckReport {
+    pub fn write_text(&self, writer: &mut impl Write) -> Result<()> {
+        for entry in &self.mismatched {
+            writeln!(writer, "MISMATCH: {} (expected {}, found {})", entry.path.display(), entry.expected, entry.actual)?;
+        }
+        for path in &self.missing {
+            writeln!(writer, "MISSING: {}", path.display())?;
+        }
+        for path in &self.only_remote {
+            writeln!(writer, "ONLY REMOTE: {}", path.display())?;
+        }
+        for entry in &self.unverifiable {
+            writeln!(writer, "UNVERIFIABLE: {}: {}", entry.path.display(), entry.reason)?;
+        }
+        writeln!(
+            writer,
+            "Summary: {} matched, {} mismatched, {} missing, {} only remote, {} unverifiable",
+            self.summary.matched, self.summary.mismatched, self.summary.missing, self.summary.only_remote, self.summary.unverifiable
+        )?;
+        Ok(())
+    }
+}
+
+/// What the service recorded about an object's content when it was written.
+#[cfg_attr(not(all(feature = "s3", feature = "gcs")), allow(dead_code))]
+enum StoredChecksum {
+    /// S3 ETag: the MD5 of a single-part upload, or `<md5 of part md5s>-<parts>`
+    Etag(String),
+    /// Hex MD5 (GCS `md5Hash`)
+    Md5(String),
+    /// GCS `crc32c`, recorded for every object including composite ones
+    #[cfg(feature = "gcs")]
+    Crc32c(u32),
+    None,
+}
+
+struct StoredObject {
+    size: u64,
+    checksum: StoredChecksum,
+}
+
+/// A bucket whose listing carries checksums.
+enum Bucket {
+    #[cfg(feature = "s3")]
+    S3(crate::s3::S3Client),
+    #[cfg(feature = "gcs")]
+    Gcs(crate::gcs::GcsClient),
+}
+
+impl Bucket {
+    fn open(url: &str) -> Result<Self> {
+        let (scheme, _) = url.split_once("://").unwrap_or((url, ""));
+        let (bucket, _) = split_url(url)?;
+        match scheme {
+            #[cfg(feature = "s3")]
+            "s3" => Ok(Bucket::S3(crate::s3::S3Client::new(bucket)?)),
+            #[cfg(feature = "gcs")]
+            "gs" => Ok(Bucket::Gcs(crate::gcs::GcsClient::new(bucket)?)),
+            _ => Err(Error::Config(format!("upload-check does not support {}:// URLs in this build", scheme)).into()),
+        }
+    }
+
+    /// Objects under `prefix` by key.
+    fn list(&self, prefix: &str, args: &Args) -> Result<Vec<(String, StoredObject)>> {
+        Ok(match self {
+            #[cfg(feature = "s3")]
+            Bucket::S3(client) => client
+                .list(prefix, args)?
+                .into_iter()
+                .map(|o| {
+                    let checksum = match o.etag {
+                        Some(etag) => StoredChecksum::Etag(etag.trim_matches('"').to_ascii_lowercase()),
+                        None => StoredChecksum::None,
+                    };
+                    (o.key, StoredObject { size: o.size, checksum })
+                })
+                .collect(),
+            #[cfg(feature = "gcs")]
+            Bucket::Gcs(client) => client
+                .list(prefix, args)?
+                .into_iter()
+                .map(|o| {
+                    let md5 = o.md5_hash.as_deref().and_then(crate::gcs::decode_md5);
+                    let crc32c = o.crc32c.as_deref().and_then(crate::gcs::decode_crc32c);
+                    let checksum = match (md5, crc32c) {
+                        (Some(md5), _) => StoredChecksum::Md5(md5),
+                        (None, Some(crc32c)) => StoredChecksum::Crc32c(crc32c),
+                        (None, None) => StoredChecksum::None,
+                    };
+                    (o.name, StoredObject { size: o.size, checksum })
+                })
+                .collect(),
+        })
+    }
+
+    /// The full-object SHA-256 checksum stored with `key`, base64 encoded.
+    fn stored_sha256(&self, key: &str) -> Result<Option<String>> {
+        match self {
+            #[cfg(feature = "s3")]
+            Bucket::S3(client) => client.stored_checksum(key),
+            #[cfg(feature = "gcs")]
+            Bucket::Gcs(_) => {
+                let _ = key;
+                Err(Error::Config("GCS stores no SHA-256 checksums; check against --local files instead".into()).into())
+            }
+        }
+    }
+}
+
+enum Outcome {
+    Match,
+    Mismatch(String, String),
+    Unverifiable(String),
+}
+
+/// The ETag S3 assigns to `file` when uploaded in `part_size` parts: the MD5
+/// of the data for a single-part upload, otherwise the MD5 of the
+/// concatenated part MD5s followed by `-<parts>`.
+fn expected_etag(path: &Path, part_size: u64, multipart: bool, buffer_size: usize) -> Result<String> {
+    let mut whole = Md5::new();
+    let mut part = Md5::new();
+    let mut part_len = 0;
+    let mut part_digests = Vec::new();
+    read_chunks(path, buffer_size, |mut chunk| {
+        if !multipart {
+            whole.update(chunk);
+            return;
+        }
+        while !chunk.is_empty() {
+            let take = chunk.len().min((part_size - part_len) as usize);
+            part.update(&chunk[..take]);
+            part_len += take as u64;
+            chunk = &chunk[take..];
+            if part_len == part_size {
+                part_digests.extend_from_slice(&part.finalize_reset());
+                part_len = 0;
+            }
+        }
+    })?;
+    if !multipart {
+        return Ok(hex(&whole.finalize()));
+    }
+    if part_len > 0 {
+        part_digests.extend_from_slice(&part.finalize());
+    }
+    let parts = part_digests.len() / 16;
+    Ok(format!("{}-{}", hex(&Md5::digest(&part_digests)), parts))
+}
+
+/// Feed the contents of `path` to `update` chunk by chunk.
+fn read_chunks(path: &Path, buffer_size: usize, mut update: impl FnMut(&[u8])) -> Result<()> {
+    let mut file = File::open(path).map_err(Error::io("Failed to open file", path))?;
+    let mut buffer = vec![0; buffer_size.max(1)];
+    loop {
+        let n = file.read(&mut buffer).map_err(Error::io("Failed to read file", path))?;
+        if n == 0 {
+            return Ok(());
+        }
+        crate::progress::bytes_hashed(n);
+        update(&buffer[..n]);
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// The part size a multipart upload of `size` bytes in `parts` parts used:
+/// `part_size` when it gives that many parts, otherwise the smallest whole
+/// number of MiB that does, as most upload tools pick.
+fn upload_part_size(size: u64, parts: u64, part_size: u64) -> Option<u64> {
+    let count = |part: u64| size.div_ceil(part).max(1);
+    if count(part_size) == parts {
+        return Some(part_size);
+    }
+    let part = size.div_ceil(parts).div_ceil(MIB).max(1) * MIB;
+    (count(part) == parts).then_some(part)
+}
+
+/// Compare a local file with the checksum stored for its object.
+fn check_local(local: &Path, object: &StoredObject, part_size: u64, args: &Args) -> Result<Outcome> {
+    let size = std::fs::metadata(local).map_err(Error::io("Failed to read metadata", local))?.len();
+    if size != object.size {
+        return Ok(Outcome::Mismatch(format!("{} bytes", size), format!("{} bytes", object.size)));
+    }
+    let (expected, actual) = match object.checksum {
+        StoredChecksum::Etag(ref etag) => match etag.split_once('-') {
+            Some((_, parts)) => {
+                let Some(parts) = parts.parse().ok() else {
+                    return Ok(Outcome::Unverifiable(format!("unrecognised ETag {}", etag)));
+                };
+                let Some(part_size) = upload_part_size(size, parts, part_size) else {
+                    return Ok(Outcome::Unverifiable(format!("no part size splits {} bytes into {} parts", size, parts)));
+                };
+                (expected_etag(local, part_size, true, args.buffer_size)?, etag.clone())
+            }
+            None => (expected_etag(local, part_size, false, args.buffer_size)?, etag.clone()),
+        },
+        StoredChecksum::Md5(ref md5) => (expected_etag(local, part_size, false, args.buffer_size)?, md5.clone()),
+        #[cfg(feature = "gcs")]
+        StoredChecksum::Crc32c(crc32c) => {
+            let mut crc = 0;
+            read_chunks(local, args.buffer_size, |chunk| crc = crc32c::crc32c_append(crc, chunk))?;
+            (format!("crc32c:{:08x}", crc), format!("crc32c:{:08x}", crc32c))
+        }
+        StoredChecksum::None => return Ok(Outcome::Unverifiable("the listing has no checksum".into())),
+    };
+    Ok(if expected == actual { Outcome::Match } else { Outcome::Mismatch(expected, actual) })
+}
+
+/// Compare a manifest hash with the full-object SHA-256 checksum S3 stored
+/// for the object, if it was uploaded with one.
+fn check_checksum(bucket: &Bucket, key: &str, expected: &str) -> Result<Outcome> {
+    let Some(checksum) = bucket.stored_sha256(key)? else {
+        return Ok(Outcome::Unverifiable("no SHA-256 checksum stored".into()));
+    };
+    if checksum.contains('-') {
+        return Ok(Outcome::Unverifiable("multipart upload with a composite checksum".into()));
+    }
+    let Ok(digest) = base64::engine::general_purpose::STANDARD.decode(&checksum) else {
+        return Ok(Outcome::Unverifiable(format!("unrecognised checksum {}", checksum)));
+    };
+    let actual = hex(&digest);
+    Ok(if actual.eq_ignore_ascii_case(expected) {
+        Outcome::Match
+    } else {
+        Outcome::Mismatch(expected.to_string(), actual)
+    })
+}
+
+/// Reconcile the objects under `url` with the files under `local` (by the
+/// ETag, MD5, or CRC32C in the listing) or the entries of `manifest` (by
+/// stored SHA-256 checksum).
+pub fn check_upload(url: &str, local: Option<&Path>, manifest: Option<&Path>, chunk_size: &str, args: &Args) -> Result<UploadCheckReport> {
+    let (_, prefix) = split_url(url)?;
+    let bucket = Bucket::open(url)?;
+    let part_size = throttle::parse_size(chunk_size)?.max(1);
+    let list_prefix = if prefix.is_empty() { String::new() } else { format!("{}/", prefix) };
+    let key = |path: &Path| format!("{}{}", list_prefix, path.to_string_lossy().replace('\\', "/"));
+
+    // Relative path to the local file or manifest hash
+    let expected: HashMap<PathBuf, PathBuf> = match (local, manifest) {
+        (Some(root), _) => collect_files(root, args)?
+            .into_iter()
+            .map(|f| (f.path.strip_prefix(root).unwrap_or(&f.path).to_path_buf(), f.path))
+            .collect(),
+        (None, Some(manifest_path)) => {
+            if !manifest_path.exists() {
+                return Err(Error::Config(format!("Manifest does not exist: {}", manifest_path.display())).into());
+            }
+            let manifest = load_existing_manifest(manifest_path)?;
+            require_sha256(&manifest, manifest_path)?;
+            manifest
+                .entries
+                .into_iter()
+                .map(|(path, entry)| (without_archive_name(&path), PathBuf::from(entry.hash)))
+                .collect()
+        }
+        (None, None) => return Err(Error::Config("Either --local or --manifest is required".into()).into()),
+    };
+    let mut objects: HashMap<PathBuf, StoredObject> = bucket
+        .list(&list_prefix, args)?
+        .into_iter()
+        .filter(|(key, _)| !key.ends_with('/'))
+        .map(|(key, o)| (PathBuf::from(&key[list_prefix.len()..]), o))
+        .collect();
+    // Objects the local walk would have excluded are not "only remote"
+    let listed = objects.iter().map(|(path, o)| FileInfo { path: path.clone(), size: o.size, mtime: None }).collect();
+    let selected: HashSet<PathBuf> = select_files(listed, args)?.into_iter().map(|f| f.path).collect();
+    objects.retain(|path, _| selected.contains(path));
+    if args.cancel.is_cancelled() {
+        return Err(Error::Cancelled.into());
+    }
+
+    let mut report = UploadCheckReport {
+        url: url.to_string(),
+        ..Default::default()
+    };
+    let mut pairs = Vec::new();
+    for (path, target) in &expected {
+        match objects.get(path) {
+            Some(object) => pairs.push((path, target, object)),
+            None => report.missing.push(path.clone()),
+        }
+    }
+    report.only_remote = objects.keys().filter(|path| !expected.contains_key(*path)).cloned().collect();
+
+    let progress = Progress::start(pairs.len() as u64, 0);
+    let results: Vec<(&PathBuf, Result<Outcome>)> = pairs
+        .into_par_iter()
+        .filter(|_| !args.cancel.is_cancelled())
+        .map(|(path, target, object)| {
+            progress.file_started(path);
+            let outcome = match local {
+                Some(_) => check_local(target, object, part_size, args),
+                None => check_checksum(&bucket, &key(path), &target.to_string_lossy()),
+            };
+            progress.file_done(path, &outcome);
+            (path, outcome)
+        })
+        .collect();
+    progress.finish("Check complete");
+    if args.cancel.is_cancelled() {
+        return Err(Error::Cancelled.into());
+    }
+
+    let mut matched = 0;
+    for (path, outcome) in results {
+        match outcome? {
+            Outcome::Match => matched += 1,
+            Outcome::Mismatch(expected, actual) => report.mismatched.push(ChecksumMismatch { path: path.clone(), expected, actual }),
+            Outcome::Unverifiable(reason) => report.unverifiable.push(Unverifiable { path: path.clone(), reason }),
+        }
+    }
+
+    report.mismatched.sort_by(|a, b| a.path.cmp(&b.path));
+    report.missing.sort();
+    report.only_remote.sort();
+    report.unverifiable.sort_by(|a, b| a.path.cmp(&b.path));
+    report.summary = CheckSummary {
+        matched,
+        mismatched: report.mismatched.len(),
+        missing: report.missing.len(),
+        only_remote: report.only_remote.len(),
+        unverifiable: report.unverifiable.len(),
+    };
+    report.passed = report.mismatched.is_empty() && report.missing.is_empty();
+    Ok(report)
+}
+
+pub fn run_upload_check(
+    url: &str,
+    local: Option<&Path>,
+    manifest: Option<&Path>,
+    chunk_size: &str,
+    format: OutputFormat,
+    output: Option<&Path>,
+    args: &Args,
+) -> Result<()> {
+    let report = check_upload(url, local, manifest, chunk_size, args)?;
+
+    let mut writer = open_output(output)?;
+    match format {
+        OutputFormat::Text => report.write_text(&mut writer)?,
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut writer, &report)?;
+            writeln!(writer)?;
+        }
+    }
+    writer.flush()?;
+
+    if !report.passed {
+        return Err(Error::Validation {
+            message: format!(
+                "Upload check failed: {} mismatched, {} missing",
+                report.summary.mismatched, report.summary.missing
+            ),
+            exit_code: 1,
+        }
+        .into());
+    }
+    Ok(())
+}
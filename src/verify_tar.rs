@@ -0,0 +1,110 @@
+//! `verify-tar`: the members of a tar file, plain or gzip or zstd
+//! compressed, checked against a manifest in one streaming pass, without
+//! extracting anything.
+//!
+//! The manifest is either given separately or read from the `manifest.txt`
+//! member that [`package`](crate::package) writes ahead of the files.
+
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+
+use crate::container::member_name;
+use crate::package::MANIFEST_MEMBER;
+use crate::reader::decompress;
+use crate::remote::validate_entries;
+use crate::{
+    extension_allowed, hash_reader, load_existing_manifest, read_manifest, require_sha256, Args, Error, ExcludeSet,
+    ManifestEntry, ManifestReader,
+};
+
+/// Check the regular files in the tar file `tar_path` against `manifest`,
+/// or against the manifest it carries as its first member. Member paths are
+/// prefixed with `archive_name/` for tars created inside the archive
+/// directory. Reports and fails like validation mode.
+pub fn run_verify_tar(tar_path: &Path, manifest: Option<&Path>, archive_name: Option<&str>, args: &Args) -> Result<()> {
+    let mut expected = match manifest {
+        Some(path) => {
+            if !path.exists() {
+                return Err(Error::Config(format!("Manifest does not exist: {}", path.display())).into());
+            }
+            Some(load_existing_manifest(path)?)
+        }
+        None => None,
+    };
+    let excludes = ExcludeSet::from_args(args)?;
+
+    status!("Verifying tar: {}", tar_path.display());
+    let file = fs::File::open(tar_path).map_err(Error::io("Failed to open tar file", tar_path))?;
+    let mut archive = tar::Archive::new(decompress(tar_path, file)?);
+    let mut actual = HashMap::new();
+    let entries = archive.entries().with_context(|| format!("Failed to read tar file {}", tar_path.display()))?;
+    for (index, entry) in entries.enumerate() {
+        if args.cancel.is_cancelled() {
+            return Err(Error::Cancelled.into());
+        }
+        let mut entry = entry.with_context(|| format!("Failed to read tar file {}", tar_path.display()))?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let Some(name) = member_name(&entry.path()?.to_string_lossy()) else { continue };
+        if index == 0 && expected.is_none() && name == MANIFEST_MEMBER {
+            // ManifestReader needs an owned reader, and a manifest is small
+            // next to the files it describes
+            let mut text = Vec::new();
+            entry.read_to_end(&mut text)?;
+            let label = embedded_label(tar_path);
+            expected = Some(read_manifest(ManifestReader::from_reader(&label, Cursor::new(text))?)?);
+            continue;
+        }
+        if !extension_allowed(Path::new(&name), args) || excludes.is_excluded(Path::new(&name)) {
+            continue;
+        }
+
+        let key = match archive_name {
+            Some(prefix) => PathBuf::from(format!("{}/{}", prefix, name)),
+            None => PathBuf::from(&name),
+        };
+        let size = entry.size();
+        let mtime = entry.header().mtime().ok().map(|secs| secs as i64 * 1_000_000_000);
+        let hash = hash_reader(&mut entry, args.buffer_size)
+            .with_context(|| format!("Failed to read {} from {}", name, tar_path.display()))?;
+        actual.insert(key, ManifestEntry {
+            hash,
+            size: Some(size),
+            mtime,
+            verified: None,
+            cid: None,
+            xattrs: None,
+            acl: None,
+            link: None,
+            format: None,
+            dev: None,
+            inode: None,
+        });
+    }
+
+    let manifest_path = match manifest {
+        Some(path) => path.to_path_buf(),
+        None => embedded_label(tar_path),
+    };
+    let Some(expected) = expected else {
+        return Err(Error::Config(format!(
+            "{} does not start with a {} member; pass the manifest with --manifest",
+            tar_path.display(),
+            MANIFEST_MEMBER
+        ))
+        .into());
+    };
+    require_sha256(&expected, &manifest_path)?;
+    validate_entries(&expected, &manifest_path, &actual, tar_path, args)
+}
+
+/// How the embedded manifest is named in messages and reports.
+fn embedded_label(tar_path: &Path) -> PathBuf {
+    let mut label = tar_path.as_os_str().to_owned();
+    label.push(format!("#{}", MANIFEST_MEMBER));
+    PathBuf::from(label)
+}
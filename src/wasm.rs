@@ -0,0 +1,114 @@
+//! WebAssembly bindings for verifying files against a published manifest in
+//! the browser. Build with
+//! `wasm-pack build --target web --no-default-features --features wasm`.
+//!
+//! ```js
+//! import init, { ManifestVerifier } from "./pkg/data_manifest.js";
+//!
+//! await init();
+//! const verifier = new ManifestVerifier(new Uint8Array(await (await fetch("manifest.txt")).arrayBuffer()));
+//! for (const file of input.files) {
+//!     const hasher = verifier.hasher();
+//!     for (let offset = 0; offset < file.size; offset += 1 << 24) {
+//!         hasher.update(new Uint8Array(await file.slice(offset, offset + (1 << 24)).arrayBuffer()));
+//!     }
+//!     console.log(file.webkitRelativePath, verifier.check(file.webkitRelativePath, hasher.finish()));
+//! }
+//! ```
+
+use std::io::Cursor;
+use std::path::Path;
+
+use wasm_bindgen::prelude::*;
+
+use crate::{Hasher, Manifest, ManifestReader};
+
+fn js_error(e: anyhow::Error) -> JsError {
+    JsError::new(&format!("{:#}", e))
+}
+
+/// A parsed manifest that files can be checked against.
+#[wasm_bindgen]
+pub struct ManifestVerifier {
+    manifest: Manifest,
+}
+
+#[wasm_bindgen]
+impl ManifestVerifier {
+    /// Parse manifest contents (plain or gzip compressed).
+    #[wasm_bindgen(constructor)]
+    pub fn new(contents: &[u8]) -> Result<ManifestVerifier, JsError> {
+        let mut reader = ManifestReader::from_reader(Path::new("manifest"), Cursor::new(contents.to_vec())).map_err(js_error)?;
+        let entries = reader.by_ref().collect::<anyhow::Result<_>>().map_err(js_error)?;
+        let manifest = Manifest {
+            extended: reader.header().extended,
+            algorithm: reader.header().algorithm,
+            entries,
+        };
+        Ok(ManifestVerifier { manifest })
+    }
+
+    /// Name of the manifest's hash algorithm, e.g. `sha256`.
+    #[wasm_bindgen(getter)]
+    pub fn algorithm(&self) -> String {
+        self.manifest.algorithm.name().to_string()
+    }
+
+    /// Every path listed in the manifest, sorted.
+    pub fn paths(&self) -> Vec<String> {
+        let mut paths: Vec<String> = self.manifest.entries.keys().map(|p| p.to_string_lossy().to_string()).collect();
+        paths.sort();
+        paths
+    }
+
+    #[wasm_bindgen(js_name = expectedHash)]
+    pub fn expected_hash(&self, path: &str) -> Option<String> {
+        self.manifest.entries.get(Path::new(path)).map(|e| e.hash.clone())
+    }
+
+    /// A hasher using the manifest's algorithm, fed with `update` chunk by chunk.
+    pub fn hasher(&self) -> FileHasher {
+        FileHasher {
+            hasher: Some(self.manifest.algorithm.hasher()),
+        }
+    }
+
+    /// Compare a file's hash with the manifest: `"valid"`, `"mismatched"`, or
+    /// `"unlisted"` when the manifest has no entry for `path`.
+    pub fn check(&self, path: &str, hash: &str) -> String {
+        match self.manifest.entries.get(Path::new(path)) {
+            Some(entry) if entry.hash.eq_ignore_ascii_case(hash) => "valid",
+            Some(_) => "mismatched",
+            None => "unlisted",
+        }
+        .to_string()
+    }
+
+    /// Hash `data` and [`check`](Self::check) it. For small files read in one piece.
+    pub fn verify(&self, path: &str, data: &[u8]) -> String {
+        let mut hasher = self.manifest.algorithm.hasher();
+        hasher.update(data);
+        self.check(path, &hasher.finish())
+    }
+}
+
+/// Incremental hash of one file.
+#[wasm_bindgen]
+pub struct FileHasher {
+    hasher: Option<Hasher>,
+}
+
+#[wasm_bindgen]
+impl FileHasher {
+    pub fn update(&mut self, chunk: &[u8]) -> Result<(), JsError> {
+        let hasher = self.hasher.as_mut().ok_or_else(|| JsError::new("FileHasher already finished"))?;
+        hasher.update(chunk);
+        Ok(())
+    }
+
+    /// The hex digest of all chunks. The hasher cannot be used afterwards.
+    pub fn finish(&mut self) -> Result<String, JsError> {
+        let hasher = self.hasher.take().ok_or_else(|| JsError::new("FileHasher already finished"))?;
+        Ok(hasher.finish())
+    }
+}
@@ -0,0 +1,167 @@
+use anyhow::{Context, Result};
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use rayon::prelude::*;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use crate::{
+    collect_files_in, extension_allowed, hash_entry, load_existing_manifest, manifest_key, require_sha256, update_manifest,
+    write_manifest, Args, ExcludeSet,
+};
+
+/// How often an idle watch checks `args.cancel`.
+const CANCEL_POLL: Duration = Duration::from_secs(1);
+
+/// Whether `path` or any directory above it (up to the archive root) is
+/// excluded by the same rules as a full walk.
+fn excluded(archive_path: &Path, path: &Path, excludes: &ExcludeSet) -> bool {
+    let Ok(relative) = path.strip_prefix(archive_path) else { return true };
+    relative.ancestors().filter(|a| !a.as_os_str().is_empty()).any(|a| excludes.is_excluded(a))
+}
+
+/// Apply a batch of changed paths to the manifest: hash files that exist and
+/// drop entries (including whole directories) that no longer do.
+fn apply_changes(
+    paths: &HashSet<PathBuf>,
+    archive_path: &Path,
+    manifest_path: &Path,
+    archive_name: &str,
+    excludes: &ExcludeSet,
+    args: &Args,
+) -> Result<()> {
+    let mut manifest = load_existing_manifest(manifest_path)?;
+    require_sha256(&manifest, manifest_path)?;
+    let mut files = Vec::new();
+    let mut removed = 0;
+
+    for path in paths {
+        if excluded(archive_path, path, excludes) {
+            continue;
+        }
+        if path.is_dir() {
+            // A directory moved into the archive produces a single event
+            files.extend(collect_files_in(archive_path, path, args)?);
+        } else if path.is_file() {
+            if extension_allowed(path, args) {
+                let metadata = std::fs::metadata(path)?;
+                let file_info = crate::FileInfo::from_metadata(path.clone(), &metadata);
+                crate::ads::add_streams(&mut files, &file_info, args.ads);
+                files.push(file_info);
+            }
+        } else {
+            let key = manifest_key(path, archive_path, archive_name);
+            let before = manifest.entries.len();
+            manifest.entries.retain(|entry_path, _| !entry_path.starts_with(&key));
+            removed += before - manifest.entries.len();
+        }
+    }
+
+    let hashed: Vec<_> = files
+        .par_iter()
+        .filter_map(|file_info| match hash_entry(file_info, archive_path, archive_name, args) {
+            Ok(result) => Some(result),
+            Err(e) => {
+                // The file may still be being written; the next event or reconciliation picks it up
+                eprintln!("Warning: Could not hash {}: {:#}", file_info.path.display(), e);
+                None
+            }
+        })
+        .collect();
+    let updated = hashed.len();
+    manifest.entries.extend(hashed);
+
+    if updated > 0 || removed > 0 {
+        write_manifest(manifest_path, &manifest.entries, manifest.extended || args.writes_extended())?;
+        println!("Manifest updated: {} files hashed, {} entries removed", updated, removed);
+    }
+    Ok(())
+}
+
+/// Keep `manifest_path` current as files under `archive_path` change. Changes
+/// are applied once the archive has been quiet for `debounce`, and a full
+/// update runs at startup and every `reconcile` to catch anything missed.
+pub fn run_watch(
+    archive_path: &Path,
+    manifest_path: &Path,
+    archive_name: &str,
+    debounce: Duration,
+    reconcile: Duration,
+    args: &Args,
+) -> Result<()> {
+    if !archive_path.is_dir() {
+        anyhow::bail!("Archive path is not a directory: {}", archive_path.display());
+    }
+    let excludes = ExcludeSet::from_args(args)?;
+    let manifest_name = manifest_path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    let manifest_dir = std::fs::canonicalize(manifest_path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new(".")))?;
+    // Our own writes (the manifest, its backups and snapshots) must not trigger updates
+    let is_own_file = |path: &Path| {
+        path.parent().and_then(|p| std::fs::canonicalize(p).ok()).as_deref() == Some(manifest_dir.as_path())
+            && path.file_name().is_some_and(|n| n.to_string_lossy().starts_with(&manifest_name))
+    };
+
+    update_manifest(archive_path, manifest_path, archive_name, args)?;
+
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+        let _ = sender.send(event);
+    })
+    .context("Failed to start file watcher")?;
+    watcher
+        .watch(archive_path, RecursiveMode::Recursive)
+        .with_context(|| format!("Failed to watch {}", archive_path.display()))?;
+    println!("Watching {} (debounce {:?}, reconcile every {:?})", archive_path.display(), debounce, reconcile);
+
+    let mut pending: HashSet<PathBuf> = HashSet::new();
+    let mut last_event = Instant::now();
+    let mut next_reconcile = Instant::now() + reconcile;
+    loop {
+        if args.cancel.is_cancelled() {
+            println!("Stopped watching {}", archive_path.display());
+            return Ok(());
+        }
+        let now = Instant::now();
+        let mut deadline = next_reconcile;
+        if !pending.is_empty() {
+            deadline = deadline.min(last_event + debounce);
+        }
+        match receiver.recv_timeout(deadline.saturating_duration_since(now).min(CANCEL_POLL)) {
+            Ok(Ok(event)) => {
+                if matches!(event.kind, EventKind::Access(_)) {
+                    continue;
+                }
+                for path in event.paths {
+                    if !is_own_file(&path) {
+                        pending.insert(path);
+                        last_event = Instant::now();
+                    }
+                }
+            }
+            Ok(Err(e)) => eprintln!("Warning: File watcher error: {}", e),
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => anyhow::bail!("File watcher stopped"),
+        }
+
+        let now = Instant::now();
+        if !pending.is_empty() && now >= last_event + debounce {
+            apply_changes(&pending, archive_path, manifest_path, archive_name, &excludes, args)?;
+            pending.clear();
+        }
+        if now >= next_reconcile {
+            println!("Reconciling manifest with a full update");
+            update_manifest(archive_path, manifest_path, archive_name, args)?;
+            next_reconcile = now + reconcile;
+        }
+    }
+}
+
+/// Parse a debounce or reconciliation interval.
+pub fn parse_interval(value: &str) -> Result<Duration> {
+    let duration = crate::parse_duration(value)?;
+    if duration.is_zero() {
+        anyhow::bail!("Interval must be greater than zero: {}", value);
+    }
+    Ok(duration)
+}
@@ -0,0 +1,313 @@
+//! Files published over HTTP, addressed by the `http://` or `https://` URL of
+//! a directory. WebDAV shares are crawled with `PROPFIND`; plain web servers
+//! can be read from a list of URLs given with `--url-list`.
+//!
+//! `WEBDAV_USER` and `WEBDAV_PASSWORD` select Basic authentication,
+//! `WEBDAV_BEARER_TOKEN` a bearer token.
+
+use anyhow::{Context, Result};
+use base64::Engine;
+use quick_xml::events::Event;
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::remote::{object_key, parse_http_date, percent_decode, uri_encode, FetchRange, RangeOptions, RangeReader};
+use crate::{Args, Error, FileInfo, FileSource};
+
+const PROPFIND_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<propfind xmlns="DAV:"><prop><resourcetype/><getcontentlength/><getlastmodified/></prop></propfind>"#;
+
+/// A `PROPFIND` response entry.
+#[derive(Default)]
+struct Resource {
+    href: String,
+    size: Option<u64>,
+    modified: Option<String>,
+    collection: bool,
+}
+
+/// Parse a `207 Multi-Status` response. Elements are matched by local name,
+/// since servers differ in the namespace prefix they use.
+fn parse_multistatus(body: &str) -> Result<Vec<Resource>> {
+    let mut reader = quick_xml::Reader::from_str(body);
+    reader.trim_text(true);
+    let mut resources = Vec::new();
+    let mut current: Option<Resource> = None;
+    let mut element = Vec::new();
+    loop {
+        match reader.read_event().context("Invalid PROPFIND response")? {
+            Event::Start(e) => {
+                element = e.local_name().as_ref().to_vec();
+                match element.as_slice() {
+                    b"response" => current = Some(Resource::default()),
+                    b"collection" => current.iter_mut().for_each(|r| r.collection = true),
+                    _ => {}
+                }
+            }
+            Event::Empty(e) if e.local_name().as_ref() == b"collection" => {
+                current.iter_mut().for_each(|r| r.collection = true);
+            }
+            Event::Text(text) => {
+                let (Some(resource), Ok(text)) = (current.as_mut(), text.unescape()) else { continue };
+                match element.as_slice() {
+                    b"href" => resource.href = text.into_owned(),
+                    b"getcontentlength" => resource.size = text.trim().parse().ok(),
+                    b"getlastmodified" => resource.modified = Some(text.into_owned()),
+                    _ => {}
+                }
+            }
+            Event::End(e) => {
+                element.clear();
+                if e.local_name().as_ref() == b"response" {
+                    resources.extend(current.take());
+                }
+            }
+            Event::Eof => return Ok(resources),
+            _ => {}
+        }
+    }
+}
+
+/// The path of `url` (which may already be just a path), decoded, without
+/// leading or trailing slashes.
+fn url_path(url: &str) -> String {
+    let path = match url.split_once("://") {
+        Some((_, rest)) => rest.find('/').map_or("", |i| &rest[i..]),
+        None => url,
+    };
+    let path = path.split(['?', '#']).next().unwrap_or("");
+    percent_decode(path).trim_matches('/').to_string()
+}
+
+/// A client for the server holding the archive.
+struct HttpClient {
+    agent: ureq::Agent,
+    authorization: Option<String>,
+}
+
+impl HttpClient {
+    fn from_env() -> Self {
+        let authorization = match (std::env::var("WEBDAV_USER"), std::env::var("WEBDAV_BEARER_TOKEN")) {
+            (Ok(user), _) => {
+                let password = std::env::var("WEBDAV_PASSWORD").unwrap_or_default();
+                let credentials = base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", user, password));
+                Some(format!("Basic {}", credentials))
+            }
+            (_, Ok(token)) => Some(format!("Bearer {}", token)),
+            _ => None,
+        };
+        HttpClient {
+            agent: ureq::AgentBuilder::new().build(),
+            authorization,
+        }
+    }
+
+    fn request(&self, method: &str, url: &str) -> ureq::Request {
+        let request = self.agent.request(method, url);
+        match self.authorization {
+            Some(ref authorization) => request.set("authorization", authorization),
+            None => request,
+        }
+    }
+
+    /// The members of the collection at `url`.
+    fn propfind(&self, url: &str) -> Result<Vec<Resource>> {
+        let response = self
+            .request("PROPFIND", url)
+            .set("depth", "1")
+            .set("content-type", "application/xml; charset=utf-8")
+            .send_string(PROPFIND_BODY);
+        let body = match response {
+            Ok(response) => response.into_string()?,
+            Err(ureq::Error::Status(code @ (405 | 501), _)) => {
+                return Err(Error::Config(format!(
+                    "{} does not support WebDAV listing (HTTP {}); list the files with --url-list instead",
+                    url, code
+                ))
+                .into())
+            }
+            Err(e) => return Err(request_error(e, url)),
+        };
+        parse_multistatus(&body)
+    }
+
+    /// Size and modification time of `url` from a `HEAD` request.
+    fn head(&self, url: &str) -> io::Result<(Option<u64>, Option<i64>)> {
+        let response = self.request("HEAD", url).call().map_err(|e| io_error(e, url))?;
+        let size = response.header("content-length").and_then(|v| v.parse().ok());
+        let mtime = response.header("last-modified").and_then(parse_http_date);
+        Ok((size, mtime))
+    }
+
+    fn get(&self, url: &str) -> io::Result<Box<dyn Read + Send>> {
+        let response = self.request("GET", url).call().map_err(|e| io_error(e, url))?;
+        Ok(Box::new(response.into_reader()))
+    }
+
+    /// Bytes `start..end` of `url`.
+    fn get_range(&self, url: &str, start: u64, end: u64) -> io::Result<Vec<u8>> {
+        let response = self
+            .request("GET", url)
+            .set("range", &format!("bytes={}-{}", start, end - 1))
+            .call()
+            .map_err(|e| io_error(e, url))?;
+        if response.status() != 206 {
+            return Err(io::Error::other(format!(
+                "{} ignores range requests; use a --part-size larger than the file to read it in one request",
+                url
+            )));
+        }
+        let mut data = Vec::with_capacity((end - start) as usize);
+        response.into_reader().read_to_end(&mut data)?;
+        Ok(data)
+    }
+}
+
+fn request_error(error: ureq::Error, what: &str) -> anyhow::Error {
+    match error {
+        ureq::Error::Status(code, response) => {
+            anyhow::anyhow!("Request for {} failed with HTTP {} {}", what, code, response.status_text())
+        }
+        e => anyhow::Error::new(e).context(format!("Request for {} failed", what)),
+    }
+}
+
+/// Like [`request_error`], keeping "not found" recognisable for validation.
+fn io_error(error: ureq::Error, what: &str) -> io::Error {
+    match error {
+        ureq::Error::Status(404 | 410, _) => io::Error::new(io::ErrorKind::NotFound, format!("{} not found", what)),
+        e => io::Error::other(format!("{:#}", request_error(e, what))),
+    }
+}
+
+/// A file found by crawling or from the URL list: its path, its size if the
+/// server reported one, and its mtime.
+type Found = (PathBuf, Option<u64>, Option<i64>);
+
+/// The files below an `http://` or `https://` directory URL.
+pub struct WebDavSource {
+    client: Arc<HttpClient>,
+    /// The directory URL with a trailing slash
+    base: String,
+    url_list: Option<PathBuf>,
+    ranges: RangeOptions,
+    /// Sizes from the listing; `None` when the server did not say
+    sizes: Mutex<HashMap<PathBuf, Option<u64>>>,
+}
+
+impl WebDavSource {
+    pub fn open(url: &str, args: &Args) -> Result<Self> {
+        Ok(WebDavSource {
+            client: Arc::new(HttpClient::from_env()),
+            base: format!("{}/", url.trim_end_matches('/')),
+            url_list: args.url_list.clone(),
+            ranges: RangeOptions::from_args(args)?,
+            sizes: Default::default(),
+        })
+    }
+
+    fn file_url(&self, path: &Path) -> String {
+        format!("{}{}", self.base, uri_encode(&object_key("", path), true))
+    }
+
+    /// Crawl the share a level at a time, each level in parallel.
+    fn crawl(&self, args: &Args) -> Result<Vec<Found>> {
+        let base_path = url_path(&self.base);
+        let mut files = Vec::new();
+        let mut level = vec![PathBuf::new()];
+        while !level.is_empty() && !args.cancel.is_cancelled() {
+            let listings = level
+                .par_iter()
+                .map(|dir| {
+                    let url = if dir.as_os_str().is_empty() { self.base.clone() } else { format!("{}/", self.file_url(dir)) };
+                    self.client.propfind(&url)
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let mut next = Vec::new();
+            for (dir, resources) in level.iter().zip(listings) {
+                for resource in resources {
+                    let path = url_path(&resource.href);
+                    let Some(relative) = path.strip_prefix(&base_path).map(|p| p.trim_start_matches('/')) else {
+                        continue;
+                    };
+                    let relative = PathBuf::from(relative);
+                    // The collection itself is listed along with its members
+                    if relative == *dir || relative.parent() != Some(dir.as_path()) {
+                        continue;
+                    }
+                    if resource.collection {
+                        next.push(relative);
+                    } else {
+                        files.push((relative, resource.size, resource.modified.as_deref().and_then(parse_http_date)));
+                    }
+                }
+            }
+            level = next;
+        }
+        Ok(files)
+    }
+
+    /// The files named in `--url-list`, absolute or relative to the archive
+    /// URL, with their sizes from `HEAD` requests.
+    fn listed(&self, list: &Path, args: &Args) -> Result<Vec<Found>> {
+        let content = std::fs::read_to_string(list).map_err(Error::io("Failed to read URL list", list))?;
+        let urls: Vec<&str> = content.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#')).collect();
+        urls.par_iter()
+            .filter(|_| !args.cancel.is_cancelled())
+            .map(|url| {
+                let relative = match url.strip_prefix(self.base.as_str()) {
+                    Some(relative) => percent_decode(relative),
+                    None if url.contains("://") => {
+                        return Err(Error::Config(format!("{} is not below the archive URL {}", url, self.base)).into());
+                    }
+                    None => url.trim_start_matches('/').to_string(),
+                };
+                let path = PathBuf::from(relative);
+                let (size, mtime) = self.client.head(&self.file_url(&path))?;
+                Ok((path, size, mtime))
+            })
+            .collect()
+    }
+}
+
+impl FileSource for WebDavSource {
+    fn files(&self, args: &Args) -> Result<Vec<FileInfo>> {
+        let found = match self.url_list {
+            Some(ref list) => self.listed(list, args)?,
+            None => self.crawl(args)?,
+        };
+        let mut sizes = self.sizes.lock().unwrap();
+        Ok(found
+            .into_iter()
+            .map(|(path, size, mtime)| {
+                sizes.insert(path.clone(), size);
+                FileInfo {
+                    path,
+                    size: size.unwrap_or(0),
+                    mtime,
+                }
+            })
+            .collect())
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send>> {
+        let url = self.file_url(path);
+        let listed = self.sizes.lock().unwrap().get(path).copied();
+        let size = match listed {
+            Some(size) => size,
+            None => self.client.head(&url)?.0,
+        };
+        // Small files, and files of unknown size, are read in one request
+        match size {
+            Some(size) if size > self.ranges.part_size => {
+                let client = Arc::clone(&self.client);
+                let fetch: FetchRange = Arc::new(move |start, end| client.get_range(&url, start, end));
+                Ok(Box::new(RangeReader::new(fetch, size, self.ranges)))
+            }
+            _ => self.client.get(&url),
+        }
+    }
+}
@@ -0,0 +1,236 @@
+//! `--metadata xattr`: extended attributes recorded per file in the extended
+//! manifest and checked by validation, so that metadata such as provenance
+//! notes is not lost silently by copies and restores that keep the contents.
+//!
+//! User attributes are recorded: `user.*` on Linux and the BSDs, and on
+//! macOS, where names have no namespace, everything outside `com.apple.*`.
+//! `--xattr-name` adds others, such as `security.selinux` or
+//! `com.apple.quarantine`. The `xattrs` column lists them sorted by name as
+//! `name=value` pairs separated by commas, names and values percent-encoded;
+//! `none` is a file without any.
+//!
+//! `--write-xattr` stamps files with the hash computed by generate and
+//! update, and the mtime it was computed for, in `user.sha256` and
+//! `user.sha256.mtime`. `--trust-xattr` takes the hash from a stamp whose
+//! mtime still matches, so the stamps act as a hash cache that travels with
+//! the files. Other tools can read and write them too. The stamps are not
+//! recorded by `--metadata xattr`.
+
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::{container, Args, Error, FileInfo, ManifestEntry, MetadataKind};
+
+/// The `xattrs` value of a file without recorded attributes
+const NONE: &str = "none";
+
+/// Attributes of the `--write-xattr` stamp
+#[cfg(unix)]
+const STAMP_HASH: &str = "user.sha256";
+#[cfg(unix)]
+const STAMP_MTIME: &str = "user.sha256.mtime";
+
+static WRITE_STAMPS: AtomicBool = AtomicBool::new(false);
+static TRUST_STAMPS: AtomicBool = AtomicBool::new(false);
+/// Set once a stamp could not be written, to warn only once per run
+static STAMP_FAILED: AtomicBool = AtomicBool::new(false);
+
+/// Whether `args` records extended attributes.
+pub(crate) fn recording(args: &Args) -> bool {
+    args.metadata.contains(&MetadataKind::Xattr)
+}
+
+/// Whether the attribute `name` is recorded without being named by
+/// `--xattr-name`.
+#[cfg(unix)]
+fn is_user_attribute(name: &str) -> bool {
+    if cfg!(target_os = "macos") {
+        !name.starts_with("com.apple.")
+    } else {
+        name.starts_with("user.")
+    }
+}
+
+/// The `xattrs` value of a file, or `None` for container members, which
+/// have no attributes of their own.
+pub(crate) fn record(file_info: &FileInfo, args: &Args) -> Result<Option<String>> {
+    if args.descend_archives && container::split_member(&file_info.path).is_some() {
+        return Ok(None);
+    }
+    read(&file_info.path, &args.xattr_name).map(Some)
+}
+
+#[cfg(unix)]
+fn read(path: &Path, extra_names: &[String]) -> Result<String> {
+    let names = match xattr::list_deref(path) {
+        Ok(names) => names,
+        // Filesystems without extended attributes hold none
+        Err(e) if e.kind() == std::io::ErrorKind::Unsupported => return Ok(NONE.to_string()),
+        Err(e) => return Err(Error::io("Failed to list extended attributes", path)(e).into()),
+    };
+    let mut attributes = BTreeMap::new();
+    for name in names {
+        let text = name.to_string_lossy();
+        if text == STAMP_HASH || text == STAMP_MTIME {
+            continue;
+        }
+        if !is_user_attribute(&text) && !extra_names.iter().any(|extra| *extra == text) {
+            continue;
+        }
+        // An attribute removed since it was listed is skipped
+        if let Some(value) = xattr::get_deref(path, &name).map_err(Error::io("Failed to read extended attributes", path))? {
+            attributes.insert(text.into_owned(), value);
+        }
+    }
+    Ok(encode(&attributes))
+}
+
+#[cfg(not(unix))]
+fn read(_path: &Path, _extra_names: &[String]) -> Result<String> {
+    Err(Error::Config("--metadata xattr is not supported on this platform".into()).into())
+}
+
+#[cfg(unix)]
+fn encode(attributes: &BTreeMap<String, Vec<u8>>) -> String {
+    if attributes.is_empty() {
+        return NONE.to_string();
+    }
+    attributes
+        .iter()
+        .map(|(name, value)| format!("{}={}", percent_encode(name.as_bytes()), percent_encode(value)))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// The attributes of an `xattrs` value. Pairs without `=` are ignored.
+pub(crate) fn decode(value: &str) -> BTreeMap<String, Vec<u8>> {
+    if value == NONE {
+        return BTreeMap::new();
+    }
+    value
+        .split(',')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(name, value)| (String::from_utf8_lossy(&percent_decode(name)).into_owned(), percent_decode(value)))
+        .collect()
+}
+
+/// Percent-encode everything but unreserved characters and `:@+/`, which
+/// keeps text values readable and the column free of spaces, `,` and `=`.
+#[cfg(unix)]
+fn percent_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::new();
+    for &byte in bytes {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b':' | b'@' | b'+' | b'/' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+fn percent_decode(value: &str) -> Vec<u8> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok()).and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    decoded
+}
+
+/// How the attributes of `file_info` differ from those recorded in
+/// `expected`, e.g. `user.origin removed, user.note changed`, or `None` if
+/// they match or none were recorded.
+pub(crate) fn changes(expected: &ManifestEntry, file_info: &FileInfo, args: &Args) -> Result<Option<String>> {
+    let Some(ref recorded) = expected.xattrs else {
+        return Ok(None);
+    };
+    let Some(actual) = record(file_info, args)? else {
+        return Ok(None);
+    };
+    if actual == *recorded {
+        return Ok(None);
+    }
+    let (recorded, actual) = (decode(recorded), decode(&actual));
+    let mut changes: Vec<String> = recorded
+        .iter()
+        .filter_map(|(name, value)| match actual.get(name) {
+            None => Some(format!("{} removed", name)),
+            Some(actual) if actual != value => Some(format!("{} changed", name)),
+            Some(_) => None,
+        })
+        .collect();
+    changes.extend(actual.keys().filter(|name| !recorded.contains_key(*name)).map(|name| format!("{} added", name)));
+    Ok((!changes.is_empty()).then(|| changes.join(", ")))
+}
+
+/// Take `--write-xattr` and `--trust-xattr` for the rest of the run.
+pub(crate) fn configure_stamps(args: &Args) -> Result<()> {
+    if !cfg!(unix) && (args.write_xattr || args.trust_xattr) {
+        return Err(Error::Config("--write-xattr and --trust-xattr are not supported on this platform".into()).into());
+    }
+    WRITE_STAMPS.store(args.write_xattr, Ordering::Relaxed);
+    TRUST_STAMPS.store(args.trust_xattr, Ordering::Relaxed);
+    STAMP_FAILED.store(false, Ordering::Relaxed);
+    Ok(())
+}
+
+/// The hash stamped on a file, with `--trust-xattr`, if the stamp was made
+/// for its current mtime.
+#[cfg(unix)]
+pub(crate) fn stamped_hash(file_info: &FileInfo) -> Option<String> {
+    if !TRUST_STAMPS.load(Ordering::Relaxed) {
+        return None;
+    }
+    let mtime = xattr::get_deref(&file_info.path, STAMP_MTIME).ok()??;
+    if mtime != crate::format_mtime(file_info.mtime?).as_bytes() {
+        return None;
+    }
+    let hash = String::from_utf8(xattr::get_deref(&file_info.path, STAMP_HASH).ok()??).ok()?;
+    (hash.len() == 64 && hash.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))).then_some(hash)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn stamped_hash(_file_info: &FileInfo) -> Option<String> {
+    None
+}
+
+/// Stamp a file with its freshly computed `hash`, with `--write-xattr`.
+/// Files that cannot take the stamp are hashed as usual; the first failure
+/// is reported.
+#[cfg(unix)]
+pub(crate) fn stamp(file_info: &FileInfo, hash: &str) {
+    if !WRITE_STAMPS.load(Ordering::Relaxed) {
+        return;
+    }
+    // Special files take no user attributes
+    if crate::special::is_marker(hash) {
+        return;
+    }
+    let Some(mtime) = file_info.mtime else { return };
+    let result = xattr::set_deref(&file_info.path, STAMP_HASH, hash.as_bytes())
+        .and_then(|()| xattr::set_deref(&file_info.path, STAMP_MTIME, crate::format_mtime(mtime).as_bytes()));
+    match result {
+        // Container members, and files removed since they were hashed
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) if !STAMP_FAILED.swap(true, Ordering::Relaxed) => {
+            eprintln!("Warning: Cannot stamp {} with its hash: {} (further failures are not reported)", file_info.path.display(), e);
+        }
+        _ => {}
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn stamp(_file_info: &FileInfo, _hash: &str) {}